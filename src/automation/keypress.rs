@@ -1,33 +1,45 @@
 #![allow(dead_code)]
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use std::time::Duration;
 use tokio::time::sleep;
 use tracing::{debug, info};
 
 use crate::automation::library_keyboard_input::LibraryBasedKeyboardMapper;
 use crate::automation::vm::{VmInstance, VmManager};
+use crate::utils::redact;
 
 #[derive(Debug, Clone)]
 pub enum KeypressAction {
     Key(String),
     KeyCombo(Vec<String>, String), // modifiers, key
     TypeText(String),
+    /// Text whose non-ASCII characters go through the guest's Ctrl+Shift+U Unicode input
+    TypeUnicode(String),
+    /// Text typed while the modifiers are held down
+    TypeHeld(Vec<String>, String), // modifiers, text
     Wait(Duration),
 }
 
 pub struct KeypressExecutor {
     // Uses VM manager to send keys through the provider abstraction
     keyboard_mapper: LibraryBasedKeyboardMapper,
+    /// Log the scancodes behind every TYPE, set by --dump-scancodes-on-type
+    dump_scancodes: bool,
 }
 
 impl KeypressExecutor {
     pub fn new() -> Self {
         Self {
             keyboard_mapper: LibraryBasedKeyboardMapper::new(),
+            dump_scancodes: false,
         }
     }
 
+    pub fn set_dump_scancodes(&mut self, enabled: bool) {
+        self.dump_scancodes = enabled;
+    }
+
     pub async fn execute_action(
         &mut self,
         vm: &VmInstance,
@@ -43,7 +55,13 @@ impl KeypressExecutor {
                     .await?;
             }
             KeypressAction::TypeText(text) => {
-                self.type_text(vm, text, vm_manager).await?;
+                self.type_text(vm, text, false, &[], vm_manager).await?;
+            }
+            KeypressAction::TypeUnicode(text) => {
+                self.type_text(vm, text, true, &[], vm_manager).await?;
+            }
+            KeypressAction::TypeHeld(modifiers, text) => {
+                self.type_text(vm, text, false, modifiers, vm_manager).await?;
             }
             KeypressAction::Wait(duration) => {
                 debug!("Waiting for {:?}", duration);
@@ -56,18 +74,62 @@ impl KeypressExecutor {
         Ok(())
     }
 
-    async fn send_key(&mut self, vm: &VmInstance, key: &str, vm_manager: &VmManager) -> Result<()> {
-        debug!("Sending key '{}' to VM {}", key, vm.name);
+    /// Send `actions` as a single scancode sequence in one provider call, keeping their order
+    pub async fn execute_batch(
+        &mut self,
+        vm: &VmInstance,
+        actions: &[KeypressAction],
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        let scancodes = self.batch_scancodes(actions)?;
+        vm_manager.send_keys_to_vm(vm, &scancodes).await?;
+
+        sleep(Duration::from_millis(50)).await;
+        Ok(())
+    }
 
+    /// Concatenated scancodes of `actions`, which must not contain waits
+    pub fn batch_scancodes(&mut self, actions: &[KeypressAction]) -> Result<Vec<String>> {
+        let mut scancodes = Vec::new();
+        for action in actions {
+            scancodes.extend(self.action_scancodes(action)?);
+        }
+        Ok(scancodes)
+    }
+
+    /// Scancodes `execute_action` sends for `action`
+    fn action_scancodes(&mut self, action: &KeypressAction) -> Result<Vec<String>> {
+        match action {
+            KeypressAction::Key(key) => self.key_scancodes(key),
+            KeypressAction::KeyCombo(modifiers, key) => {
+                self.keyboard_mapper.key_combination_to_scancodes(modifiers, key)
+            }
+            KeypressAction::TypeText(text) => self.keyboard_mapper.text_to_scancodes(text),
+            KeypressAction::TypeUnicode(text) => {
+                self.keyboard_mapper.text_to_unicode_scancodes(text)
+            }
+            KeypressAction::TypeHeld(modifiers, text) => {
+                self.keyboard_mapper.held_text_to_scancodes(modifiers, text)
+            }
+            KeypressAction::Wait(_) => Err(anyhow!("A wait has no scancodes to batch")),
+        }
+    }
+
+    fn key_scancodes(&mut self, key: &str) -> Result<Vec<String>> {
         // Use the enhanced keyboard mapper for special keys
-        let scancodes = if key.len() == 1 {
+        if key.len() == 1 {
             // Single character
-            self.keyboard_mapper.text_to_scancodes(key)?
+            self.keyboard_mapper.text_to_scancodes(key)
         } else {
             // Special key (e.g., "enter", "f1", etc.)
-            self.keyboard_mapper.special_key_to_scancodes(key)?
-        };
+            self.keyboard_mapper.special_key_to_scancodes(key)
+        }
+    }
+
+    async fn send_key(&mut self, vm: &VmInstance, key: &str, vm_manager: &VmManager) -> Result<()> {
+        debug!("Sending key '{}' to VM {}", key, vm.name);
 
+        let scancodes = self.key_scancodes(key)?;
         vm_manager.send_keys_to_vm(vm, &scancodes).await
     }
 
@@ -94,12 +156,85 @@ impl KeypressExecutor {
         &mut self,
         vm: &VmInstance,
         text: &str,
+        unicode: bool,
+        modifiers: &[String],
         vm_manager: &VmManager,
     ) -> Result<()> {
-        info!("Typing text to VM {}: '{}'", vm.name, text);
-
         // Use the enhanced keyboard mapper for comprehensive text input
-        let scancodes = self.keyboard_mapper.text_to_scancodes(text)?;
+        let shown = redact::redact(text);
+        let scancodes = if unicode {
+            debug!("Typing text to VM {} via Unicode input: '{}'", vm.name, shown);
+            self.keyboard_mapper.text_to_unicode_scancodes(text)?
+        } else if !modifiers.is_empty() {
+            debug!("Typing text to VM {} holding {}: '{}'", vm.name, modifiers.join("+"), shown);
+            self.keyboard_mapper.held_text_to_scancodes(modifiers, text)?
+        } else {
+            debug!("Typing text to VM {}: '{}'", vm.name, shown);
+            self.keyboard_mapper.text_to_scancodes(text)?
+        };
+
+        if self.dump_scancodes {
+            self.dump_text_scancodes(text, unicode, &scancodes)?;
+        }
+
         vm_manager.send_keys_to_vm(vm, &scancodes).await
     }
+
+    /// Log each character's scancodes, then the exact sequence passed to keyboardputscancode
+    fn dump_text_scancodes(
+        &mut self,
+        text: &str,
+        unicode: bool,
+        scancodes: &[String],
+    ) -> Result<()> {
+        // Log redaction masks the text but not its scancodes, which would spell out the secret
+        if redact::redact(text) != text {
+            info!("Scancode dump withheld: the typed text contains a secret");
+            return Ok(());
+        }
+
+        for ch in text.chars() {
+            let ch_text = ch.to_string();
+            let ch_scancodes = if unicode {
+                self.keyboard_mapper.text_to_unicode_scancodes(&ch_text)?
+            } else {
+                self.keyboard_mapper.text_to_scancodes(&ch_text)?
+            };
+            info!("  {:<8} {}", format!("{:?}", ch), ch_scancodes.join(" "));
+        }
+        info!("keyboardputscancode {}", scancodes.join(" "));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_scancodes_concatenate_in_order() {
+        let mut executor = KeypressExecutor::new();
+        let actions = vec![
+            KeypressAction::Key("down".to_string()),
+            KeypressAction::Key("down".to_string()),
+            KeypressAction::KeyCombo(vec!["ctrl".to_string()], "c".to_string()),
+            KeypressAction::Key("enter".to_string()),
+        ];
+
+        let mut mapper = LibraryBasedKeyboardMapper::new();
+        let mut expected = Vec::new();
+        expected.extend(mapper.special_key_to_scancodes("down").unwrap());
+        expected.extend(mapper.special_key_to_scancodes("down").unwrap());
+        expected.extend(
+            mapper
+                .key_combination_to_scancodes(&["ctrl".to_string()], "c")
+                .unwrap(),
+        );
+        expected.extend(mapper.special_key_to_scancodes("enter").unwrap());
+
+        assert_eq!(executor.batch_scancodes(&actions).unwrap(), expected);
+        assert!(executor
+            .batch_scancodes(&[KeypressAction::Wait(Duration::from_millis(1))])
+            .is_err());
+    }
 }
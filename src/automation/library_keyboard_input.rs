@@ -60,6 +60,28 @@ impl LibraryBasedKeyboardMapper {
         Ok(scancodes)
     }
 
+    /// Like `text_to_scancodes`, but non-ASCII characters are entered with Ctrl+Shift+U, their
+    /// hex code point and Space, the Unicode input of GTK and IBus on most Linux desktops
+    pub fn text_to_unicode_scancodes(&mut self, text: &str) -> Result<Vec<String>> {
+        let mut scancodes = Vec::new();
+
+        for ch in text.chars() {
+            if ch.is_ascii() {
+                scancodes.extend(self.text_to_scancodes(&ch.to_string())?);
+                continue;
+            }
+
+            scancodes.extend(self.key_combination_to_scancodes(
+                &["ctrl".to_string(), "shift".to_string()],
+                "u",
+            )?);
+            scancodes.extend(self.text_to_scancodes(&format!("{:x}", ch as u32))?);
+            scancodes.extend(self.special_key_to_scancodes("space")?);
+        }
+
+        Ok(scancodes)
+    }
+
     /// Generate scancodes for a single character using the best available method
     fn generate_char_scancodes(&self, ch: char) -> Result<Vec<String>> {
         // 1. Try fallback mappings first for critical characters
@@ -255,8 +277,8 @@ impl LibraryBasedKeyboardMapper {
         Ok(scancodes.into_iter().map(|s| s.to_string()).collect())
     }
 
-    /// Get modifier key scancodes
-    pub fn modifier_to_scancodes(&self, modifier: &str, press: bool) -> Result<String> {
+    /// Get modifier key scancodes; extended keys like meta carry an `e0` prefix byte
+    pub fn modifier_to_scancodes(&self, modifier: &str, press: bool) -> Result<Vec<String>> {
         let scancode = match modifier.to_lowercase().as_str() {
             "ctrl" | "control" => {
                 if press {
@@ -279,10 +301,48 @@ impl LibraryBasedKeyboardMapper {
                     "b8"
                 }
             }
+            "meta" | "super" | "win" | "cmd" => {
+                let code = if press { "5b" } else { "db" };
+                return Ok(vec!["e0".to_string(), code.to_string()]);
+            }
             _ => return Err(anyhow!("Unknown modifier key: {}", modifier)),
         };
 
-        Ok(scancode.to_string())
+        Ok(vec![scancode.to_string()])
+    }
+
+    /// Scancodes for one PRESS, mapped the same way the keypress executor sends them
+    pub fn press_to_scancodes(&mut self, modifiers: &[String], key: &str) -> Result<Vec<String>> {
+        if !modifiers.is_empty() {
+            self.key_combination_to_scancodes(modifiers, key)
+        } else if key.len() == 1 {
+            self.text_to_scancodes(key)
+        } else {
+            self.special_key_to_scancodes(key)
+        }
+    }
+
+    /// Check that a PRESS maps to scancodes the same way it will at runtime
+    pub fn validate_press(&mut self, modifiers: &[String], key: &str) -> Result<()> {
+        self.press_to_scancodes(modifiers, key).map(|_| ())
+    }
+
+    /// Scancodes typing `text` with `modifiers` held down: modifier makes, the text, then the
+    /// modifier breaks in reverse order. Characters that need Shift release a held Shift.
+    pub fn held_text_to_scancodes(
+        &mut self,
+        modifiers: &[String],
+        text: &str,
+    ) -> Result<Vec<String>> {
+        let mut scancodes = Vec::new();
+        for modifier in modifiers {
+            scancodes.extend(self.modifier_to_scancodes(modifier, true)?);
+        }
+        scancodes.extend(self.text_to_scancodes(text)?);
+        for modifier in modifiers.iter().rev() {
+            scancodes.extend(self.modifier_to_scancodes(modifier, false)?);
+        }
+        Ok(scancodes)
     }
 
     /// Handle complex key combinations
@@ -295,7 +355,7 @@ impl LibraryBasedKeyboardMapper {
 
         // Press all modifiers
         for modifier in modifiers {
-            scancodes.push(self.modifier_to_scancodes(modifier, true)?);
+            scancodes.extend(self.modifier_to_scancodes(modifier, true)?);
         }
 
         // Press and release the main key
@@ -314,7 +374,7 @@ impl LibraryBasedKeyboardMapper {
 
         // Release all modifiers (in reverse order)
         for modifier in modifiers.iter().rev() {
-            scancodes.push(self.modifier_to_scancodes(modifier, false)?);
+            scancodes.extend(self.modifier_to_scancodes(modifier, false)?);
         }
 
         Ok(scancodes)
@@ -362,6 +422,20 @@ mod tests {
         assert!(!result.is_empty());
     }
 
+    #[test]
+    fn test_unicode_input_method() {
+        let mut mapper = LibraryBasedKeyboardMapper::new();
+
+        // 'é' is U+00E9: Ctrl+Shift+U, "e9", Space; ASCII is typed as usual
+        let result = mapper.text_to_unicode_scancodes("aé").unwrap();
+        assert_eq!(
+            result,
+            vec![
+                "1e", "9e", "1d", "2a", "16", "96", "aa", "9d", "12", "92", "0a", "8a", "39", "b9"
+            ]
+        );
+    }
+
     #[test]
     fn test_equals_sign_mapping() {
         let mut mapper = LibraryBasedKeyboardMapper::new();
@@ -369,4 +443,47 @@ mod tests {
         // 0x0d = make, 0x8d = break for '=' on US keyboard
         assert_eq!(result, vec!["0d", "8d"]);
     }
+
+    #[test]
+    fn test_validate_press() {
+        let mut mapper = LibraryBasedKeyboardMapper::new();
+        let combo = vec!["control".to_string(), "alt".to_string()];
+
+        assert!(mapper.validate_press(&combo, "t").is_ok());
+        assert!(mapper.validate_press(&[], "F12").is_ok());
+        assert!(mapper.validate_press(&[], "f13").is_err());
+        assert!(mapper.validate_press(&["hyper".to_string()], "t").is_err());
+    }
+
+    #[test]
+    fn test_meta_combination() {
+        let mut mapper = LibraryBasedKeyboardMapper::new();
+
+        // PRESS meta+l: extended Super make, 'l' make/break, extended Super break
+        let result = mapper
+            .key_combination_to_scancodes(&["meta".to_string()], "l")
+            .unwrap();
+        assert_eq!(result, vec!["e0", "5b", "26", "a6", "e0", "db"]);
+
+        for alias in ["super", "win", "cmd"] {
+            let result = mapper
+                .key_combination_to_scancodes(&[alias.to_string()], "l")
+                .unwrap();
+            assert_eq!(result, vec!["e0", "5b", "26", "a6", "e0", "db"]);
+        }
+    }
+
+    #[test]
+    fn test_held_text() {
+        let mut mapper = LibraryBasedKeyboardMapper::new();
+
+        // TYPE --hold alt 12: Alt make, '1' and '2' make/break, Alt break
+        let result = mapper
+            .held_text_to_scancodes(&["alt".to_string()], "12")
+            .unwrap();
+        assert_eq!(result, vec!["38", "02", "82", "03", "83", "b8"]);
+        assert!(mapper
+            .held_text_to_scancodes(&["hyper".to_string()], "12")
+            .is_err());
+    }
 }
@@ -1,8 +1,12 @@
 pub mod keypress;
 pub mod library_keyboard_input;
-mod models;
+pub mod models;
 pub mod ocr;
+pub mod ocr_overlay;
 pub mod puppet;
+pub mod recorder;
+pub mod serial;
+pub mod ssh_error;
 pub mod vm;
 
 #[allow(unused_imports)]
@@ -1,10 +1,46 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
+use indicatif::{ProgressBar, ProgressStyle};
 use std::fmt;
+use std::io::{IsTerminal, Read, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use std::{fs, path::Path};
 use tracing::{debug, info};
 use url::Url;
 
+/// Bytes per second downloads are throttled to, 0 for no limit
+static DOWNLOAD_LIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Size of each read from the response body, which is also the throttling granularity
+const DOWNLOAD_CHUNK: usize = 64 * 1024;
+
+/// Throttle every download of this process to `bytes_per_sec` (`--download-limit`)
+pub fn set_download_limit(bytes_per_sec: u64) {
+    DOWNLOAD_LIMIT.store(bytes_per_sec, Ordering::Relaxed);
+}
+
+/// Parse a transfer rate such as `500K`, `2M` or `1.5MB/s` into bytes per second
+pub fn parse_rate(rate: &str) -> Result<u64, anyhow::Error> {
+    let trimmed = rate.trim();
+    let number = trimmed.trim_end_matches("/s").trim_end_matches(['B', 'b']);
+    let (digits, multiplier) = match number.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&number[..i], 1024.0),
+        Some((i, 'm' | 'M')) => (&number[..i], 1024.0 * 1024.0),
+        Some((i, 'g' | 'G')) => (&number[..i], 1024.0 * 1024.0 * 1024.0),
+        _ => (number, 1.0),
+    };
+    let value: f64 = digits
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Invalid rate '{}', expected e.g. 500K or 2M", rate))?;
+    let bytes_per_sec = (value * multiplier) as u64;
+    if bytes_per_sec == 0 {
+        return Err(anyhow!("Download rate '{}' must be above zero", rate));
+    }
+    Ok(bytes_per_sec)
+}
+
 /// Return the path to the directory in which cached models etc. should be
 /// saved.
 fn cache_dir() -> Result<PathBuf, anyhow::Error> {
@@ -29,15 +65,19 @@ fn filename_from_url(url: &str) -> Option<String> {
         .map(|s| s.to_string())
 }
 
-/// Download a file from `url` to a local cache, if not already fetched, and
-/// return the path to the local file.
-pub fn download_file(url: &str, filename: Option<&str>) -> Result<PathBuf, anyhow::Error> {
-    let cache_dir = cache_dir()?;
+/// Path `download_file` caches `url` at, whether or not it has been fetched yet.
+pub fn cached_path(url: &str, filename: Option<&str>) -> Result<PathBuf, anyhow::Error> {
     let filename = match filename {
         Some(fname) => fname.to_string(),
         None => filename_from_url(url).ok_or(anyhow!("Could not get destination filename"))?,
     };
-    let file_path = cache_dir.join(filename);
+    Ok(cache_dir()?.join(filename))
+}
+
+/// Download a file from `url` to a local cache, if not already fetched, and
+/// return the path to the local file.
+pub fn download_file(url: &str, filename: Option<&str>) -> Result<PathBuf, anyhow::Error> {
+    let file_path = cached_path(url, filename)?;
     if file_path.exists() {
         debug!("Using cached model: {:?}", file_path);
         return Ok(file_path);
@@ -45,16 +85,138 @@ pub fn download_file(url: &str, filename: Option<&str>) -> Result<PathBuf, anyho
 
     info!("Downloading OCR model from {}...", url);
 
-    let response = ureq::get(url).call()?;
-    let mut body = response.into_body();
-    let buf = body.read_to_vec()?;
-
-    fs::write(&file_path, &buf)?;
+    // Fetch into a .part file so an interrupted download resumes instead of starting over
+    let mut part_path = file_path.clone().into_os_string();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    fetch_resumable(url, &part_path)?;
+    fs::rename(&part_path, &file_path)
+        .with_context(|| format!("Failed to move download into place: {:?}", file_path))?;
     info!("Downloaded OCR model to: {:?}", file_path);
 
     Ok(file_path)
 }
 
+/// Download `url` into `part_path`, continuing from its current length with a range request
+fn fetch_resumable(url: &str, part_path: &Path) -> Result<(), anyhow::Error> {
+    let offset = fs::metadata(part_path).map(|meta| meta.len()).unwrap_or(0);
+    let mut request = ureq::get(url);
+    if offset > 0 {
+        info!("Resuming download at {} bytes", offset);
+        request = request.header("Range", format!("bytes={}-", offset));
+    }
+
+    let response = match request.call() {
+        // The partial file is no prefix of what the server has now
+        Err(ureq::Error::StatusCode(416)) if offset > 0 => {
+            fs::remove_file(part_path)?;
+            return fetch_resumable(url, part_path);
+        }
+        result => result?,
+    };
+
+    // A server that ignores the range sends the whole file again
+    let resumed = response.status().as_u16() == 206;
+    let offset = if resumed { offset } else { 0 };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part_path)
+        .with_context(|| format!("Failed to open download file: {:?}", part_path))?;
+
+    let body = response.into_body();
+    let total = body.content_length().map(|length| length + offset);
+    let mut progress = DownloadProgress::new(total, offset);
+    let limit = DOWNLOAD_LIMIT.load(Ordering::Relaxed);
+
+    let mut reader = body.into_reader();
+    let mut buffer = vec![0u8; DOWNLOAD_CHUNK];
+    let started = Instant::now();
+    let mut received = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])?;
+        received += read as u64;
+        progress.update(offset + received);
+
+        if limit > 0 {
+            let due = Duration::from_secs_f64(received as f64 / limit as f64);
+            if let Some(ahead) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(ahead);
+            }
+        }
+    }
+    file.flush()?;
+    progress.finish();
+
+    if let Some(total) = total.filter(|total| offset + received < *total) {
+        return Err(anyhow!(
+            "Download of {} ended early at {} of {} bytes, rerun to resume",
+            url,
+            offset + received,
+            total
+        ));
+    }
+    Ok(())
+}
+
+/// Progress bar with percentage and ETA on a terminal, otherwise a log line every 10%
+struct DownloadProgress {
+    bar: Option<ProgressBar>,
+    total: Option<u64>,
+    last_logged_percent: u64,
+}
+
+impl DownloadProgress {
+    fn new(total: Option<u64>, position: u64) -> Self {
+        let bar = std::io::stderr().is_terminal().then(|| {
+            let bar = match total {
+                Some(total) => ProgressBar::new(total),
+                None => ProgressBar::no_length(),
+            };
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{bytes}/{total_bytes} ({percent}%) {bytes_per_sec}, ETA {eta} {wide_bar}",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+            );
+            bar.set_position(position);
+            bar
+        });
+        Self {
+            bar,
+            total,
+            last_logged_percent: 0,
+        }
+    }
+
+    fn update(&mut self, position: u64) {
+        if let Some(bar) = &self.bar {
+            bar.set_position(position);
+            return;
+        }
+        let Some(total) = self.total.filter(|total| *total > 0) else {
+            return;
+        };
+        let percent = position * 100 / total;
+        if percent >= self.last_logged_percent + 10 {
+            self.last_logged_percent = percent - percent % 10;
+            info!("Downloaded {}% ({} of {} bytes)", percent, position, total);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
 /// Location that a model can be loaded from.
 #[derive(Clone)]
 pub enum ModelSource {
@@ -1,11 +1,14 @@
 #![allow(dead_code)]
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use image::{DynamicImage, GenericImageView};
-use ocrs::{DecodeMethod, DimOrder, ImageSource, OcrEngine as OcrsEngine, OcrEngineParams};
+use ocrs::{
+    DecodeMethod, DimOrder, ImageSource, OcrEngine as OcrsEngine, OcrEngineParams, TextLine,
+};
 use parking_lot::RwLock;
 use ring::digest;
+use rten_imageproc::{BoundingRect, RotatedRect};
 use rten_tensor::AsView;
 use std::sync::Arc;
 use std::sync::LazyLock;
@@ -84,6 +87,76 @@ impl ScreenState {
     }
 }
 
+/// Axis-aligned box found by OCR layout analysis, used for debug overlays
+#[derive(Debug, Clone)]
+pub struct DetectedTextBox {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    /// Recognized text (only set for line boxes)
+    pub text: Option<String>,
+}
+
+/// Word and line boxes detected on a screen
+#[derive(Debug, Clone, Default)]
+pub struct TextLayout {
+    pub words: Vec<DetectedTextBox>,
+    pub lines: Vec<DetectedTextBox>,
+}
+
+/// Boxes of the detected words and of the lines recognition grouped them into
+fn text_layout(
+    word_rects: &[RotatedRect],
+    line_rects: &[Vec<RotatedRect>],
+    line_texts: &[Option<TextLine>],
+) -> TextLayout {
+    let to_box = |rect: rten_imageproc::Rect<f32>, text: Option<String>| {
+        let rect = rect.integral_bounding_rect();
+        DetectedTextBox {
+            left: rect.left(),
+            top: rect.top(),
+            width: rect.width().max(1) as u32,
+            height: rect.height().max(1) as u32,
+            text,
+        }
+    };
+
+    let words = word_rects
+        .iter()
+        .map(|rect| to_box(rect.bounding_rect(), None))
+        .collect();
+
+    let lines = line_rects
+        .iter()
+        .zip(line_texts.iter())
+        .filter_map(|(words, text)| {
+            let rect = rten_imageproc::bounding_rect(words.iter())?;
+            Some(to_box(rect, text.as_ref().map(|line| line.to_string())))
+        })
+        .collect();
+
+    TextLayout { words, lines }
+}
+
+/// Recognition tuning chosen on the command line
+#[derive(Debug, Clone, Copy)]
+pub struct OcrOptions {
+    /// Beam search decoding: better on small fonts, noticeably slower than greedy
+    pub beam_search: bool,
+    /// Minimum time between screen updates before the cached OCR result is refreshed
+    pub update_threshold: Duration,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        Self {
+            beam_search: false,
+            update_threshold: Duration::from_millis(100),
+        }
+    }
+}
+
 pub struct OcrEngine {
     engine: OcrsEngine,
     /// Cached screen state to avoid race conditions
@@ -155,10 +228,11 @@ impl TimeoutTracker {
 }
 
 /// Default text detection model.
-const DETECTION_MODEL: &str = "https://ocrs-models.s3-accelerate.amazonaws.com/text-detection.rten";
+pub(crate) const DETECTION_MODEL: &str =
+    "https://ocrs-models.s3-accelerate.amazonaws.com/text-detection.rten";
 
 /// Default text recognition model.
-const RECOGNITION_MODEL: &str =
+pub(crate) const RECOGNITION_MODEL: &str =
     "https://ocrs-models.s3-accelerate.amazonaws.com/text-recognition.rten";
 
 /// Cached model paths to avoid repeated downloads
@@ -174,40 +248,71 @@ static CACHED_RECOGNITION_PATH: LazyLock<Result<std::path::PathBuf, anyhow::Erro
         super::models::download_file(RECOGNITION_MODEL, None)
     });
 
+/// Whether two frames differ in at most `tolerance` (a fraction) of their pixels
+pub fn screens_match(a: &DynamicImage, b: &DynamicImage, tolerance: f64) -> bool {
+    if a.width() != b.width() || a.height() != b.height() {
+        return false;
+    }
+
+    let (a, b) = (a.to_rgba8(), b.to_rgba8());
+    let differing = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|(pa, pb)| pa != pb)
+        .count();
+    let total = (a.width() as u64 * a.height() as u64).max(1);
+
+    (differing as f64 / total as f64) <= tolerance
+}
+
 impl OcrEngine {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         Self::with_options(false, Duration::from_millis(100))
     }
 
-    pub fn with_beam_search() -> Self {
+    pub fn with_beam_search() -> Result<Self> {
         Self::with_options(true, Duration::from_millis(100))
     }
 
-    pub fn with_update_threshold(threshold: Duration) -> Self {
+    pub fn with_update_threshold(threshold: Duration) -> Result<Self> {
         Self::with_options(false, threshold)
     }
 
-    fn with_options(beam_search: bool, update_threshold: Duration) -> Self {
+    pub fn from_options(options: OcrOptions) -> Result<Self> {
+        Self::with_options(options.beam_search, options.update_threshold)
+    }
+
+    fn with_options(beam_search: bool, update_threshold: Duration) -> Result<Self> {
         debug!("Initializing enhanced OCR engine using cached pre-trained models");
 
         // Use cached model paths to avoid repeated downloads, but still load models fresh
-        let detection_binding = CACHED_DETECTION_PATH.as_ref();
-        let detection_path = detection_binding
-            .as_ref()
-            .expect("Failed to get cached detection model path");
-        let recognition_binding = CACHED_RECOGNITION_PATH.as_ref();
-        let recognition_path = recognition_binding
-            .as_ref()
-            .expect("Failed to get cached recognition model path");
+        let detection_path = CACHED_DETECTION_PATH.as_ref().map_err(|e| {
+            anyhow!(
+                "Failed to download OCR text detection model from {}: {:#}",
+                DETECTION_MODEL,
+                e
+            )
+        })?;
+        let recognition_path = CACHED_RECOGNITION_PATH.as_ref().map_err(|e| {
+            anyhow!(
+                "Failed to download OCR text recognition model from {}: {:#}",
+                RECOGNITION_MODEL,
+                e
+            )
+        })?;
 
         let detection_model = load_model(ModelSource::Path(
             detection_path.to_string_lossy().to_string(),
         ))
-        .expect("Failed to load detection model from cached path");
+        .with_context(|| {
+            format!("Failed to load OCR detection model: {}", detection_path.display())
+        })?;
         let recognition_model = load_model(ModelSource::Path(
             recognition_path.to_string_lossy().to_string(),
         ))
-        .expect("Failed to load recognition model from cached path");
+        .with_context(|| {
+            format!("Failed to load OCR recognition model: {}", recognition_path.display())
+        })?;
 
         // Create OCR engine with enhanced parameters
         let decode_method = if beam_search {
@@ -226,7 +331,7 @@ impl OcrEngine {
             ..Default::default()
         };
 
-        let engine = OcrsEngine::new(engine_params).expect("Failed to initialize OCR engine");
+        let engine = OcrsEngine::new(engine_params).context("Failed to initialize OCR engine")?;
 
         debug!(
             "OCR engine initialized with cached models and {} decoding",
@@ -236,7 +341,7 @@ impl OcrEngine {
         // Create channels for background monitoring
         let (change_tx, change_rx) = broadcast::channel(100);
 
-        Self {
+        Ok(Self {
             engine,
             screen_state: Arc::new(RwLock::new(None)),
             update_threshold,
@@ -244,7 +349,7 @@ impl OcrEngine {
             change_rx,
             change_tx,
             timeout_tracker: Arc::new(RwLock::new(TimeoutTracker::new())),
-        }
+        })
     }
 
     /// Generate a hash of the image for change detection
@@ -298,19 +403,36 @@ impl OcrEngine {
     }
 
     pub async fn extract_text(&self, image: &DynamicImage) -> Result<String> {
+        Ok(self.extract(image, false).await?.0)
+    }
+
+    /// Like `extract_text`, but also return the word and line boxes found in the same pass,
+    /// for debug overlays. The screen state cache keeps no boxes, so this always runs OCR
+    pub async fn extract_text_and_layout(
+        &self,
+        image: &DynamicImage,
+    ) -> Result<(String, TextLayout)> {
+        self.extract(image, true).await
+    }
+
+    async fn extract(
+        &self,
+        image: &DynamicImage,
+        with_layout: bool,
+    ) -> Result<(String, TextLayout)> {
         let start_time = Instant::now();
         debug!("OCR extract_text called");
 
         // Check if we can use cached screen state
         let image_hash = self.hash_image(image);
-        if self.is_state_current(&image_hash) {
+        if !with_layout && self.is_state_current(&image_hash) {
             let cached_text = self.screen_state.read().as_ref().unwrap().text.clone();
             debug!(
                 "OCR cache hit ({}ms) - using recent screen state: '{}'",
                 start_time.elapsed().as_millis(),
                 cached_text
             );
-            return Ok(cached_text);
+            return Ok((cached_text, TextLayout::default()));
         }
 
         debug!("OCR cache miss - performing fresh text extraction");
@@ -322,11 +444,11 @@ impl OcrEngine {
         // Wrap OCR processing in timeout
         match tokio::time::timeout(
             timeout_duration,
-            self.extract_text_internal(image, image_hash.clone()),
+            self.extract_text_internal(image, image_hash.clone(), with_layout),
         )
         .await
         {
-            Ok(Ok(text)) => {
+            Ok(Ok((text, layout))) => {
                 // Success - record it and return result
                 self.timeout_tracker.write().record_success();
                 if !text.is_empty() {
@@ -337,7 +459,7 @@ impl OcrEngine {
                         text
                     );
                 }
-                Ok(text)
+                Ok((text, layout))
             }
             Ok(Err(e)) => {
                 // OCR error (not timeout)
@@ -364,17 +486,19 @@ impl OcrEngine {
                     dimensions: image.dimensions(),
                 };
                 self.update_screen_state(empty_state);
-                Ok(String::new())
+                Ok((String::new(), TextLayout::default()))
             }
         }
     }
 
-    /// Internal OCR processing without timeout wrapper
+    /// Internal OCR processing without timeout wrapper; the layout stays empty unless
+    /// `with_layout` is set
     async fn extract_text_internal(
         &self,
         image: &DynamicImage,
         image_hash: String,
-    ) -> Result<String> {
+        with_layout: bool,
+    ) -> Result<(String, TextLayout)> {
         // Convert to RGB format for ocrs (reuse existing if possible)
         let rgb_image = image.to_rgb8();
         let (width, height) = rgb_image.dimensions();
@@ -432,7 +556,7 @@ impl OcrEngine {
             };
             self.update_screen_state(empty_state);
             debug!("Fast-path: empty black screen detected");
-            return Ok(String::new());
+            return Ok((String::new(), TextLayout::default()));
         }
 
         if white_percentage > 95 {
@@ -450,7 +574,7 @@ impl OcrEngine {
             };
             self.update_screen_state(empty_state);
             debug!("Fast-path: empty white screen detected");
-            return Ok(String::new());
+            return Ok((String::new(), TextLayout::default()));
         }
 
         // Convert image to tensor format for ocrs 0.10.4
@@ -478,13 +602,14 @@ impl OcrEngine {
             Ok(rects) => rects,
             Err(e) => {
                 trace!("Word detection failed: {}", e);
-                return Ok(String::new()); // Exit early if detection fails
+                // Exit early if detection fails
+                return Ok((String::new(), TextLayout::default()));
             }
         };
 
         if word_rects.is_empty() {
             trace!("No words detected");
-            return Ok(String::new());
+            return Ok((String::new(), TextLayout::default()));
         }
 
         // Use traditional line-based approach but optimize it
@@ -492,14 +617,14 @@ impl OcrEngine {
 
         if line_rects.is_empty() {
             trace!("No text lines found");
-            return Ok(String::new());
+            return Ok((String::new(), TextLayout::default()));
         }
 
         let line_texts = match self.engine.recognize_text(&ocr_input, &line_rects) {
             Ok(texts) => texts,
             Err(e) => {
                 trace!("Text recognition failed: {}", e);
-                return Ok(String::new());
+                return Ok((String::new(), TextLayout::default()));
             }
         };
 
@@ -539,7 +664,12 @@ impl OcrEngine {
         };
         self.update_screen_state(new_state);
 
-        Ok(extracted_text)
+        let layout = if with_layout {
+            text_layout(&word_rects, &line_rects, &line_texts)
+        } else {
+            TextLayout::default()
+        };
+        Ok((extracted_text, layout))
     }
 
     pub async fn contains_text(&self, image: &DynamicImage, pattern: &str) -> Result<bool> {
@@ -584,6 +714,15 @@ impl OcrEngine {
             .filter(|state| !state.is_stale(self.update_threshold))
     }
 
+    /// Most recent screen state regardless of age, e.g. as left by the background monitor
+    pub fn latest_screen_state(&self) -> Option<ScreenState> {
+        self.screen_state.read().clone()
+    }
+
+    pub fn is_monitoring(&self) -> bool {
+        self.monitor_tx.is_some()
+    }
+
     /// Get the last known text from the screen without performing new OCR
     pub fn get_cached_text(&self) -> Option<String> {
         self.get_current_screen_state().map(|state| state.text)
@@ -977,8 +1116,3 @@ impl OcrEngine {
     }
 }
 
-impl Default for OcrEngine {
-    fn default() -> Self {
-        Self::new()
-    }
-}
@@ -0,0 +1,67 @@
+use ab_glyph::{FontVec, PxScale};
+use anyhow::{Context, Result};
+use image::{DynamicImage, Rgb, RgbImage};
+use imageproc::drawing::{draw_hollow_rect_mut, draw_text_mut};
+use imageproc::rect::Rect;
+use std::path::Path;
+use std::sync::LazyLock;
+use tracing::debug;
+
+use super::ocr::{DetectedTextBox, TextLayout};
+
+const WORD_COLOR: Rgb<u8> = Rgb([0, 200, 255]);
+const LINE_COLOR: Rgb<u8> = Rgb([255, 64, 64]);
+const TEXT_COLOR: Rgb<u8> = Rgb([255, 255, 0]);
+
+/// Common system font locations, the first readable one is used to label boxes
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+static LABEL_FONT: LazyLock<Option<FontVec>> = LazyLock::new(|| {
+    let font = FONT_PATHS.iter().find_map(|path| {
+        let data = std::fs::read(path).ok()?;
+        FontVec::try_from_vec(data).ok()
+    });
+    if font.is_none() {
+        debug!("No system font found, OCR overlays will show boxes without text labels");
+    }
+    font
+});
+
+/// Draw detected word and line boxes, with the recognized text above each line
+pub fn annotate(image: &DynamicImage, layout: &TextLayout) -> RgbImage {
+    let mut canvas = image.to_rgb8();
+
+    for word in &layout.words {
+        draw_hollow_rect_mut(&mut canvas, to_rect(word), WORD_COLOR);
+    }
+
+    for line in &layout.lines {
+        draw_hollow_rect_mut(&mut canvas, to_rect(line), LINE_COLOR);
+
+        if let (Some(font), Some(text)) = (LABEL_FONT.as_ref(), &line.text) {
+            let scale = PxScale::from(14.0);
+            let y = (line.top - 15).max(0);
+            draw_text_mut(&mut canvas, TEXT_COLOR, line.left, y, scale, font, text);
+        }
+    }
+
+    canvas
+}
+
+/// Save an annotated copy of a screenshot next to the original
+pub fn save_annotated(image: &DynamicImage, layout: &TextLayout, path: &Path) -> Result<()> {
+    annotate(image, layout)
+        .save(path)
+        .with_context(|| format!("Failed to save OCR overlay: {}", path.display()))
+}
+
+fn to_rect(text_box: &DetectedTextBox) -> Rect {
+    Rect::at(text_box.left, text_box.top).of_size(text_box.width, text_box.height)
+}
@@ -1,45 +1,169 @@
 #![allow(dead_code)]
 
 use anyhow::{anyhow, Context, Result};
-use ssh2::Session;
+use ssh2::{CheckResult, KnownHostFileKind, Session};
 use std::collections::HashMap;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 use tokio::time::{sleep, timeout};
-use tracing::{debug, error, info, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn, Span};
 
 use crate::automation::keypress::{KeypressAction, KeypressExecutor};
-use crate::automation::ocr::OcrEngine;
+use crate::automation::ocr::{screens_match, OcrEngine, OcrOptions, ScreenState, TextLayout};
+use crate::automation::ocr_overlay;
+use crate::automation::recorder::ScreenRecorder;
+use crate::automation::serial::SerialConsole;
+use crate::automation::ssh_error::SshError;
+use crate::automation::vm::providers::ProviderScreenshotCapture;
 use crate::automation::vm::{VmInstance, VmManager};
-use crate::config::{Instruction, Stage};
+use crate::config::{
+    parse_duration, parse_file_mode, screen_text_patterns, Instruction, PressKeys, SpecDefaults,
+    Stage, StageType, WaitCondition,
+};
+use crate::utils::redact;
+use crate::utils::secrets::SecretStore;
 use crate::utils::template::TemplateEngine;
 
+/// How long to wait for the guest's SSH port to accept a TCP connection
+const SSH_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Terminal size requested for `RUN --pty`
+const PTY_COLUMNS: u32 = 200;
+const PTY_ROWS: u32 = 50;
+
+/// Permissions of a file copied without `COPY --chmod`
+const DEFAULT_COPY_MODE: i32 = 0o644;
+
+/// Retry interval for `WAIT FOR port:` and `WAIT FOR ping:`
+const PORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a connected port gets to send its SSH banner
+const PORT_BANNER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Connect and read the server's first bytes. VirtualBox NAT accepts connections on the
+/// forwarded host port before the guest listens, so a bare TCP connect proves nothing.
+async fn probe_ssh_banner(host: &str, port: u16) -> Result<()> {
+    use tokio::io::AsyncReadExt;
+
+    let mut stream = timeout(PORT_BANNER_TIMEOUT, tokio::net::TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| anyhow!("connect timed out"))??;
+
+    let mut banner = [0u8; 4];
+    let read = timeout(PORT_BANNER_TIMEOUT, stream.read(&mut banner))
+        .await
+        .map_err(|_| anyhow!("no banner received"))??;
+    if read == 0 {
+        return Err(anyhow!("connection closed before a banner was sent"));
+    }
+    Ok(())
+}
+
+/// Capture interval of the OCR background monitor (`--parallel-ocr-monitor`)
+const OCR_MONITOR_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Default OCR interval for `WAIT FOR <text>`, overridden by `--poll` or DEFAULT wait-poll
+const SCREEN_TEXT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Template variable holding the alternative a `WAIT FOR "a" | "b"` matched
+const WAIT_MATCH_VAR: &str = "WAIT_MATCH";
+
+/// Capture interval for `WAIT FOR stable:<duration>`
+const STABLE_SCREEN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Fraction of pixels allowed to change (e.g. a blinking cursor) while the screen counts as stable
+const STABLE_SCREEN_TOLERANCE: f64 = 0.005;
+
+/// How often EXPECT re-reads the console log for its prompt
+const CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Debug, Clone)]
 pub struct SshCredentials {
     pub username: String,
     pub password: Option<String>,
     pub private_key: Option<PathBuf>,
+    pub host_key: HostKeyPolicy,
+}
+
+/// How the SSH server's host key is checked before any credentials are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HostKeyPolicy {
+    /// Managed VMs behind a NAT port forward, which get a new key with every build
+    #[default]
+    Unchecked,
+    /// The key must match the host's entry in ~/.ssh/known_hosts
+    KnownHosts,
+    /// Like `KnownHosts`, but the key of a host without an entry is added to the file
+    AcceptNew,
 }
 
 pub struct PuppetManager {
     keypress_executor: KeypressExecutor,
     template_engine: TemplateEngine,
     environment_vars: HashMap<String, String>,
-    ocr_engine: OcrEngine,
-    ssh_credentials: Option<SshCredentials>,
+    /// None when the OCR models could not be loaded and the spec doesn't need them
+    ocr_engine: Option<OcrEngine>,
+    /// Credentials keyed by LOGIN name
+    ssh_credentials: HashMap<String, SshCredentials>,
+    /// Name of the most recent LOGIN, used when RUN/COPY don't name one
+    active_login: Option<String>,
+    /// Fixed SSH endpoint of an externally managed host, used instead of the provider's
+    ssh_endpoint: Option<(String, u16)>,
     debug_steps_dir: PathBuf,
     step_counter: usize,
     ocr_debug_enabled: bool,
+    /// Extra pause after every instruction when slow mode is on
+    instruction_delay: Option<Duration>,
+    /// Current stage's IDLE_WATCHDOG: stall duration and optional keep-alive key
+    idle_watchdog: Option<(Duration, Option<String>)>,
+    /// Collect os_configure RUN/COPY failures and report them once the stage finishes
+    keep_going: bool,
+    /// Spec-wide DEFAULT values for WAIT timeouts and PRESS repeat delays
+    defaults: SpecDefaults,
+    /// The spec's LABELs, written into the guest by WRITE_LABELS
+    labels: HashMap<String, String>,
+    /// Snapshot the VM before every step so `build --continue` can roll back to it
+    pre_step_snapshots: bool,
+    /// 1-based instruction index, within its stage, of the step that last failed the build
+    last_failed_step: Option<usize>,
+    /// 1-based instruction index, within the current stage, of the latest pre-step snapshot
+    last_snapshot_step: Option<usize>,
+    /// Set by a WAIT FOR on a password prompt so the next TYPE is kept out of the logs
+    awaiting_password: bool,
+    /// Stop at BREAKPOINT instructions and wait for the user (only honoured on a TTY)
+    interactive: bool,
+    /// Run the OCR background monitor during os_install/os_configure and wait on its events
+    ocr_monitor: bool,
+    /// Periodic screen capture from VM start until teardown, set by `--record`
+    recorder: Option<ScreenRecorder>,
+    /// Wall-clock time of every executed step, labelled by stage, position and instruction
+    step_timings: Vec<(String, Duration)>,
+    /// Total OCR time and call count; atomics because OCR runs from `&self` wait loops
+    ocr_nanos: AtomicU64,
+    ocr_calls: AtomicUsize,
+    /// Connection to the VM's TCP serial port, opened for stages that use EXPECT
+    serial_console: Option<SerialConsole>,
+    /// Byte offset in the console log up to which EXPECT prompts have been answered
+    serial_cursor: usize,
 }
 
 impl PuppetManager {
-    pub fn new() -> Self {
-        Self::new_with_ocr_debug(false)
+    pub fn new() -> Result<Self> {
+        Self::new_with_ocr_debug(false, OcrOptions::default())
+    }
+
+    pub fn new_with_ocr_debug(ocr_debug_enabled: bool, ocr_options: OcrOptions) -> Result<Self> {
+        let ocr_engine = OcrEngine::from_options(ocr_options)?;
+        Ok(Self::with_ocr_engine(ocr_debug_enabled, Some(ocr_engine)))
+    }
+
+    /// A puppet manager for specs that never read the screen; OCR-based WAITs will fail
+    pub fn without_ocr(ocr_debug_enabled: bool) -> Self {
+        Self::with_ocr_engine(ocr_debug_enabled, None)
     }
 
-    pub fn new_with_ocr_debug(ocr_debug_enabled: bool) -> Self {
+    fn with_ocr_engine(ocr_debug_enabled: bool, ocr_engine: Option<OcrEngine>) -> Self {
         let debug_dir = std::env::current_dir()
             .unwrap_or_else(|_| PathBuf::from("."))
             .join("debug-steps");
@@ -53,14 +177,214 @@ impl PuppetManager {
             keypress_executor: KeypressExecutor::new(),
             template_engine: TemplateEngine::new(),
             environment_vars: std::env::vars().collect(),
-            ocr_engine: OcrEngine::new(),
-            ssh_credentials: None,
+            ocr_engine,
+            ssh_credentials: HashMap::new(),
+            active_login: None,
+            ssh_endpoint: None,
             debug_steps_dir: debug_dir,
             step_counter: 0,
             ocr_debug_enabled,
+            instruction_delay: None,
+            idle_watchdog: None,
+            keep_going: false,
+            defaults: SpecDefaults::default(),
+            labels: HashMap::new(),
+            pre_step_snapshots: false,
+            last_failed_step: None,
+            last_snapshot_step: None,
+            awaiting_password: false,
+            interactive: false,
+            ocr_monitor: false,
+            recorder: None,
+            step_timings: Vec::new(),
+            ocr_nanos: AtomicU64::new(0),
+            ocr_calls: AtomicUsize::new(0),
+            serial_console: None,
+            serial_cursor: 0,
+        }
+    }
+
+    /// Whether the OCR models loaded, so screen text can be read
+    pub fn has_ocr(&self) -> bool {
+        self.ocr_engine.is_some()
+    }
+
+    pub fn step_timings(&self) -> &[(String, Duration)] {
+        &self.step_timings
+    }
+
+    /// Total time spent in OCR and the number of OCR passes
+    pub fn ocr_time(&self) -> (Duration, usize) {
+        (
+            Duration::from_nanos(self.ocr_nanos.load(Ordering::Relaxed)),
+            self.ocr_calls.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn set_ocr_monitor(&mut self, enabled: bool) {
+        self.ocr_monitor = enabled;
+    }
+
+    /// Start the OCR background monitor for `vm` if it is enabled and not already running
+    async fn start_ocr_monitor(&mut self, vm: &VmInstance) {
+        if !self.ocr_monitor {
+            return;
+        }
+        let Some(ocr_engine) = self.ocr_engine.as_mut() else {
+            return;
+        };
+        if ocr_engine.is_monitoring() {
+            return;
+        }
+
+        let capture = ProviderScreenshotCapture::new(vm.clone());
+        if let Err(e) = ocr_engine.start_monitoring(capture, OCR_MONITOR_INTERVAL).await {
+            warn!("Failed to start OCR monitor, WAITs will poll instead: {:#}", e);
+        }
+    }
+
+    /// Connect to the VM's serial console unless a connection is still open
+    async fn connect_serial_console(
+        &mut self,
+        vm: &VmInstance,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        if self.serial_console.as_ref().is_some_and(|c| c.is_connected()) {
+            return Ok(());
+        }
+
+        let port = vm_manager
+            .serial_port(vm)
+            .await?
+            .ok_or_else(|| anyhow!("VM '{}' has no TCP serial port", vm.name))?;
+        let log_path = vm_manager.console_log_path(vm)?;
+        // Release the shared port of a closed connection before sharing again
+        self.serial_console = None;
+        let mut console = SerialConsole::connect(port, &log_path).await?;
+        if let Some(share_port) = vm.config.serial_share_port {
+            console.share(share_port).await?;
+        }
+        self.serial_console = Some(console);
+        // The log was truncated, so earlier matches no longer apply
+        self.serial_cursor = 0;
+        Ok(())
+    }
+
+    /// Record the screen every `interval` into `frames_dir` once the VM starts
+    pub fn set_recording(&mut self, interval: &str, frames_dir: PathBuf) -> Result<()> {
+        let interval = parse_duration(interval)?;
+        if interval.is_zero() {
+            return Err(anyhow!("Recording interval must be greater than zero"));
+        }
+        self.recorder = Some(ScreenRecorder::new(frames_dir, interval));
+        Ok(())
+    }
+
+    /// Start recording `vm` if `--record` is set and the recorder is idle
+    pub fn start_recording(&mut self, vm: &VmInstance) {
+        let Some(recorder) = self.recorder.as_mut().filter(|r| !r.is_recording()) else {
+            return;
+        };
+        if let Err(e) = recorder.start(ProviderScreenshotCapture::new(vm.clone())) {
+            warn!("Failed to start screen recording: {:#}", e);
+        }
+    }
+
+    /// Stop the screen recording if it is running
+    pub async fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.stop().await;
+        }
+    }
+
+    /// Assemble the recorded frames into a video at `output` (`--record-video`)
+    pub fn assemble_recording(&self, output: &Path) -> Result<()> {
+        self.recorder
+            .as_ref()
+            .ok_or_else(|| anyhow!("Screen recording is not enabled"))?
+            .assemble_video(output)
+    }
+
+    /// Stop the OCR background monitor if it is running
+    pub async fn stop_ocr_monitor(&mut self) {
+        if let Some(ocr_engine) = self.ocr_engine.as_mut().filter(|e| e.is_monitoring()) {
+            if let Err(e) = ocr_engine.stop_monitoring().await {
+                warn!("Failed to stop OCR monitor: {}", e);
+            }
         }
     }
 
+    pub fn set_dump_scancodes(&mut self, enabled: bool) {
+        self.keypress_executor.set_dump_scancodes(enabled);
+    }
+
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
+
+    pub fn set_secrets(&mut self, secrets: SecretStore) {
+        self.template_engine.set_secrets(secrets);
+    }
+
+    pub fn set_pre_step_snapshots(&mut self, enabled: bool) {
+        self.pre_step_snapshots = enabled;
+    }
+
+    /// The failed step of the stage that just returned an error, cleared once taken
+    pub fn take_failed_step(&mut self) -> Option<usize> {
+        self.last_failed_step.take()
+    }
+
+    /// Step of the current stage the pre-step snapshot was last taken before, if any
+    pub fn last_snapshot_step(&self) -> Option<usize> {
+        self.last_snapshot_step
+    }
+
+    pub fn set_defaults(&mut self, defaults: SpecDefaults) {
+        self.defaults = defaults;
+    }
+
+    pub fn set_labels(&mut self, labels: HashMap<String, String>) {
+        self.labels = labels;
+    }
+
+    /// Send SSH traffic to `host:port` instead of asking the VM's provider for an endpoint
+    pub fn set_ssh_endpoint(&mut self, host: String, port: u16) {
+        self.ssh_endpoint = Some((host, port));
+    }
+
+    fn ssh_endpoint(&self, vm: &VmInstance) -> (String, u16) {
+        match &self.ssh_endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => crate::automation::vm::providers::create_provider(&vm.provider)
+                .get_ssh_endpoint(vm),
+        }
+    }
+
+    /// Run every os_configure RUN/COPY even when earlier ones fail, failing at the end
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        if keep_going {
+            info!("Keep-going mode active: os_configure failures will be reported at the end");
+        }
+        self.keep_going = keep_going;
+    }
+
+    /// Pause for `delay` after every instruction, on top of the per-key delays
+    pub fn set_slow_mode(&mut self, delay: &str) -> Result<()> {
+        let delay = parse_duration(delay)?;
+        info!("Slow mode active: pausing {:?} after each instruction", delay);
+        self.instruction_delay = Some(delay);
+        Ok(())
+    }
+
+    /// Redirect debug screenshots (including failure frames) to another directory
+    pub fn set_debug_steps_dir(&mut self, dir: PathBuf) {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("Failed to create debug steps directory {}: {}", dir.display(), e);
+        }
+        self.debug_steps_dir = dir;
+    }
+
     pub async fn execute_stage_instructions(
         &mut self,
         vm: &VmInstance,
@@ -71,6 +395,11 @@ impl PuppetManager {
             .await
     }
 
+    #[instrument(
+        name = "stage",
+        skip_all,
+        fields(stage = %stage.display_name(), vm_name = %vm.name, step)
+    )]
     pub async fn execute_stage_instructions_from_step(
         &mut self,
         vm: &VmInstance,
@@ -78,7 +407,7 @@ impl PuppetManager {
         vm_manager: &VmManager,
         continue_from_step: Option<usize>,
     ) -> Result<()> {
-        info!("Executing puppet instructions for stage: {:?}", stage.name);
+        info!("Executing puppet instructions for stage: {}", stage.display_name());
 
         let start_from = if let Some(step) = continue_from_step {
             if step == 0 {
@@ -100,8 +429,37 @@ impl PuppetManager {
             0
         };
 
+        redact::register_stage(stage);
+        self.last_snapshot_step = None;
+
+        self.idle_watchdog = match &stage.idle_watchdog {
+            Some(watchdog) => Some((parse_duration(&watchdog.after)?, watchdog.key.clone())),
+            None => None,
+        };
+
+        if matches!(stage.name, StageType::OsInstall | StageType::OsConfigure) {
+            self.start_ocr_monitor(vm).await;
+        }
+
+        // Connect before the first step so console output printed before an EXPECT is kept
+        if stage
+            .instructions
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::Expect { .. }))
+        {
+            self.connect_serial_console(vm, vm_manager).await?;
+        } else if vm.config.serial_share_port.is_some() {
+            // Live monitoring only, the build doesn't depend on it
+            if let Err(e) = self.connect_serial_console(vm, vm_manager).await {
+                warn!("Serial console of {} is not shared: {:#}", vm.name, e);
+            }
+        }
+
+        let mut collected_failures = Vec::new();
+
         for (i, instruction) in stage.instructions.iter().enumerate().skip(start_from) {
             self.step_counter += 1;
+            Span::current().record("step", self.step_counter);
             info!(
                 "Executing instruction {}/{} (step {}): {:?}",
                 i + 1,
@@ -110,75 +468,414 @@ impl PuppetManager {
                 instruction
             );
 
+            if self.pre_step_snapshots {
+                match vm_manager.take_pre_step_snapshot(vm).await {
+                    Ok(()) => self.last_snapshot_step = Some(i + 1),
+                    Err(e) => {
+                        warn!("Failed to snapshot VM before step {}: {:#}", self.step_counter, e)
+                    }
+                }
+            }
+
             // Capture pre-step screenshot
             self.capture_debug_screenshot(vm, "pre", self.step_counter, vm_manager).await?;
 
+            let step_started = Instant::now();
+            let result = self.execute_instruction(vm, instruction, vm_manager).await;
+            self.step_timings.push((
+                format!("{} #{} {}", stage.display_name(), i + 1, instruction.keyword()),
+                step_started.elapsed(),
+            ));
+
+            if let Err(e) = result {
+                // os_install stays fail-fast since later keypresses depend on earlier ones
+                let configure_stage = matches!(stage.name, StageType::OsConfigure);
+                let continue_on_error =
+                    matches!(instruction, Instruction::Run { continue_on_error: true, .. });
+                let collectable = self.keep_going
+                    && matches!(
+                        instruction,
+                        Instruction::Run { .. }
+                            | Instruction::Copy { .. }
+                            | Instruction::CopyFrom { .. }
+                            | Instruction::WriteLabels { .. }
+                    );
+
+                if configure_stage && (continue_on_error || collectable) {
+                    warn!(
+                        "Step {} failed in {:?} stage (instruction {}/{}), continuing: {:#}",
+                        self.step_counter,
+                        stage.name,
+                        i + 1,
+                        stage.instructions.len(),
+                        e
+                    );
+                    self.capture_debug_screenshot(vm, "failure", self.step_counter, vm_manager)
+                        .await?;
+                    if !continue_on_error {
+                        if collected_failures.is_empty() {
+                            self.last_failed_step = Some(i + 1);
+                        }
+                        collected_failures.push(format!(
+                            "step {} (instruction {}/{}) {:?}: {:#}",
+                            self.step_counter,
+                            i + 1,
+                            stage.instructions.len(),
+                            instruction,
+                            e
+                        ));
+                    }
+                    continue;
+                }
+
+                error!(
+                    "Step {} failed in {:?} stage (instruction {}/{}): {}",
+                    self.step_counter,
+                    stage.name,
+                    i + 1,
+                    stage.instructions.len(),
+                    e
+                );
+                self.last_failed_step = Some(i + 1);
+                self.capture_debug_screenshot(vm, "failure", self.step_counter, vm_manager)
+                    .await?;
+                return Err(e.context(format!(
+                    "Step {} failed in {:?} stage (instruction {}/{}): {:?}",
+                    self.step_counter,
+                    stage.name,
+                    i + 1,
+                    stage.instructions.len(),
+                    instruction
+                )));
+            }
+            
+            // Capture post-step screenshot
+            self.capture_debug_screenshot(vm, "post", self.step_counter, vm_manager).await?;
+
+            if let Some(delay) = self.instruction_delay {
+                debug!("Slow mode: sleeping {:?}", delay);
+                sleep(delay).await;
+            }
+        }
+
+        if !collected_failures.is_empty() {
+            for failure in &collected_failures {
+                error!("Failed: {}", failure);
+            }
+            return Err(anyhow!(
+                "{} instruction(s) failed in {:?} stage:\n{}",
+                collected_failures.len(),
+                stage.name,
+                collected_failures.join("\n")
+            ));
+        }
+
+        info!("Completed puppet execution for stage");
+        Ok(())
+    }
+
+    async fn execute_instruction(
+        &mut self,
+        vm: &VmInstance,
+        instruction: &Instruction,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        match instruction {
+            // OS Installation instructions (keypress automation)
+            Instruction::Wait {
+                duration,
+                condition,
+                poll,
+                matches,
+            } => {
+                // A WAIT FOR without its own duration uses the spec default timeout
+                let duration = duration
+                    .as_ref()
+                    .or(self.defaults.wait_timeout.as_ref())
+                    .ok_or_else(|| {
+                        anyhow!("WAIT has no duration and no DEFAULT wait-timeout is set")
+                    })?
+                    .clone();
+                self.awaiting_password = condition
+                    .as_deref()
+                    .is_some_and(redact::is_password_prompt);
+                let poll = poll
+                    .as_ref()
+                    .or(self.defaults.wait_poll.as_ref())
+                    .map(|poll| parse_duration(poll))
+                    .transpose()?;
+                let matches = matches.unwrap_or(1);
+                self.execute_wait_instruction(
+                    vm,
+                    &duration,
+                    condition.as_ref(),
+                    poll,
+                    matches,
+                    vm_manager,
+                )
+                .await?;
+            }
+            Instruction::Press {
+                key,
+                repeat,
+                modifiers,
+            } => {
+                self.execute_press_instruction(vm, key, *repeat, modifiers, vm_manager)
+                    .await?;
+            }
+            Instruction::Type {
+                text,
+                unicode,
+                modifiers,
+            } => {
+                let modifiers = modifiers.as_deref().unwrap_or_default();
+                self.execute_type_instruction(vm, text, *unicode, modifiers, vm_manager)
+                    .await?;
+            }
+            Instruction::Breakpoint { message } => {
+                self.execute_breakpoint_instruction(vm, message.as_deref(), vm_manager)
+                    .await?;
+            }
+            Instruction::Expect {
+                prompt,
+                send,
+                timeout,
+            } => {
+                let timeout = timeout
+                    .as_ref()
+                    .or(self.defaults.wait_timeout.as_ref())
+                    .ok_or_else(|| {
+                        anyhow!("EXPECT has no timeout and no DEFAULT wait-timeout is set")
+                    })?;
+                let timeout = parse_duration(timeout)?;
+                self.execute_expect_instruction(vm, prompt, send, timeout, vm_manager)
+                    .await?;
+            }
+            Instruction::Capture {
+                pattern,
+                var,
+                timeout,
+            } => {
+                let timeout = timeout
+                    .as_ref()
+                    .or(self.defaults.wait_timeout.as_ref())
+                    .ok_or_else(|| {
+                        anyhow!("CAPTURE has no timeout and no DEFAULT wait-timeout is set")
+                    })?;
+                let timeout = parse_duration(timeout)?;
+                self.execute_capture_instruction(vm, pattern, var, timeout, vm_manager)
+                    .await?;
+            }
+
+            // OS Configuration instructions (live OS commands)
+            Instruction::Copy {
+                from,
+                to,
+                login,
+                method,
+                ..
+            } if method == "guest" => {
+                self.execute_guest_copy_instruction(vm, from, to, login.as_deref(), vm_manager)
+                    .await?;
+            }
+            Instruction::Run { .. }
+            | Instruction::Copy { .. }
+            | Instruction::CopyFrom { .. }
+            | Instruction::WaitForSsh { .. }
+            | Instruction::WriteLabels { .. }
+            | Instruction::Login { .. } => {
+                self.execute_remote_instruction(vm, instruction).await?;
+            }
+
+            _ => {
+                warn!(
+                    "Ignoring unsupported instruction in puppet execution: {:?}",
+                    instruction
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the SSH instructions of os_configure `stage` against an externally managed host
+    /// set with `set_ssh_endpoint`. Every LOGIN resolves to `credentials`, plain WAITs sleep
+    /// and steps that need a managed VM (keys, screen, serial console, guest agent) are skipped.
+    pub async fn provision_stage(
+        &mut self,
+        vm: &VmInstance,
+        stage: &Stage,
+        credentials: &SshCredentials,
+    ) -> Result<()> {
+        info!("Provisioning {} on {}", stage.display_name(), vm.name);
+        redact::register_stage(stage);
+
+        for (index, instruction) in stage.instructions.iter().enumerate() {
+            let step = index + 1;
             match instruction {
-                // OS Installation instructions (keypress automation)
+                Instruction::Login { name, .. } => {
+                    info!("Step {}: LOGIN '{}' uses the --host credentials", step, name);
+                    self.ssh_credentials.insert(name.clone(), credentials.clone());
+                    self.active_login = Some(name.clone());
+                }
                 Instruction::Wait {
-                    duration,
-                    condition,
+                    duration: Some(duration),
+                    condition: None,
+                    ..
                 } => {
-                    self.execute_wait_instruction(vm, duration, condition.as_ref(), vm_manager)
-                        .await?;
+                    info!("Step {}: waiting {}", step, duration);
+                    sleep(parse_duration(duration)?).await;
                 }
-                Instruction::Press {
-                    key,
-                    repeat,
-                    modifiers,
-                } => {
-                    self.execute_press_instruction(vm, key, *repeat, modifiers, vm_manager)
-                        .await?;
+                Instruction::Copy { method, .. } if method == "guest" => {
+                    warn!("Skipping step {}: COPY --guest needs a managed VM", step);
                 }
-                Instruction::Type { text } => {
-                    self.execute_type_instruction(vm, text, vm_manager).await?;
+                Instruction::Run { .. }
+                | Instruction::Copy { .. }
+                | Instruction::CopyFrom { .. }
+                | Instruction::WaitForSsh { .. }
+                | Instruction::WriteLabels { .. } => {
+                    info!("Step {}: {}", step, instruction.keyword());
+                    let result = self.execute_remote_instruction(vm, instruction).await;
+                    match (result, instruction) {
+                        (Ok(()), _) => {}
+                        (Err(e), Instruction::Run { continue_on_error: true, .. }) => {
+                            warn!("Step {} failed, continuing: {:#}", step, e);
+                        }
+                        (Err(e), _) => {
+                            return Err(e.context(format!(
+                                "Step {} of {} failed",
+                                step,
+                                stage.display_name()
+                            )))
+                        }
+                    }
                 }
+                _ => warn!(
+                    "Skipping step {}: {} needs a managed VM",
+                    step,
+                    instruction.keyword()
+                ),
+            }
+        }
 
-                // OS Configuration instructions (live OS commands)
-                Instruction::Run { command } => {
-                    self.execute_run_instruction(vm, command).await?;
+        Ok(())
+    }
+
+    /// Execute an instruction that only needs SSH access to the guest, not its VM
+    async fn execute_remote_instruction(
+        &mut self,
+        vm: &VmInstance,
+        instruction: &Instruction,
+    ) -> Result<()> {
+        match instruction {
+            Instruction::Run {
+                command,
+                login,
+                env,
+                sudo,
+                pty,
+                ..
+            } => {
+                self.execute_run_instruction(vm, command, login.as_deref(), env, *sudo, *pty)
+                    .await?;
+            }
+            Instruction::Copy {
+                from,
+                to,
+                login,
+                method,
+                mode,
+                owner,
+                group,
+            } => {
+                if method == "guest" {
+                    return Err(anyhow!("COPY --guest needs the VM's guest agent"));
                 }
-                Instruction::Copy { from, to } => {
-                    self.execute_copy_instruction(vm, from, to).await?;
+                let mode = match mode {
+                    Some(mode) => parse_file_mode(mode)?,
+                    None => DEFAULT_COPY_MODE,
+                };
+                self.execute_copy_instruction(vm, from, to, login.as_deref(), mode)
+                    .await?;
+                if owner.is_some() || group.is_some() {
+                    let (owner, group) = (owner.as_deref(), group.as_deref());
+                    self.execute_chown(vm, to, owner, group, login.as_deref())
+                        .await?;
                 }
-                Instruction::Login {
-                    username,
-                    password,
-                    private_key,
-                    ..
-                } => {
-                    self.ssh_credentials = Some(SshCredentials {
+            }
+            Instruction::CopyFrom {
+                remote,
+                local,
+                login,
+            } => {
+                self.copy_file_from_vm(vm, remote, local, login.as_deref())
+                    .await
+                    .with_context(|| {
+                        format!("COPY_FROM failed: {} -> {}", remote, local.display())
+                    })?;
+            }
+            Instruction::WaitForSsh { timeout, login } => {
+                let timeout = timeout
+                    .as_ref()
+                    .or(self.defaults.wait_timeout.as_ref())
+                    .ok_or_else(|| {
+                        anyhow!("WAIT_FOR_SSH has no timeout and no DEFAULT wait-timeout is set")
+                    })?;
+                let timeout = parse_duration(timeout)?;
+                self.wait_for_ssh_login(vm, login.as_deref(), timeout).await?;
+            }
+            Instruction::WriteLabels { path, login, sudo } => {
+                self.execute_write_labels(vm, path, login.as_deref(), *sudo)
+                    .await
+                    .with_context(|| format!("WRITE_LABELS failed: {}", path.display()))?;
+            }
+            Instruction::Login {
+                name,
+                username,
+                password,
+                private_key,
+            } => {
+                // Passwords may reference {{secret:NAME}} instead of being written inline
+                let password = password
+                    .as_deref()
+                    .map(|password| self.template_engine.resolve_secrets(password))
+                    .transpose()?;
+                if let Some(password) = &password {
+                    redact::register(password);
+                }
+                self.ssh_credentials.insert(
+                    name.clone(),
+                    SshCredentials {
                         username: username.clone(),
-                        password: password.clone(),
+                        password,
                         private_key: private_key.clone(),
-                    });
-                    info!("SSH credentials configured for {}", username);
-                }
-
-                _ => {
-                    warn!(
-                        "Ignoring unsupported instruction in puppet execution: {:?}",
-                        instruction
-                    );
-                }
+                        host_key: HostKeyPolicy::Unchecked,
+                    },
+                );
+                self.active_login = Some(name.clone());
+                info!("SSH credentials '{}' configured for {}", name, username);
+            }
+            _ => {
+                return Err(anyhow!(
+                    "{} is not a remote instruction",
+                    instruction.keyword()
+                ))
             }
-            
-            // Capture post-step screenshot
-            self.capture_debug_screenshot(vm, "post", self.step_counter, vm_manager).await?;
         }
 
-        info!("Completed puppet execution for stage");
         Ok(())
     }
 
     async fn execute_wait_instruction(
-        &self,
+        &mut self,
         vm: &VmInstance,
         duration: &str,
         condition: Option<&String>,
+        poll: Option<Duration>,
+        matches: u32,
         vm_manager: &VmManager,
     ) -> Result<()> {
-        let wait_duration = self.parse_duration(duration)?;
+        let wait_duration = parse_duration(duration)?;
 
         if let Some(condition_text) = condition {
             info!(
@@ -188,14 +885,19 @@ impl PuppetManager {
 
             // Wait with condition checking
             let result = timeout(wait_duration, async {
-                self.wait_for_condition(vm, condition_text, vm_manager)
+                self.wait_for_condition_watched(vm, condition_text, poll, matches, vm_manager)
                     .await
             })
             .await;
 
             match result {
-                Ok(Ok(())) => {
+                Ok(Ok(matched)) => {
                     info!("Condition '{}' met successfully", condition_text);
+                    // Which alternative of a `"a" | "b"` list showed up, for later TYPE/RUN
+                    if let Some(matched) = matched {
+                        info!("Matched screen text '{}', available as {{{{WAIT_MATCH}}}}", matched);
+                        self.environment_vars.insert(WAIT_MATCH_VAR.to_string(), matched);
+                    }
                     // Capture notice frame when condition is satisfied
                     self.capture_debug_screenshot(vm, "notice", self.step_counter, vm_manager).await?;
                 }
@@ -219,62 +921,196 @@ impl PuppetManager {
         Ok(())
     }
 
+    /// Pause until the user presses Enter, showing the screen text; 's' saves a screenshot
+    async fn execute_breakpoint_instruction(
+        &self,
+        vm: &VmInstance,
+        message: Option<&str>,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        if !self.interactive {
+            warn!("BREAKPOINT ignored: run with --interactive to pause here");
+            return Ok(());
+        }
+        if !std::io::stdin().is_terminal() {
+            warn!("BREAKPOINT ignored: --interactive needs a terminal on stdin");
+            return Ok(());
+        }
+
+        println!();
+        println!(
+            "=== Breakpoint at step {}: {} ===",
+            self.step_counter,
+            message.unwrap_or("paused")
+        );
+        match vm_manager.capture_screen(vm).await {
+            Ok(image) => match self.extract_text_timed(&image).await {
+                Ok(text) => println!("Screen text:\n{}", redact::redact(&text)),
+                Err(e) => println!("Screen text unavailable: {}", e),
+            },
+            Err(e) => println!("Screen capture failed: {}", e),
+        }
+
+        loop {
+            print!("Press Enter to continue, or 's' then Enter to save a screenshot: ");
+            std::io::stdout().flush().ok();
+
+            let input = tokio::task::spawn_blocking(|| {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line).map(|_| line)
+            })
+            .await
+            .context("Breakpoint input task failed")?
+            .context("Failed to read breakpoint input")?;
+
+            if input.trim().eq_ignore_ascii_case("s") {
+                self.capture_debug_screenshot(vm, "breakpoint", self.step_counter, vm_manager)
+                    .await?;
+                println!("Screenshot saved to {}", self.debug_steps_dir.display());
+                continue;
+            }
+
+            info!("Resuming after breakpoint");
+            return Ok(());
+        }
+    }
+
+    /// Wait for `prompt` on the serial console, then answer it with `send` and a carriage return
+    async fn execute_expect_instruction(
+        &mut self,
+        vm: &VmInstance,
+        prompt: &str,
+        send: &str,
+        timeout: Duration,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        // Reconnect if the port was closed, e.g. by a power cycle in an earlier step
+        self.connect_serial_console(vm, vm_manager).await?;
+
+        info!("Expecting '{}' on the serial console (timeout {:?})", prompt, timeout);
+        let start = Instant::now();
+        loop {
+            let output = vm_manager.get_console_output(vm).await?;
+            if let Some(found) = output
+                .get(self.serial_cursor..)
+                .and_then(|unseen| unseen.find(prompt))
+            {
+                self.serial_cursor += found + prompt.len();
+                break;
+            }
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "Timed out after {:?} waiting for '{}' on the serial console",
+                    timeout,
+                    prompt
+                ));
+            }
+            sleep(CONSOLE_POLL_INTERVAL).await;
+        }
+
+        let response = self
+            .template_engine
+            .render_string(send, &self.environment_vars)?;
+        if redact::is_password_prompt(prompt) {
+            redact::register(&response);
+        }
+        debug!("Answering '{}' with: {}", prompt, response);
+
+        self.serial_console
+            .as_mut()
+            .ok_or_else(|| anyhow!("Serial console is not connected"))?
+            .send(&format!("{}\r", response))
+            .await
+    }
+
+    /// OCR the screen until `pattern` matches, then store its first capture group as `var` for
+    /// later `{{var}}` references
+    async fn execute_capture_instruction(
+        &mut self,
+        vm: &VmInstance,
+        pattern: &str,
+        var: &str,
+        timeout: Duration,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        let regex = regex::Regex::new(pattern)
+            .with_context(|| format!("Invalid CAPTURE pattern '{}'", pattern))?;
+        self.ocr()?;
+
+        info!("Capturing '{}' off the screen into {} (timeout {:?})", pattern, var, timeout);
+        let start = Instant::now();
+        let value = loop {
+            match vm_manager.capture_screen(vm).await {
+                Ok(image) => match self.extract_text_timed(&image).await {
+                    Ok(text) => {
+                        if let Some(value) = captured_value(&regex, &text) {
+                            break value;
+                        }
+                        trace!("Pattern '{}' not found in extracted text: '{}'", pattern, text);
+                    }
+                    Err(e) => warn!("OCR error during text extraction: {}", e),
+                },
+                Err(e) => warn!("Failed to capture screen: {}", e),
+            }
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "Timed out after {:?} waiting for '{}' on the screen to CAPTURE {}",
+                    timeout,
+                    pattern,
+                    var
+                ));
+            }
+            sleep(SCREEN_TEXT_POLL_INTERVAL).await;
+        };
+
+        info!("Captured '{}', available as {{{{{}}}}}", value, var);
+        self.environment_vars.insert(var.to_string(), value);
+        self.capture_debug_screenshot(vm, "notice", self.step_counter, vm_manager)
+            .await
+    }
+
     async fn execute_press_instruction(
         &mut self,
         vm: &VmInstance,
-        key: &str,
+        keys: &PressKeys,
         repeat: Option<u32>,
         modifiers: &Option<Vec<String>>,
         vm_manager: &VmManager,
     ) -> Result<()> {
         let repeat_count = repeat.unwrap_or(1);
+        let modifier_list = modifiers.as_deref().unwrap_or_default();
+        let keys = keys.keys();
 
-        // Check if this is a key combination with modifiers
-        if let Some(modifier_list) = modifiers {
-            if !modifier_list.is_empty() {
-                for i in 0..repeat_count {
-                    if repeat_count > 1 {
-                        debug!(
-                            "Pressing key combination '{:?}+{}' ({}/{})",
-                            modifier_list,
-                            key,
-                            i + 1,
-                            repeat_count
-                        );
-                    } else {
-                        debug!("Pressing key combination '{:?}+{}'", modifier_list, key);
-                    }
-
-                    let action = KeypressAction::KeyCombo(modifier_list.clone(), key.to_string());
-                    self.keypress_executor
-                        .execute_action(vm, &action, vm_manager)
-                        .await?;
-
-                    // Small delay between repeated keypresses
-                    if i < repeat_count - 1 {
-                        sleep(Duration::from_millis(100)).await;
-                    }
-                }
-                return Ok(());
+        let mut actions = Vec::with_capacity(keys.len() * repeat_count as usize);
+        for _ in 0..repeat_count {
+            for key in keys {
+                actions.push(if modifier_list.is_empty() {
+                    self.parse_key_action(key)?
+                } else {
+                    KeypressAction::KeyCombo(modifier_list.to_vec(), key.to_string())
+                });
             }
         }
 
-        // Regular key press
-        for i in 0..repeat_count {
-            if repeat_count > 1 {
-                debug!("Pressing key '{}' ({}/{})", key, i + 1, repeat_count);
-            } else {
-                debug!("Pressing key '{}'", key);
-            }
+        // Without a press delay the whole PRESS goes out as one scancode sequence, in order
+        let Some(press_delay) = &self.defaults.press_delay else {
+            debug!("Pressing {:?} as one batch of {} keys", keys, actions.len());
+            return self
+                .keypress_executor
+                .execute_batch(vm, &actions, vm_manager)
+                .await;
+        };
+        let press_delay = parse_duration(press_delay)?;
 
-            let action = self.parse_key_action(key)?;
+        // The press delay separates every key, both within a key list and between repeats
+        for (i, action) in actions.iter().enumerate() {
+            debug!("Pressing {:?} ({}/{})", action, i + 1, actions.len());
             self.keypress_executor
-                .execute_action(vm, &action, vm_manager)
+                .execute_action(vm, action, vm_manager)
                 .await?;
 
-            // Small delay between repeated keypresses
-            if i < repeat_count - 1 {
-                sleep(Duration::from_millis(100)).await;
+            if i < actions.len() - 1 {
+                sleep(press_delay).await;
             }
         }
 
@@ -285,6 +1121,8 @@ impl PuppetManager {
         &mut self,
         vm: &VmInstance,
         text: &str,
+        unicode: bool,
+        modifiers: &[String],
         vm_manager: &VmManager,
     ) -> Result<()> {
         // Process template variables in text
@@ -292,9 +1130,18 @@ impl PuppetManager {
             .template_engine
             .render_string(text, &self.environment_vars)?;
 
+        if std::mem::take(&mut self.awaiting_password) {
+            redact::register(&processed_text);
+        }
         debug!("Typing text: {}", processed_text);
 
-        let action = KeypressAction::TypeText(processed_text);
+        let action = if unicode {
+            KeypressAction::TypeUnicode(processed_text)
+        } else if !modifiers.is_empty() {
+            KeypressAction::TypeHeld(modifiers.to_vec(), processed_text)
+        } else {
+            KeypressAction::TypeText(processed_text)
+        };
         self.keypress_executor
             .execute_action(vm, &action, vm_manager)
             .await?;
@@ -302,20 +1149,51 @@ impl PuppetManager {
         Ok(())
     }
 
-    async fn execute_run_instruction(&mut self, vm: &VmInstance, command: &str) -> Result<()> {
+    async fn execute_run_instruction(
+        &mut self,
+        vm: &VmInstance,
+        command: &str,
+        login: Option<&str>,
+        env: &HashMap<String, String>,
+        sudo: bool,
+        pty: bool,
+    ) -> Result<()> {
         // Process template variables in command
-        let processed_command = self
+        let mut processed_command = self
             .template_engine
             .render_string(command, &self.environment_vars)?;
+
+        // A heredoc script runs as one bash invocation, so --env and --sudo cover all of it
+        if processed_command.contains('\n') {
+            processed_command = format!("bash -c {}", shell_quote(&processed_command));
+        }
+
+        // Export the environment ahead of the command, sorted so the command line is
+        // deterministic; an assignment prefix would only reach its first simple command
+        let mut keys: Vec<&String> = env.keys().collect();
+        keys.sort();
+        let mut assignments = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = self
+                .template_engine
+                .render_string(&env[key], &self.environment_vars)?;
+            assignments.push((key.as_str(), value));
+        }
+        processed_command = with_env(&processed_command, &assignments);
         info!("RUN: Executing command in live OS: {}", processed_command);
+
+        let remote_command = if sudo {
+            self.sudo_command(&processed_command, login)?
+        } else {
+            processed_command.clone()
+        };
         // Execute command via SSH/remote connection
-        match self.execute_remote_command(vm, &processed_command).await {
+        match self.execute_remote_command(vm, &remote_command, login, pty).await {
             Ok(_) => Ok(()),
             Err(e) => {
-                let ssh_info = if let Some(creds) = &self.ssh_credentials {
-                    // Get actual endpoint from provider to ensure accurate error reporting
-                    let provider = crate::automation::vm::providers::create_provider(&vm.provider);
-                    let (host, port) = provider.get_ssh_endpoint(vm);
+                let ssh_info = if let Ok(creds) = self.resolve_credentials(login) {
+                    // Get actual endpoint to ensure accurate error reporting
+                    let (host, port) = self.ssh_endpoint(vm);
                     format!(
                         "user='{}' host='{}' port='{}'",
                         creds.username, host, port
@@ -327,85 +1205,528 @@ impl PuppetManager {
                     "RUN: Command failed: {}\nError: {}\nSSH: {}",
                     processed_command, e, ssh_info
                 );
-                error!("RUN: Troubleshooting tips: Check if the VM is running, SSH is enabled, network is accessible, and credentials are correct.");
+                match e.downcast_ref::<SshError>() {
+                    Some(SshError::Auth { .. }) => {
+                        error!("RUN: Troubleshooting tips: Check the LOGIN username, password or private key.")
+                    }
+                    Some(ssh_error) if !ssh_error.is_transient() => {}
+                    _ => error!("RUN: Troubleshooting tips: Check if the VM is running, SSH is enabled, network is accessible, and credentials are correct."),
+                }
+                // Keep the SshError as the source so callers can branch on its kind
+                let message = format!("RUN failed: '{}': {}\nSSH: {}", processed_command, e, ssh_info);
+                Err(e.context(message))
+            }
+        }
+    }
+
+    /// Wrap `command` so sudo reads the LOGIN password from stdin instead of prompting.
+    /// printf is a shell builtin, so the password never shows up in the guest's process list.
+    fn sudo_command(&self, command: &str, login: Option<&str>) -> Result<String> {
+        let credentials = self.resolve_credentials(login)?;
+        let password = credentials.password.ok_or_else(|| {
+            anyhow!(
+                "sudo needs a LOGIN with a password, '{}' only has a private key",
+                credentials.username
+            )
+        })?;
+        redact::register(&password);
+
+        Ok(sudo_wrapped(command, &password))
+    }
+
+    /// Write the spec's labels as sorted `key=value` lines to `path` in the guest
+    async fn execute_write_labels(
+        &self,
+        vm: &VmInstance,
+        path: &Path,
+        login: Option<&str>,
+        sudo: bool,
+    ) -> Result<()> {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort();
+        let lines: Vec<String> = labels
+            .into_iter()
+            .map(|(key, value)| shell_quote(&format!("{}={}", key, value)))
+            .collect();
+
+        let path = path.to_string_lossy();
+        info!("Writing {} labels to {} in VM {}", lines.len(), path, vm.name);
+        let mut command = format!("printf '%s\\n' {} > {}", lines.join(" "), shell_quote(&path));
+        if sudo {
+            command = self.sudo_command(&command, login)?;
+        }
+        self.execute_remote_command(vm, &command, login, false).await
+    }
+
+    async fn execute_copy_instruction(
+        &mut self,
+        vm: &VmInstance,
+        from: &Path,
+        to: &Path,
+        login: Option<&str>,
+        mode: i32,
+    ) -> Result<()> {
+        info!(
+            "COPY: Copying file {} to VM path {}",
+            from.display(),
+            to.display()
+        );
+        if !from.exists() {
+            error!("COPY: Source file does not exist: {}", from.display());
+            return Err(anyhow!(
+                "COPY failed: Source file does not exist: {}",
+                from.display()
+            ));
+        }
+        // Copy file to VM via SCP/remote copy
+        match self.copy_file_to_vm(vm, from, to, login, mode).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!(
+                    "COPY: Failed to copy {} to {}: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                );
                 Err(anyhow!(
-                    "RUN failed: '{}': {}\nSSH: {}",
-                    processed_command,
-                    e,
-                    ssh_info
+                    "COPY failed: {} -> {}: {}",
+                    from.display(),
+                    to.display(),
+                    e
                 ))
             }
         }
     }
 
-    async fn execute_copy_instruction(
-        &mut self,
+    /// chown a copied file over SSH, through sudo unless the LOGIN is root
+    async fn execute_chown(
+        &self,
+        vm: &VmInstance,
+        path: &Path,
+        owner: Option<&str>,
+        group: Option<&str>,
+        login: Option<&str>,
+    ) -> Result<()> {
+        let ownership = match group {
+            Some(group) => format!("{}:{}", owner.unwrap_or_default(), group),
+            None => owner.unwrap_or_default().to_string(),
+        };
+        let command = format!(
+            "chown {} {}",
+            shell_quote(&ownership),
+            shell_quote(&path.to_string_lossy())
+        );
+        info!("COPY: Setting ownership of {} to {}", path.display(), ownership);
+
+        let remote_command = if self.resolve_credentials(login)?.username == "root" {
+            command
+        } else {
+            self.sudo_command(&command, login)?
+        };
+        self.execute_remote_command(vm, &remote_command, login, false)
+            .await
+            .with_context(|| format!("COPY failed to chown {} to {}", path.display(), ownership))
+    }
+
+    /// Copy through the provider's guest agent, falling back to SCP if that fails
+    async fn execute_guest_copy_instruction(
+        &mut self,
+        vm: &VmInstance,
+        from: &Path,
+        to: &Path,
+        login: Option<&str>,
+        vm_manager: &VmManager,
+    ) -> Result<()> {
+        let credentials = self.resolve_credentials(login)?;
+        let guest_result = match &credentials.password {
+            Some(password) => {
+                vm_manager
+                    .copy_to_guest(vm, from, to, &credentials.username, password)
+                    .await
+            }
+            None => Err(anyhow!("guest copy requires a LOGIN with a password")),
+        };
+
+        match guest_result {
+            Ok(()) => {
+                info!(
+                    "COPY: Copied {} to {} via guest agent",
+                    from.display(),
+                    to.display()
+                );
+                Ok(())
+            }
+            Err(guest_error) => {
+                warn!(
+                    "COPY: Guest agent copy failed ({}), falling back to SCP",
+                    guest_error
+                );
+                self.execute_copy_instruction(vm, from, to, login, DEFAULT_COPY_MODE)
+                    .await
+                    .with_context(|| format!("Guest agent copy also failed: {}", guest_error))
+            }
+        }
+    }
+
+    /// `wait_for_condition`, reporting each screen stall the stage's IDLE_WATCHDOG sees and
+    /// pressing its keep-alive key if it has one
+    async fn wait_for_condition_watched(
+        &mut self,
+        vm: &VmInstance,
+        condition: &str,
+        poll: Option<Duration>,
+        matches: u32,
+        vm_manager: &VmManager,
+    ) -> Result<Option<String>> {
+        // A stable screen condition waits for exactly the stall the watchdog would report
+        let watchdog = self
+            .idle_watchdog
+            .clone()
+            .filter(|_| !matches!(WaitCondition::parse(condition), WaitCondition::StableScreen(_)));
+        let Some((idle_after, key)) = watchdog else {
+            return self.wait_for_condition(vm, condition, poll, matches, vm_manager).await;
+        };
+
+        loop {
+            tokio::select! {
+                result = self.wait_for_condition(vm, condition, poll, matches, vm_manager) => {
+                    return result
+                }
+                idle = self.wait_for_stable_screen(
+                    vm,
+                    idle_after,
+                    STABLE_SCREEN_POLL_INTERVAL,
+                    vm_manager,
+                ) => idle?,
+            }
+
+            match &key {
+                Some(key) => {
+                    warn!(
+                        "Screen idle for {:?} while waiting for '{}', pressing {}",
+                        idle_after, condition, key
+                    );
+                    let action = self.parse_key_action(key)?;
+                    self.keypress_executor
+                        .execute_action(vm, &action, vm_manager)
+                        .await?;
+                }
+                None => warn!("Screen idle for {:?} while waiting for '{}'", idle_after, condition),
+            }
+        }
+    }
+
+    /// Check `condition` every `poll`, or at the condition kind's default interval. Returns the
+    /// screen text pattern that matched, if the condition was screen text, which has to show
+    /// on `matches` consecutive polls.
+    async fn wait_for_condition(
+        &self,
+        vm: &VmInstance,
+        condition: &str,
+        poll: Option<Duration>,
+        matches: u32,
+        vm_manager: &VmManager,
+    ) -> Result<Option<String>> {
+        match WaitCondition::parse(condition) {
+            WaitCondition::StableScreen(window) => {
+                let window = parse_duration(window)?;
+                let poll = poll.unwrap_or(STABLE_SCREEN_POLL_INTERVAL);
+                self.wait_for_stable_screen(vm, window, poll, vm_manager).await?;
+            }
+            WaitCondition::Port(_) => {
+                self.wait_for_ssh_port(vm, poll.unwrap_or(PORT_POLL_INTERVAL)).await?;
+            }
+            WaitCondition::Ping(host) => {
+                self.wait_for_ping(host, poll.unwrap_or(PORT_POLL_INTERVAL)).await?;
+            }
+            // Just wait for the exact text the user specified - no hardcoded logic
+            WaitCondition::ScreenText(text) => {
+                return self
+                    .wait_for_screen_text(vm, text, poll, matches, vm_manager)
+                    .await
+                    .map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolve once the guest's SSH server answers on its forwarded host port
+    async fn wait_for_ssh_port(&self, vm: &VmInstance, poll: Duration) -> Result<()> {
+        let (host, port) = self.ssh_endpoint(vm);
+        info!("Waiting for SSH on {}:{} (VM {})", host, port, vm.name);
+
+        loop {
+            match probe_ssh_banner(&host, port).await {
+                Ok(()) => {
+                    debug!("SSH is accepting connections on {}:{}", host, port);
+                    return Ok(());
+                }
+                Err(e) => trace!("SSH not ready on {}:{}: {}", host, port, e),
+            }
+            sleep(poll).await;
+        }
+    }
+
+    /// Retry an SSH connect, handshake and login with the LOGIN credentials until one
+    /// succeeds, failing with the last attempt's error once `timeout` runs out
+    async fn wait_for_ssh_login(
+        &self,
+        vm: &VmInstance,
+        login: Option<&str>,
+        timeout: Duration,
+    ) -> Result<()> {
+        let credentials = self.resolve_credentials(login)?;
+        let (host, port) = self.ssh_endpoint(vm);
+        info!(
+            "Waiting up to {:?} for SSH login as '{}' on {}:{} (VM {})",
+            timeout, credentials.username, host, port, vm.name
+        );
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let attempt = tokio::task::spawn_blocking({
+                let (credentials, host) = (credentials.clone(), host.clone());
+                move || Self::open_ssh_session(&credentials, &host, port).map(|_| ())
+            });
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let error = match tokio::time::timeout(remaining, attempt).await {
+                Ok(Ok(Ok(()))) => {
+                    info!("SSH login as '{}' works on {}:{}", credentials.username, host, port);
+                    return Ok(());
+                }
+                Ok(Ok(Err(e))) => anyhow::Error::from(e),
+                Ok(Err(e)) => anyhow::Error::from(e).context("SSH login task failed"),
+                Err(_) => anyhow!("attempt still running"),
+            };
+
+            // Auth can fail until the installer's first boot has created the user
+            if Instant::now() + PORT_POLL_INTERVAL >= deadline {
+                return Err(error.context(format!(
+                    "Timed out after {:?} waiting for SSH login on {}:{}",
+                    timeout, host, port
+                )));
+            }
+            trace!("SSH login not possible yet on {}:{}: {:#}", host, port, error);
+            sleep(PORT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Resolve once `host` answers a single ping from the host machine
+    async fn wait_for_ping(&self, host: &str, poll: Duration) -> Result<()> {
+        info!("Waiting for {} to answer ping", host);
+
+        let mut command = tokio::process::Command::new("ping");
+        if cfg!(windows) {
+            command.args(["-n", "1", "-w", "1000", host]);
+        } else {
+            command.args(["-c", "1", "-W", "1", "--", host]);
+        }
+        command
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+
+        loop {
+            let status = command.status().await.context("Failed to run ping")?;
+            if status.success() {
+                debug!("{} answered ping", host);
+                return Ok(());
+            }
+            trace!("{} did not answer ping yet", host);
+            sleep(poll).await;
+        }
+    }
+
+    fn ocr(&self) -> Result<&OcrEngine> {
+        self.ocr_engine
+            .as_ref()
+            .ok_or_else(|| anyhow!("OCR is unavailable because its models failed to load"))
+    }
+
+    async fn extract_text_timed(&self, image: &image::DynamicImage) -> Result<String> {
+        let started = Instant::now();
+        let result = self.ocr()?.extract_text(image).await;
+        self.record_ocr_call(started);
+        result
+    }
+
+    async fn extract_text_and_layout_timed(
+        &self,
+        image: &image::DynamicImage,
+    ) -> Result<(String, TextLayout)> {
+        let started = Instant::now();
+        let result = self.ocr()?.extract_text_and_layout(image).await;
+        self.record_ocr_call(started);
+        result
+    }
+
+    /// Count an OCR call that began at `started` in the profile totals
+    fn record_ocr_call(&self, started: Instant) {
+        self.ocr_nanos
+            .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.ocr_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Resolve once the screen has stopped changing for `window`
+    async fn wait_for_stable_screen(
+        &self,
         vm: &VmInstance,
-        from: &Path,
-        to: &Path,
+        window: Duration,
+        poll: Duration,
+        vm_manager: &VmManager,
     ) -> Result<()> {
-        info!(
-            "COPY: Copying file {} to VM path {}",
-            from.display(),
-            to.display()
-        );
-        if !from.exists() {
-            error!("COPY: Source file does not exist: {}", from.display());
-            return Err(anyhow!(
-                "COPY failed: Source file does not exist: {}",
-                from.display()
-            ));
+        info!("Waiting for the screen of VM {} to be stable for {:?}", vm.name, window);
+
+        let mut last_frame: Option<image::DynamicImage> = None;
+        let mut stable_since = Instant::now();
+
+        loop {
+            match vm_manager.capture_screen(vm).await {
+                Ok(frame) => {
+                    let unchanged = last_frame.as_ref().is_some_and(|last| {
+                        screens_match(last, &frame, STABLE_SCREEN_TOLERANCE)
+                    });
+                    if !unchanged {
+                        trace!("Screen changed, restarting stable window");
+                        stable_since = Instant::now();
+                    } else if stable_since.elapsed() >= window {
+                        debug!("Screen stable for {:?}", stable_since.elapsed());
+                        return Ok(());
+                    }
+                    last_frame = Some(frame);
+                }
+                Err(e) => warn!("Failed to capture screen while waiting for stability: {}", e),
+            }
+
+            sleep(poll).await;
         }
-        // Copy file to VM via SCP/remote copy
-        match self.copy_file_to_vm(vm, from, to).await {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                error!(
-                    "COPY: Failed to copy {} to {}: {}",
-                    from.display(),
-                    to.display(),
-                    e
-                );
-                Err(anyhow!(
-                    "COPY failed: {} -> {}: {}",
-                    from.display(),
-                    to.display(),
-                    e
-                ))
+    }
+
+    /// Wait on the OCR monitor's change events after one fresh read of the screen
+    async fn wait_for_monitored_text(
+        &self,
+        vm: &VmInstance,
+        ocr_engine: &OcrEngine,
+        pattern: &str,
+        vm_manager: &VmManager,
+    ) -> Result<String> {
+        // Only screens read after the WAIT started count: the monitor's cached state may be
+        // from before the previous step's keys took effect
+        let started = Instant::now();
+        let matches = |state: &ScreenState| {
+            (state.timestamp >= started)
+                .then(|| matching_pattern(&state.text, pattern))
+                .flatten()
+        };
+
+        // Subscribe before reading the current screen so no change slips in between. The
+        // monitor only reports changes, so a screen already showing the text is read here.
+        let mut changes = ocr_engine.subscribe_to_changes();
+        match vm_manager.capture_screen(vm).await {
+            Ok(image) => {
+                let text = self.extract_text_timed(&image).await?;
+                if let Some(matched) = matching_pattern(&text, pattern) {
+                    return Ok(matched);
+                }
+            }
+            Err(e) => warn!("Failed to capture screen: {}", e),
+        }
+
+        loop {
+            match changes.recv().await {
+                Ok(event) => match matches(&event.new_state) {
+                    Some(matched) => return Ok(matched),
+                    None => trace!("Screen changed, pattern '{}' not there yet", pattern),
+                },
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    trace!("Missed {} screen change events, checking latest state", skipped);
+                    if let Some(matched) =
+                        ocr_engine.latest_screen_state().and_then(|state| matches(&state))
+                    {
+                        return Ok(matched);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(anyhow!("OCR monitor stopped while waiting for '{}'", pattern));
+                }
             }
         }
     }
 
-    async fn wait_for_condition(
+    /// Wait for the `boot-wait` condition of a freshly started VM, up to the VM timeout.
+    /// Screen text falls back to sleeping the default boot wait when OCR is unavailable.
+    pub async fn wait_for_boot_condition(
         &self,
         vm: &VmInstance,
         condition: &str,
         vm_manager: &VmManager,
     ) -> Result<()> {
-        // Just wait for the exact text the user specified - no hardcoded logic
-        self.wait_for_screen_text(vm, condition, vm_manager).await
+        if matches!(WaitCondition::parse(condition), WaitCondition::ScreenText(_))
+            && !self.has_ocr()
+        {
+            warn!(
+                "OCR is unavailable, waiting {:?} instead of boot-wait '{}'",
+                vm.config.boot_wait, condition
+            );
+            sleep(vm.config.boot_wait).await;
+            return Ok(());
+        }
+
+        info!("Waiting up to {:?} for VM {} to show '{}'", vm.config.timeout, vm.name, condition);
+        timeout(vm.config.timeout, self.wait_for_condition(vm, condition, None, 1, vm_manager))
+            .await
+            .map_err(|_| anyhow!("Timeout waiting for boot-wait condition '{}'", condition))??;
+        info!("Boot condition '{}' met on VM {}", condition, vm.name);
+        Ok(())
     }
 
-    async fn wait_for_screen_text(
+    /// Resolve once OCR finds `pattern`, or any alternative of a `"a" | "b"` list, on the VM
+    /// screen and return the one it found, with no timeout of its own. Screens are read every
+    /// `poll` (default 2s) unless the OCR monitor is running. The text has to be found on
+    /// `matches` consecutive reads, so a transitional frame doesn't end the wait early.
+    pub async fn wait_for_screen_text(
         &self,
         vm: &VmInstance,
         pattern: &str,
+        poll: Option<Duration>,
+        matches: u32,
         vm_manager: &VmManager,
-    ) -> Result<()> {
+    ) -> Result<String> {
         info!("Waiting for screen text '{}' on VM {}", pattern, vm.name);
+        self.ocr()?;
+
+        // Monitor events only come on screen changes, so counting matches needs polling
+        if let Some(ocr_engine) = self
+            .ocr_engine
+            .as_ref()
+            .filter(|e| e.is_monitoring() && matches <= 1)
+        {
+            return self
+                .wait_for_monitored_text(vm, ocr_engine, pattern, vm_manager)
+                .await;
+        }
+
+        // The console log keeps everything the guest printed, so the fallback below only
+        // looks at what was written after this wait started
+        let console_start = vm_manager
+            .console_log_path(vm)
+            .ok()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map_or(0, |metadata| metadata.len() as usize);
 
         // No max attempts limit - let the outer timeout handle the duration
         let mut attempts = 0;
+        let mut consecutive = 0;
 
         loop {
             attempts += 1;
             debug!("Screen text detection attempt {}", attempts);
 
+            let mut found = None;
             // Capture the VM screen
             match vm_manager.capture_screen(vm).await {
                 Ok(image) => {
                     // Extract all text to see what OCR is finding
-                    match self.ocr_engine.extract_text(&image).await {
+                    match self.extract_text_timed(&image).await {
                         Ok(extracted_text) => {
                             if self.ocr_debug_enabled && (attempts <= 3 || attempts % 10 == 0) {
                                 trace!(
@@ -416,19 +1737,16 @@ impl PuppetManager {
                             }
 
                             // Check if pattern is found in the extracted text (case-insensitive)
-                            if extracted_text
-                                .to_lowercase()
-                                .contains(&pattern.to_lowercase())
-                            {
+                            if let Some(matched) = matching_pattern(&extracted_text, pattern) {
                                 if self.ocr_debug_enabled {
                                     trace!(
                                         "Found screen text '{}' on VM {} (attempt {})",
-                                        pattern,
+                                        matched,
                                         vm.name,
                                         attempts
                                     );
                                 }
-                                return Ok(());
+                                found = Some(matched);
                             } else if self.ocr_debug_enabled {
                                 trace!(
                                     "Pattern '{}' not found in extracted text (attempt {})",
@@ -446,107 +1764,288 @@ impl PuppetManager {
                     warn!("Failed to capture screen: {}", e);
                     // Try console output as fallback
                     if let Ok(console_output) = vm_manager.get_console_output(vm).await {
-                        if console_output
-                            .to_lowercase()
-                            .contains(&pattern.to_lowercase())
-                        {
-                            info!("Found pattern '{}' in console output", pattern);
-                            return Ok(());
+                        let new_output = output_since(&console_output, console_start);
+                        if let Some(matched) = matching_pattern(new_output, pattern) {
+                            info!("Found pattern '{}' in console output", matched);
+                            found = Some(matched);
                         }
                     }
                 }
             }
 
+            // Any read without the text starts the count over
+            match found {
+                Some(matched) => {
+                    consecutive += 1;
+                    if consecutive >= matches {
+                        return Ok(matched);
+                    }
+                    debug!(
+                        "Found '{}' on {} of {} consecutive reads",
+                        matched, consecutive, matches
+                    );
+                }
+                None => consecutive = 0,
+            }
+
             // Wait before next attempt
-            sleep(Duration::from_secs(2)).await;
+            sleep(poll.unwrap_or(SCREEN_TEXT_POLL_INTERVAL)).await;
         }
     }
 
-    async fn execute_remote_command(&self, vm: &VmInstance, command: &str) -> Result<()> {
+    /// Look up the credentials for a named LOGIN, or the most recent one if no name is given
+    fn resolve_credentials(&self, login: Option<&str>) -> Result<SshCredentials> {
+        let name = match login.or(self.active_login.as_deref()) {
+            Some(name) => name,
+            None => {
+                return Err(anyhow!(
+                    "No SSH credentials configured. Use LOGIN instruction first."
+                ))
+            }
+        };
+
+        self.ssh_credentials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("No SSH credentials configured for login '{}'", name))
+    }
+
+    async fn execute_remote_command(
+        &self,
+        vm: &VmInstance,
+        command: &str,
+        login: Option<&str>,
+        pty: bool,
+    ) -> Result<()> {
         info!("Executing remote command on VM {}: {}", vm.name, command);
-        if self.ssh_credentials.is_none() {
-            return Err(anyhow!(
-                "No SSH credentials configured. Use LOGIN instruction first."
-            ));
-        }
-        
+        let credentials = self.resolve_credentials(login)?;
+
         // Get endpoint from provider
-        let provider = crate::automation::vm::providers::create_provider(&vm.provider);
-        let (host, port) = provider.get_ssh_endpoint(vm);
+        let (host, port) = self.ssh_endpoint(vm);
         
         info!("SSH connection details: {}:{}", host, port);
         
-        let credentials = self.ssh_credentials.as_ref().unwrap().clone();
         let command_clone = command.to_string();
         tokio::task::spawn_blocking(move || {
-            Self::ssh_execute_command_with_endpoint(&credentials, &host, port, &command_clone)
+            Self::ssh_execute_command_with_endpoint(&credentials, &host, port, &command_clone, pty)
         })
         .await
         .context("Failed to spawn SSH command task")?
+        .map_err(anyhow::Error::from)
     }
 
-    fn ssh_execute_command_with_endpoint(
+    /// Connect, handshake and authenticate, classifying each failure
+    fn open_ssh_session(
         credentials: &SshCredentials,
         host: &str,
         port: u16,
-        command: &str,
-    ) -> Result<()> {
-        // Attempt TCP connection with detailed error info
-        let tcp = std::net::TcpStream::connect(format!("{}:{}", host, port))
-            .context(format!("Failed to connect to VM via SSH at {}:{}", host, port))?;
-            
-        let mut sess = Session::new().context("Failed to create SSH session")?;
+    ) -> std::result::Result<Session, SshError> {
+        let endpoint = format!("{}:{}", host, port);
+        let addr = std::net::ToSocketAddrs::to_socket_addrs(&(host, port))
+            .and_then(|mut addrs| {
+                addrs.next().ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "no address resolved")
+                })
+            })
+            .map_err(|source| SshError::Connect {
+                endpoint: endpoint.clone(),
+                source,
+            })?;
+
+        let tcp = std::net::TcpStream::connect_timeout(&addr, SSH_CONNECT_TIMEOUT).map_err(
+            |source| match source.kind() {
+                std::io::ErrorKind::TimedOut => SshError::Timeout {
+                    endpoint: endpoint.clone(),
+                    seconds: SSH_CONNECT_TIMEOUT.as_secs(),
+                },
+                _ => SshError::Connect {
+                    endpoint: endpoint.clone(),
+                    source,
+                },
+            },
+        )?;
+
+        let mut sess = Session::new().map_err(|source| SshError::Handshake {
+            endpoint: endpoint.clone(),
+            source,
+        })?;
         sess.set_tcp_stream(tcp);
-        sess.handshake()
-            .context(format!("SSH handshake failed to {}:{}", host, port))?;
+        sess.handshake().map_err(|source| SshError::Handshake {
+            endpoint: endpoint.clone(),
+            source,
+        })?;
+        Self::verify_host_key(&sess, host, port, credentials.host_key)?;
+
+        let auth_error = |reason: String| SshError::Auth {
+            username: credentials.username.clone(),
+            reason,
+        };
+
         // Try authentication methods in order of preference
         if let Some(ref private_key_path) = credentials.private_key {
-            if private_key_path.exists() {
-                sess.userauth_pubkey_file(&credentials.username, None, private_key_path, None)
-                    .context("SSH private key authentication failed")?;
-            } else {
-                return Err(anyhow!(
-                    "SSH private key file not found: {}",
+            if !private_key_path.exists() {
+                return Err(auth_error(format!(
+                    "private key file not found: {}",
                     private_key_path.display()
-                ));
+                )));
             }
+            sess.userauth_pubkey_file(&credentials.username, None, private_key_path, None)
+                .map_err(|e| auth_error(format!("private key rejected: {}", e)))?;
         } else if let Some(ref password) = credentials.password {
             sess.userauth_password(&credentials.username, password)
-                .context("SSH password authentication failed")?;
+                .map_err(|e| auth_error(format!("password rejected: {}", e)))?;
         } else {
-            return Err(anyhow!(
-                "No SSH credentials provided (need either private key or password)"
+            return Err(auth_error(
+                "no credentials provided (need either private key or password)".to_string(),
             ));
         }
+
+        Ok(sess)
+    }
+
+    /// Check the host key `sess` was offered against ~/.ssh/known_hosts as `policy` says
+    fn verify_host_key(
+        sess: &Session,
+        host: &str,
+        port: u16,
+        policy: HostKeyPolicy,
+    ) -> std::result::Result<(), SshError> {
+        if policy == HostKeyPolicy::Unchecked {
+            return Ok(());
+        }
+        let host_key_error = |reason: String| SshError::HostKey {
+            endpoint: format!("{}:{}", host, port),
+            reason,
+        };
+
+        let (key, key_type) = sess
+            .host_key()
+            .ok_or_else(|| host_key_error("the server sent no host key".to_string()))?;
+        let path = home::home_dir()
+            .ok_or_else(|| host_key_error("no home directory to find known_hosts in".to_string()))?
+            .join(".ssh")
+            .join("known_hosts");
+        let mut known_hosts = sess.known_hosts().map_err(|e| host_key_error(e.to_string()))?;
+        if path.exists() {
+            known_hosts
+                .read_file(&path, KnownHostFileKind::OpenSSH)
+                .map_err(|e| host_key_error(format!("failed to read {}: {}", path.display(), e)))?;
+        }
+
+        match known_hosts.check_port(host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(host_key_error(format!(
+                "the host key differs from the one in {}; the host may be impersonated",
+                path.display()
+            ))),
+            CheckResult::NotFound if policy == HostKeyPolicy::AcceptNew => {
+                let line = Self::known_hosts_line(sess, host, port, key, key_type)
+                    .map_err(|e| host_key_error(format!("{:#}", e)))?;
+                Self::append_known_host(&path, &line).map_err(|e| {
+                    host_key_error(format!("failed to add it to {}: {:#}", path.display(), e))
+                })?;
+                warn!("Added the host key of {}:{} to {}", host, port, path.display());
+                Ok(())
+            }
+            CheckResult::NotFound => Err(host_key_error(format!(
+                "the host is not in {}; connect once with ssh to check its key, \
+                 or pass --accept-new-host-key",
+                path.display()
+            ))),
+            CheckResult::Failure => Err(host_key_error("failed to check the host key".to_string())),
+        }
+    }
+
+    /// known_hosts line for `key`; OpenSSH writes hosts on other ports than 22 as [host]:port
+    fn known_hosts_line(
+        sess: &Session,
+        host: &str,
+        port: u16,
+        key: &[u8],
+        key_type: ssh2::HostKeyType,
+    ) -> Result<String> {
+        let entry = match port {
+            22 => host.to_string(),
+            port => format!("[{}]:{}", host, port),
+        };
+        // A fresh set holds only this key, so existing entries are never rewritten
+        let mut known_hosts = sess.known_hosts()?;
+        known_hosts.add(&entry, key, "", key_type.into())?;
+        let added = known_hosts
+            .hosts()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("the host key was not added"))?;
+        Ok(known_hosts.write_string(&added, KnownHostFileKind::OpenSSH)?)
+    }
+
+    /// Append `line` to the known_hosts file at `path`, leaving its other entries untouched
+    fn append_known_host(path: &Path, line: &str) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let existing = fs::read(path).unwrap_or_default();
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        if existing.last().is_some_and(|byte| *byte != b'\n') {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn ssh_execute_command_with_endpoint(
+        credentials: &SshCredentials,
+        host: &str,
+        port: u16,
+        command: &str,
+        pty: bool,
+    ) -> std::result::Result<(), SshError> {
+        let sess = Self::open_ssh_session(credentials, host, port)?;
+
         let mut channel = sess
             .channel_session()
-            .context("Failed to create SSH channel")?;
+            .map_err(|e| SshError::exec("Failed to create SSH channel", e))?;
+        if pty {
+            // A wide terminal keeps progress output from wrapping into extra lines
+            channel
+                .request_pty("xterm", None, Some((PTY_COLUMNS, PTY_ROWS, 0, 0)))
+                .map_err(|e| SshError::exec("Failed to request a PTY", e))?;
+        }
         channel
             .exec(command)
-            .context("Failed to execute command via SSH")?;
+            .map_err(|e| SshError::exec("Failed to execute command via SSH", e))?;
         let mut output = String::new();
         channel
             .read_to_string(&mut output)
-            .context("Failed to read command output")?;
+            .map_err(|e| SshError::exec("Failed to read command output", e))?;
+        if pty {
+            // The terminal translates newlines, and stderr is merged into this stream
+            output = output.replace("\r\n", "\n");
+        }
         let exit_status = channel
             .exit_status()
-            .context("Failed to get command exit status")?;
+            .map_err(|e| SshError::exec("Failed to get command exit status", e))?;
         channel
             .wait_close()
-            .context("Failed to close SSH channel")?;
-        if exit_status == 0 {
-            info!("Command executed successfully. Output: {}", output.trim());
-        } else {
-            return Err(anyhow!(
-                "Command failed with exit status {}. Output: {}",
-                exit_status,
-                output.trim()
-            ));
+            .map_err(|e| SshError::exec("Failed to close SSH channel", e))?;
+        if exit_status != 0 {
+            return Err(SshError::NonZeroExit {
+                code: exit_status,
+                output: output.trim().to_string(),
+            });
         }
+        info!("Command executed successfully. Output: {}", output.trim());
         Ok(())
     }
 
-    async fn copy_file_to_vm(&self, vm: &VmInstance, from: &Path, to: &Path) -> Result<()> {
+    async fn copy_file_to_vm(
+        &self,
+        vm: &VmInstance,
+        from: &Path,
+        to: &Path,
+        login: Option<&str>,
+        mode: i32,
+    ) -> Result<()> {
         info!(
             "Copying {} to VM {} at {}",
             from.display(),
@@ -558,64 +2057,111 @@ impl PuppetManager {
             return Err(anyhow!("Source file does not exist: {}", from.display()));
         }
 
-        if self.ssh_credentials.is_none() {
-            return Err(anyhow!(
-                "No SSH credentials configured. Use LOGIN instruction first."
-            ));
-        }
+        let credentials = self.resolve_credentials(login)?;
 
         // Use tokio::task::spawn_blocking to run SSH/SCP in blocking context
-        let credentials = self.ssh_credentials.as_ref().unwrap().clone();
         let from_path = from.to_path_buf();
         let to_path = to.to_path_buf();
-        let provider = crate::automation::vm::providers::create_provider(&vm.provider);
-        let (host, port) = provider.get_ssh_endpoint(vm);
+        let (host, port) = self.ssh_endpoint(vm);
         
         info!("SCP connection details: {}:{}", host, port);
         
         tokio::task::spawn_blocking(move || {
-            Self::scp_copy_file_with_endpoint(&credentials, &host, port, &from_path, &to_path)
+            Self::scp_copy_file_with_endpoint(&credentials, &host, port, &from_path, &to_path, mode)
+        })
+        .await
+        .context("Failed to spawn SCP file transfer task")?
+    }
+
+    async fn copy_file_from_vm(
+        &self,
+        vm: &VmInstance,
+        remote: &str,
+        local: &Path,
+        login: Option<&str>,
+    ) -> Result<()> {
+        info!("Copying {} from VM {} to {}", remote, vm.name, local.display());
+
+        if let Some(parent) = local.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create local directory: {}", parent.display())
+            })?;
+        }
+
+        let credentials = self.resolve_credentials(login)?;
+
+        let remote_path = PathBuf::from(remote);
+        let local_path = local.to_path_buf();
+        let (host, port) = self.ssh_endpoint(vm);
+
+        info!("SCP connection details: {}:{}", host, port);
+
+        tokio::task::spawn_blocking(move || {
+            Self::scp_recv_file_with_endpoint(&credentials, &host, port, &remote_path, &local_path)
         })
         .await
         .context("Failed to spawn SCP file transfer task")?
     }
 
+    fn scp_recv_file_with_endpoint(
+        credentials: &SshCredentials,
+        host: &str,
+        port: u16,
+        from: &Path,
+        to: &Path,
+    ) -> Result<()> {
+        let sess = Self::open_ssh_session(credentials, host, port)
+            .map_err(anyhow::Error::from)
+            .context("Failed to open SSH session for file transfer")?;
+
+        let (mut remote_file, stat) = sess
+            .scp_recv(from)
+            .context("Failed to open remote file via SCP")?;
+
+        let mut file_contents = Vec::with_capacity(stat.size() as usize);
+        remote_file
+            .read_to_end(&mut file_contents)
+            .context("Failed to read file contents via SCP")?;
+
+        remote_file
+            .send_eof()
+            .context("Failed to send EOF via SCP")?;
+        remote_file
+            .wait_eof()
+            .context("Failed to wait for EOF via SCP")?;
+        remote_file.close().context("Failed to close SCP channel")?;
+        remote_file
+            .wait_close()
+            .context("Failed to wait for SCP channel close")?;
+
+        std::fs::write(to, &file_contents)
+            .with_context(|| format!("Failed to write local file: {}", to.display()))?;
+
+        info!(
+            "File copied successfully from VM: {} -> {}",
+            from.display(),
+            to.display()
+        );
+        Ok(())
+    }
+
     fn scp_copy_file_with_endpoint(
         credentials: &SshCredentials,
         host: &str,
         port: u16,
         from: &Path,
         to: &Path,
+        mode: i32,
     ) -> Result<()> {
-        let tcp = std::net::TcpStream::connect(format!("{}:{}", host, port))
-            .context(format!("Failed to connect to VM via SSH for file transfer at {}:{}", host, port))?;
-        let mut sess = Session::new().context("Failed to create SSH session for file transfer")?;
-        sess.set_tcp_stream(tcp);
-        sess.handshake()
-            .context(format!("SSH handshake failed for file transfer to {}:{}", host, port))?;
-        // Try authentication methods in order of preference
-        if let Some(ref private_key_path) = credentials.private_key {
-            if private_key_path.exists() {
-                sess.userauth_pubkey_file(&credentials.username, None, private_key_path, None)
-                    .context("SSH private key authentication failed for file transfer")?;
-            } else {
-                return Err(anyhow!(
-                    "SSH private key file not found: {}",
-                    private_key_path.display()
-                ));
-            }
-        } else if let Some(ref password) = credentials.password {
-            sess.userauth_password(&credentials.username, password)
-                .context("SSH password authentication failed for file transfer")?;
-        } else {
-            return Err(anyhow!("No SSH credentials provided for file transfer (need either private key or password)"));
-        }
+        let sess = Self::open_ssh_session(credentials, host, port)
+            .map_err(anyhow::Error::from)
+            .context("Failed to open SSH session for file transfer")?;
         // Read the source file
         let file_contents = std::fs::read(from).context("Failed to read source file")?;
 
         // Create the remote file using SCP
         let mut remote_file = sess
-            .scp_send(to, 0o644, file_contents.len() as u64, None)
+            .scp_send(to, mode, file_contents.len() as u64, None)
             .context("Failed to create remote file via SCP")?;
 
         remote_file
@@ -688,42 +2234,11 @@ impl PuppetManager {
         }
     }
 
-    fn parse_duration(&self, duration: &str) -> Result<Duration> {
-        let duration_lower = duration.to_lowercase();
-        if duration_lower.ends_with("s") {
-            let secs: u64 = duration_lower
-                .trim_end_matches("s")
-                .parse()
-                .context("Invalid seconds format")?;
-            Ok(Duration::from_secs(secs))
-        } else if duration_lower.ends_with("m") {
-            let mins: u64 = duration_lower
-                .trim_end_matches("m")
-                .parse()
-                .context("Invalid minutes format")?;
-            Ok(Duration::from_secs(mins * 60))
-        } else if duration_lower.ends_with("h") {
-            let hours: u64 = duration_lower
-                .trim_end_matches("h")
-                .parse()
-                .context("Invalid hours format")?;
-            Ok(Duration::from_secs(hours * 3600))
-        } else if duration_lower.ends_with("ms") {
-            let millis: u64 = duration_lower
-                .trim_end_matches("ms")
-                .parse()
-                .context("Invalid milliseconds format")?;
-            Ok(Duration::from_millis(millis))
-        } else {
-            Err(anyhow!("Invalid duration format: {}", duration))
-        }
-    }
-
     /// Capture debug screenshot and generate OCR text file
     async fn capture_debug_screenshot(
         &self,
         vm: &VmInstance,
-        prefix: &str, // "pre", "post", or "notice"
+        prefix: &str, // "pre", "post", "notice", "failure" or "breakpoint"
         step: usize,
         vm_manager: &VmManager,
     ) -> Result<()> {
@@ -746,9 +2261,29 @@ impl PuppetManager {
                     warn!("Failed to save debug screenshot {}: {}", image_path.display(), e);
                     return Ok(());
                 }
-                
-                // Generate OCR text
-                match self.ocr_engine.extract_text(&image).await {
+
+                if self.ocr_engine.is_none() {
+                    info!("Debug screenshot captured (no OCR): {}", image_path.display());
+                    return Ok(());
+                }
+
+                // Generate OCR text, with OCR debugging on also saving a frame with the text
+                // boxes the same pass detected drawn on it
+                let extracted = if self.ocr_debug_enabled {
+                    self.extract_text_and_layout_timed(&image).await.map(|(ocr_text, layout)| {
+                        let overlay_path = self
+                            .debug_steps_dir
+                            .join(format!("{}-annotated.png", filename_base));
+                        if let Err(e) = ocr_overlay::save_annotated(&image, &layout, &overlay_path)
+                        {
+                            warn!("{}", e);
+                        }
+                        ocr_text
+                    })
+                } else {
+                    self.extract_text_timed(&image).await
+                };
+                match extracted {
                     Ok(ocr_text) => {
                         if let Err(e) = fs::write(&text_path, &ocr_text) {
                             warn!("Failed to save OCR text {}: {}", text_path.display(), e);
@@ -777,3 +2312,131 @@ impl PuppetManager {
         Ok(())
     }
 }
+
+/// First of the `"a" | "b"` alternatives of `pattern` found in `text`, ignoring case
+fn matching_pattern(text: &str, pattern: &str) -> Option<String> {
+    let text = text.to_lowercase();
+    screen_text_patterns(pattern)
+        .into_iter()
+        .find(|pattern| text.contains(&pattern.to_lowercase()))
+        .map(str::to_string)
+}
+
+/// First capture group of `regex` in OCR'd `text`, trimmed; an empty group doesn't count
+fn captured_value(regex: &regex::Regex, text: &str) -> Option<String> {
+    regex
+        .captures_iter(text)
+        .filter_map(|captures| Some(captures.get(1)?.as_str().trim().to_string()))
+        .find(|value| !value.is_empty())
+}
+
+/// `command` with `export` of the given variables in front, so every command of a `&&`
+/// chain, `;` list or pipeline sees them
+fn with_env(command: &str, env: &[(&str, String)]) -> String {
+    if env.is_empty() {
+        return command.to_string();
+    }
+    let assignments: Vec<String> = env
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, shell_quote(value)))
+        .collect();
+    format!("export {}; {}", assignments.join(" "), command)
+}
+
+/// `command` run as root by `sudo -S` with `password` on its stdin. `-k` makes sudo ask even
+/// with cached credentials, which would otherwise leave the password for the command to read.
+fn sudo_wrapped(command: &str, password: &str) -> String {
+    format!(
+        "printf '%s\\n' {} | sudo -k -S -p '' sh -c {}",
+        shell_quote(password),
+        shell_quote(command)
+    )
+}
+
+/// Part of a console log written after it was `start` bytes long; all of it if the log was
+/// truncated since, e.g. by a reconnect
+fn output_since(output: &str, start: usize) -> &str {
+    output.get(start..).unwrap_or(output)
+}
+
+/// Quote a value for safe interpolation into a POSIX shell command line
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_hosts_line() {
+        let sess = Session::new().unwrap();
+        let key: &[u8] = b"\0\0\0\x0bssh-ed25519\0\0\0\x20abcdefghijklmnopqrstuvwxyz012345";
+        let other: &[u8] = b"\0\0\0\x0bssh-ed25519\0\0\0\x20ABCDEFGHIJKLMNOPQRSTUVWXYZ012345";
+        let key_type = ssh2::HostKeyType::Ed25519;
+        let line = PuppetManager::known_hosts_line(&sess, "10.0.0.7", 2222, key, key_type).unwrap();
+        assert!(line.starts_with("[10.0.0.7]:2222 ssh-ed25519 "), "{}", line);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".ssh/known_hosts");
+        PuppetManager::append_known_host(&path, &line).unwrap();
+        fs::write(&path, fs::read_to_string(&path).unwrap().trim_end()).unwrap();
+        PuppetManager::append_known_host(&path, &line).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // The appended entry is what a later connection is checked against
+        let mut known_hosts = sess.known_hosts().unwrap();
+        known_hosts.read_file(&path, KnownHostFileKind::OpenSSH).unwrap();
+        assert!(matches!(known_hosts.check_port("10.0.0.7", 2222, key), CheckResult::Match));
+        assert!(matches!(known_hosts.check_port("10.0.0.7", 22, key), CheckResult::NotFound));
+        assert!(matches!(known_hosts.check_port("10.0.0.7", 2222, other), CheckResult::Mismatch));
+    }
+
+    #[test]
+    fn test_captured_value() {
+        let regex = regex::Regex::new(r"Code: ([A-Z0-9]{6})").unwrap();
+        let text = "Welcome\nCode: AB12CD\nPress enter";
+        assert_eq!(captured_value(&regex, text).as_deref(), Some("AB12CD"));
+        assert_eq!(captured_value(&regex, "Welcome\nPress enter"), None);
+
+        // OCR padding is trimmed, and an empty first match gives way to a later one
+        let regex = regex::Regex::new(r"IP:([^\n]*)").unwrap();
+        let text = "IP:\nIP:  10.0.2.15 \n";
+        assert_eq!(captured_value(&regex, text).as_deref(), Some("10.0.2.15"));
+        assert_eq!(captured_value(&regex, "IP:   \n"), None);
+
+        // An optional group that didn't take part in the match doesn't count
+        let regex = regex::Regex::new(r"ready(?: as (\w+))?").unwrap();
+        assert_eq!(captured_value(&regex, "ready"), None);
+        assert_eq!(captured_value(&regex, "ready as admin").as_deref(), Some("admin"));
+    }
+
+    #[test]
+    fn test_output_since() {
+        let log = "boot\nlogin: \nwelcome\n";
+        assert_eq!(output_since(log, 0), log);
+        assert_eq!(output_since(log, 5), "login: \nwelcome\n");
+        assert_eq!(output_since(log, log.len()), "");
+        // A log shorter than at the start was truncated, so all of it is new
+        assert_eq!(output_since("login: ", 40), "login: ");
+    }
+
+    #[test]
+    fn test_with_env() {
+        let env = [("GREETING", "it's here".to_string()), ("LANG", "C".to_string())];
+        assert_eq!(
+            with_env("cd /tmp && echo $GREETING | wc -c", &env),
+            "export GREETING='it'\\''s here' LANG='C'; cd /tmp && echo $GREETING | wc -c"
+        );
+        assert_eq!(with_env("whoami", &[]), "whoami");
+    }
+
+    #[test]
+    fn test_sudo_wrapped() {
+        assert_eq!(
+            sudo_wrapped("apt-get update && apt-get -y upgrade", "pa'ss"),
+            "printf '%s\\n' 'pa'\\''ss' | sudo -k -S -p '' sh -c \
+             'apt-get update && apt-get -y upgrade'"
+        );
+    }
+}
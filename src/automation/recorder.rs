@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::automation::ocr::ScreenshotCapture;
+
+/// Playback rate of the assembled video, so a long build plays back as a time-lapse
+const VIDEO_FPS: u32 = 5;
+/// Every frame is scaled and padded to this size, since the guest resolution changes
+/// between the boot menu, the installer and the installed system
+const VIDEO_WIDTH: u32 = 1280;
+const VIDEO_HEIGHT: u32 = 720;
+
+/// Captures the VM screen at a fixed interval into numbered PNG frames (`--record`)
+pub struct ScreenRecorder {
+    frames_dir: PathBuf,
+    interval: Duration,
+    /// Frames written so far; numbering continues when recording restarts for another stage
+    frame_count: Arc<AtomicUsize>,
+    /// Stop signal and handle of the running capture task
+    task: Option<(oneshot::Sender<()>, JoinHandle<()>)>,
+}
+
+impl ScreenRecorder {
+    pub fn new(frames_dir: PathBuf, interval: Duration) -> Self {
+        Self {
+            frames_dir,
+            interval,
+            frame_count: Arc::new(AtomicUsize::new(0)),
+            task: None,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.task.is_some()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count.load(Ordering::Relaxed)
+    }
+
+    /// Capture frames from `capture` in a background task until `stop` is called. Frames of
+    /// an earlier build in the frames directory are removed on the first start.
+    pub fn start<T: ScreenshotCapture + 'static>(&mut self, capture: T) -> Result<()> {
+        if self.task.is_some() {
+            return Err(anyhow!("Screen recording is already running"));
+        }
+        if self.frame_count() == 0 && self.frames_dir.exists() {
+            std::fs::remove_dir_all(&self.frames_dir).with_context(|| {
+                format!("Failed to clear recording directory: {}", self.frames_dir.display())
+            })?;
+        }
+        std::fs::create_dir_all(&self.frames_dir).with_context(|| {
+            format!("Failed to create recording directory: {}", self.frames_dir.display())
+        })?;
+
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let frames_dir = self.frames_dir.clone();
+        let frame_count = self.frame_count.clone();
+        let interval = self.interval;
+
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = sleep(interval) => {}
+                }
+                // A failed capture, e.g. while the VM reboots, leaves no gap in the numbering
+                let image = match capture.capture().await {
+                    Ok(image) => image,
+                    Err(e) => {
+                        debug!("Skipping recording frame: {:#}", e);
+                        continue;
+                    }
+                };
+                let frame = frame_count.load(Ordering::Relaxed) + 1;
+                match image.save(frame_path(&frames_dir, frame)) {
+                    Ok(()) => frame_count.store(frame, Ordering::Relaxed),
+                    Err(e) => warn!("Failed to save recording frame {}: {}", frame, e),
+                }
+            }
+        });
+
+        info!(
+            "Recording the screen every {:?} into {}",
+            self.interval,
+            self.frames_dir.display()
+        );
+        self.task = Some((stop_tx, handle));
+        Ok(())
+    }
+
+    /// Stop the capture task, waiting for a frame being written to finish
+    pub async fn stop(&mut self) {
+        if let Some((stop_tx, handle)) = self.task.take() {
+            let _ = stop_tx.send(());
+            if let Err(e) = handle.await {
+                warn!("Screen recording task failed: {}", e);
+            }
+            info!("Screen recording stopped after {} frames", self.frame_count());
+        }
+    }
+
+    /// Assemble the frames into a time-lapse video with ffmpeg; the format follows the
+    /// extension of `output` (mp4 or gif)
+    pub fn assemble_video(&self, output: &Path) -> Result<()> {
+        if self.frame_count() == 0 {
+            return Err(anyhow!("No frames were recorded"));
+        }
+
+        let fit = format!(
+            "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2",
+            w = VIDEO_WIDTH,
+            h = VIDEO_HEIGHT
+        );
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-y", "-loglevel", "error", "-framerate"])
+            .arg(VIDEO_FPS.to_string())
+            .arg("-i")
+            .arg(self.frames_dir.join("frame-%06d.png"))
+            .arg("-vf")
+            .arg(fit);
+        if output.extension().is_some_and(|extension| extension == "mp4") {
+            command.args(["-c:v", "libx264", "-pix_fmt", "yuv420p"]);
+        }
+        let output_status = command
+            .arg(output)
+            .output()
+            .context("Failed to run ffmpeg, is it installed?")?;
+
+        if !output_status.status.success() {
+            return Err(anyhow!(
+                "ffmpeg failed: {}",
+                String::from_utf8_lossy(&output_status.stderr).trim()
+            ));
+        }
+        info!(
+            "Assembled {} recorded frames into {}",
+            self.frame_count(),
+            output.display()
+        );
+        Ok(())
+    }
+}
+
+fn frame_path(frames_dir: &Path, frame: usize) -> PathBuf {
+    frames_dir.join(format!("frame-{:06}.png", frame))
+}
@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info, warn};
+
+/// Host side of a VM serial port served over TCP. Everything the guest prints is appended to
+/// the console log, so `get_console_output` keeps working, and `send` types into the port.
+pub struct SerialConsole {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    reader: JoinHandle<()>,
+    /// Guest output, fanned out to the viewers of a shared console
+    output: broadcast::Sender<Vec<u8>>,
+    share: Option<JoinHandle<()>>,
+}
+
+impl SerialConsole {
+    /// Connect to the serial port on `port` and start mirroring it into `log_path`, which is
+    /// truncated so earlier runs don't satisfy a prompt
+    pub async fn connect(port: u16, log_path: &Path) -> Result<Self> {
+        let stream = TcpStream::connect(("127.0.0.1", port))
+            .await
+            .with_context(|| format!("Failed to connect to the serial console on port {}", port))?;
+        let mut log = tokio::fs::File::create(log_path)
+            .await
+            .with_context(|| format!("Failed to create console log: {}", log_path.display()))?;
+        info!("Connected to serial console on port {}", port);
+
+        let (mut read_half, writer) = stream.into_split();
+        let (output, _) = broadcast::channel(256);
+        let viewers = output.clone();
+        let reader = tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match read_half.read(&mut buffer).await {
+                    // The port closes when the VM powers off
+                    Ok(0) => break,
+                    Ok(read) => {
+                        // No viewers is not an error
+                        let _ = viewers.send(buffer[..read].to_vec());
+                        let written = log.write_all(&buffer[..read]).await;
+                        if let Err(e) = written.and(log.flush().await) {
+                            warn!("Failed to write serial console log: {}", e);
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        debug!("Serial console connection closed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            reader,
+            output,
+            share: None,
+        })
+    }
+
+    /// Serve the console on `port` of localhost, e.g. for `nc 127.0.0.1 <port>`. VirtualBox
+    /// takes a single client on its own port, so viewers go through this connection.
+    /// Sharing again replaces the previous share and disconnects its viewers.
+    pub async fn share(&mut self, port: u16) -> Result<()> {
+        // The old listener has to be gone before its port can be bound again
+        if let Some(previous) = self.share.take() {
+            previous.abort();
+            let _ = previous.await;
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .with_context(|| format!("Failed to share the serial console on port {}", port))?;
+        info!("Serial console shared on 127.0.0.1:{}, attach with: nc 127.0.0.1 {}", port, port);
+
+        let output = self.output.clone();
+        let writer = self.writer.clone();
+        self.share = Some(tokio::spawn(async move {
+            // Dropping the set when the share is aborted disconnects every viewer
+            let mut viewers = JoinSet::new();
+            loop {
+                while viewers.try_join_next().is_some() {}
+                let (stream, peer) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        warn!("Serial console share stopped accepting viewers: {}", e);
+                        break;
+                    }
+                };
+                info!("Serial console viewer connected from {}", peer);
+                let (mut viewer_reader, mut viewer_writer) = stream.into_split();
+
+                let mut guest_output = output.subscribe();
+                viewers.spawn(async move {
+                    loop {
+                        match guest_output.recv().await {
+                            Ok(bytes) => {
+                                if viewer_writer.write_all(&bytes).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                debug!("Serial console viewer missed {} chunks", skipped);
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        }
+                    }
+                });
+
+                // Keystrokes from the viewer go to the guest
+                let writer = writer.clone();
+                viewers.spawn(async move {
+                    let mut buffer = [0u8; 1024];
+                    loop {
+                        match viewer_reader.read(&mut buffer).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(read) => {
+                                let mut writer = writer.lock().await;
+                                if writer.write_all(&buffer[..read]).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    debug!("Serial console viewer {} disconnected", peer);
+                });
+            }
+        }));
+        Ok(())
+    }
+
+    /// Whether the connection is still up, false once the VM has closed the port
+    pub fn is_connected(&self) -> bool {
+        !self.reader.is_finished()
+    }
+
+    pub async fn send(&mut self, text: &str) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer
+            .write_all(text.as_bytes())
+            .await
+            .context("Failed to write to the serial console")?;
+        writer
+            .flush()
+            .await
+            .context("Failed to write to the serial console")
+    }
+}
+
+impl Drop for SerialConsole {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if let Some(share) = &self.share {
+            share.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// A localhost port that was free a moment ago
+    async fn free_port() -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_share_relays_and_rebinds() {
+        let vm_port = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = vm_port.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("console.log");
+        let (console, accepted) =
+            tokio::join!(SerialConsole::connect(port, &log_path), vm_port.accept());
+        let mut console = console.unwrap();
+        let (mut guest, _) = accepted.unwrap();
+
+        let share_port = free_port().await;
+        console.share(share_port).await.unwrap();
+        let mut viewer = TcpStream::connect(("127.0.0.1", share_port)).await.unwrap();
+        // Let the share subscribe the viewer before the guest prints
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        guest.write_all(b"login: ").await.unwrap();
+        let mut buffer = [0u8; 16];
+        let read = timeout(Duration::from_secs(5), viewer.read(&mut buffer)).await.unwrap();
+        assert_eq!(&buffer[..read.unwrap()], b"login: ");
+
+        viewer.write_all(b"root\n").await.unwrap();
+        let read = timeout(Duration::from_secs(5), guest.read(&mut buffer)).await.unwrap();
+        assert_eq!(&buffer[..read.unwrap()], b"root\n");
+
+        // Sharing on the same port again replaces the old share and drops its viewer
+        console.share(share_port).await.unwrap();
+        let read = timeout(Duration::from_secs(5), viewer.read(&mut buffer)).await.unwrap();
+        assert!(matches!(read, Ok(0) | Err(_)));
+        TcpStream::connect(("127.0.0.1", share_port)).await.unwrap();
+    }
+}
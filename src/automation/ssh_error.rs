@@ -0,0 +1,50 @@
+use thiserror::Error;
+
+/// Classified failures from SSH helpers so callers can decide whether a retry makes sense
+#[derive(Debug, Error)]
+pub enum SshError {
+    #[error("Failed to connect to VM via SSH at {endpoint}: {source}")]
+    Connect {
+        endpoint: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Timed out connecting to VM via SSH at {endpoint} after {seconds}s")]
+    Timeout { endpoint: String, seconds: u64 },
+
+    #[error("SSH handshake failed with {endpoint}: {source}")]
+    Handshake {
+        endpoint: String,
+        #[source]
+        source: ssh2::Error,
+    },
+
+    #[error("SSH host key verification failed for {endpoint}: {reason}")]
+    HostKey { endpoint: String, reason: String },
+
+    #[error("SSH authentication failed for user '{username}': {reason}")]
+    Auth { username: String, reason: String },
+
+    #[error("SSH command execution failed: {reason}")]
+    Exec { reason: String },
+
+    #[error("Command failed with exit status {code}. Output: {output}")]
+    NonZeroExit { code: i32, output: String },
+}
+
+impl SshError {
+    /// Network-level failures that may succeed on another attempt (e.g. the guest is still booting)
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            SshError::Connect { .. } | SshError::Timeout { .. } | SshError::Handshake { .. }
+        )
+    }
+
+    pub(crate) fn exec(context: &str, source: impl std::fmt::Display) -> Self {
+        SshError::Exec {
+            reason: format!("{}: {}", context, source),
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Highest optical drive slot; slot 0 holds the install ISO
+pub const MAX_ISO_SLOT: u32 = 3;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VmInstance {
@@ -11,7 +15,16 @@ pub struct VmInstance {
     pub config: VmConfig,
     pub state: VmState,
     pub disk_path: Option<PathBuf>,
-    pub iso_path: Option<PathBuf>,
+    /// Attached ISOs keyed by drive slot, slot 0 is the install media
+    #[serde(default)]
+    pub iso_paths: BTreeMap<u32, PathBuf>,
+    /// Where the provider writes its temporary screenshot files, inside the build's working
+    /// directory; the system temp directory when unset
+    #[serde(skip)]
+    pub screenshot_dir: Option<PathBuf>,
+    /// Every state the instance went through and when, starting with `Created`
+    #[serde(skip)]
+    pub state_history: Vec<(VmState, Instant)>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -19,23 +32,440 @@ pub enum VmProvider {
     VirtualBox,
 }
 
+impl VmProvider {
+    /// Every provider isotope can drive, as listed by `isotope providers`
+    pub const ALL: [VmProvider; 1] = [VmProvider::VirtualBox];
+
+    /// Name of the provider in `VM provider=`
+    pub fn name(&self) -> &'static str {
+        match self {
+            VmProvider::VirtualBox => "virtualbox",
+        }
+    }
+}
+
 impl std::str::FromStr for VmProvider {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "virtualbox" => Ok(VmProvider::VirtualBox),
+        Self::ALL
+            .into_iter()
+            .find(|provider| provider.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let supported: Vec<&str> = Self::ALL.iter().map(VmProvider::name).collect();
+                anyhow::anyhow!(
+                    "Unsupported VM provider: {}. Supported providers: {} \
+                     (run `isotope providers` to see which are installed)",
+                    s,
+                    supported.join(", ")
+                )
+            })
+    }
+}
+
+impl Default for VmProvider {
+    fn default() -> Self {
+        VmProvider::VirtualBox
+    }
+}
+
+/// Storage controller the VM's system disk is attached to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum DiskController {
+    #[default]
+    Sata,
+    Nvme,
+    Scsi,
+    Virtio,
+}
+
+impl std::str::FromStr for DiskController {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sata" => Ok(DiskController::Sata),
+            "nvme" => Ok(DiskController::Nvme),
+            "scsi" => Ok(DiskController::Scsi),
+            "virtio" | "virtio-scsi" => Ok(DiskController::Virtio),
             _ => Err(anyhow::anyhow!(
-                "Unsupported VM provider: {}. Only VirtualBox is supported.",
+                "Unsupported disk controller: {}. Supported: sata, nvme, scsi, virtio",
                 s
             )),
         }
     }
 }
 
-impl Default for VmProvider {
+impl DiskController {
+    /// Legacy BIOS firmware can't boot from these controllers
+    pub fn requires_efi(&self) -> bool {
+        matches!(self, DiskController::Nvme | DiskController::Virtio)
+    }
+}
+
+/// Firmware the VM boots with, from `VM firmware=`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Firmware {
+    Bios,
+    Efi,
+}
+
+impl std::str::FromStr for Firmware {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bios" => Ok(Firmware::Bios),
+            "efi" | "uefi" => Ok(Firmware::Efi),
+            _ => Err(anyhow::anyhow!("Unsupported firmware: {}. Supported: bios, efi", s)),
+        }
+    }
+}
+
+impl Firmware {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Firmware::Bios => "bios",
+            Firmware::Efi => "efi",
+        }
+    }
+}
+
+/// Device the firmware may boot from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootDevice {
+    Dvd,
+    Disk,
+    Net,
+    Floppy,
+}
+
+impl BootDevice {
+    /// Name used by VBoxManage `--bootN`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootDevice::Dvd => "dvd",
+            BootDevice::Disk => "disk",
+            BootDevice::Net => "net",
+            BootDevice::Floppy => "floppy",
+        }
+    }
+}
+
+impl std::str::FromStr for BootDevice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "dvd" | "cdrom" => Ok(BootDevice::Dvd),
+            "disk" | "hdd" => Ok(BootDevice::Disk),
+            "net" | "pxe" => Ok(BootDevice::Net),
+            "floppy" => Ok(BootDevice::Floppy),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported boot device: {}. Supported: dvd, disk, net, floppy",
+                s
+            )),
+        }
+    }
+}
+
+/// Firmware boot order, highest priority first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootOrder(pub Vec<BootDevice>);
+
+impl BootOrder {
+    /// VirtualBox has four boot slots
+    pub const MAX_DEVICES: usize = 4;
+
+    /// Install media first, used when creating a VM around a reused disk
+    pub fn install() -> Self {
+        Self(vec![BootDevice::Dvd, BootDevice::Disk])
+    }
+
+    /// Installed OS first, so a reboot doesn't re-enter the installer; an empty disk falls
+    /// through to the install media
+    pub fn installed() -> Self {
+        Self(vec![BootDevice::Disk, BootDevice::Dvd])
+    }
+}
+
+impl std::str::FromStr for BootOrder {
+    type Err = anyhow::Error;
+
+    /// Parses a comma-separated device list, e.g. `disk,dvd,net`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let devices = s
+            .split(',')
+            .map(str::parse::<BootDevice>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if devices.is_empty() || devices.len() > Self::MAX_DEVICES {
+            return Err(anyhow::anyhow!(
+                "Invalid boot order: {}. Expected 1 to {} devices",
+                s,
+                Self::MAX_DEVICES
+            ));
+        }
+        for (index, device) in devices.iter().enumerate() {
+            if devices[..index].contains(device) {
+                return Err(anyhow::anyhow!(
+                    "Invalid boot order: {}. {} is listed twice",
+                    s,
+                    device.as_str()
+                ));
+            }
+        }
+
+        Ok(Self(devices))
+    }
+}
+
+impl std::fmt::Display for BootOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(BootDevice::as_str).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// Guest display mode requested so screenshots (and OCR regions) have a known size
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+impl Resolution {
+    const MIN: (u32, u32) = (640, 480);
+    const MAX: (u32, u32) = (3840, 2160);
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = anyhow::Error;
+
+    /// Parses `WIDTHxHEIGHT` or `WIDTHxHEIGHTxDEPTH`, e.g. `1280x800` or `1024x768x24`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(['x', 'X']).collect();
+        let numbers = parts
+            .iter()
+            .map(|part| part.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow::anyhow!("Invalid resolution: {}. Expected WxH or WxHxD", s))?;
+
+        let (width, height, depth) = match numbers.as_slice() {
+            [width, height] => (*width, *height, 32),
+            [width, height, depth] => (*width, *height, *depth),
+            _ => return Err(anyhow::anyhow!("Invalid resolution: {}. Expected WxH or WxHxD", s)),
+        };
+
+        if !(Self::MIN.0..=Self::MAX.0).contains(&width)
+            || !(Self::MIN.1..=Self::MAX.1).contains(&height)
+        {
+            return Err(anyhow::anyhow!(
+                "Resolution {}x{} out of range ({}x{} to {}x{})",
+                width,
+                height,
+                Self::MIN.0,
+                Self::MIN.1,
+                Self::MAX.0,
+                Self::MAX.1
+            ));
+        }
+        if ![16, 24, 32].contains(&depth) {
+            return Err(anyhow::anyhow!(
+                "Unsupported color depth: {}. Supported: 16, 24, 32",
+                depth
+            ));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            depth,
+        })
+    }
+}
+
+impl std::fmt::Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}x{}", self.width, self.height, self.depth)
+    }
+}
+
+/// Guest clock settings from the `rtc`, `rtc-offset` and `timesync` VM keys
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClockConfig {
+    /// Whether the emulated RTC keeps UTC (`rtc=utc`) or local time (`rtc=local`)
+    pub rtc_utc: bool,
+    /// Offset of the guest's BIOS clock from the host clock, in milliseconds
+    pub bios_time_offset_ms: i64,
+    /// Whether Guest Additions keep the guest clock in sync with the host
+    pub time_sync: bool,
+}
+
+impl ClockConfig {
+    /// Whether `key` is one of the VM keys `apply` understands
+    pub fn is_clock_key(key: &str) -> bool {
+        matches!(key, "rtc" | "rtc-offset" | "timesync")
+    }
+
+    /// Apply one clock VM key, e.g. `rtc=local`, `rtc-offset=-2h` or `timesync=off`
+    pub fn apply(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "rtc" => {
+                self.rtc_utc = match value.to_lowercase().as_str() {
+                    "utc" => true,
+                    "local" => false,
+                    _ => {
+                        return Err(anyhow::anyhow!("Invalid rtc: {}. Expected utc or local", value))
+                    }
+                }
+            }
+            "rtc-offset" => self.bios_time_offset_ms = Self::parse_offset(value)?,
+            "timesync" => {
+                self.time_sync = match value.to_lowercase().as_str() {
+                    "on" | "true" | "yes" => true,
+                    "off" | "false" | "no" => false,
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Invalid timesync: {}. Expected on or off",
+                            value
+                        ))
+                    }
+                }
+            }
+            _ => return Err(anyhow::anyhow!("Unknown clock setting: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Parses a signed offset such as `-90s`, `+2h`, `1d` or plain milliseconds
+    fn parse_offset(value: &str) -> Result<i64> {
+        let invalid = || {
+            anyhow::anyhow!(
+                "Invalid rtc-offset: {}. Expected a signed duration such as -2h, 30m or 1500ms",
+                value
+            )
+        };
+
+        let lower = value.trim().to_lowercase();
+        let (negative, magnitude) = match lower.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, lower.strip_prefix('+').unwrap_or(&lower)),
+        };
+
+        // Longer suffixes first so `ms` isn't read as minutes
+        let units = [("ms", 1), ("s", 1000), ("m", 60_000), ("h", 3_600_000), ("d", 86_400_000)];
+        let (number, unit_ms) = units
+            .into_iter()
+            .find_map(|(suffix, unit)| magnitude.strip_suffix(suffix).map(|n| (n, unit)))
+            .unwrap_or((magnitude, 1));
+
+        // Digits only, since parse would also take a second sign as in `--5s`
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let millis = number
+            .parse::<i64>()
+            .ok()
+            .and_then(|n| n.checked_mul(unit_ms))
+            .ok_or_else(invalid)?;
+        Ok(if negative { -millis } else { millis })
+    }
+}
+
+impl Default for ClockConfig {
     fn default() -> Self {
-        VmProvider::VirtualBox
+        Self {
+            rtc_utc: true,
+            bios_time_offset_ms: 0,
+            time_sync: true,
+        }
+    }
+}
+
+impl std::fmt::Display for ClockConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rtc {}, offset {}ms, timesync {}",
+            if self.rtc_utc { "utc" } else { "local" },
+            self.bios_time_offset_ms,
+            if self.time_sync { "on" } else { "off" }
+        )
+    }
+}
+
+/// Optional devices from the `audio`, `usb` and `clipboard` VM keys, all off by default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// VirtualBox audio driver, `none` for no audio device
+    pub audio: String,
+    /// USB controller: off, ohci (USB 1.1), ehci (USB 2.0) or xhci (USB 3.0)
+    pub usb: String,
+    /// Shared clipboard: disabled, hosttoguest, guesttohost or bidirectional
+    pub clipboard: String,
+}
+
+impl DeviceConfig {
+    const AUDIO_DRIVERS: [&'static str; 9] =
+        ["none", "null", "default", "pulse", "alsa", "oss", "dsound", "was", "coreaudio"];
+    const USB_CONTROLLERS: [&'static str; 4] = ["off", "ohci", "ehci", "xhci"];
+    const CLIPBOARD_MODES: [&'static str; 4] =
+        ["disabled", "hosttoguest", "guesttohost", "bidirectional"];
+
+    /// Whether `key` is one of the VM keys `apply` understands
+    pub fn is_device_key(key: &str) -> bool {
+        matches!(key, "audio" | "usb" | "clipboard")
+    }
+
+    /// Apply one device VM key, e.g. `audio=pulse`, `usb=xhci` or `clipboard=bidirectional`;
+    /// `off` disables audio and the clipboard, `on` picks the default driver or USB 1.1
+    pub fn apply(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = match (key, value.to_lowercase().as_str()) {
+            ("audio", "off") => "none".to_string(),
+            ("audio", "on") => "default".to_string(),
+            ("usb", "on") => "ohci".to_string(),
+            ("clipboard", "off") => "disabled".to_string(),
+            (_, value) => value.to_string(),
+        };
+        let (field, accepted): (&mut String, &[&str]) = match key {
+            "audio" => (&mut self.audio, &Self::AUDIO_DRIVERS),
+            "usb" => (&mut self.usb, &Self::USB_CONTROLLERS),
+            "clipboard" => (&mut self.clipboard, &Self::CLIPBOARD_MODES),
+            _ => return Err(anyhow::anyhow!("Unknown device setting: {}", key)),
+        };
+
+        if !accepted.contains(&value.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Invalid {}: {}. Expected one of: {}",
+                key,
+                value,
+                accepted.join(", ")
+            ));
+        }
+        *field = value;
+        Ok(())
+    }
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            audio: "none".to_string(),
+            usb: "off".to_string(),
+            clipboard: "disabled".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "audio {}, usb {}, clipboard {}",
+            self.audio, self.usb, self.clipboard
+        )
     }
 }
 
@@ -44,9 +474,45 @@ pub struct VmConfig {
     pub memory_mb: u64,
     pub cpus: u32,
     pub disk_size_gb: u64,
+    #[serde(default)]
+    pub disk_controller: DiskController,
+    /// `firmware`; None picks EFI for controllers BIOS can't boot from, BIOS otherwise
+    #[serde(default)]
+    pub firmware: Option<Firmware>,
     pub boot_wait: Duration,
+    /// WAIT condition from `boot-wait`, awaited after start instead of sleeping `boot_wait`
+    #[serde(default)]
+    pub boot_condition: Option<String>,
     pub timeout: Duration,
     pub additional_args: Vec<String>,
+    /// Arguments from VM_RAW passed verbatim to the provider after standard configuration
+    pub raw_args: Vec<String>,
+    /// Extra ISOs from ATTACH_ISO as (slot, path), attached alongside the install media
+    pub additional_isos: Vec<(u32, PathBuf)>,
+    /// Attach a leftover disk image instead of deleting it (set when continuing a build)
+    pub reuse_existing_disk: bool,
+    /// Display mode hinted to the guest after start; None keeps the provider default
+    #[serde(default)]
+    pub resolution: Option<Resolution>,
+    /// Guest OS from `os-type`, either a known alias or a raw provider ID
+    #[serde(default)]
+    pub os_type: Option<String>,
+    /// Explicit `boot-order`; None boots disk-first, which reaches the install media while the
+    /// disk is still empty
+    #[serde(default)]
+    pub boot_order: Option<BootOrder>,
+    /// RTC base, BIOS clock offset and Guest Additions time sync
+    #[serde(default)]
+    pub clock: ClockConfig,
+    /// Audio, USB and shared clipboard devices
+    #[serde(default)]
+    pub devices: DeviceConfig,
+    /// Serve the first serial port on a host TCP port so EXPECT can answer console prompts
+    #[serde(default)]
+    pub serial_console: bool,
+    /// `serial-port`: localhost port the console is shared on for live monitoring
+    #[serde(default)]
+    pub serial_share_port: Option<u16>,
     pub network_config: NetworkConfig,
 }
 
@@ -54,10 +520,34 @@ pub struct VmConfig {
 pub struct NetworkConfig {
     pub adapter_type: NetworkAdapterType,
     pub enable_ssh: bool,
+    /// Host port forwarded to the guest's sshd, picked when the VM is created
     pub ssh_port: u16,
+    /// `ssh-port`: port sshd listens on inside the guest
+    #[serde(default = "default_guest_ssh_port")]
+    pub guest_ssh_port: u16,
+    /// Adapters from NETWORK instructions; adapter 1 is always the NAT adapter used for SSH
+    #[serde(default)]
+    pub extra_adapters: Vec<NetworkAdapter>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_guest_ssh_port() -> u16 {
+    22
+}
+
+/// Highest network adapter index (VirtualBox supports eight)
+pub const MAX_NETWORK_ADAPTERS: u8 = 8;
+
+/// An additional network adapter declared with `NETWORK <index> <mode> [adapter]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkAdapter {
+    /// 2 to MAX_NETWORK_ADAPTERS
+    pub index: u8,
+    pub adapter_type: NetworkAdapterType,
+    /// Host interface for bridged/host-only, network name for internal
+    pub attachment: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum NetworkAdapterType {
     NAT,
     Bridged,
@@ -65,6 +555,30 @@ pub enum NetworkAdapterType {
     Internal,
 }
 
+impl std::str::FromStr for NetworkAdapterType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nat" => Ok(NetworkAdapterType::NAT),
+            "bridged" => Ok(NetworkAdapterType::Bridged),
+            "hostonly" | "host-only" => Ok(NetworkAdapterType::HostOnly),
+            "intnet" | "internal" => Ok(NetworkAdapterType::Internal),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported network mode: {}. Supported: nat, bridged, hostonly, intnet",
+                s
+            )),
+        }
+    }
+}
+
+impl NetworkAdapterType {
+    /// Bridged and host-only adapters must name the host interface they attach to
+    pub fn requires_attachment(&self) -> bool {
+        matches!(self, NetworkAdapterType::Bridged | NetworkAdapterType::HostOnly)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VmState {
     Created,
@@ -72,19 +586,52 @@ pub enum VmState {
     Running,
     Stopping,
     Stopped,
+    /// Paused in memory; resumes where it left off
     Suspended,
+    /// The VM process died without a shutdown, e.g. when the host crashed
+    Aborted,
     Error(String),
 }
 
+impl VmConfig {
+    /// Bounds for memory overrides given on the command line
+    pub const MIN_MEMORY_MB: u64 = 256;
+    pub const MAX_MEMORY_MB: u64 = 1024 * 1024;
+    /// VirtualBox allows at most 32 virtual CPUs
+    pub const MAX_CPUS: u32 = 32;
+
+    /// Firmware the VM boots with, filling in the default for the disk controller
+    pub fn firmware(&self) -> Firmware {
+        self.firmware.unwrap_or(if self.disk_controller.requires_efi() {
+            Firmware::Efi
+        } else {
+            Firmware::Bios
+        })
+    }
+}
+
 impl Default for VmConfig {
     fn default() -> Self {
         Self {
             memory_mb: 2048,
             cpus: 2,
             disk_size_gb: 20,
+            disk_controller: DiskController::default(),
+            firmware: None,
             boot_wait: Duration::from_secs(10),
+            boot_condition: None,
             timeout: Duration::from_secs(1800),
             additional_args: Vec::new(),
+            raw_args: Vec::new(),
+            additional_isos: Vec::new(),
+            reuse_existing_disk: false,
+            resolution: None,
+            os_type: None,
+            boot_order: None,
+            clock: ClockConfig::default(),
+            devices: DeviceConfig::default(),
+            serial_console: false,
+            serial_share_port: None,
             network_config: NetworkConfig::default(),
         }
     }
@@ -96,6 +643,8 @@ impl Default for NetworkConfig {
             adapter_type: NetworkAdapterType::NAT,
             enable_ssh: true,
             ssh_port: 22,
+            guest_ssh_port: default_guest_ssh_port(),
+            extra_adapters: Vec::new(),
         }
     }
 }
@@ -109,15 +658,20 @@ impl VmInstance {
             config,
             state: VmState::Created,
             disk_path: None,
-            iso_path: None,
+            iso_paths: BTreeMap::new(),
+            screenshot_dir: None,
+            state_history: vec![(VmState::Created, Instant::now())],
         }
     }
 
-    pub fn set_iso_path(&mut self, path: PathBuf) {
-        self.iso_path = Some(path);
+    pub fn set_iso_path(&mut self, slot: u32, path: PathBuf) {
+        self.iso_paths.insert(slot, path);
     }
 
     pub fn set_state(&mut self, state: VmState) {
+        if self.state != state {
+            self.state_history.push((state.clone(), Instant::now()));
+        }
         self.state = state;
     }
 
@@ -133,3 +687,29 @@ impl VmInstance {
         matches!(self.state, VmState::Error(_))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offset() {
+        let cases = [
+            ("1500", 1500),
+            ("1500ms", 1500),
+            ("-90s", -90_000),
+            ("+30m", 1_800_000),
+            ("2H", 7_200_000),
+            (" -1d ", -86_400_000),
+            ("0s", 0),
+        ];
+        for (value, millis) in cases {
+            assert_eq!(ClockConfig::parse_offset(value).unwrap(), millis, "{}", value);
+        }
+
+        let invalid = ["", "-", "s", "--5s", "+-5s", "5w", "1.5h", "ten", "99999999999999999d"];
+        for value in invalid {
+            assert!(ClockConfig::parse_offset(value).is_err(), "{}", value);
+        }
+    }
+}
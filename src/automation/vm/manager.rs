@@ -4,13 +4,22 @@ use crate::utils::vm_metadata::VmMetadata;
 use anyhow::{anyhow, Context, Result};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
-use tracing::{info, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
-use super::providers::{create_provider, VmProviderTrait};
-use super::{NetworkConfig, VmConfig, VmInstance, VmProvider};
-use crate::config::{Instruction, Stage};
+use super::providers::{create_provider, Capability, VmProviderTrait};
+use super::{
+    BootOrder, ClockConfig, DeviceConfig, DiskController, NetworkAdapter, NetworkConfig,
+    Resolution, VmConfig, VmInstance, VmProvider, VmState,
+};
+use crate::config::{parse_duration, Instruction, Stage};
+
+/// Name of the rolling snapshot taken before each step when step snapshots are enabled
+pub const PRE_STEP_SNAPSHOT: &str = "isotope-pre-step";
+
+/// Random VM names tried before giving up on finding one no registered VM has
+const VM_NAME_ATTEMPTS: usize = 5;
 
 pub struct VmManager {
     instances: HashMap<String, VmInstance>,
@@ -18,6 +27,12 @@ pub struct VmManager {
     working_dir: PathBuf,
     default_config: VmConfig,
     configured_provider: VmProvider,
+    /// Fixed name for new VMs instead of `isotope-vm-<uuid8>`
+    vm_name: Option<String>,
+    /// Temporary screenshot directory of the VMs this manager creates, removed on cleanup
+    screenshot_dir: Option<PathBuf>,
+    /// State transitions of removed VMs, kept for the build report
+    removed_state_history: Vec<(String, VmState, Instant)>,
 }
 
 impl VmManager {
@@ -28,32 +43,64 @@ impl VmManager {
             working_dir: std::env::temp_dir().join("isotope-vms"),
             default_config: VmConfig::default(),
             configured_provider: VmProvider::VirtualBox, // Only VirtualBox is supported
+            vm_name: None,
+            screenshot_dir: None,
+            removed_state_history: Vec::new(),
         }
     }
 
     pub fn configure_from_stage(&mut self, stage: &Stage) -> Result<()> {
         info!("Configuring VM from init stage");
 
-        let mut provider = VmProvider::VirtualBox; // Only VirtualBox is supported
+        let mut provider = VmProvider::VirtualBox;
         let mut memory_mb = 2048;
         let mut cpus = 2;
         let mut disk_size_gb = 20;
+        let mut disk_controller = DiskController::default();
+        let mut firmware = None;
         let mut boot_wait = Duration::from_secs(10);
+        let mut boot_condition = None;
+        let mut serial_share_port = None;
         let mut timeout = Duration::from_secs(1800);
         let mut additional_args = Vec::new();
+        let mut raw_args = Vec::new();
+        let mut additional_isos = Vec::new();
+        let mut resolution = None;
+        let mut boot_order = None;
+        let mut os_type = None;
+        let mut clock = ClockConfig::default();
+        let mut devices = DeviceConfig::default();
+        let mut network_config = NetworkConfig::default();
 
         for instruction in &stage.instructions {
+            if let Instruction::VmRaw { args } = instruction {
+                raw_args.extend(args.iter().cloned());
+                continue;
+            }
+
+            if let Instruction::Network {
+                index,
+                mode,
+                adapter,
+            } = instruction
+            {
+                network_config.extra_adapters.push(NetworkAdapter {
+                    index: *index,
+                    adapter_type: mode.parse()?,
+                    attachment: adapter.clone(),
+                });
+                continue;
+            }
+
+            if let Instruction::AttachIso { path, slot } = instruction {
+                additional_isos.push((*slot, path.clone()));
+                continue;
+            }
+
             if let Instruction::Vm { key, value } = instruction {
                 match key.as_str() {
                     "provider" => {
-                        // Only VirtualBox is supported now
-                        if value != "virtualbox" {
-                            return Err(anyhow!(
-                                "Unsupported VM provider: {}. Only VirtualBox is supported.",
-                                value
-                            ));
-                        }
-                        provider = VmProvider::VirtualBox;
+                        provider = value.parse()?;
                     }
                     "memory" => {
                         memory_mb = self.parse_memory_size(value)?;
@@ -66,11 +113,56 @@ impl VmManager {
                     "disk" => {
                         disk_size_gb = self.parse_disk_size(value)?;
                     }
+                    "disk-controller" => {
+                        disk_controller = value.parse()?;
+                    }
+                    "firmware" => {
+                        firmware = Some(value.parse()?);
+                    }
                     "boot-wait" => {
-                        boot_wait = self.parse_duration(value)?;
+                        // Anything but a duration is a WAIT condition, e.g. the installer menu
+                        let value = value.trim().trim_matches('"');
+                        match parse_duration(value) {
+                            Ok(duration) => boot_wait = duration,
+                            Err(_) => boot_condition = Some(value.to_string()),
+                        }
                     }
                     "timeout" => {
-                        timeout = self.parse_duration(value)?;
+                        timeout = parse_duration(value)?;
+                    }
+                    "resolution" => {
+                        resolution = Some(value.parse()?);
+                    }
+                    "boot-order" => {
+                        boot_order = Some(value.parse()?);
+                    }
+                    "os-type" => {
+                        os_type = Some(value.clone());
+                    }
+                    key if ClockConfig::is_clock_key(key) => {
+                        clock.apply(key, value)?;
+                    }
+                    key if DeviceConfig::is_device_key(key) => {
+                        devices.apply(key, value)?;
+                    }
+                    "slow" => {
+                        // Applied to the puppet manager by the builder
+                    }
+                    "serial-port" => {
+                        serial_share_port = Some(
+                            value
+                                .parse::<u16>()
+                                .ok()
+                                .filter(|port| *port != 0)
+                                .ok_or_else(|| anyhow!("Invalid serial-port: {}", value))?,
+                        );
+                    }
+                    "ssh-port" => {
+                        network_config.guest_ssh_port = value
+                            .parse::<u16>()
+                            .ok()
+                            .filter(|port| *port != 0)
+                            .ok_or_else(|| anyhow!("Invalid ssh-port: {}", value))?;
                     }
                     _ => {
                         additional_args.push(format!("--{}", key));
@@ -84,10 +176,23 @@ impl VmManager {
             memory_mb,
             cpus,
             disk_size_gb,
+            disk_controller,
+            firmware,
             boot_wait,
+            boot_condition,
             timeout,
             additional_args,
-            network_config: NetworkConfig::default(),
+            raw_args,
+            additional_isos,
+            reuse_existing_disk: self.default_config.reuse_existing_disk,
+            resolution,
+            os_type,
+            boot_order,
+            clock,
+            devices,
+            serial_console: self.default_config.serial_console,
+            serial_share_port,
+            network_config,
         };
 
         info!(
@@ -98,15 +203,130 @@ impl VmManager {
         Ok(())
     }
 
-    pub fn create_vm(&mut self) -> Result<VmInstance> {
-        let vm_id = Uuid::new_v4().to_string();
-        let vm_name = format!("isotope-vm-{}", &vm_id[..8]);
+    /// VM configuration new instances are created with
+    pub fn default_config(&self) -> &VmConfig {
+        &self.default_config
+    }
+
+    pub fn configured_provider(&self) -> VmProvider {
+        self.configured_provider
+    }
+
+    /// Give new VMs a fixed name so repeated builds reuse (or replace) the same VM
+    pub fn set_vm_name(&mut self, name: String) -> Result<()> {
+        // The name also becomes the disk file name
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(anyhow!(
+                "Invalid VM name: '{}'. Use letters, digits, '-', '_' and '.'",
+                name
+            ));
+        }
+
+        self.vm_name = Some(name);
+        Ok(())
+    }
+
+    pub fn vm_name(&self) -> Option<&str> {
+        self.vm_name.as_deref()
+    }
+
+    /// Remove a VM registered under `name`, if there is one
+    pub async fn force_remove_vm_named(&self, name: &str) -> Result<()> {
+        let provider = self.get_provider(&self.configured_provider)?;
+        if !provider.vm_exists(name).await? {
+            debug!("No registered VM named {} to remove", name);
+            return Ok(());
+        }
+
         let instance = VmInstance::new(
+            Uuid::new_v4().to_string(),
+            name.to_string(),
+            self.configured_provider,
+            self.default_config.clone(),
+        );
+        self.force_remove_vm(&instance).await
+    }
+
+    /// Override the guest memory, taking precedence over `VM memory=`
+    pub fn set_memory_mb(&mut self, memory_mb: u64) {
+        self.default_config.memory_mb = memory_mb;
+    }
+
+    /// Have new VMs write their temporary screenshots under `dir` instead of the temp directory
+    pub fn set_screenshot_dir(&mut self, dir: PathBuf) {
+        self.screenshot_dir = Some(dir);
+    }
+
+    /// Override the virtual CPU count, taking precedence over `VM cpus=`
+    pub fn set_cpus(&mut self, cpus: u32) {
+        self.default_config.cpus = cpus;
+    }
+
+    /// Override the guest display mode, taking precedence over `VM resolution=`
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.default_config.resolution = Some(resolution);
+    }
+
+    /// Route the first serial port to a host TCP port, needed by EXPECT
+    pub fn set_serial_console(&mut self, enabled: bool) {
+        self.default_config.serial_console = enabled;
+    }
+
+    /// Reuse leftover disk images from a previous run instead of deleting them
+    pub fn set_reuse_existing_disk(&mut self, reuse: bool) {
+        self.default_config.reuse_existing_disk = reuse;
+    }
+
+    /// Power off, unregister and delete a VM along with its disk, ignoring a VM that is already gone
+    pub async fn force_remove_vm(&self, instance: &VmInstance) -> Result<()> {
+        info!("Force removing VM {} and its disks", instance.name);
+
+        let provider = self.get_provider(&instance.provider)?;
+        let mut instance = instance.clone();
+
+        if provider.is_running(&instance).await.unwrap_or(false) {
+            instance.set_state(VmState::Running);
+            provider
+                .stop_vm(&mut instance)
+                .await
+                .with_context(|| format!("Failed to stop VM {}", instance.name))?;
+        }
+
+        if let Err(e) = provider.delete_vm(&mut instance).await {
+            warn!("Could not unregister VM {}: {}", instance.name, e);
+        }
+
+        let disk_path = PathBuf::from(format!("{}.vdi", instance.name));
+        if disk_path.exists() {
+            std::fs::remove_file(&disk_path).with_context(|| {
+                format!("Failed to remove VM disk: {}", disk_path.display())
+            })?;
+            info!("Removed leftover disk {}", disk_path.display());
+        }
+
+        Ok(())
+    }
+
+    /// Create a VM under a name no registered VM has, so the provider never adopts an
+    /// unrelated VM; a fixed `--vm-name` that is taken is an error
+    pub async fn create_vm(&mut self) -> Result<VmInstance> {
+        let provider = self.get_provider(&self.configured_provider)?;
+        let (vm_id, vm_name) = choose_vm_name(self.vm_name.as_deref(), |name| {
+            let provider = &provider;
+            async move { provider.vm_exists(&name).await }
+        })
+        .await?;
+        let mut instance = VmInstance::new(
             vm_id.clone(),
             vm_name,
             self.configured_provider,
             self.default_config.clone(),
         );
+        instance.screenshot_dir = self.screenshot_dir.clone();
         self.instances.insert(vm_id.clone(), instance.clone());
         
         // Clean up old VM metadata and VMs, then save new VM to .isometa
@@ -131,6 +351,83 @@ impl VmManager {
         Ok(instance)
     }
 
+    /// Throwaway VM booting from a copy of a packed image, kept out of the spec's VM metadata
+    pub async fn create_vm_from_image(&mut self, image_path: &Path) -> Result<VmInstance> {
+        if !image_path.exists() {
+            return Err(anyhow!("Image does not exist: {}", image_path.display()));
+        }
+
+        let vm_id = Uuid::new_v4().to_string();
+        let mut config = self.default_config.clone();
+        config.boot_order = Some(BootOrder::installed());
+        config.reuse_existing_disk = true;
+        config.additional_isos.clear();
+
+        let mut instance = VmInstance::new(
+            vm_id.clone(),
+            format!("isotope-verify-{}", &vm_id[..8]),
+            self.configured_provider,
+            config,
+        );
+        instance.screenshot_dir = self.screenshot_dir.clone();
+
+        // Boot a copy so the VM can't modify the artifact itself
+        let provider = self.get_provider(&instance.provider)?;
+        provider
+            .import_disk(&instance, image_path)
+            .await
+            .context("Failed to import image as VM disk")?;
+        provider
+            .create_vm(&mut instance)
+            .await
+            .context("Failed to create VM")?;
+
+        self.instances.insert(vm_id, instance.clone());
+        info!("Created VM {} from {}", instance.name, image_path.display());
+        Ok(instance)
+    }
+
+    /// Force remove a VM created by this manager and stop tracking it
+    pub async fn remove_vm(&mut self, instance: &VmInstance) -> Result<()> {
+        if let Some(removed) = self.instances.remove(&instance.id) {
+            self.retire_state_history(&removed);
+        }
+        self.force_remove_vm(instance).await
+    }
+
+    /// The tracked copy of `instance`, which has the state history of earlier operations
+    fn tracked(&self, instance: &VmInstance) -> VmInstance {
+        self.instances
+            .get(&instance.id)
+            .cloned()
+            .unwrap_or_else(|| instance.clone())
+    }
+
+    fn retire_state_history(&mut self, instance: &VmInstance) {
+        self.removed_state_history.extend(
+            instance
+                .state_history
+                .iter()
+                .map(|(state, at)| (instance.name.clone(), state.clone(), *at)),
+        );
+    }
+
+    /// State transitions of every VM this manager created, as (VM name, state, time) in
+    /// the order they happened
+    pub fn state_history(&self) -> Vec<(String, VmState, Instant)> {
+        let mut history = self.removed_state_history.clone();
+        for instance in self.instances.values() {
+            history.extend(
+                instance
+                    .state_history
+                    .iter()
+                    .map(|(state, at)| (instance.name.clone(), state.clone(), *at)),
+            );
+        }
+        history.sort_by_key(|(_, _, at)| *at);
+        history
+    }
+
     pub async fn attach_iso(&mut self, instance: &VmInstance, iso_path: &Path) -> Result<()> {
         info!(
             "Attaching ISO {} to VM {}",
@@ -144,19 +441,58 @@ impl VmManager {
 
         let provider = self.get_provider(&instance.provider)?;
 
-        let mut updated_instance = instance.clone();
-        provider.attach_iso(&mut updated_instance, iso_path).await?;
+        let mut updated_instance = self.tracked(instance);
+        provider.attach_iso(&mut updated_instance, iso_path, 0).await?;
+
+        // Driver/extra ISOs from ATTACH_ISO go into the remaining slots
+        for (slot, extra_iso) in instance.config.additional_isos.clone() {
+            if !extra_iso.exists() {
+                return Err(anyhow!("ISO file does not exist: {}", extra_iso.display()));
+            }
+            info!(
+                "Attaching additional ISO {} to slot {}",
+                extra_iso.display(),
+                slot
+            );
+            provider
+                .attach_iso(&mut updated_instance, &extra_iso, slot)
+                .await
+                .with_context(|| {
+                    format!("Failed to attach ISO {} to slot {}", extra_iso.display(), slot)
+                })?;
+        }
 
         self.instances.insert(instance.id.clone(), updated_instance);
         Ok(())
     }
 
+    /// Detach every ISO tracked on the instance, logging failures instead of aborting
+    async fn detach_all_isos(&self, instance: &mut VmInstance) {
+        let provider = match self.get_provider(&instance.provider) {
+            Ok(provider) => provider,
+            Err(e) => {
+                warn!("Failed to get provider for VM {}: {}", instance.name, e);
+                return;
+            }
+        };
+
+        let slots: Vec<u32> = instance.iso_paths.keys().copied().collect();
+        for slot in slots {
+            if let Err(e) = provider.detach_iso(instance, slot).await {
+                warn!(
+                    "Failed to detach ISO in slot {} from VM {}: {}",
+                    slot, instance.name, e
+                );
+            }
+        }
+    }
+
     pub async fn start_vm(&mut self, instance: &VmInstance) -> Result<()> {
         info!("Starting VM: {}", instance.name);
 
         let provider = self.get_provider(&instance.provider)?;
 
-        let mut updated_instance = instance.clone();
+        let mut updated_instance = self.tracked(instance);
 
         // Start the VM (it should already be created)
         provider
@@ -204,6 +540,27 @@ impl VmManager {
         }
     }
 
+    /// Poll the serial console log until it contains `pattern`
+    pub async fn wait_for_console_text(&self, instance: &VmInstance, pattern: &str) -> Result<()> {
+        info!("Waiting for console output '{}' on VM {}", pattern, instance.name);
+
+        let provider = self.get_provider(&instance.provider)?;
+        let pattern = pattern.to_lowercase();
+
+        loop {
+            match provider.get_console_output(instance).await {
+                Ok(output) if output.to_lowercase().contains(&pattern) => return Ok(()),
+                Ok(_) => debug!("Console output of {} has no '{}' yet", instance.name, pattern),
+                Err(e) => warn!("Failed to read console output of {}: {}", instance.name, e),
+            }
+
+            if !provider.is_running(instance).await? {
+                return Err(anyhow!("VM {} stopped before '{}' appeared", instance.name, pattern));
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+
     pub async fn wait_for_shutdown(&self, instance: &VmInstance) -> Result<()> {
         info!("Waiting for VM {} to shutdown", instance.name);
 
@@ -216,7 +573,7 @@ impl VmManager {
 
         let provider = self.get_provider(&instance.provider)?;
 
-        let mut updated_instance = instance.clone();
+        let mut updated_instance = self.tracked(instance);
         provider
             .stop_vm(&mut updated_instance)
             .await
@@ -238,6 +595,72 @@ impl VmManager {
         Ok(())
     }
 
+    /// Change the firmware boot order of a powered-off VM
+    pub async fn set_boot_order(&self, instance: &VmInstance, order: &BootOrder) -> Result<()> {
+        info!("Setting boot order of VM {} to {}", instance.name, order);
+
+        let provider = self.get_provider(&instance.provider)?;
+        provider.require(Capability::BootOrder)?;
+        provider
+            .set_boot_order(instance, order)
+            .await
+            .context("Failed to set boot order")
+    }
+
+    /// Replace the rolling snapshot taken before each puppet step
+    pub async fn take_pre_step_snapshot(&self, instance: &VmInstance) -> Result<()> {
+        let provider = self.get_provider(&instance.provider)?;
+        provider.require(Capability::LiveSnapshot)?;
+
+        // Only one pre-step snapshot is kept; it may not exist yet on the first step
+        if let Err(e) = provider.delete_snapshot(instance, PRE_STEP_SNAPSHOT).await {
+            debug!("No previous pre-step snapshot to delete: {}", e);
+        }
+
+        provider
+            .create_snapshot(instance, PRE_STEP_SNAPSHOT)
+            .await
+            .context("Failed to create pre-step snapshot")
+    }
+
+    /// Names of the snapshots the provider holds for the VM
+    pub async fn list_snapshots(&self, instance: &VmInstance) -> Result<Vec<String>> {
+        let provider = self.get_provider(&instance.provider)?;
+        provider
+            .list_snapshots(instance)
+            .await
+            .context("Failed to list snapshots")
+    }
+
+    /// Roll the VM back to the state captured before the last step started
+    pub async fn restore_pre_step_snapshot(&self, instance: &VmInstance) -> Result<()> {
+        info!("Restoring pre-step snapshot for VM: {}", instance.name);
+
+        self.get_provider(&instance.provider)?
+            .require(Capability::LiveSnapshot)?;
+        let snapshots = self.list_snapshots(instance).await?;
+        if !snapshots.iter().any(|name| name == PRE_STEP_SNAPSHOT) {
+            return Err(anyhow!(
+                "VM {} has no '{}' snapshot (existing snapshots: {})",
+                instance.name,
+                PRE_STEP_SNAPSHOT,
+                if snapshots.is_empty() { "none".to_string() } else { snapshots.join(", ") }
+            ));
+        }
+
+        let provider = self.get_provider(&instance.provider)?;
+        let mut instance = instance.clone();
+
+        if provider.is_running(&instance).await.unwrap_or(false) {
+            instance.set_state(VmState::Running);
+        }
+
+        provider
+            .restore_snapshot(&mut instance, PRE_STEP_SNAPSHOT)
+            .await
+            .context("Failed to restore pre-step snapshot")
+    }
+
     pub fn get_live_snapshot_path(&self) -> Result<PathBuf> {
         // Return path to the live snapshot that can be converted to ISO
         let snapshot_path = self.working_dir.join("live-snapshot.qcow2");
@@ -263,7 +686,7 @@ impl VmManager {
         }
     }
 
-    pub fn get_or_create_configured_vm(&mut self) -> Result<VmInstance> {
+    pub async fn get_or_create_configured_vm(&mut self) -> Result<VmInstance> {
         // Try to find an existing VM instance with the same configuration
         for instance in self.instances.values() {
             if instance.provider == self.configured_provider
@@ -277,7 +700,7 @@ impl VmManager {
 
         // If no existing VM found, create a new one
         info!("No compatible existing VM found, creating new instance");
-        self.create_vm()
+        self.create_vm().await
     }
 
     pub fn get_instance(&self, instance_id: &str) -> Option<&VmInstance> {
@@ -292,20 +715,31 @@ impl VmManager {
         for instance_id in instance_ids {
             if let Some(instance) = self.instances.get(&instance_id) {
                 let provider = self.get_provider(&instance.provider)?;
+                let mut instance = instance.clone();
 
                 if instance.is_running() {
-                    if let Err(e) = provider.stop_vm(&mut instance.clone()).await {
+                    if let Err(e) = provider.stop_vm(&mut instance).await {
                         warn!("Failed to stop VM {}: {}", instance.name, e);
                     }
                 }
 
-                if let Err(e) = provider.delete_vm(&mut instance.clone()).await {
+                self.detach_all_isos(&mut instance).await;
+
+                if let Err(e) = provider.delete_vm(&mut instance).await {
                     warn!("Failed to delete VM {}: {}", instance.name, e);
                 }
+                self.retire_state_history(&instance);
             }
         }
 
         self.instances.clear();
+
+        // Screenshots are deleted once read, this only catches ones a killed capture left
+        if let Some(dir) = self.screenshot_dir.as_ref().filter(|dir| dir.exists()) {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                warn!("Failed to remove screenshot directory {}: {}", dir.display(), e);
+            }
+        }
         Ok(())
     }
 
@@ -314,8 +748,24 @@ impl VmManager {
         provider.send_keys(instance, keys).await
     }
 
+    pub async fn copy_to_guest(
+        &self,
+        instance: &VmInstance,
+        from: &Path,
+        to: &Path,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        let provider = self.get_provider(&instance.provider)?;
+        provider.require(Capability::GuestCopy)?;
+        provider
+            .copy_to_guest(instance, from, to, username, password)
+            .await
+    }
+
     pub async fn capture_screen(&self, instance: &VmInstance) -> Result<image::DynamicImage> {
         let provider = self.get_provider(&instance.provider)?;
+        provider.require(Capability::Screenshot)?;
         provider.capture_screen(instance).await
     }
 
@@ -324,6 +774,20 @@ impl VmManager {
         provider.get_console_output(instance).await
     }
 
+    pub fn console_log_path(&self, instance: &VmInstance) -> Result<PathBuf> {
+        Ok(self.get_provider(&instance.provider)?.console_log_path(instance))
+    }
+
+    pub async fn serial_port(&self, instance: &VmInstance) -> Result<Option<u16>> {
+        let provider = self.get_provider(&instance.provider)?;
+        provider.serial_port(instance).await
+    }
+
+    /// Fail before a build relies on a feature the configured provider lacks
+    pub fn require_capability(&self, capability: Capability) -> Result<()> {
+        self.get_provider(&self.configured_provider)?.require(capability)
+    }
+
     pub fn get_provider(&self, provider_type: &VmProvider) -> Result<Box<dyn VmProviderTrait>> {
         Ok(create_provider(provider_type))
     }
@@ -367,23 +831,96 @@ impl VmManager {
             Err(anyhow!("Invalid disk size format: {}", size))
         }
     }
+}
 
-    fn parse_duration(&self, duration: &str) -> Result<Duration> {
-        let duration_lower = duration.to_lowercase();
-        if duration_lower.ends_with('s') {
-            let secs: u64 = duration_lower.trim_end_matches('s').parse()?;
-            Ok(Duration::from_secs(secs))
-        } else if duration_lower.ends_with('m') {
-            let mins: u64 = duration_lower.trim_end_matches('m').parse()?;
-            Ok(Duration::from_secs(mins * 60))
-        } else if duration_lower.ends_with('h') {
-            let hours: u64 = duration_lower.trim_end_matches('h').parse()?;
-            Ok(Duration::from_secs(hours * 3600))
-        } else if duration_lower.ends_with("ms") {
-            let millis: u64 = duration_lower.trim_end_matches("ms").parse()?;
-            Ok(Duration::from_millis(millis))
-        } else {
-            Err(anyhow!("Invalid duration format: {}", duration))
+/// (id, name) for a new VM: `fixed` unless `exists` reports it taken, otherwise the first
+/// random `isotope-vm-<id8>` that isn't
+async fn choose_vm_name<F, Fut>(fixed: Option<&str>, mut exists: F) -> Result<(String, String)>
+where
+    F: FnMut(String) -> Fut,
+    Fut: std::future::Future<Output = Result<bool>>,
+{
+    if let Some(name) = fixed {
+        if exists(name.to_string()).await? {
+            return Err(anyhow!(
+                "A VM named {} already exists. Use --force-recreate to replace it or --continue \
+                 to resume the build that created it",
+                name
+            ));
         }
+        return Ok((Uuid::new_v4().to_string(), name.to_string()));
+    }
+
+    for _ in 0..VM_NAME_ATTEMPTS {
+        let vm_id = Uuid::new_v4().to_string();
+        let vm_name = format!("isotope-vm-{}", &vm_id[..8]);
+        if !exists(vm_name.clone()).await? {
+            return Ok((vm_id, vm_name));
+        }
+        warn!("VM name {} is already taken, generating another", vm_name);
+    }
+    Err(anyhow!(
+        "Could not find an unused VM name after {} attempts",
+        VM_NAME_ATTEMPTS
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_choose_vm_name() {
+        let (_, name) = choose_vm_name(Some("my-dev-vm"), |_| async { Ok(false) })
+            .await
+            .unwrap();
+        assert_eq!(name, "my-dev-vm");
+
+        // A taken fixed name is never adopted
+        let taken = choose_vm_name(Some("my-dev-vm"), |_| async { Ok(true) }).await;
+        assert!(taken.unwrap_err().to_string().contains("--force-recreate"));
+
+        // A colliding random name is regenerated
+        let mut checked = Vec::new();
+        let (vm_id, name) = choose_vm_name(None, |name| {
+            checked.push(name);
+            let collides = checked.len() == 1;
+            async move { Ok(collides) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(checked.len(), 2);
+        assert_eq!(name, checked[1]);
+        assert_eq!(name, format!("isotope-vm-{}", &vm_id[..8]));
+
+        assert!(choose_vm_name(None, |_| async { Ok(true) }).await.is_err());
+    }
+
+    #[test]
+    fn test_boot_wait_duration_or_condition() {
+        let configure = |boot_wait: &str| {
+            let spec = crate::config::parser::parse_isotope_spec(&format!(
+                "FROM ./base.iso\nSTAGE init\nVM boot-wait={}\n",
+                boot_wait
+            ))
+            .unwrap();
+            let mut vm_manager = VmManager::new();
+            vm_manager
+                .configure_from_stage(spec.get_stage(&crate::config::StageType::Init).unwrap())
+                .unwrap();
+            vm_manager.default_config
+        };
+
+        let config = configure("500ms");
+        assert_eq!(config.boot_wait, Duration::from_millis(500));
+        assert_eq!(config.boot_condition, None);
+
+        let config = configure("2m");
+        assert_eq!(config.boot_wait, Duration::from_secs(120));
+        assert_eq!(config.boot_condition, None);
+
+        let config = configure("\"Install Ubuntu\"");
+        assert_eq!(config.boot_wait, Duration::from_secs(10));
+        assert_eq!(config.boot_condition.as_deref(), Some("Install Ubuntu"));
     }
 }
@@ -2,6 +2,10 @@ pub mod instance;
 pub mod manager;
 pub mod providers;
 
-pub use instance::{NetworkAdapterType, NetworkConfig, VmConfig, VmInstance, VmProvider, VmState};
+pub use instance::{
+    BootOrder, ClockConfig, DeviceConfig, DiskController, Firmware, NetworkAdapter,
+    NetworkAdapterType, NetworkConfig, Resolution, VmConfig, VmInstance, VmProvider, VmState,
+    MAX_ISO_SLOT, MAX_NETWORK_ADAPTERS,
+};
 pub use manager::VmManager;
-pub use providers::VmProviderTrait;
+pub use providers::{Capability, VmProviderTrait};
@@ -1,11 +1,75 @@
+mod vboxmanage;
 pub mod virtualbox;
 
-use anyhow::Result;
+pub use vboxmanage::VBoxManageCommand;
+
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use image::DynamicImage;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::automation::ocr::ScreenshotCapture;
+use crate::automation::vm::{BootOrder, VmInstance, VmState};
+
+/// Optional provider feature that builds check for before relying on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Snapshot a running VM and restore it later (pre-step snapshots, --continue)
+    LiveSnapshot,
+    /// Capture the VM display, needed by screen WAITs and the idle watchdog
+    Screenshot,
+    /// Serve the serial console to the host for EXPECT and serial boot checks
+    SerialConsole,
+    /// Copy files through a guest agent, for COPY --guest
+    GuestCopy,
+    /// Change the firmware boot order between stages
+    BootOrder,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::LiveSnapshot,
+        Capability::Screenshot,
+        Capability::SerialConsole,
+        Capability::GuestCopy,
+        Capability::BootOrder,
+    ];
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Capability::LiveSnapshot => "live snapshots",
+            Capability::Screenshot => "screenshots",
+            Capability::SerialConsole => "serial console access",
+            Capability::GuestCopy => "guest agent file copy",
+            Capability::BootOrder => "boot order changes",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a provider implements beyond the basic VM lifecycle; all off unless it says so
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    pub live_snapshot: bool,
+    pub screenshot: bool,
+    pub serial_console: bool,
+    pub guest_copy: bool,
+    pub boot_order: bool,
+}
 
-use crate::automation::vm::VmInstance;
+impl ProviderCapabilities {
+    pub fn supports(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::LiveSnapshot => self.live_snapshot,
+            Capability::Screenshot => self.screenshot,
+            Capability::SerialConsole => self.serial_console,
+            Capability::GuestCopy => self.guest_copy,
+            Capability::BootOrder => self.boot_order,
+        }
+    }
+}
 
 #[async_trait]
 pub trait VmProviderTrait: Send + Sync {
@@ -13,20 +77,101 @@ pub trait VmProviderTrait: Send + Sync {
     async fn start_vm(&self, instance: &mut VmInstance) -> Result<()>;
     async fn stop_vm(&self, instance: &mut VmInstance) -> Result<()>;
     async fn delete_vm(&self, instance: &mut VmInstance) -> Result<()>;
-    async fn attach_iso(&self, instance: &mut VmInstance, iso_path: &Path) -> Result<()>;
-    async fn detach_iso(&self, instance: &mut VmInstance) -> Result<()>;
+    /// Create the disk `create_vm` attaches from a packed image instead of an empty one
+    async fn import_disk(&self, instance: &VmInstance, image_path: &Path) -> Result<()>;
+    /// Attach an ISO to an optical drive slot (0 is the install media, up to MAX_ISO_SLOT)
+    async fn attach_iso(
+        &self,
+        instance: &mut VmInstance,
+        iso_path: &Path,
+        slot: u32,
+    ) -> Result<()>;
+    async fn detach_iso(&self, instance: &mut VmInstance, slot: u32) -> Result<()>;
     async fn create_snapshot(&self, instance: &VmInstance, snapshot_name: &str) -> Result<()>;
     async fn restore_snapshot(&self, instance: &mut VmInstance, snapshot_name: &str) -> Result<()>;
+    async fn delete_snapshot(&self, instance: &VmInstance, snapshot_name: &str) -> Result<()>;
+    /// Names of all snapshots of the VM, empty when it has none
+    async fn list_snapshots(&self, instance: &VmInstance) -> Result<Vec<String>>;
+    /// Set the firmware boot order; the VM must be powered off
+    async fn set_boot_order(&self, instance: &VmInstance, order: &BootOrder) -> Result<()>;
+    async fn vm_exists(&self, vm_name: &str) -> Result<bool>;
     async fn is_running(&self, instance: &VmInstance) -> Result<bool>;
+    /// Power state as the hypervisor reports it; only running vs stopped unless overridden
+    async fn vm_state(&self, instance: &VmInstance) -> Result<VmState> {
+        Ok(if self.is_running(instance).await? {
+            VmState::Running
+        } else {
+            VmState::Stopped
+        })
+    }
     async fn wait_for_shutdown(&self, instance: &VmInstance) -> Result<()>;
     async fn send_keys(&self, instance: &VmInstance, keys: &[String]) -> Result<()>;
     async fn capture_screen(&self, instance: &VmInstance) -> Result<DynamicImage>;
     async fn get_console_output(&self, instance: &VmInstance) -> Result<String>;
+    /// File the serial console output of `instance` is logged to
+    fn console_log_path(&self, instance: &VmInstance) -> PathBuf {
+        PathBuf::from(format!("{}-console.log", instance.name))
+    }
+    /// Host TCP port the first serial port is served on, when it is routed to one
+    async fn serial_port(&self, instance: &VmInstance) -> Result<Option<u16>> {
+        let _ = instance;
+        Ok(None)
+    }
+    /// Copy a host file into the guest through the provider's guest agent, bypassing SSH
+    async fn copy_to_guest(
+        &self,
+        instance: &VmInstance,
+        from: &Path,
+        to: &Path,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        let _ = (instance, from, to, username, password);
+        Err(anyhow!(
+            "Provider {} does not support guest agent file copy",
+            self.name()
+        ))
+    }
     fn name(&self) -> &'static str;
+    /// Version of the hypervisor tool the provider drives, an error when it isn't installed
+    fn tool_version(&self) -> Result<String>;
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+    /// Fail with a clear message when the provider lacks `capability`
+    fn require(&self, capability: Capability) -> Result<()> {
+        if self.capabilities().supports(capability) {
+            Ok(())
+        } else {
+            Err(anyhow!("Provider {} doesn't support {}", self.name(), capability))
+        }
+    }
     /// Returns (host, port) for SSH endpoint
     fn get_ssh_endpoint(&self, instance: &VmInstance) -> (String, u16);
 }
 
+/// Screenshot source for the OCR background monitor, capturing through the VM's provider
+pub struct ProviderScreenshotCapture {
+    provider: Box<dyn VmProviderTrait>,
+    instance: VmInstance,
+}
+
+impl ProviderScreenshotCapture {
+    pub fn new(instance: VmInstance) -> Self {
+        Self {
+            provider: create_provider(&instance.provider),
+            instance,
+        }
+    }
+}
+
+#[async_trait]
+impl ScreenshotCapture for ProviderScreenshotCapture {
+    async fn capture(&self) -> Result<DynamicImage> {
+        self.provider.capture_screen(&self.instance).await
+    }
+}
+
 pub fn create_provider(
     provider_type: &crate::automation::vm::VmProvider,
 ) -> Box<dyn VmProviderTrait> {
@@ -0,0 +1,172 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::process::{Command, Output};
+use std::time::Duration;
+use tracing::warn;
+
+/// Runs of a command whose failure looks transient, including the first one
+const ATTEMPTS: u32 = 3;
+/// Delay before the first retry; it grows with each further attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// stderr of failures that go away on their own, usually a session lock that is still held
+/// right after a state change, or a busy COM server on Windows
+const TRANSIENT_ERRORS: &[&str] = &[
+    "object is not ready",
+    "is already locked",
+    "vbox_e_invalid_object_state",
+    "rpc_e_servercall_retrylater",
+    "rpc_e_call_rejected",
+];
+
+/// A VBoxManage invocation that is re-run when it fails transiently, unless running it
+/// twice could have a different effect than running it once
+pub struct VBoxManageCommand {
+    command: Command,
+    args: Vec<OsString>,
+}
+
+impl VBoxManageCommand {
+    pub fn new() -> Self {
+        #[cfg(windows)]
+        let command = Command::new("VBoxManage.exe");
+        #[cfg(unix)]
+        let command = Command::new("VBoxManage");
+
+        Self {
+            command,
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_os_string());
+        self.command.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        for arg in args {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Run the command, retrying transient failures of idempotent commands
+    pub fn output(&mut self) -> io::Result<Output> {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        let retryable = is_idempotent(&args);
+
+        let mut attempt = 1;
+        loop {
+            let output = self.command.output()?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success()
+                || !retryable
+                || attempt == ATTEMPTS
+                || !is_transient(&stderr)
+            {
+                return Ok(output);
+            }
+
+            warn!(
+                "VBoxManage {} failed transiently (attempt {}/{}), retrying: {}",
+                args.first().map_or("", String::as_str),
+                attempt,
+                ATTEMPTS,
+                stderr.trim()
+            );
+            retry_sleep(RETRY_DELAY * attempt);
+            attempt += 1;
+        }
+    }
+}
+
+/// Wait before a retry. Most callers are async provider methods, so on a multi-threaded
+/// runtime the worker's other tasks are handed off instead of stalling behind the sleep.
+fn retry_sleep(delay: Duration) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| std::thread::sleep(delay))
+        }
+        _ => std::thread::sleep(delay),
+    }
+}
+
+impl Default for VBoxManageCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a failure with this stderr is worth retrying
+fn is_transient(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    TRANSIENT_ERRORS.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Whether re-running the command after a failure can't create, type or start anything twice
+fn is_idempotent(args: &[String]) -> bool {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    !matches!(
+        args[..],
+        ["createvm", ..]
+            | ["registervm", ..]
+            | ["createmedium", ..]
+            | ["clonemedium", ..]
+            | ["convertfromraw", ..]
+            | ["startvm", ..]
+            | ["guestcontrol", ..]
+            | ["snapshot", _, "take", ..]
+            | ["controlvm", _, "keyboardputscancode" | "keyboardputstring", ..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        assert!(is_transient(
+            "VBoxManage: error: The object is not ready\nVBoxManage: error: Details: code \
+             VBOX_E_INVALID_OBJECT_STATE (0x80bb0007)"
+        ));
+        assert!(is_transient(
+            "VBoxManage: error: The machine 'isotope-vm' is already locked for a session"
+        ));
+        assert!(!is_transient("VBoxManage: error: Could not find a registered machine"));
+
+        assert!(is_idempotent(&args("modifyvm isotope-vm --memory 2048")));
+        assert!(is_idempotent(&args("snapshot isotope-vm restore isotope-pre-step")));
+        assert!(is_idempotent(&args("controlvm isotope-vm poweroff")));
+        assert!(!is_idempotent(&args("createvm --name isotope-vm --register")));
+        assert!(!is_idempotent(&args("snapshot isotope-vm take isotope-pre-step")));
+        assert!(!is_idempotent(&args("controlvm isotope-vm keyboardputscancode 1c 9c")));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_retry_sleep_lets_other_tasks_run() {
+        // With a single worker, the ticker only finishes during the sleep if it was handed off
+        let ticker_finished = tokio::spawn(async {
+            let ticker = tokio::spawn(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            });
+            retry_sleep(Duration::from_millis(300));
+            ticker.is_finished()
+        });
+        assert!(ticker_finished.await.unwrap());
+    }
+}
@@ -1,17 +1,58 @@
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use image::DynamicImage;
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
 use std::time::Duration;
 use tokio::time::{sleep, timeout};
 use tracing::{debug, info, trace, warn};
 
-use super::VmProviderTrait;
+use super::vboxmanage::VBoxManageCommand;
+use super::{ProviderCapabilities, VmProviderTrait};
 use crate::automation::library_keyboard_input::LibraryBasedKeyboardMapper;
-use crate::automation::vm::{VmInstance, VmState};
+use crate::automation::ocr::screens_match;
+use crate::automation::vm::{
+    BootOrder, DeviceConfig, DiskController, NetworkAdapterType, Resolution, VmInstance, VmState,
+    MAX_NETWORK_ADAPTERS,
+};
 use crate::utils::net;
 
+/// Attempts per screenshot before giving up; the delay grows with each retry
+const SCREENSHOT_ATTEMPTS: u32 = 4;
+const SCREENSHOT_RETRY_DELAY: Duration = Duration::from_millis(200);
+/// Extra captures taken to find two matching frames before settling for the latest one
+const SCREEN_STABILITY_CHECKS: u32 = 3;
+const SCREEN_STABILITY_DELAY: Duration = Duration::from_millis(150);
+/// Fraction of pixels allowed to differ (e.g. a blinking cursor) for frames to match
+const SCREEN_STABILITY_TOLERANCE: f64 = 0.005;
+
+/// Storage controller holding the optical drives
+const IDE_CONTROLLER: &str = "IDE Controller";
+
+/// ostype used when the init stage has no `os-type`
+const DEFAULT_OS_TYPE: &str = "Linux_64";
+
+/// Friendly `os-type` names and the VirtualBox ostype IDs they stand for
+const OS_TYPE_ALIASES: &[(&str, &str)] = &[
+    ("linux", "Linux_64"),
+    ("ubuntu", "Ubuntu_64"),
+    ("debian", "Debian_64"),
+    ("fedora", "Fedora_64"),
+    ("rhel", "RedHat_64"),
+    ("centos", "RedHat_64"),
+    ("rocky", "RedHat_64"),
+    ("alma", "RedHat_64"),
+    ("opensuse", "OpenSUSE_64"),
+    ("arch", "ArchLinux_64"),
+    ("windows10", "Windows10_64"),
+    ("windows11", "Windows11_64"),
+    ("windows2019", "Windows2019_64"),
+    ("windows2022", "Windows2022_64"),
+    ("freebsd", "FreeBSD_64"),
+    ("openbsd", "OpenBSD_64"),
+    ("netbsd", "NetBSD_64"),
+];
+
 pub struct VirtualBoxProvider {
     keyboard_mapper: LibraryBasedKeyboardMapper,
 }
@@ -23,40 +64,228 @@ impl VirtualBoxProvider {
         }
     }
 
-    fn vboxmanage_cmd(&self) -> Command {
-        #[cfg(windows)]
-        {
-            Command::new("VBoxManage.exe")
+    fn vboxmanage_cmd(&self) -> VBoxManageCommand {
+        VBoxManageCommand::new()
+    }
+
+    /// VirtualBox ostype ID for an `os-type` value; unknown values are passed through as raw IDs
+    pub fn resolve_os_type(os_type: &str) -> &str {
+        OS_TYPE_ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(os_type))
+            .map_or(os_type, |(_, id)| id)
+    }
+
+    /// Check `os_type` against `VBoxManage list ostypes`, skipping the check if listing fails
+    fn check_os_type(&self, os_type: &str) -> Result<()> {
+        let output = match self.vboxmanage_cmd().args(["list", "ostypes"]).output() {
+            Ok(output) if output.status.success() => output,
+            _ => {
+                debug!("Could not list VirtualBox ostypes, not validating {}", os_type);
+                return Ok(());
+            }
+        };
+
+        let known = String::from_utf8_lossy(&output.stdout).lines().any(|line| {
+            line.strip_prefix("ID:")
+                .is_some_and(|id| id.trim().eq_ignore_ascii_case(os_type))
+        });
+        if !known {
+            return Err(anyhow!(
+                "Unknown VirtualBox ostype: {}. See `VBoxManage list ostypes` for valid IDs",
+                os_type
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Configure NETWORK adapters 2..N, switching off any that are no longer declared
+    fn configure_extra_adapters(&self, instance: &VmInstance) -> Result<()> {
+        let adapters = &instance.config.network_config.extra_adapters;
+        let mut args = vec!["modifyvm".to_string(), instance.name.clone()];
+
+        for index in 2..=MAX_NETWORK_ADAPTERS {
+            let Some(adapter) = adapters.iter().find(|adapter| adapter.index == index) else {
+                args.extend([format!("--nic{}", index), "none".to_string()]);
+                continue;
+            };
+
+            let (mode, attachment_flag) = match adapter.adapter_type {
+                NetworkAdapterType::NAT => ("nat", None),
+                NetworkAdapterType::Bridged => ("bridged", Some("--bridgeadapter")),
+                NetworkAdapterType::HostOnly => ("hostonly", Some("--hostonlyadapter")),
+                NetworkAdapterType::Internal => ("intnet", Some("--intnet")),
+            };
+            info!("Configuring network adapter {} of {} as {}", index, instance.name, mode);
+
+            args.extend([format!("--nic{}", index), mode.to_string()]);
+            if let (Some(flag), Some(attachment)) = (attachment_flag, &adapter.attachment) {
+                args.extend([format!("{}{}", flag, index), attachment.clone()]);
+            }
+            args.extend([format!("--cableconnected{}", index), "on".to_string()]);
         }
-        #[cfg(unix)]
-        {
-            Command::new("VBoxManage")
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(&args)
+            .output()
+            .context("Failed to configure network adapters")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to configure network adapters: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
         }
+
+        Ok(())
     }
 
-    async fn vm_exists(&self, vm_name: &str) -> Result<bool> {
+    /// IDE (port, device) for an optical drive slot; the disk controller holds the disk
+    fn ide_location(slot: u32) -> Result<(&'static str, &'static str)> {
+        // Slot 0 keeps the original install ISO location on the secondary master
+        match slot {
+            0 => Ok(("1", "0")),
+            1 => Ok(("1", "1")),
+            2 => Ok(("0", "0")),
+            3 => Ok(("0", "1")),
+            _ => Err(anyhow!(
+                "ISO slot {} is not available, VirtualBox supports slots 0-{}",
+                slot,
+                crate::automation::vm::MAX_ISO_SLOT
+            )),
+        }
+    }
+
+    /// Ask the guest to switch display mode; guests without video drivers may ignore it
+    fn set_video_mode_hint(&self, instance: &VmInstance, resolution: Resolution) {
+        info!("Setting guest resolution of {} to {}", instance.name, resolution);
+
         let output = self
             .vboxmanage_cmd()
-            .args(["list", "vms"])
+            .args([
+                "controlvm",
+                &instance.name,
+                "setvideomodehint",
+                &resolution.width.to_string(),
+                &resolution.height.to_string(),
+                &resolution.depth.to_string(),
+            ])
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => warn!(
+                "Failed to set guest resolution: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+            Err(e) => warn!("Failed to run setvideomodehint: {}", e),
+        }
+    }
+
+    /// Capture a screenshot, retrying with backoff while VBoxManage hands back a bad file
+    async fn capture_screenshot_with_retry(&self, instance: &VmInstance) -> Result<DynamicImage> {
+        let mut last_error = None;
+
+        for attempt in 1..=SCREENSHOT_ATTEMPTS {
+            match self.capture_screenshot(instance) {
+                Ok(image) => return Ok(image),
+                Err(e) => {
+                    debug!(
+                        "Screenshot attempt {}/{} failed: {:#}",
+                        attempt, SCREENSHOT_ATTEMPTS, e
+                    );
+                    last_error = Some(e);
+                    if attempt < SCREENSHOT_ATTEMPTS {
+                        sleep(SCREENSHOT_RETRY_DELAY * attempt).await;
+                    }
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow!("No screenshot attempts made"))
+            .context(format!(
+                "Failed to capture screenshot after {} attempts",
+                SCREENSHOT_ATTEMPTS
+            )))
+    }
+
+    fn capture_screenshot(&self, instance: &VmInstance) -> Result<DynamicImage> {
+        let screenshot_dir = instance.screenshot_dir.clone().unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&screenshot_dir).with_context(|| {
+            format!("Failed to create screenshot directory: {}", screenshot_dir.display())
+        })?;
+        // Unique per capture since the OCR monitor may capture alongside the puppet
+        let screenshot_path = screenshot_dir.join(format!(
+            "{}-screenshot-{}.png",
+            instance.name,
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        ));
+        trace!("Screenshot will be saved to: {}", screenshot_path.display());
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(["controlvm", &instance.name, "screenshotpng"])
+            .arg(&screenshot_path)
             .output()
-            .context("Failed to list VirtualBox VMs")?;
+            .context("Failed to capture screenshot")?;
+
+        trace!("VBoxManage screenshotpng exit code: {}", output.status);
+        if !output.stderr.is_empty() {
+            trace!(
+                "VBoxManage stderr: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
 
         if !output.status.success() {
             return Err(anyhow!(
-                "Failed to list VMs: {}",
+                "Failed to capture screenshot: {}",
                 String::from_utf8_lossy(&output.stderr)
             ));
         }
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        Ok(output_str.contains(&format!("\"{}\"", vm_name)))
+        let bytes = std::fs::read(&screenshot_path);
+        // Clean up the temporary file
+        let _ = std::fs::remove_file(&screenshot_path);
+        let bytes = bytes.with_context(|| {
+            format!("Screenshot file was not created: {}", screenshot_path.display())
+        })?;
+
+        if bytes.is_empty() {
+            return Err(anyhow!("Screenshot file is empty (0 bytes)"));
+        }
+
+        let image =
+            image::load_from_memory(&bytes).context("Failed to decode screenshot image")?;
+        trace!(
+            "Screenshot loaded: {}x{} pixels, format: {:?}",
+            image.width(),
+            image.height(),
+            image.color()
+        );
+
+        Ok(image)
+    }
+
+    /// storagectl name, bus and controller type for the system disk
+    fn disk_controller_args(
+        controller: DiskController,
+    ) -> (&'static str, &'static str, &'static str) {
+        match controller {
+            DiskController::Sata => ("SATA Controller", "sata", "IntelAHCI"),
+            DiskController::Nvme => ("NVMe Controller", "pcie", "NVMe"),
+            DiskController::Scsi => ("SCSI Controller", "scsi", "LSILogic"),
+            DiskController::Virtio => ("VirtIO Controller", "virtio", "VirtIO"),
+        }
     }
 }
 
 #[async_trait]
 impl VmProviderTrait for VirtualBoxProvider {
     fn get_ssh_endpoint(&self, instance: &VmInstance) -> (String, u16) {
-        // For VirtualBox, we use port forwarding which maps localhost:HOST_PORT -> VM:22
+        // For VirtualBox, we use port forwarding which maps localhost:HOST_PORT -> VM:ssh-port
         // Always query VirtualBox directly to get the actual forwarded port
         match tokio::task::block_in_place(|| {
             tokio::runtime::Handle::current().block_on(async {
@@ -87,10 +316,31 @@ impl VmProviderTrait for VirtualBoxProvider {
                 instance.name
             );
             
+            let guest_port = instance.config.network_config.guest_ssh_port;
             // Check if SSH port forwarding exists
-            if let Some(actual_port) = self.get_ssh_port_from_vbox(&instance.name).await? {
+            if let Some((actual_port, forwarded_guest_port)) =
+                self.get_ssh_forwarding_from_vbox(&instance.name).await?
+            {
                 instance.config.network_config.ssh_port = actual_port;
                 info!("Found existing SSH port forwarding: {}", actual_port);
+                if forwarded_guest_port != guest_port {
+                    info!(
+                        "Moving SSH port forwarding from guest port {} to {}",
+                        forwarded_guest_port, guest_port
+                    );
+                    let output = self
+                        .vboxmanage_cmd()
+                        .args(["modifyvm", &instance.name, "--natpf1", "delete", "ssh"])
+                        .args(["--natpf1", &ssh_forwarding_rule(actual_port, guest_port)])
+                        .output()
+                        .context("Failed to set up port forwarding for SSH")?;
+                    if !output.status.success() {
+                        return Err(anyhow!(
+                            "Failed to set up port forwarding: {}",
+                            String::from_utf8_lossy(&output.stderr)
+                        ));
+                    }
+                }
             } else {
                 // No port forwarding exists, find a free port that's not used by other VMs
                 let used_ports = self.get_all_used_ssh_ports().await.unwrap_or_default();
@@ -112,7 +362,7 @@ impl VmProviderTrait for VirtualBoxProvider {
                         "modifyvm",
                         &instance.name,
                         "--natpf1",
-                        &format!("ssh,tcp,,{},,22", ssh_host_port),
+                        &ssh_forwarding_rule(ssh_host_port, guest_port),
                     ])
                     .output()
                     .context("Failed to set up port forwarding for SSH")?;
@@ -127,10 +377,25 @@ impl VmProviderTrait for VirtualBoxProvider {
                 }
             }
             
+            // A running VM can't be reconfigured; it keeps the adapters it was created with
+            if let Err(e) = self.configure_extra_adapters(instance) {
+                warn!("Could not update network adapters of {}: {}", instance.name, e);
+            }
+
             instance.set_state(VmState::Stopped);
             return Ok(());
         }
 
+        let os_type = match &instance.config.os_type {
+            Some(os_type) => {
+                let os_type = Self::resolve_os_type(os_type);
+                self.check_os_type(os_type)?;
+                os_type
+            }
+            None => DEFAULT_OS_TYPE,
+        };
+        info!("Using VirtualBox ostype {}", os_type);
+
         // Create VM
         let output = self
             .vboxmanage_cmd()
@@ -139,7 +404,7 @@ impl VmProviderTrait for VirtualBoxProvider {
                 "--name",
                 &instance.name,
                 "--ostype",
-                "Linux_64", // Default, could be configurable
+                os_type,
                 "--register",
             ])
             .output()
@@ -153,16 +418,24 @@ impl VmProviderTrait for VirtualBoxProvider {
         }
 
         // Configure VM settings
-        let configs = [
+        let clock = instance.config.clock;
+        let mut configs = vec![
             ("--memory", instance.config.memory_mb.to_string()),
             ("--cpus", instance.config.cpus.to_string()),
             ("--vram", "128".to_string()),
-            ("--boot1", "dvd".to_string()),
-            ("--boot2", "disk".to_string()),
             ("--acpi", "on".to_string()),
             ("--ioapic", "on".to_string()),
-            ("--rtcuseutc", "on".to_string()),
+            ("--rtcuseutc", if clock.rtc_utc { "on" } else { "off" }.to_string()),
         ];
+        if clock.bios_time_offset_ms != 0 {
+            configs.push(("--biossystemtimeoffset", clock.bios_time_offset_ms.to_string()));
+        }
+        configs.extend(device_args(&instance.config.devices));
+
+        // BIOS can't boot from NVMe/virtio disks, so those VMs default to EFI
+        let firmware = instance.config.firmware();
+        info!("Using {} firmware", firmware.name().to_uppercase());
+        configs.push(("--firmware", firmware.name().to_string()));
 
         for (key, value) in &configs {
             let output = self
@@ -180,6 +453,38 @@ impl VmProviderTrait for VirtualBoxProvider {
             }
         }
 
+        if !clock.time_sync {
+            info!("Disabling Guest Additions time sync for {}", instance.name);
+            let output = self
+                .vboxmanage_cmd()
+                .args([
+                    "setextradata",
+                    &instance.name,
+                    "VBoxInternal/Devices/VMMDev/0/Config/GetHostTimeDisabled",
+                    "1",
+                ])
+                .output()
+                .context("Failed to disable guest time sync")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to disable guest time sync: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
+
+        // A fresh disk has nothing to boot, so the firmware falls through to the install media
+        // and the installer's own reboot comes up from the installed OS instead of the DVD. A
+        // reused disk may hold a half-finished install, which must not take over.
+        let boot_order = instance.config.boot_order.clone().unwrap_or_else(|| {
+            if instance.config.reuse_existing_disk {
+                BootOrder::install()
+            } else {
+                BootOrder::installed()
+            }
+        });
+        self.set_boot_order(instance, &boot_order).await?;
+
         // Configure network adapter (NAT with port forwarding for SSH)
         let output = self
             .vboxmanage_cmd()
@@ -202,6 +507,8 @@ impl VmProviderTrait for VirtualBoxProvider {
             ));
         }
 
+        self.configure_extra_adapters(instance)?;
+
         // Find a random unoccupied port for SSH forwarding that's not used by other VMs
         let used_ports = self.get_all_used_ssh_ports().await.unwrap_or_default();
         if !used_ports.is_empty() {
@@ -211,17 +518,38 @@ impl VmProviderTrait for VirtualBoxProvider {
             .ok_or_else(|| anyhow!("No free port found for SSH forwarding"))?;
         
         info!("Selected SSH port {} for new VM {}", ssh_host_port, instance.name);
+
+        if instance.config.serial_console {
+            let mut excluded_ports = used_ports.clone();
+            excluded_ports.insert(ssh_host_port);
+            let serial_port = net::find_free_port_with_exclusions(&excluded_ports)
+                .ok_or_else(|| anyhow!("No free port found for the serial console"))?;
+            info!("Serving serial console of {} on port {}", instance.name, serial_port);
+
+            let output = self
+                .vboxmanage_cmd()
+                .args(["modifyvm", &instance.name, "--uart1", "0x3F8", "4"])
+                .args(["--uartmode1", "tcpserver", &serial_port.to_string()])
+                .output()
+                .context("Failed to configure serial console")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to configure serial console: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
         // Store the port in the VM config for later use
         instance.config.network_config.ssh_port = ssh_host_port;
 
-        // Set up port forwarding for SSH (host port to guest 22)
+        // Set up port forwarding for SSH (host port to the guest's ssh-port)
         let output = self
             .vboxmanage_cmd()
             .args([
                 "modifyvm",
                 &instance.name,
                 "--natpf1",
-                &format!("ssh,tcp,,{},,22", ssh_host_port),
+                &ssh_forwarding_rule(ssh_host_port, instance.config.network_config.guest_ssh_port),
             ])
             .output()
             .context("Failed to set up port forwarding for SSH")?;
@@ -235,47 +563,65 @@ impl VmProviderTrait for VirtualBoxProvider {
         // Create and attach disk
         let disk_path = format!("{}.vdi", instance.name);
 
-        let output = self
-            .vboxmanage_cmd()
-            .args([
-                "createmedium",
-                "disk",
-                "--filename",
-                &disk_path,
-                "--size",
-                &(instance.config.disk_size_gb * 1024).to_string(), // Convert to MB
-                "--format",
-                "VDI",
-            ])
-            .output()
-            .context("Failed to create VM disk")?;
+        // A disk left behind by a crashed run would make createmedium fail
+        let reuse_disk = Path::new(&disk_path).exists() && instance.config.reuse_existing_disk;
+        if reuse_disk {
+            info!("Reusing existing disk {} for VM {}", disk_path, instance.name);
+        } else {
+            if Path::new(&disk_path).exists() {
+                warn!(
+                    "Found leftover disk {} from a previous run, deleting it before creating a fresh one",
+                    disk_path
+                );
+                self.remove_stale_medium(&disk_path)?;
+            }
 
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to create VirtualBox disk: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+            let output = self
+                .vboxmanage_cmd()
+                .args([
+                    "createmedium",
+                    "disk",
+                    "--filename",
+                    &disk_path,
+                    "--size",
+                    &(instance.config.disk_size_gb * 1024).to_string(), // Convert to MB
+                    "--format",
+                    "VDI",
+                ])
+                .output()
+                .context("Failed to create VM disk")?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to create VirtualBox disk: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
         }
 
         // Attach disk to VM
+        let (controller_name, bus, controller_type) =
+            Self::disk_controller_args(instance.config.disk_controller);
+        info!("Attaching disk via {} ({})", controller_name, controller_type);
         let output = self
             .vboxmanage_cmd()
             .args([
                 "storagectl",
                 &instance.name,
                 "--name",
-                "SATA Controller",
+                controller_name,
                 "--add",
-                "sata",
+                bus,
                 "--controller",
-                "IntelAHCI",
+                controller_type,
             ])
             .output()
-            .context("Failed to add SATA controller")?;
+            .with_context(|| format!("Failed to add {}", controller_name))?;
 
         if !output.status.success() {
             return Err(anyhow!(
-                "Failed to add SATA controller: {}",
+                "Failed to add {}: {}",
+                controller_name,
                 String::from_utf8_lossy(&output.stderr)
             ));
         }
@@ -286,7 +632,7 @@ impl VmProviderTrait for VirtualBoxProvider {
                 "storageattach",
                 &instance.name,
                 "--storagectl",
-                "SATA Controller",
+                controller_name,
                 "--port",
                 "0",
                 "--device",
@@ -306,6 +652,29 @@ impl VmProviderTrait for VirtualBoxProvider {
             ));
         }
 
+        // Apply VM_RAW arguments last so they can override the defaults above
+        if !instance.config.raw_args.is_empty() {
+            warn!(
+                "Applying unvalidated VM_RAW arguments to {}: {:?}",
+                instance.name, instance.config.raw_args
+            );
+            let output = self
+                .vboxmanage_cmd()
+                .args(["modifyvm", &instance.name])
+                .args(&instance.config.raw_args)
+                .output()
+                .context("Failed to apply VM_RAW arguments")?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to apply VM_RAW arguments {:?}: {}",
+                    instance.config.raw_args,
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+            info!("Applied VM_RAW arguments to {}", instance.name);
+        }
+
         instance.set_state(VmState::Stopped);
         Ok(())
     }
@@ -322,6 +691,23 @@ impl VmProviderTrait for VirtualBoxProvider {
             self.create_vm(instance).await?;
         }
 
+        match self.vm_state(instance).await? {
+            VmState::Running => {
+                instance.set_state(VmState::Running);
+                return Ok(());
+            }
+            VmState::Suspended => return self.resume_vm(instance).await,
+            VmState::Aborted => {
+                return Err(anyhow!(
+                    "VM {0} is aborted: its process died without shutting down, e.g. in a host \
+                     crash, so its disk may be inconsistent. Rebuild with --force-recreate, or \
+                     boot it as is with `VBoxManage startvm {0}` and rerun",
+                    instance.name
+                ));
+            }
+            _ => {}
+        }
+
         instance.set_state(VmState::Starting);
 
         let output = self
@@ -341,6 +727,10 @@ impl VmProviderTrait for VirtualBoxProvider {
 
         if self.is_running(instance).await? {
             instance.set_state(VmState::Running);
+
+            if let Some(resolution) = instance.config.resolution {
+                self.set_video_mode_hint(instance, resolution);
+            }
         } else {
             instance.set_state(VmState::Error("VM failed to start".to_string()));
             return Err(anyhow!("VM failed to start properly"));
@@ -419,8 +809,69 @@ impl VmProviderTrait for VirtualBoxProvider {
         Ok(())
     }
 
-    async fn attach_iso(&self, instance: &mut VmInstance, iso_path: &Path) -> Result<()> {
-        info!("Attaching ISO to VirtualBox VM: {}", iso_path.display());
+    async fn import_disk(&self, instance: &VmInstance, image_path: &Path) -> Result<()> {
+        let disk_path = format!("{}.vdi", instance.name);
+        info!("Importing {} as disk {}", image_path.display(), disk_path);
+
+        if Path::new(&disk_path).exists() {
+            self.remove_stale_medium(&disk_path)?;
+        }
+
+        // Raw IMGs have no header VirtualBox can open as a medium, so they need convertfromraw
+        let image = image_path.to_string_lossy();
+        let mut cmd = self.vboxmanage_cmd();
+        let extension = image_path.extension().and_then(|ext| ext.to_str());
+        let cloned = matches!(extension, Some("vmdk" | "vhd" | "vhdx"));
+        if cloned {
+            cmd.args(["clonemedium", "disk", &image, &disk_path]);
+        } else {
+            cmd.args(["convertfromraw", &image, &disk_path]);
+        }
+        let output = cmd
+            .args(["--format", "VDI"])
+            .output()
+            .context("Failed to execute VBoxManage to import disk image")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to import disk image {}: {}",
+                image_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        // clonemedium registers the source too, which would clash with a rebuilt image at
+        // the same path
+        if cloned {
+            let output = self
+                .vboxmanage_cmd()
+                .args(["closemedium", "disk", &image])
+                .output()
+                .context("Failed to execute VBoxManage closemedium")?;
+            if !output.status.success() {
+                warn!(
+                    "Failed to unregister {} from VirtualBox: {}",
+                    image_path.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn attach_iso(
+        &self,
+        instance: &mut VmInstance,
+        iso_path: &Path,
+        slot: u32,
+    ) -> Result<()> {
+        info!(
+            "Attaching ISO to VirtualBox VM (slot {}): {}",
+            slot,
+            iso_path.display()
+        );
+        let (port, device) = Self::ide_location(slot)?;
 
         if !iso_path.exists() {
             return Err(anyhow!("ISO file does not exist: {}", iso_path.display()));
@@ -431,18 +882,37 @@ impl VmProviderTrait for VirtualBoxProvider {
             self.create_vm(instance).await?;
         }
 
-        // Create IDE controller if it doesn't exist
-        let _ = self
+        let output = self
             .vboxmanage_cmd()
-            .args([
-                "storagectl",
-                &instance.name,
-                "--name",
-                "IDE Controller",
-                "--add",
-                "ide",
-            ])
-            .output();
+            .args(["showvminfo", &instance.name, "--machinereadable"])
+            .output()
+            .context("Failed to get VM info")?;
+        let info = String::from_utf8_lossy(&output.stdout);
+
+        // A resumed build finds the controller and possibly the ISO already there
+        if !has_storage_controller(&info, IDE_CONTROLLER) {
+            let output = self
+                .vboxmanage_cmd()
+                .args(["storagectl", &instance.name, "--name", IDE_CONTROLLER, "--add", "ide"])
+                .output()
+                .context("Failed to add IDE controller")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "Failed to add IDE controller: {}",
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
+        if let Some(attached) = attached_medium(&info, IDE_CONTROLLER, port, device) {
+            let wanted = iso_path.canonicalize().unwrap_or_else(|_| iso_path.to_path_buf());
+            if Path::new(attached) == wanted {
+                debug!("ISO {} is already attached in slot {}", attached, slot);
+                instance.set_iso_path(slot, iso_path.to_path_buf());
+                return Ok(());
+            }
+            info!("Detaching stale ISO {} from slot {}", attached, slot);
+            self.detach_iso(instance, slot).await?;
+        }
 
         // Attach ISO
         let output = self
@@ -451,11 +921,11 @@ impl VmProviderTrait for VirtualBoxProvider {
                 "storageattach",
                 &instance.name,
                 "--storagectl",
-                "IDE Controller",
+                IDE_CONTROLLER,
                 "--port",
-                "1",
+                port,
                 "--device",
-                "0",
+                device,
                 "--type",
                 "dvddrive",
                 "--medium",
@@ -471,12 +941,13 @@ impl VmProviderTrait for VirtualBoxProvider {
             ));
         }
 
-        instance.set_iso_path(iso_path.to_path_buf());
+        instance.set_iso_path(slot, iso_path.to_path_buf());
         Ok(())
     }
 
-    async fn detach_iso(&self, instance: &mut VmInstance) -> Result<()> {
-        info!("Detaching ISO from VirtualBox VM");
+    async fn detach_iso(&self, instance: &mut VmInstance, slot: u32) -> Result<()> {
+        info!("Detaching ISO from VirtualBox VM (slot {})", slot);
+        let (port, device) = Self::ide_location(slot)?;
 
         let output = self
             .vboxmanage_cmd()
@@ -484,11 +955,11 @@ impl VmProviderTrait for VirtualBoxProvider {
                 "storageattach",
                 &instance.name,
                 "--storagectl",
-                "IDE Controller",
+                IDE_CONTROLLER,
                 "--port",
-                "1",
+                port,
                 "--device",
-                "0",
+                device,
                 "--medium",
                 "none",
             ])
@@ -502,7 +973,7 @@ impl VmProviderTrait for VirtualBoxProvider {
             ));
         }
 
-        instance.iso_path = None;
+        instance.iso_paths.remove(&slot);
         Ok(())
     }
 
@@ -556,19 +1027,108 @@ impl VmProviderTrait for VirtualBoxProvider {
         Ok(())
     }
 
+    async fn delete_snapshot(&self, instance: &VmInstance, snapshot_name: &str) -> Result<()> {
+        info!("Deleting VirtualBox snapshot: {}", snapshot_name);
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(["snapshot", &instance.name, "delete", snapshot_name])
+            .output()
+            .context("Failed to delete snapshot")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to delete snapshot: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn list_snapshots(&self, instance: &VmInstance) -> Result<Vec<String>> {
+        let output = self
+            .vboxmanage_cmd()
+            .args(["snapshot", &instance.name, "list", "--machinereadable"])
+            .output()
+            .context("Failed to list snapshots")?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            // VBoxManage exits non-zero for a VM without snapshots
+            if stdout.contains("does not have any snapshots") {
+                return Ok(Vec::new());
+            }
+            return Err(anyhow!(
+                "Failed to list snapshots: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(parse_snapshot_names(&stdout))
+    }
+
+    async fn vm_exists(&self, vm_name: &str) -> Result<bool> {
+        let output = self
+            .vboxmanage_cmd()
+            .args(["list", "vms"])
+            .output()
+            .context("Failed to list VirtualBox VMs")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to list VMs: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        Ok(output_str.contains(&format!("\"{}\"", vm_name)))
+    }
+
+    async fn set_boot_order(&self, instance: &VmInstance, order: &BootOrder) -> Result<()> {
+        let mut args = vec!["modifyvm".to_string(), instance.name.clone()];
+        // Clear the remaining slots so devices from an earlier order don't linger
+        for slot in 0..BootOrder::MAX_DEVICES {
+            let device = order.0.get(slot).map_or("none", |device| device.as_str());
+            args.push(format!("--boot{}", slot + 1));
+            args.push(device.to_string());
+        }
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(&args)
+            .output()
+            .context("Failed to execute VBoxManage modifyvm")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to set boot order: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(())
+    }
+
     async fn is_running(&self, instance: &VmInstance) -> Result<bool> {
+        Ok(self.vm_state(instance).await? == VmState::Running)
+    }
+
+    async fn vm_state(&self, instance: &VmInstance) -> Result<VmState> {
         let output = self
             .vboxmanage_cmd()
             .args(["showvminfo", &instance.name, "--machinereadable"])
             .output()
             .context("Failed to get VM info")?;
 
+        // Not registered (yet)
         if !output.status.success() {
-            return Ok(false);
+            return Ok(VmState::Created);
         }
 
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        Ok(output_str.contains("VMState=\"running\""))
+        let info = String::from_utf8_lossy(&output.stdout);
+        parse_vm_state(&info).ok_or_else(|| anyhow!("No VMState in VM info of {}", instance.name))
     }
 
     async fn wait_for_shutdown(&self, instance: &VmInstance) -> Result<()> {
@@ -614,72 +1174,24 @@ impl VmProviderTrait for VirtualBoxProvider {
         trace!("=== VBOX SCREEN CAPTURE START ===");
         trace!("Capturing screen from VirtualBox VM: {}", instance.name);
 
-        let screenshot_path = format!("{}-screenshot.png", instance.name);
-        trace!("Screenshot will be saved to: {}", screenshot_path);
-
-        let output = self
-            .vboxmanage_cmd()
-            .args([
-                "controlvm",
-                &instance.name,
-                "screenshotpng",
-                &screenshot_path,
-            ])
-            .output()
-            .context("Failed to capture screenshot")?;
-
-        trace!("VBoxManage screenshotpng exit code: {}", output.status);
-        if !output.stdout.is_empty() {
-            trace!(
-                "VBoxManage stdout: {}",
-                String::from_utf8_lossy(&output.stdout)
-            );
-        }
-        if !output.stderr.is_empty() {
-            trace!(
-                "VBoxManage stderr: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
-
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to capture screenshot: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
-
-        // Wait a moment for the file to be written
-        sleep(Duration::from_millis(300)).await;
-
-        // Check if file exists and get its size
-        if let Ok(metadata) = std::fs::metadata(&screenshot_path) {
-            trace!(
-                "Screenshot file created successfully. Size: {} bytes",
-                metadata.len()
-            );
-            if metadata.len() == 0 {
-                warn!("Screenshot file is empty (0 bytes)");
+        // Compare consecutive frames so OCR doesn't run on a half-redrawn screen
+        let mut frame = self.capture_screenshot_with_retry(instance).await?;
+        for _ in 0..SCREEN_STABILITY_CHECKS {
+            sleep(SCREEN_STABILITY_DELAY).await;
+            let next_frame = self.capture_screenshot_with_retry(instance).await?;
+            if screens_match(&frame, &next_frame, SCREEN_STABILITY_TOLERANCE) {
+                trace!("=== VBOX SCREEN CAPTURE END ===");
+                return Ok(next_frame);
             }
-        } else {
-            warn!("Screenshot file was not created: {}", screenshot_path);
+            frame = next_frame;
         }
 
-        trace!("Loading screenshot image...");
-        let image = image::open(&screenshot_path).context("Failed to load screenshot image")?;
-
-        trace!(
-            "Screenshot loaded: {}x{} pixels, format: {:?}",
-            image.width(),
-            image.height(),
-            image.color()
+        debug!(
+            "Screen still changing after {} checks, using latest frame",
+            SCREEN_STABILITY_CHECKS
         );
-
-        // Clean up the temporary file
-        let _ = std::fs::remove_file(&screenshot_path);
         trace!("=== VBOX SCREEN CAPTURE END ===");
-
-        Ok(image)
+        Ok(frame)
     }
 
     async fn get_console_output(&self, instance: &VmInstance) -> Result<String> {
@@ -689,7 +1201,7 @@ impl VmProviderTrait for VirtualBoxProvider {
         );
 
         // Check if VM has serial port configured for console output
-        let serial_file_path = format!("{}-console.log", instance.name);
+        let serial_file_path = self.console_log_path(instance).to_string_lossy().to_string();
 
         // First, ensure serial port is configured for this VM
         self.configure_console_output(instance, &serial_file_path)
@@ -705,12 +1217,140 @@ impl VmProviderTrait for VirtualBoxProvider {
         }
     }
 
+    async fn serial_port(&self, instance: &VmInstance) -> Result<Option<u16>> {
+        self.get_serial_port_from_vbox(&instance.name).await
+    }
+
+    async fn copy_to_guest(
+        &self,
+        instance: &VmInstance,
+        from: &Path,
+        to: &Path,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        info!(
+            "Copying {} to {} via VirtualBox guest control",
+            from.display(),
+            to.display()
+        );
+
+        self.ensure_guest_additions(instance)?;
+
+        // A password in argv is readable by any local user through ps; tempfile creates
+        // the file readable by its owner only and removes it when dropped
+        let mut password_file = tempfile::Builder::new()
+            .prefix("isotope-guestcontrol-")
+            .tempfile()
+            .context("Failed to create guest control password file")?;
+        password_file
+            .write_all(password.as_bytes())
+            .and_then(|_| password_file.flush())
+            .context("Failed to write guest control password file")?;
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(["guestcontrol", &instance.name, "copyto", "--username", username])
+            .arg("--passwordfile")
+            .arg(password_file.path())
+            .arg(from)
+            .arg(to)
+            .output()
+            .context("Failed to execute VBoxManage guestcontrol copyto")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "guestcontrol copyto failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(())
+    }
+
     fn name(&self) -> &'static str {
         "virtualbox"
     }
+
+    fn tool_version(&self) -> Result<String> {
+        let output = self
+            .vboxmanage_cmd()
+            .arg("--version")
+            .output()
+            .context("VBoxManage not found, is VirtualBox installed?")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "VBoxManage --version failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(format!("VBoxManage {}", String::from_utf8_lossy(&output.stdout).trim()))
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            live_snapshot: true,
+            screenshot: true,
+            serial_console: true,
+            guest_copy: true,
+            boot_order: true,
+        }
+    }
 }
 
 impl VirtualBoxProvider {
+    /// Fail with a clear message unless the guest reports a running Guest Additions version
+    fn ensure_guest_additions(&self, instance: &VmInstance) -> Result<()> {
+        let output = self
+            .vboxmanage_cmd()
+            .args([
+                "guestproperty",
+                "get",
+                &instance.name,
+                "/VirtualBox/GuestAdd/Version",
+            ])
+            .output()
+            .context("Failed to query Guest Additions version")?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.trim().strip_prefix("Value:") {
+            Some(version) if output.status.success() => {
+                debug!("Guest Additions {} detected in {}", version.trim(), instance.name);
+                Ok(())
+            }
+            _ => Err(anyhow!(
+                "VirtualBox Guest Additions are not running in VM {}; install them in the guest \
+                 or use COPY without --guest",
+                instance.name
+            )),
+        }
+    }
+
+    /// Delete a leftover disk image, unregistering it from the media registry if needed
+    fn remove_stale_medium(&self, disk_path: &str) -> Result<()> {
+        let output = self
+            .vboxmanage_cmd()
+            .args(["closemedium", "disk", disk_path, "--delete"])
+            .output()
+            .context("Failed to execute VBoxManage closemedium")?;
+
+        if output.status.success() {
+            info!("Closed and deleted stale medium {}", disk_path);
+            return Ok(());
+        }
+
+        // Not registered with VirtualBox, so it is just a file on disk
+        debug!(
+            "closemedium failed for {}: {}",
+            disk_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        std::fs::remove_file(disk_path)
+            .with_context(|| format!("Failed to delete stale disk {}", disk_path))?;
+        info!("Deleted stale disk file {}", disk_path);
+        Ok(())
+    }
+
     /// Get all SSH ports currently forwarded by any VirtualBox VM
     pub async fn get_all_used_ssh_ports(&self) -> Result<std::collections::HashSet<u16>> {
         let mut used_ports = std::collections::HashSet::new();
@@ -744,8 +1384,16 @@ impl VirtualBoxProvider {
         Ok(used_ports)
     }
 
-    /// Get SSH port forwarding from VirtualBox VM configuration
+    /// Host port of the VM's SSH port forwarding
     pub async fn get_ssh_port_from_vbox(&self, vm_name: &str) -> Result<Option<u16>> {
+        Ok(self
+            .get_ssh_forwarding_from_vbox(vm_name)
+            .await?
+            .map(|(host_port, _)| host_port))
+    }
+
+    /// Host and guest port of the VM's SSH port forwarding
+    async fn get_ssh_forwarding_from_vbox(&self, vm_name: &str) -> Result<Option<(u16, u16)>> {
         let output = self
             .vboxmanage_cmd()
             .args(["showvminfo", vm_name, "--machinereadable"])
@@ -758,24 +1406,44 @@ impl VirtualBoxProvider {
 
         let output_str = String::from_utf8_lossy(&output.stdout);
         
-        // Look for NAT port forwarding rule for SSH
-        // Format: Forwarding(0)="ssh,tcp,,PORT,,22"
-        for line in output_str.lines() {
-            if line.contains("Forwarding(") && line.contains("ssh,tcp") && line.contains(",,22") {
-                // Extract the host port from the line
-                // Example: Forwarding(0)="ssh,tcp,,20000,,22"
-                if let Some(start) = line.find(",,") {
-                    if let Some(end) = line[start+2..].find(",,") {
-                        let port_str = &line[start+2..start+2+end];
-                        if let Ok(port) = port_str.parse::<u16>() {
-                            return Ok(Some(port));
-                        }
-                    }
-                }
-            }
+        Ok(output_str.lines().find_map(parse_ssh_forwarding))
+    }
+
+    /// Continue a paused VM where it left off
+    async fn resume_vm(&self, instance: &mut VmInstance) -> Result<()> {
+        info!("Resuming paused VirtualBox VM: {}", instance.name);
+
+        let output = self
+            .vboxmanage_cmd()
+            .args(["controlvm", &instance.name, "resume"])
+            .output()
+            .context("Failed to resume VirtualBox VM")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "Failed to resume VirtualBox VM: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
         }
-        
-        Ok(None)
+
+        instance.set_state(VmState::Running);
+        Ok(())
+    }
+
+    /// Host port of a `tcpserver` serial port 1, from `uartmode1="tcpserver,PORT"`
+    async fn get_serial_port_from_vbox(&self, vm_name: &str) -> Result<Option<u16>> {
+        let output = self
+            .vboxmanage_cmd()
+            .args(["showvminfo", vm_name, "--machinereadable"])
+            .output()
+            .context("Failed to get VM info")?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("uartmode1=\"tcpserver,"))
+            .and_then(|rest| rest.trim_end_matches('"').parse().ok()))
     }
 
     async fn configure_console_output(
@@ -792,6 +1460,11 @@ impl VirtualBoxProvider {
             return Ok(());
         }
 
+        // A port served over TCP for EXPECT is logged by the host-side bridge instead
+        if self.serial_port(instance).await?.is_some() {
+            return Ok(());
+        }
+
         // Configure serial port 1 to output to file
         let configs = [
             ("--uart1", "0x3F8", "4"),
@@ -859,3 +1532,186 @@ impl VirtualBoxProvider {
         Ok(console_lines.join("\n"))
     }
 }
+
+/// Map `VMState="..."` from `showvminfo --machinereadable` output onto a `VmState`
+fn parse_vm_state(info: &str) -> Option<VmState> {
+    let state = info
+        .lines()
+        .find_map(|line| line.strip_prefix("VMState="))?
+        .trim()
+        .trim_matches('"');
+
+    Some(match state {
+        "running" => VmState::Running,
+        "starting" | "restoring" => VmState::Starting,
+        "stopping" | "saving" => VmState::Stopping,
+        // A saved VM restores its memory on startvm, like a normal boot from our side
+        "poweroff" | "saved" => VmState::Stopped,
+        "paused" => VmState::Suspended,
+        "aborted" | "aborted-saved" => VmState::Aborted,
+        "gurumeditation" => VmState::Error("VirtualBox guru meditation".to_string()),
+        other => VmState::Error(format!("Unexpected VirtualBox state '{}'", other)),
+    })
+}
+
+/// `modifyvm` arguments for the audio device, USB controller and shared clipboard
+fn device_args(devices: &DeviceConfig) -> Vec<(&'static str, String)> {
+    let mut args = vec![("--audio", devices.audio.clone())];
+    if devices.audio != "none" {
+        args.push(("--audioout", "on".to_string()));
+    }
+
+    // Each controller is its own switch; EHCI needs the OHCI companion for USB 1.1 devices
+    let usb = devices.usb.as_str();
+    for (flag, enabled) in [
+        ("--usb", matches!(usb, "ohci" | "ehci")),
+        ("--usbehci", usb == "ehci"),
+        ("--usbxhci", usb == "xhci"),
+    ] {
+        args.push((flag, if enabled { "on" } else { "off" }.to_string()));
+    }
+
+    args.push(("--clipboard", devices.clipboard.clone()));
+    args
+}
+
+/// Whether `showvminfo --machinereadable` output lists a storage controller named `name`
+fn has_storage_controller(info: &str, name: &str) -> bool {
+    info.lines().any(|line| {
+        line.strip_prefix("storagecontrollername")
+            .and_then(|line| line.split_once('='))
+            .is_some_and(|(_, value)| value.trim_matches('"') == name)
+    })
+}
+
+/// Medium attached at `controller` port/device in `showvminfo --machinereadable` output,
+/// None for an empty drive
+fn attached_medium<'a>(
+    info: &'a str,
+    controller: &str,
+    port: &str,
+    device: &str,
+) -> Option<&'a str> {
+    let key = format!("\"{}-{}-{}\"=", controller, port, device);
+    let value = info.lines().find_map(|line| line.strip_prefix(&key))?.trim_matches('"');
+    (value != "none" && value != "emptydrive").then_some(value)
+}
+
+/// `ssh,tcp,,HOST_PORT,,GUEST_PORT`, the NAT rule forwarding a host port to the guest's sshd
+fn ssh_forwarding_rule(host_port: u16, guest_port: u16) -> String {
+    format!("ssh,tcp,,{},,{}", host_port, guest_port)
+}
+
+/// Host and guest port of a `Forwarding(N)="ssh,tcp,,HOST_PORT,,GUEST_PORT"` line of
+/// `showvminfo --machinereadable`
+fn parse_ssh_forwarding(line: &str) -> Option<(u16, u16)> {
+    let rule = line
+        .strip_prefix("Forwarding(")?
+        .split_once('=')?
+        .1
+        .trim_matches('"');
+    match rule.split(',').collect::<Vec<_>>()[..] {
+        ["ssh", "tcp", _, host_port, _, guest_port] => {
+            Some((host_port.parse().ok()?, guest_port.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Snapshot names from `snapshot <vm> list --machinereadable` output, where nested snapshots
+/// appear as `SnapshotName-1-2="..."`
+fn parse_snapshot_names(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let suffix = key.strip_prefix("SnapshotName")?;
+            (suffix.is_empty() || suffix.starts_with('-'))
+                .then(|| value.trim().trim_matches('"').to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_state() {
+        let info = "name=\"isotope-build\"\n\
+                    memory=2048\n\
+                    VMState=\"aborted\"\n\
+                    VMStateChangeTime=\"2026-10-18T04:12:33.000000000\"\n\
+                    uartmode1=\"disconnected\"\n";
+        assert_eq!(parse_vm_state(info), Some(VmState::Aborted));
+
+        let paused = info.replace("VMState=\"aborted\"", "VMState=\"paused\"");
+        assert_eq!(parse_vm_state(&paused), Some(VmState::Suspended));
+        let running = info.replace("VMState=\"aborted\"", "VMState=\"running\"");
+        assert_eq!(parse_vm_state(&running), Some(VmState::Running));
+        let saved = info.replace("VMState=\"aborted\"", "VMState=\"saved\"");
+        assert_eq!(parse_vm_state(&saved), Some(VmState::Stopped));
+
+        assert_eq!(parse_vm_state("name=\"isotope-build\"\n"), None);
+    }
+
+    #[test]
+    fn test_parse_snapshot_names() {
+        let listing = "SnapshotName=\"base\"\n\
+                       SnapshotUUID=\"0c4b6c1e-7d0a-4c55-9a43-3b1f6e2f6a10\"\n\
+                       SnapshotName-1=\"isotope-pre-step\"\n\
+                       SnapshotUUID-1=\"5f0e3f5e-2b9c-4d7e-8f0e-1d2c3b4a5968\"\n\
+                       CurrentSnapshotName=\"isotope-pre-step\"\n\
+                       CurrentSnapshotUUID=\"5f0e3f5e-2b9c-4d7e-8f0e-1d2c3b4a5968\"\n\
+                       CurrentSnapshotNode=\"SnapshotName-1\"\n";
+        assert_eq!(parse_snapshot_names(listing), vec!["base", "isotope-pre-step"]);
+        assert!(parse_snapshot_names("").is_empty());
+    }
+
+    #[test]
+    fn test_attached_medium() {
+        let info = "storagecontrollername0=\"SATA Controller\"\n\
+                    storagecontrollername1=\"IDE Controller\"\n\
+                    \"IDE Controller-1-0\"=\"/isos/ubuntu.iso\"\n\
+                    \"IDE Controller-1-1\"=\"emptydrive\"\n\
+                    \"IDE Controller-0-0\"=\"none\"\n";
+        assert!(has_storage_controller(info, IDE_CONTROLLER));
+        let sata_only = "storagecontrollername0=\"SATA Controller\"\n";
+        assert!(!has_storage_controller(sata_only, IDE_CONTROLLER));
+
+        assert_eq!(attached_medium(info, IDE_CONTROLLER, "1", "0"), Some("/isos/ubuntu.iso"));
+        assert_eq!(attached_medium(info, IDE_CONTROLLER, "1", "1"), None);
+        assert_eq!(attached_medium(info, IDE_CONTROLLER, "0", "0"), None);
+        assert_eq!(attached_medium(info, IDE_CONTROLLER, "0", "1"), None);
+    }
+
+    #[test]
+    fn test_parse_ssh_forwarding() {
+        let rule = format!("Forwarding(0)=\"{}\"", ssh_forwarding_rule(20000, 2222));
+        assert_eq!(parse_ssh_forwarding(&rule), Some((20000, 2222)));
+        assert_eq!(
+            parse_ssh_forwarding("Forwarding(1)=\"http,tcp,,8080,,80\""),
+            None
+        );
+        assert_eq!(parse_ssh_forwarding("natnet1=\"nat\""), None);
+    }
+
+    #[test]
+    fn test_device_args() {
+        let mut devices = DeviceConfig::default();
+        let args = device_args(&devices);
+        assert!(args.contains(&("--audio", "none".to_string())));
+        assert!(args.contains(&("--usb", "off".to_string())));
+        assert!(args.contains(&("--clipboard", "disabled".to_string())));
+
+        devices.apply("usb", "ehci").unwrap();
+        devices.apply("audio", "on").unwrap();
+        let args = device_args(&devices);
+        assert!(args.contains(&("--usb", "on".to_string())));
+        assert!(args.contains(&("--usbehci", "on".to_string())));
+        assert!(args.contains(&("--usbxhci", "off".to_string())));
+        assert!(args.contains(&("--audio", "default".to_string())));
+
+        assert!(devices.apply("clipboard", "both").is_err());
+    }
+}
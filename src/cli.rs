@@ -1,6 +1,14 @@
 use clap::Subcommand;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::automation::vm::{Resolution, VmConfig};
+use crate::core::gc::parse_age;
+use crate::core::provision::ExternalHost;
+use crate::core::BootCheck;
+
+// Parsed once at startup, so the size of the Build variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Build an ISO from an Isotope specification
@@ -10,19 +18,192 @@ pub enum Commands {
         /// Output path for the generated ISO (overrides spec)
         #[arg(short, long)]
         output: Option<PathBuf>,
-        /// Continue from a specific step number (1-based index)
+        /// Directory collecting the image, checksums, report.json, screenshots and logs
+        #[arg(long)]
+        output_dir: Option<PathBuf>,
+        /// Continue from a specific step number (1-based index) or `# @label:` name
+        #[arg(long, value_name = "STEP|LABEL")]
+        continue_from: Option<String>,
+        /// Resume from the step that failed the previous build, restoring its pre-step snapshot
+        #[arg(long = "continue", conflicts_with = "continue_from")]
+        continue_failed: bool,
+        /// Snapshot the VM before every step so `--continue` can roll back to it
+        #[arg(long)]
+        step_snapshots: bool,
+        /// Unregister the VM from a previous build of this spec and delete its disks first
+        #[arg(long, conflicts_with_all = ["continue_from", "continue_failed"])]
+        force_recreate: bool,
+        /// Re-run the whole build on a fresh VM up to N times when it fails on a transient
+        /// error (SSH connect, network, port collision), but not on a failing step
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 0,
+            conflicts_with_all = ["continue_from", "continue_failed"]
+        )]
+        retries: u32,
+        /// Exit successfully without building when the artifact's `.fingerprint` sidecar
+        /// matches the spec file, the host files it reads, source ISO checksum, VM overrides,
+        /// the variables and secrets it renders and tool versions
+        #[arg(long, conflicts_with_all = ["continue_from", "continue_failed"])]
+        skip_if_unchanged: bool,
+        /// Fixed VM name instead of a random one per build; without a value it is derived from
+        /// the spec file path. An existing VM with the name is never reused: a fresh build
+        /// fails unless --force-recreate replaces it
+        #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = "")]
+        vm_name: Option<String>,
+        /// Pause after every instruction (default 1s), overrides the init stage `slow` key
+        #[arg(long, value_name = "DELAY", num_args = 0..=1, default_missing_value = "1s")]
+        slow: Option<String>,
+        /// Attempt every os_configure RUN/COPY and fail at the end with all collected errors
+        #[arg(long)]
+        keep_going: bool,
+        /// Guest display mode such as 1280x800 (or 1280x800x24), overrides `VM resolution=`
+        #[arg(long, value_name = "WxH")]
+        resolution: Option<Resolution>,
+        /// Guest memory in MB, overrides `VM memory=`
+        #[arg(
+            long,
+            value_name = "MB",
+            value_parser = clap::value_parser!(u64)
+                .range(VmConfig::MIN_MEMORY_MB..=VmConfig::MAX_MEMORY_MB)
+        )]
+        memory: Option<u64>,
+        /// Virtual CPU count, overrides `VM cpus=`
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u32).range(1..=VmConfig::MAX_CPUS as i64)
+        )]
+        cpus: Option<u32>,
+        /// Stop at BREAKPOINT/PAUSE instructions until Enter is pressed (needs a terminal)
+        #[arg(long)]
+        interactive: bool,
+        /// Cache extracted source ISOs here, keyed by checksum, to skip re-extraction
+        #[arg(long, value_name = "DIR")]
+        iso_cache_dir: Option<PathBuf>,
+        /// Dotenv-style NAME=value file resolving `{{secret:NAME}}` tokens
+        #[arg(long, value_name = "FILE")]
+        secrets_file: Option<PathBuf>,
+        /// Keep the working directory (one subdirectory per stage) for inspection
+        #[arg(long)]
+        no_cleanup: bool,
+        /// Print a per-phase timing breakdown and VM state timeline (and add them to report.json)
+        #[arg(long)]
+        profile: bool,
+        /// OCR the screen continuously in the background and resolve WAIT FOR from its events
         #[arg(long)]
-        continue_from: Option<usize>,
+        parallel_ocr_monitor: bool,
+        /// Save a screenshot every INTERVAL (e.g. 2s) while the VM runs, as numbered frames
+        /// in <output-dir>/recording
+        #[arg(long, value_name = "INTERVAL", requires = "output_dir")]
+        record: Option<String>,
+        /// Assemble the recorded frames into <output-dir>/recording.mp4 (or .gif) with ffmpeg
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            requires = "record",
+            num_args = 0..=1,
+            default_missing_value = "mp4",
+            value_parser = ["mp4", "gif"]
+        )]
+        record_video: Option<String>,
+        /// Log each TYPE's characters and the exact scancodes sent to the VM, to debug
+        /// keyboard layout problems; dumps for text containing a secret are withheld
+        #[arg(long)]
+        dump_scancodes_on_type: bool,
+        /// Boot the packed image in a throwaway VM and fail the build unless it shows
+        /// `serial:TEXT`, `screen:TEXT` (or bare screen text); without a value the VM only has
+        /// to keep running
+        #[arg(long, value_name = "CONDITION", num_args = 0..=1, default_missing_value = "")]
+        verify_boot: Option<BootCheck>,
+        /// Seconds `--verify-boot` waits for its condition
+        #[arg(long, value_name = "SECS", default_value_t = 300, requires = "verify_boot")]
+        verify_boot_timeout: u64,
     },
     /// Validate an Isotope specification
     Validate {
         /// Path to the Isotope specification file
         spec_file: PathBuf,
+        /// Also check that the external tools the spec's features need are installed
+        #[arg(long)]
+        check_tools: bool,
     },
     /// Test an Isotope specification in a VM
     Test {
         /// Path to the Isotope specification file
         spec_file: PathBuf,
+        /// Run os_install and os_configure end to end, skipping pack
+        #[arg(long)]
+        full: bool,
+    },
+    /// Run a specification's os_configure stages over SSH against an existing host, without a VM
+    Provision {
+        /// Path to the Isotope specification file
+        spec_file: PathBuf,
+        /// Host to provision, e.g. admin@10.0.0.5 or admin@build-box:2222
+        #[arg(long, value_name = "USER@HOST[:PORT]")]
+        host: ExternalHost,
+        /// Private key to log in with
+        #[arg(long, value_name = "PATH")]
+        key: Option<PathBuf>,
+        /// File whose first line is the password to log in with, also used for sudo.
+        /// Without it the password comes from ISOTOPE_SSH_PASSWORD, or a prompt when
+        /// there is no --key
+        #[arg(long, value_name = "PATH")]
+        password_file: Option<PathBuf>,
+        /// Add the host key to ~/.ssh/known_hosts if the host has no entry there yet, instead
+        /// of refusing to connect. A key that differs from a recorded one is always refused
+        #[arg(long)]
+        accept_new_host_key: bool,
+    },
+    /// Remove working directories and screenshots that failed or killed builds left behind
+    Gc {
+        /// Only remove leftovers nothing was written to for this long, e.g. 90m, 12h or 7d
+        #[arg(long, value_name = "AGE", default_value = "24h", value_parser = parse_age)]
+        older_than: Duration,
+        /// List what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the VM providers, whether their tools are installed and what they support
+    Providers,
+    /// Print a specification's effective VM configuration, steps, labels and output path
+    Inspect {
+        /// Path to the Isotope specification file
+        spec_file: PathBuf,
+    },
+    /// Check the spec's screen text WAITs against saved screenshots, without a VM
+    OcrCheck {
+        /// Directory of PNG screenshots, e.g. the debug-steps of an earlier build
+        #[arg(long, value_name = "DIR")]
+        images: PathBuf,
+        /// Path to the Isotope specification file
+        spec_file: PathBuf,
+    },
+    /// Print the scancodes text (as TYPE) or a key press (as PRESS) maps to, without a VM
+    #[command(hide = true)]
+    Keys {
+        /// Text to map the way TYPE does
+        #[arg(required_unless_present = "press", conflicts_with = "press")]
+        text: Option<String>,
+        /// Key or combination to map the way PRESS does, e.g. ctrl+alt+t or "tab x 3"
+        #[arg(long, value_name = "KEY")]
+        press: Option<String>,
+        /// Map text the way `TYPE --unicode` does
+        #[arg(long, conflicts_with = "press")]
+        unicode: bool,
+    },
+    /// List every spec keyword with its syntax, fields and the stages it is valid in
+    Instructions {
+        /// Print the list as JSON, e.g. for editor completion
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the JSON Schema of the spec format, for editor validation of `.isotope.json` specs
+    Schema {
+        /// Write the schema to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
     /// Convert a JSON config to Isotope format
     Convert {
@@ -0,0 +1,516 @@
+use serde::{Serialize, Serializer};
+
+use super::{Instruction, StageType};
+
+/// One keyword of the spec language: how it is written and where it may appear. The parser
+/// and validator look keywords up here, and `isotope instructions` prints this table.
+#[derive(Debug, Serialize)]
+pub struct KeywordSpec {
+    pub keyword: &'static str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub aliases: &'static [&'static str],
+    /// Arguments after the keyword, optional parts in brackets
+    pub syntax: &'static str,
+    pub example: &'static str,
+    /// Parsed fields and their types
+    pub fields: &'static [Field],
+    /// Stages the keyword may appear in; empty for directives written outside any stage
+    #[serde(serialize_with = "stage_names")]
+    pub stages: &'static [StageType],
+    /// Whether it is a numbered step, as opposed to a directive or stage setting
+    pub step: bool,
+    /// Whether `KEYWORD@login` may name a LOGIN
+    pub login: bool,
+    pub summary: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+impl KeywordSpec {
+    pub fn valid_in(&self, stage: &StageType) -> bool {
+        self.stages.contains(stage)
+    }
+
+    /// Stage names joined for messages, e.g. "os_install, os_configure"
+    pub fn stage_list(&self) -> String {
+        let names: Vec<&str> = self.stages.iter().map(StageType::name).collect();
+        names.join(", ")
+    }
+}
+
+fn stage_names<S: Serializer>(stages: &&[StageType], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(stages.iter().map(StageType::name))
+}
+
+const fn field(name: &'static str, kind: &'static str) -> Field {
+    Field { name, kind }
+}
+
+const INIT: &[StageType] = &[StageType::Init];
+const INSTALL_AND_CONFIGURE: &[StageType] = &[StageType::OsInstall, StageType::OsConfigure];
+const CONFIGURE: &[StageType] = &[StageType::OsConfigure];
+const PACK: &[StageType] = &[StageType::Pack];
+
+/// Every keyword the parser accepts, spec-level directives first, then steps by stage
+pub const KEYWORDS: &[KeywordSpec] = &[
+    KeywordSpec {
+        keyword: "FROM",
+        aliases: &[],
+        syntax: "<path>",
+        example: "FROM ./ubuntu-22.04-server.iso",
+        fields: &[field("from", "path")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Source ISO the build starts from",
+    },
+    KeywordSpec {
+        keyword: "CHECKSUM",
+        aliases: &[],
+        syntax: "<algorithm>:<value>",
+        example: "CHECKSUM sha256:a4acfda10b18da50e2ec50ccaf860d7f20ce1ee42895e3840b57b2b371fc734",
+        fields: &[field("algorithm", "string"), field("value", "string")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Expected checksum of the source ISO",
+    },
+    KeywordSpec {
+        keyword: "LABEL",
+        aliases: &[],
+        syntax: "<key>=\"<value>\"",
+        example: "LABEL version=\"1.0.0\"",
+        fields: &[field("key", "string"), field("value", "string")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Metadata such as name, version and maintainer",
+    },
+    KeywordSpec {
+        keyword: "DEFAULT",
+        aliases: &[],
+        syntax: "<key>=<value>",
+        example: "DEFAULT wait-timeout=5m",
+        fields: &[field("key", "string"), field("value", "duration")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Spec-wide default for wait-timeout, wait-poll or press-delay",
+    },
+    KeywordSpec {
+        keyword: "HOOK",
+        aliases: &[],
+        syntax: "<pre|post>[:stage] <command>",
+        example: "HOOK post:pack ./upload.sh",
+        fields: &[field("when", "pre|post"), field("stage", "stage?"), field("command", "string")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Host command run before or after a stage, or every stage",
+    },
+    KeywordSpec {
+        keyword: "STAGE",
+        aliases: &[],
+        syntax: "<init|os_install|os_configure|pack> [title]",
+        example: "STAGE os_configure",
+        fields: &[field("name", "stage"), field("title", "string?")],
+        stages: &[],
+        step: false,
+        login: false,
+        summary: "Start a stage; the keywords below it belong to that stage",
+    },
+    KeywordSpec {
+        keyword: "IDLE_WATCHDOG",
+        aliases: &[],
+        syntax: "<duration> [key]",
+        example: "IDLE_WATCHDOG 90s enter",
+        fields: &[field("after", "duration"), field("key", "string?")],
+        stages: INSTALL_AND_CONFIGURE,
+        step: false,
+        login: false,
+        summary: "Warn, and optionally press a key, when the screen stalls during WAIT FOR",
+    },
+    KeywordSpec {
+        keyword: "VM",
+        aliases: &[],
+        syntax: "<key>=<value>",
+        example: "VM memory=4096",
+        fields: &[field("key", "string"), field("value", "string")],
+        stages: INIT,
+        step: true,
+        login: false,
+        summary: "VM setting such as provider, memory, cpus, disk or boot-order",
+    },
+    KeywordSpec {
+        keyword: "VM_RAW",
+        aliases: &[],
+        syntax: "<arg>...",
+        example: "VM_RAW --chipset ich9",
+        fields: &[field("args", "string[]")],
+        stages: INIT,
+        step: true,
+        login: false,
+        summary: "Provider arguments passed through verbatim",
+    },
+    KeywordSpec {
+        keyword: "ANSWER_FILE",
+        aliases: &[],
+        syntax: "<preseed|kickstart|autoyast> <path>",
+        example: "ANSWER_FILE preseed ./preseed.cfg",
+        fields: &[field("kind", "string"), field("path", "path")],
+        stages: INIT,
+        step: true,
+        login: false,
+        summary: "Inject an unattended-install file into the source ISO",
+    },
+    KeywordSpec {
+        keyword: "ATTACH_ISO",
+        aliases: &[],
+        syntax: "<path> [slot]",
+        example: "ATTACH_ISO ./virtio-win.iso 1",
+        fields: &[field("path", "path"), field("slot", "u32")],
+        stages: INIT,
+        step: true,
+        login: false,
+        summary: "Attach an extra ISO in drive slot 1-3",
+    },
+    KeywordSpec {
+        keyword: "NETWORK",
+        aliases: &[],
+        syntax: "<index> <nat|bridged|hostonly|intnet> [adapter]",
+        example: "NETWORK 2 bridged eth0",
+        fields: &[field("index", "u8"), field("mode", "string"), field("adapter", "string?")],
+        stages: INIT,
+        step: true,
+        login: false,
+        summary: "Add a network adapter next to the NAT adapter used for SSH",
+    },
+    KeywordSpec {
+        keyword: "WAIT",
+        aliases: &[],
+        syntax: "[duration] [--poll <duration>] [--matches <n>] [FOR <condition>]",
+        example: "WAIT 5m FOR \"Complete\"",
+        fields: &[
+            field("duration", "duration?"),
+            field("condition", "string?"),
+            field("poll", "duration?"),
+            field("matches", "u32?"),
+        ],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Sleep, or wait until screen text, stable:, port: or ping: condition holds",
+    },
+    KeywordSpec {
+        keyword: "PRESS",
+        aliases: &[],
+        syntax: "<key|modifier+key|[key, ...]> [x <count>]",
+        example: "PRESS [down, down, enter] x 2",
+        fields: &[
+            field("key", "string|string[]"),
+            field("repeat", "u32?"),
+            field("modifiers", "string[]?"),
+        ],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Press keys or key combinations",
+    },
+    KeywordSpec {
+        keyword: "TYPE",
+        aliases: &[],
+        syntax: "[--unicode] [--hold <modifier>[+<modifier>...]] <text>",
+        example: "TYPE ubuntu",
+        fields: &[
+            field("text", "string"),
+            field("unicode", "bool"),
+            field("modifiers", "string[]?"),
+        ],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Type text on the VM keyboard",
+    },
+    KeywordSpec {
+        keyword: "BREAKPOINT",
+        aliases: &["PAUSE"],
+        syntax: "[message]",
+        example: "BREAKPOINT \"check the partitioner\"",
+        fields: &[field("message", "string?")],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Wait for Enter when building with --interactive",
+    },
+    KeywordSpec {
+        keyword: "EXPECT",
+        aliases: &[],
+        syntax: "[timeout] \"<prompt>\" SEND \"<text>\"",
+        example: "EXPECT 2m \"login:\" SEND \"root\"",
+        fields: &[
+            field("prompt", "string"),
+            field("send", "string"),
+            field("timeout", "duration?"),
+        ],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Answer a serial console prompt",
+    },
+    KeywordSpec {
+        keyword: "CAPTURE",
+        aliases: &[],
+        syntax: "[timeout] \"<regex>\" AS <VAR>",
+        example: "CAPTURE 2m \"IP address: ([0-9.]+)\" AS GUEST_IP",
+        fields: &[
+            field("pattern", "string"),
+            field("var", "string"),
+            field("timeout", "duration?"),
+        ],
+        stages: INSTALL_AND_CONFIGURE,
+        step: true,
+        login: false,
+        summary: "Read a value off the screen into a {{VAR}}",
+    },
+    KeywordSpec {
+        keyword: "RUN",
+        aliases: &[],
+        syntax: "[--env K=V]... [--continue-on-error] [--sudo] [--pty] <command|<<EOF>",
+        example: "RUN --sudo apt-get update",
+        fields: &[
+            field("command", "string"),
+            field("login", "string?"),
+            field("env", "map"),
+            field("continue_on_error", "bool"),
+            field("sudo", "bool"),
+            field("pty", "bool"),
+        ],
+        stages: CONFIGURE,
+        step: true,
+        login: true,
+        summary: "Run a command in the guest over SSH",
+    },
+    KeywordSpec {
+        keyword: "COPY",
+        aliases: &[],
+        syntax: "[--guest] [--chmod=<mode>] [--chown=<user[:group]>] <source> <destination>",
+        example: "COPY --chmod=755 ./run.sh /opt/app/run.sh",
+        fields: &[
+            field("from", "path"),
+            field("to", "path"),
+            field("login", "string?"),
+            field("method", "scp|guest"),
+            field("mode", "string?"),
+            field("owner", "string?"),
+            field("group", "string?"),
+        ],
+        stages: CONFIGURE,
+        step: true,
+        login: true,
+        summary: "Copy a host file into the guest",
+    },
+    KeywordSpec {
+        keyword: "COPY_FROM",
+        aliases: &[],
+        syntax: "<remote source> <local destination>",
+        example: "COPY_FROM /etc/machine-id ./out/machine-id",
+        fields: &[field("remote", "string"), field("local", "path"), field("login", "string?")],
+        stages: CONFIGURE,
+        step: true,
+        login: true,
+        summary: "Copy a guest file to the host",
+    },
+    KeywordSpec {
+        keyword: "WAIT_FOR_SSH",
+        aliases: &[],
+        syntax: "[timeout]",
+        example: "WAIT_FOR_SSH 5m",
+        fields: &[field("timeout", "duration?"), field("login", "string?")],
+        stages: CONFIGURE,
+        step: true,
+        login: true,
+        summary: "Wait until the LOGIN can sign in over SSH",
+    },
+    KeywordSpec {
+        keyword: "WRITE_LABELS",
+        aliases: &[],
+        syntax: "[--sudo] [path]",
+        example: "WRITE_LABELS --sudo /etc/isotope-build",
+        fields: &[field("path", "path"), field("login", "string?"), field("sudo", "bool")],
+        stages: CONFIGURE,
+        step: true,
+        login: true,
+        summary: "Write the spec's LABELs to a guest file",
+    },
+    KeywordSpec {
+        keyword: "LOGIN",
+        aliases: &[],
+        syntax: "<username> [password=<password>] [private_key=<path>] [name=<name>]",
+        example: "LOGIN ubuntu password=ubuntu",
+        fields: &[
+            field("name", "string"),
+            field("username", "string"),
+            field("password", "string?"),
+            field("private_key", "path?"),
+        ],
+        stages: CONFIGURE,
+        step: true,
+        login: false,
+        summary: "SSH credentials for the following guest commands",
+    },
+    KeywordSpec {
+        keyword: "EXPORT",
+        aliases: &[],
+        syntax: "<path>",
+        example: "EXPORT ./output/custom.iso",
+        fields: &[field("path", "path")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Output path of the packed image",
+    },
+    KeywordSpec {
+        keyword: "FORMAT",
+        aliases: &[],
+        syntax: "<img|vmdk|vhd|vhdx|docker>",
+        example: "FORMAT vmdk",
+        fields: &[field("format", "string")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Image format of the packed output",
+    },
+    KeywordSpec {
+        keyword: "BOOTABLE",
+        aliases: &[],
+        syntax: "<true|false>",
+        example: "BOOTABLE true",
+        fields: &[field("enabled", "bool")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Whether the output should be bootable",
+    },
+    KeywordSpec {
+        keyword: "VOLUME_LABEL",
+        aliases: &[],
+        syntax: "\"<label>\"",
+        example: "VOLUME_LABEL \"Custom OS\"",
+        fields: &[field("label", "string")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Volume label of the output",
+    },
+    KeywordSpec {
+        keyword: "RESIZE_DISK",
+        aliases: &[],
+        syntax: "<size>",
+        example: "RESIZE_DISK 8G",
+        fields: &[field("size", "string")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Grow or shrink the packed image",
+    },
+    KeywordSpec {
+        keyword: "SHRINK",
+        aliases: &[],
+        syntax: "[true|false]",
+        example: "SHRINK",
+        fields: &[field("enabled", "bool")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Sparsify the packed image",
+    },
+    KeywordSpec {
+        keyword: "EXPECT_CHECKSUM",
+        aliases: &[],
+        syntax: "<algorithm>:<value>",
+        example: "EXPECT_CHECKSUM sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+        fields: &[field("algorithm", "string"), field("value", "string")],
+        stages: PACK,
+        step: true,
+        login: false,
+        summary: "Fail unless the packed artifact has this checksum",
+    },
+];
+
+/// Metadata of `keyword` or one of its aliases
+pub fn lookup(keyword: &str) -> Option<&'static KeywordSpec> {
+    KEYWORDS
+        .iter()
+        .find(|spec| spec.keyword == keyword || spec.aliases.contains(&keyword))
+}
+
+impl Instruction {
+    /// Keyword the instruction is written with in spec files
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Instruction::Vm { .. } => "VM",
+            Instruction::VmRaw { .. } => "VM_RAW",
+            Instruction::AnswerFile { .. } => "ANSWER_FILE",
+            Instruction::AttachIso { .. } => "ATTACH_ISO",
+            Instruction::Network { .. } => "NETWORK",
+            Instruction::Wait { .. } => "WAIT",
+            Instruction::Press { .. } => "PRESS",
+            Instruction::Type { .. } => "TYPE",
+            Instruction::Breakpoint { .. } => "BREAKPOINT",
+            Instruction::Expect { .. } => "EXPECT",
+            Instruction::Capture { .. } => "CAPTURE",
+            Instruction::Run { .. } => "RUN",
+            Instruction::Copy { .. } => "COPY",
+            Instruction::CopyFrom { .. } => "COPY_FROM",
+            Instruction::WaitForSsh { .. } => "WAIT_FOR_SSH",
+            Instruction::WriteLabels { .. } => "WRITE_LABELS",
+            Instruction::Login { .. } => "LOGIN",
+            Instruction::Export { .. } => "EXPORT",
+            Instruction::Format { .. } => "FORMAT",
+            Instruction::Bootable { .. } => "BOOTABLE",
+            Instruction::VolumeLabel { .. } => "VOLUME_LABEL",
+            Instruction::ResizeDisk { .. } => "RESIZE_DISK",
+            Instruction::Shrink { .. } => "SHRINK",
+            Instruction::ExpectChecksum { .. } => "EXPECT_CHECKSUM",
+        }
+    }
+
+    /// Keyword metadata of this instruction
+    pub fn keyword_spec(&self) -> &'static KeywordSpec {
+        lookup(self.keyword()).expect("every instruction keyword is in KEYWORDS")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_instruction_has_a_keyword() {
+        // Each externally tagged variant is one `oneOf` entry of the schema
+        let schema = schemars::schema_for!(Instruction);
+        let variants: Vec<String> = schema
+            .schema
+            .subschemas
+            .and_then(|subschemas| subschemas.one_of)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|variant| variant.into_object().object?.required.into_iter().next())
+            .collect();
+        assert!(!variants.is_empty());
+
+        // Step examples parse to distinct variants (see test_keyword_examples_parse), so one step
+        // keyword per variant means none is missing and keyword_spec can't fail
+        let steps: Vec<&str> = KEYWORDS
+            .iter()
+            .filter(|spec| spec.step)
+            .map(|spec| spec.keyword)
+            .collect();
+        assert_eq!(steps.len(), variants.len(), "variants {:?}, keywords {:?}", variants, steps);
+    }
+}
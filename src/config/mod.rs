@@ -1,33 +1,80 @@
 pub mod converter;
+pub mod keywords;
 pub mod parser;
 pub mod validator;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IsotopeSpec {
     pub from: String,
     pub checksum: Option<ChecksumInfo>,
     pub labels: HashMap<String, String>,
     pub stages: Vec<Stage>,
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+    #[serde(default)]
+    pub defaults: SpecDefaults,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Spec-wide fallbacks set with `DEFAULT key=value`, used when an instruction leaves them out
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SpecDefaults {
+    pub wait_timeout: Option<String>, // Timeout for `WAIT FOR` without a duration
+    pub press_delay: Option<String>,  // Delay between repeated PRESS keys (default 100ms)
+    pub wait_poll: Option<String>,    // Interval between `WAIT FOR` condition checks
+}
+
+/// Host shell command run around stages, declared with `HOOK pre|post[:stage] <command>`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Hook {
+    pub when: HookWhen,
+    pub stage: Option<StageType>, // None runs the hook around every stage
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum HookWhen {
+    StageStart,
+    StageEnd,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ChecksumInfo {
     pub algorithm: String,
     pub value: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Stage {
     pub name: StageType,
+    /// Optional name after the type, e.g. `STAGE os_configure post-reboot`, to tell apart
+    /// several stages of one type
+    #[serde(default)]
+    pub title: Option<String>,
     pub instructions: Vec<Instruction>,
+    /// `# @label: NAME` comments, mapped to the index of the instruction that follows them
+    #[serde(default)]
+    pub step_labels: HashMap<String, usize>,
+    /// `IDLE_WATCHDOG`: what to do when the screen stalls during this stage's WAIT FORs
+    #[serde(default)]
+    pub idle_watchdog: Option<IdleWatchdog>,
+}
+
+/// `IDLE_WATCHDOG <duration> [KEY]`: while a WAIT FOR is pending, the screen staying unchanged
+/// for `after` is reported and answered with `key`, e.g. for unexpected "press any key" prompts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct IdleWatchdog {
+    pub after: String,
+    pub key: Option<String>, // Keep-alive key; None only logs the stall
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum StageType {
     Init,
     OsInstall,
@@ -35,38 +82,254 @@ pub enum StageType {
     Pack,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Stage {
+    /// Stage type, followed by the title when the stage has one
+    pub fn display_name(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{} ({})", self.name.name(), title),
+            None => self.name.name().to_string(),
+        }
+    }
+}
+
+impl StageType {
+    /// Parse the stage name used after `STAGE` in spec files
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "init" => Some(StageType::Init),
+            "os_install" => Some(StageType::OsInstall),
+            "os_configure" => Some(StageType::OsConfigure),
+            "pack" => Some(StageType::Pack),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StageType::Init => "init",
+            StageType::OsInstall => "os_install",
+            StageType::OsConfigure => "os_configure",
+            StageType::Pack => "pack",
+        }
+    }
+}
+
+/// Settle window of a `WAIT FOR stable:<duration>` condition, if that's what `condition` is
+pub fn stable_wait_window(condition: &str) -> Option<&str> {
+    condition.trim().strip_prefix("stable:").map(str::trim)
+}
+
+/// Alternatives of a `WAIT FOR "a" | "b"` screen text condition; any other text is one pattern
+pub fn screen_text_patterns(text: &str) -> Vec<&str> {
+    let alternatives: Option<Vec<&str>> = text
+        .split('|')
+        .map(|part| part.trim().strip_prefix('"')?.strip_suffix('"'))
+        .collect();
+    match alternatives {
+        Some(alternatives)
+            if alternatives.len() > 1 && alternatives.iter().all(|pattern| !pattern.is_empty()) =>
+        {
+            alternatives
+        }
+        _ => vec![text],
+    }
+}
+
+/// A spec duration such as `500ms`, `30s`, `5m` or `1h`
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration_lower = duration.to_lowercase();
+    // Check "ms" before "s" so millisecond durations aren't parsed as seconds
+    let (number, scale): (&str, fn(u64) -> Duration) =
+        if let Some(millis) = duration_lower.strip_suffix("ms") {
+            (millis, Duration::from_millis)
+        } else if let Some(secs) = duration_lower.strip_suffix('s') {
+            (secs, Duration::from_secs)
+        } else if let Some(mins) = duration_lower.strip_suffix('m') {
+            (mins, |mins| Duration::from_secs(mins * 60))
+        } else if let Some(hours) = duration_lower.strip_suffix('h') {
+            (hours, |hours| Duration::from_secs(hours * 3600))
+        } else {
+            return Err(anyhow!("Invalid duration format: {}", duration));
+        };
+    let count = number
+        .parse()
+        .with_context(|| format!("Invalid duration format: {}", duration))?;
+    Ok(scale(count))
+}
+
+/// Permission bits of a `COPY --chmod` mode such as `755` or `0640`
+pub fn parse_file_mode(mode: &str) -> Result<i32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    if !(3..=4).contains(&digits.len()) {
+        return Err(anyhow!("Invalid file mode '{}': expected 3 or 4 octal digits", mode));
+    }
+    i32::from_str_radix(digits, 8)
+        .map_err(|_| anyhow!("Invalid file mode '{}': expected octal digits", mode))
+}
+
+/// What a `WAIT FOR <condition>` waits on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitCondition<'a> {
+    /// `stable:<duration>`: the screen stops changing for the window
+    StableScreen(&'a str),
+    /// `port:<port>`: the guest port answers through its host port forward
+    Port(&'a str),
+    /// `ping:<host>`: the host answers an ICMP echo
+    Ping(&'a str),
+    /// Anything else: text read off the screen with OCR
+    ScreenText(&'a str),
+}
+
+impl<'a> WaitCondition<'a> {
+    pub fn parse(condition: &'a str) -> Self {
+        let trimmed = condition.trim();
+        if let Some(window) = stable_wait_window(trimmed) {
+            WaitCondition::StableScreen(window)
+        } else if let Some(port) = trimmed.strip_prefix("port:") {
+            WaitCondition::Port(port.trim())
+        } else if let Some(host) = trimmed.strip_prefix("ping:") {
+            WaitCondition::Ping(host.trim())
+        } else {
+            WaitCondition::ScreenText(condition)
+        }
+    }
+}
+
+/// Key(s) of a PRESS: one key, or `[down, down, enter]` pressed in order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PressKeys {
+    Single(String),
+    Sequence(Vec<String>),
+}
+
+impl PressKeys {
+    pub fn keys(&self) -> &[String] {
+        match self {
+            PressKeys::Single(key) => std::slice::from_ref(key),
+            PressKeys::Sequence(keys) => keys,
+        }
+    }
+}
+
+impl std::fmt::Display for PressKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PressKeys::Single(key) => write!(f, "{}", key),
+            PressKeys::Sequence(keys) => write!(f, "[{}]", keys.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum Instruction {
     // VM Configuration (init stage)
     Vm {
         key: String,
         value: String,
     },
+    // Raw provider arguments passed verbatim (init stage)
+    VmRaw {
+        args: Vec<String>,
+    },
+    // Unattended-install file injected into the source ISO before boot (init stage)
+    AnswerFile {
+        kind: String, // "preseed", "kickstart" or "autoyast"
+        path: PathBuf,
+    },
+    // Additional ISO (e.g. virtio drivers) attached next to the install media (init stage)
+    AttachIso {
+        path: PathBuf,
+        slot: u32, // 1-3, slot 0 is the install ISO
+    },
+    // Additional network adapter (init stage); adapter 1 stays the NAT adapter used for SSH
+    Network {
+        index: u8,
+        mode: String,            // nat, bridged, hostonly or intnet
+        adapter: Option<String>, // Host interface, or internal network name for intnet
+    },
 
     // OS Installation (os_install stage)
     Wait {
-        duration: String,
+        duration: Option<String>, // None uses DEFAULT wait-timeout (conditional WAITs only)
         condition: Option<String>,
+        poll: Option<String>, // `--poll <duration>`, None uses DEFAULT wait-poll
+        /// `--matches <n>`: screen text must be found on n consecutive polls (default 1)
+        #[serde(default)]
+        matches: Option<u32>,
     },
     Press {
-        key: String,
-        repeat: Option<u32>,
-        modifiers: Option<Vec<String>>,
+        key: PressKeys,
+        repeat: Option<u32>, // Repeats the whole key list
+        modifiers: Option<Vec<String>>, // Single keys only, a key list has no modifiers
     },
     Type {
         text: String,
+        unicode: bool, // `--unicode`: non-ASCII via Ctrl+Shift+U hex input (Linux guests)
+        /// `--hold ctrl+alt`: modifiers held down for the whole text
+        #[serde(default)]
+        modifiers: Option<Vec<String>>,
+    },
+    // Interactive pause while developing a spec (os_install and os_configure stages)
+    Breakpoint {
+        message: Option<String>,
+    },
+    /// Wait for `prompt` on the serial console, then answer it with `send` and Enter
+    Expect {
+        prompt: String,
+        send: String,
+        timeout: Option<String>, // Falls back to DEFAULT wait-timeout
+    },
+    /// OCR the screen until `pattern` matches, then store its first capture group as `var`
+    Capture {
+        pattern: String,
+        var: String,
+        timeout: Option<String>, // Falls back to DEFAULT wait-timeout
     },
 
     // OS Configuration (os_configure stage)
     Run {
-        command: String,
+        command: String, // Several lines for a `RUN <<EOF` script, which runs under bash
+        login: Option<String>, // Named LOGIN to use, defaults to the most recent one
+        env: HashMap<String, String>,
+        continue_on_error: bool, // Log a failure and carry on instead of stopping the stage
+        #[serde(default)]
+        sudo: bool, // Run through `sudo -S`, answering its prompt with the LOGIN password
+        #[serde(default)]
+        pty: bool, // Allocate a terminal for commands that misbehave without one
     },
     Copy {
         from: PathBuf,
         to: PathBuf,
+        login: Option<String>,
+        method: String, // "scp" (default) or "guest" for the provider's guest agent
+        #[serde(default)]
+        mode: Option<String>, // Octal permissions of the copied file, 0644 when unset
+        #[serde(default)]
+        owner: Option<String>, // chown'ed to this user and/or group after the copy
+        #[serde(default)]
+        group: Option<String>,
+    },
+    // Pull a file out of the guest to the host over SCP
+    CopyFrom {
+        remote: String,
+        local: PathBuf,
+        login: Option<String>,
+    },
+    // Block until the guest accepts an SSH login with the LOGIN credentials
+    WaitForSsh {
+        timeout: Option<String>, // Falls back to DEFAULT wait-timeout
+        login: Option<String>,
+    },
+    // Write the spec's LABELs as `key=value` lines to a guest file for provenance
+    WriteLabels {
+        path: PathBuf, // Defaults to /etc/isotope-build
+        login: Option<String>,
+        sudo: bool, // Write through `sudo -S`, e.g. into /etc as a non-root LOGIN
     },
     // SSH login configuration for remote operations
     Login {
+        name: String, // Handle referenced by RUN@name / COPY@name, defaults to username
         username: String,
         password: Option<String>,
         private_key: Option<PathBuf>,
@@ -85,6 +348,19 @@ pub enum Instruction {
     VolumeLabel {
         label: String,
     },
+    /// Resize the packed raw image to `size` (e.g. 8G) with qemu-img
+    ResizeDisk {
+        size: String,
+    },
+    /// Sparsify the packed raw image so unused space takes no room on disk
+    Shrink {
+        enabled: bool,
+    },
+    /// Fail the build unless the packed artifact hashes to `value`, for reproducible builds
+    ExpectChecksum {
+        algorithm: String,
+        value: String,
+    },
 }
 
 impl IsotopeSpec {
@@ -92,14 +368,92 @@ impl IsotopeSpec {
         let content = std::fs::read_to_string(&path)
             .with_context(|| format!("Failed to read file: {}", path.as_ref().display()))?;
 
-        parser::parse_isotope_spec(&content)
-            .with_context(|| format!("Failed to parse Isotope spec: {}", path.as_ref().display()))
+        // `.json` specs are the serde form described by `isotope schema`
+        let is_json = path.as_ref().extension().is_some_and(|ext| ext == "json");
+        let mut spec = if is_json {
+            serde_json::from_str(&content).with_context(|| {
+                format!("Failed to parse JSON Isotope spec: {}", path.as_ref().display())
+            })?
+        } else {
+            parser::parse_isotope_spec(&content).with_context(|| {
+                format!("Failed to parse Isotope spec: {}", path.as_ref().display())
+            })?
+        };
+
+        // Relative input paths are relative to the spec file, not the invocation directory
+        if let Some(base_dir) = path.as_ref().parent() {
+            spec.resolve_relative_paths(base_dir);
+        }
+
+        Ok(spec)
+    }
+
+    /// Make relative input file paths (FROM, COPY sources, answer files, extra ISOs and
+    /// private keys) relative to `base_dir`; absolute paths and URLs are left untouched
+    pub fn resolve_relative_paths(&mut self, base_dir: &Path) {
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = base_dir.join(&*path);
+            }
+        };
+
+        if !self.from.contains("://") {
+            let mut from = PathBuf::from(&self.from);
+            resolve(&mut from);
+            self.from = from.to_string_lossy().to_string();
+        }
+
+        for stage in &mut self.stages {
+            for instruction in &mut stage.instructions {
+                match instruction {
+                    Instruction::Copy { from, .. } => resolve(from),
+                    Instruction::CopyFrom { local, .. } => resolve(local),
+                    Instruction::AnswerFile { path, .. } => resolve(path),
+                    Instruction::AttachIso { path, .. } => resolve(path),
+                    Instruction::Login {
+                        private_key: Some(key),
+                        ..
+                    } => resolve(key),
+                    _ => {}
+                }
+            }
+        }
     }
 
     pub fn validate(&self) -> Result<()> {
         validator::validate_spec(self)
     }
 
+    /// Whether any step needs to read text off the screen: a screen text WAIT FOR or
+    /// `VM boot-wait` (stable, port and ping don't) or a CAPTURE
+    pub fn uses_ocr(&self) -> bool {
+        self.ocr_steps().next().is_some()
+    }
+
+    /// Steps that read text off the screen in spec order, e.g. `WAIT FOR "login:"`
+    pub fn ocr_steps(&self) -> impl Iterator<Item = String> + '_ {
+        self.stages
+            .iter()
+            .flat_map(|stage| &stage.instructions)
+            .filter_map(|instruction| match instruction {
+                Instruction::Wait {
+                    condition: Some(condition),
+                    ..
+                } if matches!(WaitCondition::parse(condition), WaitCondition::ScreenText(_)) => {
+                    Some(format!("WAIT FOR \"{}\"", condition))
+                }
+                Instruction::Capture { pattern, .. } => Some(format!("CAPTURE \"{}\"", pattern)),
+                Instruction::Vm { key, value } if key == "boot-wait" => {
+                    let condition = value.trim().trim_matches('"');
+                    let screen_text = parse_duration(condition).is_err()
+                        && matches!(WaitCondition::parse(condition), WaitCondition::ScreenText(_));
+                    screen_text.then(|| format!("VM boot-wait=\"{}\"", condition))
+                }
+                _ => None,
+            })
+    }
+
+    /// First stage of `stage_type`; only os_install and os_configure may occur more than once
     pub fn get_stage(&self, stage_type: &StageType) -> Option<&Stage> {
         self.stages
             .iter()
@@ -109,4 +463,33 @@ impl IsotopeSpec {
     pub fn get_label(&self, key: &str) -> Option<&String> {
         self.labels.get(key)
     }
+
+    /// EXPORT path from the pack stage, or `<name label>.iso` when there is none
+    pub fn export_path(&self, pack_stage: &Stage) -> PathBuf {
+        for instruction in &pack_stage.instructions {
+            if let Instruction::Export { path } = instruction {
+                return path.clone();
+            }
+        }
+
+        // Fall back to default based on spec name
+        let default_name = self
+            .get_label("name")
+            .map(|s| format!("{}.iso", s))
+            .unwrap_or_else(|| "output.iso".to_string());
+
+        PathBuf::from(default_name)
+    }
+
+    /// Hooks to run at `when` for the given stage, in declaration order
+    pub fn get_hooks(&self, when: HookWhen, stage_type: &StageType) -> Vec<&Hook> {
+        self.hooks
+            .iter()
+            .filter(|hook| hook.when == when)
+            .filter(|hook| match &hook.stage {
+                Some(stage) => std::mem::discriminant(stage) == std::mem::discriminant(stage_type),
+                None => true,
+            })
+            .collect()
+    }
 }
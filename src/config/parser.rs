@@ -1,8 +1,15 @@
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
-use super::{ChecksumInfo, Instruction, IsotopeSpec, Stage, StageType};
+use super::{keywords, validator};
+use super::{
+    screen_text_patterns, ChecksumInfo, Hook, HookWhen, IdleWatchdog, Instruction, IsotopeSpec,
+    PressKeys, SpecDefaults, Stage, StageType,
+};
+
+/// Guest file WRITE_LABELS writes when no path is given
+const DEFAULT_LABELS_PATH: &str = "/etc/isotope-build";
 
 pub fn parse_isotope_spec(content: &str) -> Result<IsotopeSpec> {
     let mut lines = content.lines().enumerate().peekable();
@@ -10,11 +17,37 @@ pub fn parse_isotope_spec(content: &str) -> Result<IsotopeSpec> {
     let mut checksum = None;
     let mut labels = HashMap::new();
     let mut stages = Vec::new();
+    let mut hooks = Vec::new();
+    let mut defaults = SpecDefaults::default();
     let mut current_stage: Option<Stage> = None;
+    // A `# @label:` comment waiting for the instruction it names
+    let mut pending_label: Option<(String, usize)> = None;
+    let mut seen_labels = HashSet::new();
 
     while let Some((line_num, line)) = lines.next() {
         let line = line.trim();
 
+        if let Some(label) = parse_step_label(line, line_num + 1)? {
+            if current_stage.is_none() {
+                return Err(anyhow!(
+                    "Line {}: Step label '{}' found outside of stage",
+                    line_num + 1,
+                    label
+                ));
+            }
+            if !seen_labels.insert(label.clone()) {
+                return Err(anyhow!("Line {}: Duplicate step label '{}'", line_num + 1, label));
+            }
+            if let Some((previous, previous_line)) = pending_label.replace((label, line_num + 1)) {
+                return Err(anyhow!(
+                    "Line {}: Step label '{}' is not followed by an instruction",
+                    previous_line,
+                    previous
+                ));
+            }
+            continue;
+        }
+
         // Skip empty lines and comments
         if line.is_empty() || line.starts_with('#') {
             continue;
@@ -54,35 +87,100 @@ pub fn parse_isotope_spec(content: &str) -> Result<IsotopeSpec> {
                     label_parts[1].trim_matches('"').to_string(),
                 );
             }
+            "DEFAULT" => {
+                let (key, value) = args.split_once('=').ok_or_else(|| {
+                    anyhow!(
+                        "Line {}: Invalid DEFAULT format. Expected 'key=value'",
+                        line_num + 1
+                    )
+                })?;
+                let value = Some(value.trim().trim_matches('"').to_string());
+                match key.trim() {
+                    "wait-timeout" => defaults.wait_timeout = value,
+                    "press-delay" => defaults.press_delay = value,
+                    "wait-poll" => defaults.wait_poll = value,
+                    other => {
+                        return Err(anyhow!(
+                            "Line {}: Unknown DEFAULT key '{}'. \
+                             Supported: wait-timeout, press-delay, wait-poll",
+                            line_num + 1,
+                            other
+                        ))
+                    }
+                }
+            }
+            "HOOK" => {
+                hooks.push(parse_hook(args, line_num + 1)?);
+            }
             "STAGE" => {
+                check_label_attached(&pending_label)?;
+
                 // Save previous stage if exists
                 if let Some(stage) = current_stage.take() {
                     stages.push(stage);
                 }
 
-                let stage_type = match args {
-                    "init" => StageType::Init,
-                    "os_install" => StageType::OsInstall,
-                    "os_configure" => StageType::OsConfigure,
-                    "pack" => StageType::Pack,
-                    _ => {
+                // Example: STAGE os_configure post-reboot
+                let (stage_name, title) = match args.split_once(char::is_whitespace) {
+                    Some((stage_name, title)) => (stage_name, Some(title.trim().to_string())),
+                    None => (args, None),
+                };
+                let stage_type = match StageType::from_name(stage_name) {
+                    Some(stage_type) => stage_type,
+                    None => {
                         return Err(anyhow!(
                             "Line {}: Unknown stage type '{}'",
                             line_num + 1,
-                            args
+                            stage_name
                         ))
                     }
                 };
 
                 current_stage = Some(Stage {
                     name: stage_type,
+                    title,
                     instructions: Vec::new(),
+                    step_labels: HashMap::new(),
+                    idle_watchdog: None,
                 });
             }
+            "IDLE_WATCHDOG" => {
+                // Example: IDLE_WATCHDOG 90s enter
+                // A stage setting rather than a step, so it takes no step number
+                let stage = current_stage.as_mut().ok_or_else(|| {
+                    anyhow!("Line {}: IDLE_WATCHDOG found outside of stage", line_num + 1)
+                })?;
+                let watchdog = match args.split_whitespace().collect::<Vec<_>>()[..] {
+                    [after] => IdleWatchdog {
+                        after: after.to_string(),
+                        key: None,
+                    },
+                    [after, key] => IdleWatchdog {
+                        after: after.to_string(),
+                        key: Some(key.to_string()),
+                    },
+                    _ => {
+                        return Err(anyhow!(
+                            "Line {}: Invalid IDLE_WATCHDOG format. Expected '<duration> [KEY]'",
+                            line_num + 1
+                        ))
+                    }
+                };
+                stage.idle_watchdog = Some(watchdog);
+            }
             _ => {
                 // Parse stage-specific instructions
                 if let Some(ref mut stage) = current_stage {
-                    let instruction = parse_stage_instruction(instruction, args, line_num + 1)?;
+                    let mut instruction = parse_stage_instruction(instruction, args, line_num + 1)?;
+                    // `RUN <<EOF` takes the following lines up to `EOF` as its script
+                    if let Instruction::Run { command, .. } = &mut instruction {
+                        if let Some(terminator) = heredoc_terminator(command, line_num + 1)? {
+                            *command = read_heredoc(&mut lines, &terminator, line_num + 1)?;
+                        }
+                    }
+                    if let Some((label, _)) = pending_label.take() {
+                        stage.step_labels.insert(label, stage.instructions.len());
+                    }
                     stage.instructions.push(instruction);
                 } else {
                     return Err(anyhow!(
@@ -95,6 +193,8 @@ pub fn parse_isotope_spec(content: &str) -> Result<IsotopeSpec> {
         }
     }
 
+    check_label_attached(&pending_label)?;
+
     // Save the last stage
     if let Some(stage) = current_stage {
         stages.push(stage);
@@ -109,10 +209,160 @@ pub fn parse_isotope_spec(content: &str) -> Result<IsotopeSpec> {
         checksum,
         labels,
         stages,
+        hooks,
+        defaults,
     })
 }
 
-fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Result<Instruction> {
+/// Read a `# @label: NAME` comment, which lets `--continue-from NAME` find the next instruction
+fn parse_step_label(line: &str, line_num: usize) -> Result<Option<String>> {
+    let Some(label) = line
+        .strip_prefix('#')
+        .and_then(|comment| comment.trim_start().strip_prefix("@label:"))
+    else {
+        return Ok(None);
+    };
+
+    let label = label.trim();
+    if label.is_empty() || label.contains(char::is_whitespace) {
+        return Err(anyhow!(
+            "Line {}: Invalid step label '{}'. Expected '# @label: name' without spaces",
+            line_num,
+            label
+        ));
+    }
+    // A numeric label would be read as a step number by --continue-from
+    if label.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow!(
+            "Line {}: Step label '{}' must not be a number",
+            line_num,
+            label
+        ));
+    }
+
+    Ok(Some(label.to_string()))
+}
+
+/// The terminator of a RUN whose whole command is a heredoc marker: `<<EOF`, `<<'EOF'` or
+/// `<<"EOF"`
+fn heredoc_terminator(command: &str, line_num: usize) -> Result<Option<String>> {
+    let Some(marker) = command.trim().strip_prefix("<<") else {
+        return Ok(None);
+    };
+    let terminator = marker
+        .strip_prefix('\'')
+        .and_then(|marker| marker.strip_suffix('\''))
+        .or_else(|| marker.strip_prefix('"').and_then(|marker| marker.strip_suffix('"')))
+        .unwrap_or(marker);
+
+    if terminator.is_empty()
+        || !terminator.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(anyhow!(
+            "Line {}: Invalid RUN heredoc terminator '{}'. Expected e.g. 'RUN <<EOF'",
+            line_num,
+            marker
+        ));
+    }
+    Ok(Some(terminator.to_string()))
+}
+
+/// Collect the script lines after a `RUN <<EOF` up to the line holding only the terminator
+fn read_heredoc<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    terminator: &str,
+    line_num: usize,
+) -> Result<String> {
+    let mut script = Vec::new();
+    for (_, line) in lines.by_ref() {
+        if line.trim() == terminator {
+            if script.iter().all(|line: &&str| line.trim().is_empty()) {
+                return Err(anyhow!("Line {}: RUN heredoc has an empty script", line_num));
+            }
+            return Ok(script.join("\n"));
+        }
+        script.push(line);
+    }
+    Err(anyhow!(
+        "Line {}: RUN heredoc is missing its '{}' terminator",
+        line_num,
+        terminator
+    ))
+}
+
+fn check_label_attached(pending_label: &Option<(String, usize)>) -> Result<()> {
+    match pending_label {
+        Some((label, line_num)) => Err(anyhow!(
+            "Line {}: Step label '{}' is not followed by an instruction",
+            line_num,
+            label
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Parse `HOOK pre|post[:stage] <command>`
+fn parse_hook(args: &str, line_num: usize) -> Result<Hook> {
+    let (trigger, command) = args.split_once(' ').unwrap_or((args, ""));
+    let command = command.trim();
+    if command.is_empty() {
+        return Err(anyhow!(
+            "Line {}: Invalid HOOK format. Expected 'pre|post[:stage] command'",
+            line_num
+        ));
+    }
+
+    let (when, stage) = match trigger.split_once(':') {
+        Some((when, stage)) => (when, Some(stage)),
+        None => (trigger, None),
+    };
+
+    let when = match when {
+        "pre" => HookWhen::StageStart,
+        "post" => HookWhen::StageEnd,
+        _ => {
+            return Err(anyhow!(
+                "Line {}: Unknown HOOK trigger '{}'. Expected 'pre' or 'post'",
+                line_num,
+                when
+            ))
+        }
+    };
+
+    let stage = match stage {
+        Some(name) => Some(StageType::from_name(name).ok_or_else(|| {
+            anyhow!("Line {}: Unknown stage type '{}' in HOOK", line_num, name)
+        })?),
+        None => None,
+    };
+
+    Ok(Hook {
+        when,
+        stage,
+        command: command.to_string(),
+    })
+}
+
+pub(crate) fn parse_stage_instruction(
+    instruction: &str,
+    args: &str,
+    line_num: usize,
+) -> Result<Instruction> {
+    // SSH instructions may target a named LOGIN, e.g. "RUN@admin whoami"
+    let (instruction, login) = match instruction.split_once('@') {
+        Some((keyword, name)) if keywords::lookup(keyword).is_some_and(|spec| spec.login) => {
+            if name.is_empty() {
+                return Err(anyhow!(
+                    "Line {}: Missing login name after '{}@'",
+                    line_num,
+                    keyword
+                ));
+            }
+            (keyword, Some(name.to_string()))
+        }
+        _ => (instruction, None),
+    };
+
     match instruction {
         // VM Configuration
         "VM" => {
@@ -129,8 +379,84 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
             })
         }
 
+        "VM_RAW" => {
+            let raw_args: Vec<String> = args.split_whitespace().map(String::from).collect();
+            if raw_args.is_empty() {
+                return Err(anyhow!(
+                    "Line {}: VM_RAW requires at least one argument",
+                    line_num
+                ));
+            }
+            Ok(Instruction::VmRaw { args: raw_args })
+        }
+
+        "ANSWER_FILE" => {
+            // Example: ANSWER_FILE preseed ./preseed.cfg
+            let answer_parts: Vec<&str> = args.splitn(2, ' ').collect();
+            if answer_parts.len() != 2 {
+                return Err(anyhow!(
+                    "Line {}: Invalid ANSWER_FILE format. Expected 'kind path'",
+                    line_num
+                ));
+            }
+            Ok(Instruction::AnswerFile {
+                kind: answer_parts[0].to_lowercase(),
+                path: PathBuf::from(answer_parts[1].trim()),
+            })
+        }
+
+        "ATTACH_ISO" => {
+            // Example: ATTACH_ISO ./virtio-win.iso 1
+            // The slot is optional and defaults to 1, the first slot after the install ISO
+            let (path, slot) = match args.rsplit_once(char::is_whitespace) {
+                Some((path, slot)) if slot.parse::<u32>().is_ok() => {
+                    (path.trim(), slot.parse().unwrap())
+                }
+                _ => (args.trim(), 1),
+            };
+            if path.is_empty() {
+                return Err(anyhow!(
+                    "Line {}: ATTACH_ISO requires a path. Expected 'path [slot]'",
+                    line_num
+                ));
+            }
+            Ok(Instruction::AttachIso {
+                path: PathBuf::from(path),
+                slot,
+            })
+        }
+
+        "NETWORK" => {
+            // Example: NETWORK 2 bridged eth0
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            let (index, mode, adapter) = match parts.as_slice() {
+                [index, mode] => (index, mode, None),
+                [index, mode, adapter] => (index, mode, Some(adapter.to_string())),
+                _ => {
+                    return Err(anyhow!(
+                        "Line {}: Invalid NETWORK format. Expected 'index mode [adapter]'",
+                        line_num
+                    ))
+                }
+            };
+            let index = index.parse::<u8>().map_err(|_| {
+                anyhow!("Line {}: Invalid NETWORK adapter index: {}", line_num, index)
+            })?;
+            Ok(Instruction::Network {
+                index,
+                mode: mode.to_string(),
+                adapter,
+            })
+        }
+
         // OS Installation
         "WAIT" => {
+            // "WAIT FOR ..." without a duration falls back to DEFAULT wait-timeout
+            let args = if args.starts_with("FOR ") {
+                format!(" {}", args)
+            } else {
+                args.to_string()
+            };
             if args.contains(" FOR ") {
                 let wait_parts: Vec<&str> = args.splitn(2, " FOR ").collect();
                 let mut condition_text = wait_parts[1].trim();
@@ -140,25 +466,92 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
                     condition_text = condition_text[..comment_pos].trim();
                 }
 
-                // Then strip quotes from the cleaned text
-                condition_text = condition_text.trim_matches('"');
+                // Then strip quotes from the cleaned text, unless it is a `"a" | "b"` list
+                if screen_text_patterns(condition_text).len() == 1 {
+                    condition_text = condition_text.trim_matches('"');
+                }
+
+                // Before FOR: an optional duration, "--poll <duration>" and "--matches <n>"
+                let mut duration = None;
+                let mut poll = None;
+                let mut matches = None;
+                let mut options = wait_parts[0].split_whitespace();
+                while let Some(option) = options.next() {
+                    match option {
+                        "--poll" => {
+                            let interval = options.next().ok_or_else(|| {
+                                anyhow!("Line {}: WAIT --poll requires a duration", line_num)
+                            })?;
+                            poll = Some(interval.to_string());
+                        }
+                        "--matches" => {
+                            let count = options.next().and_then(|count| count.parse().ok());
+                            matches = Some(count.ok_or_else(|| {
+                                anyhow!("Line {}: WAIT --matches requires a count", line_num)
+                            })?);
+                        }
+                        _ if duration.is_none() => duration = Some(option.to_string()),
+                        _ => {
+                            return Err(anyhow!(
+                                "Line {}: Unexpected '{}' in WAIT before FOR",
+                                line_num,
+                                option
+                            ))
+                        }
+                    }
+                }
 
                 Ok(Instruction::Wait {
-                    duration: wait_parts[0].to_string(),
+                    duration,
                     condition: Some(condition_text.to_string()),
+                    poll,
+                    matches,
                 })
+            } else if let Some(option) = args
+                .split_whitespace()
+                .find(|arg| *arg == "--poll" || *arg == "--matches")
+            {
+                Err(anyhow!("Line {}: WAIT {} only applies to WAIT FOR", line_num, option))
             } else {
+                // A bare WAIT has no duration, which the validator rejects
                 Ok(Instruction::Wait {
-                    duration: args.to_string(),
+                    duration: Some(args.trim().to_string()).filter(|d| !d.is_empty()),
                     condition: None,
+                    poll: None,
+                    matches: None,
                 })
             }
         }
         "PRESS" => {
+            // "[down, down, enter]" presses each key in order, while a lone "[" is the key
+            let trimmed = args.trim_start();
+            let bracket_key = trimmed.split_whitespace().next() == Some("[")
+                && !trimmed.contains(']');
+            if let Some(list) = trimmed.strip_prefix('[').filter(|_| !bracket_key) {
+                let (list, rest) = list
+                    .split_once(']')
+                    .ok_or_else(|| anyhow!("Line {}: Missing ']' in PRESS key list", line_num))?;
+                let keys: Vec<String> = list.split(',').map(|key| key.trim().to_string()).collect();
+                if keys.iter().any(|key| key.is_empty()) {
+                    return Err(anyhow!("Line {}: Empty key in PRESS key list", line_num));
+                }
+                if keys.iter().any(|key| key.len() > 1 && key.contains('+')) {
+                    return Err(anyhow!(
+                        "Line {}: Key combinations can't be part of a PRESS key list, \
+                         press them on their own line",
+                        line_num
+                    ));
+                }
+
+                return Ok(Instruction::Press {
+                    key: PressKeys::Sequence(keys),
+                    repeat: parse_press_repeat(rest.split_whitespace()),
+                    modifiers: None,
+                });
+            }
+
             let mut parts = args.split_whitespace();
             let key_or_combo = parts.next().unwrap_or("").to_string();
-            let mut repeat = None;
-            let mut modifiers: Option<Vec<String>> = None;
 
             // Check if this is a key combination (e.g., "ctrl+alt+t")
             if key_or_combo.contains('+') {
@@ -192,48 +585,198 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
 
                     // If all parts are valid modifiers, treat as key combination
                     if valid_modifiers.len() == modifier_parts.len() {
-                        // Check for repeat count
-                        if let Some(next) = parts.next() {
-                            if next == "repeat" || next == "x" {
-                                if let Some(count_str) = parts.next() {
-                                    repeat = count_str.parse().ok();
-                                }
-                            }
-                        }
-
                         return Ok(Instruction::Press {
-                            key: key.to_string(),
-                            repeat,
+                            key: PressKeys::Single(key.to_string()),
+                            repeat: parse_press_repeat(parts),
                             modifiers: Some(valid_modifiers),
                         });
                     }
                 }
             }
 
-            // Regular key press - check for repeat count
-            if let Some(next) = parts.next() {
-                if next == "repeat" || next == "x" {
-                    if let Some(count_str) = parts.next() {
-                        repeat = count_str.parse().ok();
-                    }
+            // Regular key press
+            Ok(Instruction::Press {
+                key: PressKeys::Single(key_or_combo),
+                repeat: parse_press_repeat(parts),
+                modifiers: None,
+            })
+        }
+        "TYPE" => {
+            // Leading `--unicode` and `--hold <modifiers>` options, in any order
+            let mut text = args;
+            let mut unicode = false;
+            let mut modifiers = None;
+            loop {
+                if let Some(rest) = text.strip_prefix("--unicode ") {
+                    unicode = true;
+                    text = rest.trim_start();
+                } else if let Some(rest) = text.strip_prefix("--hold ") {
+                    let (held, rest) = rest.trim_start().split_once(' ').ok_or_else(|| {
+                        anyhow!("Line {}: TYPE --hold requires modifiers and text", line_num)
+                    })?;
+                    modifiers = Some(held.split('+').map(str::to_lowercase).collect());
+                    text = rest.trim_start();
+                } else {
+                    break;
                 }
             }
+            if unicode && modifiers.is_some() {
+                return Err(anyhow!(
+                    "Line {}: TYPE --unicode can't be combined with --hold",
+                    line_num
+                ));
+            }
+            Ok(Instruction::Type {
+                text: text.trim_matches('"').to_string(),
+                unicode,
+                modifiers,
+            })
+        }
+        "EXPECT" => {
+            // EXPECT [timeout] "prompt" SEND "response"
+            let (expect, send) = args.split_once(" SEND").ok_or_else(|| {
+                anyhow!(
+                    "Line {}: Invalid EXPECT format. Expected '[timeout] \"prompt\" SEND \"text\"'",
+                    line_num
+                )
+            })?;
+            let expect = expect.trim();
+            let (timeout, prompt) = if expect.starts_with('"') {
+                (None, expect)
+            } else {
+                let (timeout, prompt) = expect.split_once(' ').unwrap_or((expect, ""));
+                (Some(timeout.to_string()), prompt.trim())
+            };
 
-            Ok(Instruction::Press {
-                key: key_or_combo,
-                repeat,
-                modifiers: None,
+            let prompt = prompt.trim_matches('"');
+            if prompt.is_empty() {
+                return Err(anyhow!("Line {}: EXPECT requires a prompt", line_num));
+            }
+            Ok(Instruction::Expect {
+                prompt: prompt.to_string(),
+                send: send.trim().trim_matches('"').to_string(),
+                timeout,
+            })
+        }
+        "CAPTURE" => {
+            // CAPTURE [timeout] "regex" AS VAR
+            let (pattern, var) = args.rsplit_once(" AS ").ok_or_else(|| {
+                anyhow!(
+                    "Line {}: Invalid CAPTURE format. Expected '[timeout] \"regex\" AS VAR'",
+                    line_num
+                )
+            })?;
+            let pattern = pattern.trim();
+            let (timeout, pattern) = if pattern.starts_with('"') {
+                (None, pattern)
+            } else {
+                let (timeout, pattern) = pattern.split_once(' ').unwrap_or((pattern, ""));
+                (Some(timeout.to_string()), pattern.trim())
+            };
+
+            let pattern = pattern.trim_matches('"');
+            if pattern.is_empty() {
+                return Err(anyhow!("Line {}: CAPTURE requires a pattern", line_num));
+            }
+            Ok(Instruction::Capture {
+                pattern: pattern.to_string(),
+                var: var.trim().to_string(),
+                timeout,
+            })
+        }
+        "BREAKPOINT" | "PAUSE" => {
+            let message = args.trim().trim_matches('"');
+            Ok(Instruction::Breakpoint {
+                message: (!message.is_empty()).then(|| message.to_string()),
             })
         }
-        "TYPE" => Ok(Instruction::Type {
-            text: args.trim_matches('"').to_string(),
-        }),
 
         // OS Configuration
-        "RUN" => Ok(Instruction::Run {
-            command: args.to_string(),
-        }),
+        "RUN" => {
+            // Leading "--env KEY=VALUE" flags set environment variables for the command,
+            // "--continue-on-error" lets the stage carry on if the command fails,
+            // "--sudo" runs it as root with the LOGIN password and "--pty" gives it a terminal
+            let mut env = HashMap::new();
+            let mut continue_on_error = false;
+            let mut sudo = false;
+            let mut pty = false;
+            let mut command = args.trim_start();
+            loop {
+                let flags = [
+                    ("--continue-on-error", &mut continue_on_error),
+                    ("--sudo", &mut sudo),
+                    ("--pty", &mut pty),
+                ];
+                let matched = flags.into_iter().find_map(|(flag, value)| {
+                    let rest = command.strip_prefix(flag)?;
+                    (rest.is_empty() || rest.starts_with(' ')).then_some((rest, value))
+                });
+                if let Some((rest, value)) = matched {
+                    *value = true;
+                    command = rest.trim_start();
+                    continue;
+                }
+                let Some(rest) = command.strip_prefix("--env ") else {
+                    break;
+                };
+                let (key, value, remainder) = parse_env_pair(rest.trim_start()).ok_or_else(|| {
+                    anyhow!(
+                        "Line {}: Invalid RUN --env format. Expected 'KEY=VALUE'",
+                        line_num
+                    )
+                })?;
+                env.insert(key.to_string(), value.to_string());
+                command = remainder.trim_start();
+            }
+            Ok(Instruction::Run {
+                command: command.to_string(),
+                login,
+                env,
+                continue_on_error,
+                sudo,
+                pty,
+            })
+        }
         "COPY" => {
+            // "--guest" copies through the provider's guest agent instead of SCP,
+            // "--chmod=MODE" sets the file mode and "--chown=USER[:GROUP]" its ownership
+            let mut method = "scp";
+            let mut mode = None;
+            let mut owner = None;
+            let mut group = None;
+            let mut args = args.trim_start();
+            while args.starts_with("--") {
+                let (flag, rest) = args.split_once(' ').unwrap_or((args, ""));
+                if flag == "--guest" {
+                    method = "guest";
+                } else if let Some(value) = flag.strip_prefix("--chmod=") {
+                    mode = Some(value.to_string());
+                } else if let Some(value) = flag.strip_prefix("--chown=") {
+                    let (user, group_name) = value.split_once(':').unwrap_or((value, ""));
+                    owner = Some(user.to_string()).filter(|user| !user.is_empty());
+                    group = Some(group_name.to_string()).filter(|group| !group.is_empty());
+                    if owner.is_none() && group.is_none() {
+                        return Err(anyhow!(
+                            "Line {}: COPY --chown needs a user, ':group' or 'user:group'",
+                            line_num
+                        ));
+                    }
+                    if let Some(name) = owner
+                        .iter()
+                        .chain(&group)
+                        .find(|name| !validator::is_valid_owner_name(name))
+                    {
+                        return Err(anyhow!(
+                            "Line {}: Invalid COPY --chown user or group: {}",
+                            line_num,
+                            name
+                        ));
+                    }
+                } else {
+                    return Err(anyhow!("Line {}: Unknown COPY flag: {}", line_num, flag));
+                }
+                args = rest.trim_start();
+            }
             let copy_parts: Vec<&str> = args.splitn(2, ' ').collect();
             if copy_parts.len() != 2 {
                 return Err(anyhow!(
@@ -244,12 +787,60 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
             Ok(Instruction::Copy {
                 from: PathBuf::from(copy_parts[0]),
                 to: PathBuf::from(copy_parts[1]),
+                login,
+                method: method.to_string(),
+                mode,
+                owner,
+                group,
+            })
+        }
+
+        "COPY_FROM" => {
+            let copy_parts: Vec<&str> = args.splitn(2, ' ').collect();
+            if copy_parts.len() != 2 {
+                return Err(anyhow!(
+                    "Line {}: Invalid COPY_FROM format. Expected 'remote_source local_destination'",
+                    line_num
+                ));
+            }
+            Ok(Instruction::CopyFrom {
+                remote: copy_parts[0].to_string(),
+                local: PathBuf::from(copy_parts[1].trim()),
+                login,
+            })
+        }
+        "WAIT_FOR_SSH" => {
+            // Example: WAIT_FOR_SSH 5m
+            let timeout = args.trim();
+            if timeout.contains(' ') {
+                return Err(anyhow!(
+                    "Line {}: Invalid WAIT_FOR_SSH format. Expected 'WAIT_FOR_SSH [timeout]'",
+                    line_num
+                ));
+            }
+            Ok(Instruction::WaitForSsh {
+                timeout: (!timeout.is_empty()).then(|| timeout.to_string()),
+                login,
+            })
+        }
+        "WRITE_LABELS" => {
+            // Example: WRITE_LABELS --sudo /etc/isotope-build
+            let (sudo, path) = match args.trim().strip_prefix("--sudo") {
+                Some(rest) if rest.is_empty() || rest.starts_with(' ') => (true, rest.trim()),
+                _ => (false, args.trim()),
+            };
+            let path = if path.is_empty() { DEFAULT_LABELS_PATH } else { path };
+            Ok(Instruction::WriteLabels {
+                path: PathBuf::from(path),
+                login,
+                sudo,
             })
         }
         // SSH Login
         "LOGIN" => {
-            // Example: LOGIN root password=mypassword
+            // Example: LOGIN root password=mypassword name=admin
             let mut username = String::new();
+            let mut name = None;
             let mut password = None;
             let mut private_key = None;
             let mut parts = args.split_whitespace();
@@ -261,11 +852,13 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
                     match k {
                         "password" => password = Some(v.to_string()),
                         "private_key" => private_key = Some(PathBuf::from(v)),
+                        "name" => name = Some(v.to_string()),
                         _ => {}
                     }
                 }
             }
             Ok(Instruction::Login {
+                name: name.unwrap_or_else(|| username.clone()),
                 username,
                 password,
                 private_key,
@@ -291,6 +884,39 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
             };
             Ok(Instruction::Bootable { enabled })
         }
+        "RESIZE_DISK" => {
+            if args.trim().is_empty() {
+                return Err(anyhow!("Line {}: RESIZE_DISK requires a size", line_num));
+            }
+            Ok(Instruction::ResizeDisk {
+                size: args.trim().to_string(),
+            })
+        }
+        "SHRINK" => {
+            let enabled = match args.to_lowercase().as_str() {
+                "" | "true" | "yes" | "1" => true,
+                "false" | "no" | "0" => false,
+                _ => {
+                    return Err(anyhow!(
+                        "Line {}: Invalid SHRINK value. Expected true/false",
+                        line_num
+                    ))
+                }
+            };
+            Ok(Instruction::Shrink { enabled })
+        }
+        "EXPECT_CHECKSUM" => {
+            let (algorithm, value) = args.trim().split_once(':').ok_or_else(|| {
+                anyhow!(
+                    "Line {}: Invalid EXPECT_CHECKSUM format. Expected 'algorithm:value'",
+                    line_num
+                )
+            })?;
+            Ok(Instruction::ExpectChecksum {
+                algorithm: algorithm.to_lowercase(),
+                value: value.to_lowercase(),
+            })
+        }
         "VOLUME_LABEL" => Ok(Instruction::VolumeLabel {
             label: args.trim_matches('"').to_string(),
         }),
@@ -301,3 +927,756 @@ fn parse_stage_instruction(instruction: &str, args: &str, line_num: usize) -> Re
         )),
     }
 }
+
+/// Repeat count following a PRESS key, written `repeat N` or `x N`
+fn parse_press_repeat<'a>(mut parts: impl Iterator<Item = &'a str>) -> Option<u32> {
+    match parts.next() {
+        Some("repeat" | "x") => parts.next().and_then(|count| count.parse().ok()),
+        _ => None,
+    }
+}
+
+/// Split a RUN `--env` argument into key, value and the rest of the line. The key ends at the
+/// first `=`; a value in double or single quotes may contain spaces, otherwise it ends at the
+/// next space
+fn parse_env_pair(args: &str) -> Option<(&str, &str, &str)> {
+    let (key, rest) = args.split_once('=')?;
+    if key.is_empty() || key.contains(' ') {
+        return None;
+    }
+    match rest.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let (value, remainder) = rest[1..].split_once(quote)?;
+            Some((key, value, remainder))
+        }
+        _ => {
+            let (value, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+            Some((key, value, remainder))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_file_mode, stable_wait_window, WaitCondition};
+
+    #[test]
+    fn test_run_with_login_and_env() {
+        let instruction = parse_stage_instruction(
+            "RUN@admin",
+            "--env FOO=bar --env LANG=C whoami",
+            1,
+        )
+        .unwrap();
+
+        match instruction {
+            Instruction::Run {
+                command,
+                login,
+                env,
+                continue_on_error,
+                sudo,
+                ..
+            } => {
+                assert_eq!(command, "whoami");
+                assert!(!continue_on_error);
+                assert!(!sudo);
+                assert_eq!(login.as_deref(), Some("admin"));
+                assert_eq!(env.get("FOO").map(String::as_str), Some("bar"));
+                assert_eq!(env.get("LANG").map(String::as_str), Some("C"));
+            }
+            other => panic!("Expected RUN, got {:?}", other),
+        }
+
+        let args = "--env GREETING=\"hello world\" --env OPTS='a=1 b=2' --env URL=x=y env";
+        match parse_stage_instruction("RUN", args, 1).unwrap() {
+            Instruction::Run { command, env, .. } => {
+                assert_eq!(command, "env");
+                assert_eq!(env["GREETING"], "hello world");
+                assert_eq!(env["OPTS"], "a=1 b=2");
+                assert_eq!(env["URL"], "x=y");
+            }
+            other => panic!("Expected RUN, got {:?}", other),
+        }
+        assert!(parse_stage_instruction("RUN", "--env FOO=\"unterminated ls", 1).is_err());
+    }
+
+    #[test]
+    fn test_run_continue_on_error() {
+        let args = "--continue-on-error --env A=1 --sudo --pty rm /tmp/x";
+        let instruction = parse_stage_instruction("RUN", args, 1).unwrap();
+
+        match instruction {
+            Instruction::Run {
+                command,
+                env,
+                continue_on_error,
+                sudo,
+                pty,
+                ..
+            } => {
+                assert_eq!(command, "rm /tmp/x");
+                assert_eq!(env.get("A").map(String::as_str), Some("1"));
+                assert!(continue_on_error);
+                assert!(sudo);
+                assert!(pty);
+            }
+            other => panic!("Expected RUN, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_login_name_defaults_to_username() {
+        let instruction = parse_stage_instruction("LOGIN", "ubuntu password=ubuntu", 1).unwrap();
+
+        match instruction {
+            Instruction::Login { name, username, .. } => {
+                assert_eq!(name, "ubuntu");
+                assert_eq!(username, "ubuntu");
+            }
+            other => panic!("Expected LOGIN, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_attach_iso_slot() {
+        let with_slot = parse_stage_instruction("ATTACH_ISO", "./virtio-win.iso 2", 1).unwrap();
+        let without_slot = parse_stage_instruction("ATTACH_ISO", "./drivers.iso", 1).unwrap();
+
+        match (with_slot, without_slot) {
+            (
+                Instruction::AttachIso { path, slot },
+                Instruction::AttachIso {
+                    path: default_path,
+                    slot: default_slot,
+                },
+            ) => {
+                assert_eq!(path, PathBuf::from("./virtio-win.iso"));
+                assert_eq!(slot, 2);
+                assert_eq!(default_path, PathBuf::from("./drivers.iso"));
+                assert_eq!(default_slot, 1);
+            }
+            other => panic!("Expected ATTACH_ISO, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_network_instruction() {
+        let bridged = parse_stage_instruction("NETWORK", "2 bridged eth0", 1).unwrap();
+        match bridged {
+            Instruction::Network {
+                index,
+                mode,
+                adapter,
+            } => {
+                assert_eq!(index, 2);
+                assert_eq!(mode, "bridged");
+                assert_eq!(adapter.as_deref(), Some("eth0"));
+            }
+            other => panic!("Expected NETWORK, got {:?}", other),
+        }
+
+        assert!(parse_stage_instruction("NETWORK", "lan bridged", 1).is_err());
+        assert!(parse_stage_instruction("NETWORK", "2", 1).is_err());
+    }
+
+    #[test]
+    fn test_wait_for_without_duration_uses_defaults() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\nDEFAULT wait-timeout=10m\nSTAGE os_install\nWAIT FOR \"Login\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(spec.defaults.wait_timeout.as_deref(), Some("10m"));
+        match &spec.stages[0].instructions[0] {
+            Instruction::Wait {
+                duration,
+                condition,
+                ..
+            } => {
+                assert!(duration.is_none());
+                assert_eq!(condition.as_deref(), Some("Login"));
+            }
+            other => panic!("Expected WAIT, got {:?}", other),
+        }
+
+        // Without the DEFAULT, a step falling back on it fails validation instead of the build
+        let spec_with = |defaults: &str, install: &str, configure: &str| {
+            parse_isotope_spec(&format!(
+                "FROM ./base.iso\n{}STAGE init\nVM provider=virtualbox\nVM memory=2G\n\
+                 VM cpus=2\nVM disk=20G\nSTAGE os_install\n{}\nSTAGE os_configure\n\
+                 LOGIN root password=root\n{}\nSTAGE pack\nEXPORT ./out.iso\n",
+                defaults, install, configure
+            ))
+            .unwrap()
+        };
+        let error = spec_with("", "WAIT 5s", "WAIT_FOR_SSH").validate().unwrap_err();
+        assert!(error.to_string().contains("DEFAULT wait-timeout"), "{}", error);
+        let error = spec_with("", "WAIT FOR \"login:\"", "RUN true").validate().unwrap_err();
+        assert!(error.to_string().contains("DEFAULT wait-timeout"), "{}", error);
+        let error = spec_with("", "WAIT", "RUN true").validate().unwrap_err();
+        assert!(error.to_string().contains("WAIT requires a duration"), "{}", error);
+        spec_with("DEFAULT wait-timeout=10m\n", "WAIT FOR \"login:\"", "WAIT_FOR_SSH")
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_relative_paths_resolve_against_spec_dir() {
+        let mut spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_configure\n\
+             COPY files/app.conf /etc/app.conf\n\
+             COPY /abs/a /b\n\
+             COPY_FROM /etc/machine-id out/machine-id\n",
+        )
+        .unwrap();
+        spec.resolve_relative_paths(std::path::Path::new("/specs"));
+
+        assert_eq!(spec.from, "/specs/./base.iso");
+        let sources: Vec<_> = spec.stages[0]
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Copy { from, .. } => Some(from.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sources,
+            vec![PathBuf::from("/specs/files/app.conf"), PathBuf::from("/abs/a")]
+        );
+        let expected = PathBuf::from("/specs/out/machine-id");
+        assert!(spec.stages[0].instructions.iter().any(|instruction| matches!(
+            instruction,
+            Instruction::CopyFrom { local, .. } if *local == expected
+        )));
+    }
+
+    #[test]
+    fn test_hook_trigger_and_stage() {
+        let hook = parse_hook("pre:pack zfs snapshot tank/builds@pre-pack", 1).unwrap();
+        assert_eq!(hook.when, HookWhen::StageStart);
+        assert!(matches!(hook.stage, Some(StageType::Pack)));
+        assert_eq!(hook.command, "zfs snapshot tank/builds@pre-pack");
+
+        let hook = parse_hook("post ./notify.sh", 1).unwrap();
+        assert_eq!(hook.when, HookWhen::StageEnd);
+        assert!(hook.stage.is_none());
+
+        assert!(parse_hook("during:pack true", 1).is_err());
+        assert!(parse_hook("pre:pack", 1).is_err());
+    }
+
+    #[test]
+    fn test_wait_for_stable_screen() {
+        let instruction = parse_stage_instruction("WAIT", "2m FOR stable:3s", 1).unwrap();
+
+        match instruction {
+            Instruction::Wait {
+                duration,
+                condition,
+                ..
+            } => {
+                assert_eq!(duration.as_deref(), Some("2m"));
+                assert_eq!(condition.as_deref().and_then(stable_wait_window), Some("3s"));
+            }
+            other => panic!("Expected WAIT, got {:?}", other),
+        }
+        assert_eq!(stable_wait_window("Login"), None);
+    }
+
+    #[test]
+    fn test_wait_condition_kinds() {
+        assert_eq!(WaitCondition::parse("port:22"), WaitCondition::Port("22"));
+        assert_eq!(WaitCondition::parse("ping: 10.0.2.15"), WaitCondition::Ping("10.0.2.15"));
+        assert_eq!(WaitCondition::parse("stable:5s"), WaitCondition::StableScreen("5s"));
+        assert_eq!(WaitCondition::parse("login:"), WaitCondition::ScreenText("login:"));
+    }
+
+    #[test]
+    fn test_step_labels() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_configure\n\
+             RUN apt-get update\n\
+             # @label: post-reboot\n\
+             # a plain comment in between\n\
+             WAIT 2m FOR port:22\n",
+        )
+        .unwrap();
+        let stage = spec.get_stage(&StageType::OsConfigure).unwrap();
+        assert_eq!(stage.step_labels.get("post-reboot"), Some(&1));
+
+        assert!(parse_isotope_spec("FROM x\nSTAGE pack\n# @label: 12\nEXPORT a.iso\n").is_err());
+        assert!(parse_isotope_spec("FROM x\nSTAGE pack\nEXPORT a.iso\n# @label: end\n").is_err());
+    }
+
+    #[test]
+    fn test_multiple_configure_stages() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_install\n\
+             PRESS enter\n\
+             STAGE os_configure pre-reboot\n\
+             RUN apt-get update\n\
+             RUN reboot\n\
+             STAGE os_configure post-reboot\n\
+             # @label: after\n\
+             RUN uname -r\n",
+        )
+        .unwrap();
+
+        let ranges = crate::core::steps::step_ranges(&spec);
+        let summary: Vec<(String, usize, usize)> = ranges
+            .iter()
+            .map(|range| (range.stage(&spec).display_name(), range.first, range.count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("os_install".to_string(), 1, 1),
+                ("os_configure (pre-reboot)".to_string(), 2, 2),
+                ("os_configure (post-reboot)".to_string(), 4, 1),
+            ]
+        );
+        assert_eq!(crate::core::steps::resolve_step(&spec, "after").unwrap(), 4);
+
+        let (range, step_in_stage) = crate::core::steps::stage_for_step(&spec, 3).unwrap();
+        assert_eq!(range.stage(&spec).title.as_deref(), Some("pre-reboot"));
+        assert_eq!(step_in_stage, 2);
+
+        // An os_install after an os_configure can't run in file order
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE init\n\
+             VM provider=virtualbox\n\
+             VM memory=2G\n\
+             VM cpus=2\n\
+             VM disk=20G\n\
+             STAGE os_configure\n\
+             RUN uname -r\n\
+             STAGE os_install\n\
+             PRESS enter\n\
+             STAGE pack\n\
+             EXPORT ./out.iso\n",
+        )
+        .unwrap();
+        let error = spec.validate().unwrap_err().to_string();
+        assert!(error.contains("must come before the first os_configure"), "{}", error);
+    }
+
+    #[test]
+    fn test_expect() {
+        let instruction =
+            parse_stage_instruction("EXPECT", "10m \"login:\" SEND \"root\"", 1).unwrap();
+        match instruction {
+            Instruction::Expect {
+                prompt,
+                send,
+                timeout,
+            } => {
+                assert_eq!(prompt, "login:");
+                assert_eq!(send, "root");
+                assert_eq!(timeout.as_deref(), Some("10m"));
+            }
+            other => panic!("Expected EXPECT, got {:?}", other),
+        }
+
+        let instruction = parse_stage_instruction("EXPECT", "\"Continue? \" SEND", 1).unwrap();
+        match instruction {
+            Instruction::Expect {
+                prompt,
+                send,
+                timeout,
+            } => {
+                assert_eq!(prompt, "Continue? ");
+                assert!(send.is_empty());
+                assert!(timeout.is_none());
+            }
+            other => panic!("Expected EXPECT, got {:?}", other),
+        }
+
+        assert!(parse_stage_instruction("EXPECT", "\"login:\"", 1).is_err());
+    }
+
+    #[test]
+    fn test_capture() {
+        let instruction =
+            parse_stage_instruction("CAPTURE", "2m \"Code: ([A-Z0-9]{6})\" AS SETUP_CODE", 1)
+                .unwrap();
+        match instruction {
+            Instruction::Capture {
+                pattern,
+                var,
+                timeout,
+            } => {
+                assert_eq!(pattern, "Code: ([A-Z0-9]{6})");
+                assert_eq!(var, "SETUP_CODE");
+                assert_eq!(timeout.as_deref(), Some("2m"));
+            }
+            other => panic!("Expected CAPTURE, got {:?}", other),
+        }
+
+        assert!(parse_stage_instruction("CAPTURE", "\"IP: ([0-9.]+)\"", 1).is_err());
+    }
+
+    #[test]
+    fn test_boot_wait_text_uses_ocr() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE init\n\
+             VM boot-wait=\"Install Ubuntu\"\n\
+             STAGE os_install\n\
+             PRESS enter\n",
+        )
+        .unwrap();
+        assert!(spec.uses_ocr());
+        assert_eq!(spec.ocr_steps().next().as_deref(), Some("VM boot-wait=\"Install Ubuntu\""));
+
+        for boot_wait in ["30s", "500ms", "port:22", "stable:5s"] {
+            let spec = parse_isotope_spec(&format!(
+                "FROM ./base.iso\nSTAGE init\nVM boot-wait={}\nSTAGE os_install\nPRESS enter\n",
+                boot_wait
+            ))
+            .unwrap();
+            assert!(!spec.uses_ocr(), "{}", boot_wait);
+        }
+
+        // Conditions other than a duration are checked like WAIT FOR ones
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE init\n\
+             VM provider=virtualbox\n\
+             VM memory=2G\n\
+             VM cpus=2\n\
+             VM disk=20G\n\
+             VM boot-wait=port:8080\n\
+             STAGE os_install\n\
+             PRESS enter\n\
+             STAGE pack\n\
+             EXPORT ./out.iso\n",
+        )
+        .unwrap();
+        let error = spec.validate().unwrap_err().to_string();
+        assert!(error.contains("Invalid boot-wait 'port:8080'"), "{}", error);
+
+        // Nor may a ping host pass itself off as one of ping's options
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE init\n\
+             VM provider=virtualbox\n\
+             VM memory=2G\n\
+             VM cpus=2\n\
+             VM disk=20G\n\
+             VM boot-wait=ping:-f\n\
+             STAGE os_install\n\
+             PRESS enter\n\
+             STAGE pack\n\
+             EXPORT ./out.iso\n",
+        )
+        .unwrap();
+        let error = spec.validate().unwrap_err().to_string();
+        assert!(error.contains("Invalid ping host: '-f'"), "{}", error);
+    }
+
+    #[test]
+    fn test_copy_mode_and_owner() {
+        let args = "--chmod=0755 --chown=app:staff ./run.sh /opt/run.sh";
+        let instruction = parse_stage_instruction("COPY", args, 1).unwrap();
+        match instruction {
+            Instruction::Copy {
+                from,
+                mode,
+                owner,
+                group,
+                ..
+            } => {
+                assert_eq!(from, PathBuf::from("./run.sh"));
+                assert_eq!(mode.as_deref(), Some("0755"));
+                assert_eq!(owner.as_deref(), Some("app"));
+                assert_eq!(group.as_deref(), Some("staff"));
+                assert_eq!(parse_file_mode("0755").unwrap(), 0o755);
+            }
+            other => panic!("Expected COPY, got {:?}", other),
+        }
+
+        assert!(parse_stage_instruction("COPY", "--chown=: ./a /b", 1).is_err());
+        assert!(parse_stage_instruction("COPY", "--chown=app:www-data ./a /b", 1).is_ok());
+        assert!(parse_stage_instruction("COPY", "--chown=app;reboot ./a /b", 1).is_err());
+        assert!(parse_stage_instruction("COPY", "--chown=:$(id) ./a /b", 1).is_err());
+        assert!(parse_file_mode("u+x").is_err());
+    }
+
+    #[test]
+    fn test_press_key_list() {
+        let instruction = parse_stage_instruction("PRESS", "[down, down, enter] x 2", 1).unwrap();
+        match instruction {
+            Instruction::Press {
+                key,
+                repeat,
+                modifiers,
+            } => {
+                assert_eq!(
+                    key,
+                    PressKeys::Sequence(vec!["down".into(), "down".into(), "enter".into()])
+                );
+                assert_eq!(repeat, Some(2));
+                assert!(modifiers.is_none());
+            }
+            other => panic!("Expected PRESS, got {:?}", other),
+        }
+
+        assert!(parse_stage_instruction("PRESS", "[down, ctrl+c]", 1).is_err());
+        assert!(parse_stage_instruction("PRESS", "[down, , enter]", 1).is_err());
+        assert!(parse_stage_instruction("PRESS", "[down", 1).is_err());
+
+        // Bracket keys are still keys, on their own or inside a list
+        let press = |args: &str| match parse_stage_instruction("PRESS", args, 1).unwrap() {
+            Instruction::Press {
+                key,
+                repeat,
+                modifiers,
+            } => (key, repeat, modifiers),
+            other => panic!("Expected PRESS, got {:?}", other),
+        };
+        assert_eq!(press("["), (PressKeys::Single("[".into()), None, None));
+        assert_eq!(press("[ x 3"), (PressKeys::Single("[".into()), Some(3), None));
+        assert_eq!(press("]"), (PressKeys::Single("]".into()), None, None));
+        assert_eq!(
+            press("ctrl+["),
+            (PressKeys::Single("[".into()), None, Some(vec!["ctrl".to_string()]))
+        );
+        assert_eq!(
+            press("[[, enter]"),
+            (PressKeys::Sequence(vec!["[".into(), "enter".into()]), None, None)
+        );
+        assert_eq!(
+            press("[ down, enter ]"),
+            (PressKeys::Sequence(vec!["down".into(), "enter".into()]), None, None)
+        );
+    }
+
+    #[test]
+    fn test_wait_poll_interval() {
+        let instruction =
+            parse_stage_instruction("WAIT", "5m --poll 500ms FOR \"login:\"", 1).unwrap();
+        match instruction {
+            Instruction::Wait {
+                duration,
+                condition,
+                poll,
+                matches,
+            } => {
+                assert_eq!(duration.as_deref(), Some("5m"));
+                assert_eq!(condition.as_deref(), Some("login:"));
+                assert_eq!(poll.as_deref(), Some("500ms"));
+                assert_eq!(matches, None);
+            }
+            other => panic!("Expected WAIT, got {:?}", other),
+        }
+
+        let instruction = parse_stage_instruction("WAIT", "--poll 5s FOR Login", 1).unwrap();
+        assert!(matches!(
+            instruction,
+            Instruction::Wait { duration: None, poll: Some(_), .. }
+        ));
+
+        assert!(parse_stage_instruction("WAIT", "5s --poll 1s", 1).is_err());
+        assert!(parse_stage_instruction("WAIT", "5m --poll FOR Login", 1).is_err());
+    }
+
+    #[test]
+    fn test_wait_matches() {
+        let instruction =
+            parse_stage_instruction("WAIT", "5m --matches 2 FOR \"Install\"", 1).unwrap();
+        assert!(matches!(
+            instruction,
+            Instruction::Wait { matches: Some(2), .. }
+        ));
+
+        assert!(parse_stage_instruction("WAIT", "5m --matches FOR Install", 1).is_err());
+        assert!(parse_stage_instruction("WAIT", "5s --matches 2", 1).is_err());
+    }
+
+    #[test]
+    fn test_run_heredoc() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_configure\n\
+             RUN --sudo <<'EOF'\n\
+             set -e\n\
+             \x20 echo \"{{HOSTNAME}}\" > /etc/hostname\n\
+             \n\
+             # not an instruction\n\
+             EOF\n\
+             RUN uname -r\n",
+        )
+        .unwrap();
+
+        let instructions = &spec.stages[0].instructions;
+        assert_eq!(instructions.len(), 2);
+        let Instruction::Run { command, sudo, .. } = &instructions[0] else {
+            panic!("expected RUN, got {:?}", instructions[0]);
+        };
+        assert!(*sudo);
+        assert_eq!(
+            command,
+            "set -e\n  echo \"{{HOSTNAME}}\" > /etc/hostname\n\n# not an instruction"
+        );
+
+        let unterminated = "FROM ./base.iso\nSTAGE os_configure\nRUN <<EOF\necho hi\n";
+        assert!(parse_isotope_spec(unterminated).is_err());
+        let bad_marker = "FROM ./base.iso\nSTAGE os_configure\nRUN <<E-F\necho hi\nE-F\n";
+        assert!(parse_isotope_spec(bad_marker).is_err());
+    }
+
+    #[test]
+    fn test_keyword_examples_parse() {
+        for keyword in keywords::KEYWORDS {
+            let (name, args) = keyword.example.split_once(' ').unwrap_or((keyword.example, ""));
+            assert_eq!(name, keyword.keyword);
+            if keyword.step {
+                let instruction = parse_stage_instruction(name, args, 1).unwrap();
+                assert_eq!(instruction.keyword(), keyword.keyword);
+            } else {
+                // Directives and stage settings are read by the spec parser itself
+                let spec = match (keyword.keyword, keyword.stages.first()) {
+                    ("FROM", _) => keyword.example.to_string(),
+                    (_, None) => format!("FROM ./base.iso\n{}\n", keyword.example),
+                    (_, Some(stage)) => {
+                        format!("FROM ./base.iso\nSTAGE {}\n{}\n", stage.name(), keyword.example)
+                    }
+                };
+                parse_isotope_spec(&spec).unwrap();
+            }
+        }
+        for alias in ["PAUSE", "WRITE_LABELS", "RUN"] {
+            assert!(keywords::lookup(alias).is_some());
+        }
+    }
+
+    #[test]
+    fn test_wait_for_ssh() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_configure\n\
+             WAIT_FOR_SSH\n\
+             WAIT_FOR_SSH@admin 5m\n",
+        )
+        .unwrap();
+
+        let instructions = &spec.stages[0].instructions;
+        assert!(matches!(
+            &instructions[0],
+            Instruction::WaitForSsh { timeout: None, login: None }
+        ));
+        let Instruction::WaitForSsh { timeout, login } = &instructions[1] else {
+            panic!("expected WAIT_FOR_SSH, got {:?}", instructions[1]);
+        };
+        assert_eq!(timeout.as_deref(), Some("5m"));
+        assert_eq!(login.as_deref(), Some("admin"));
+
+        let extra = "FROM ./base.iso\nSTAGE os_configure\nWAIT_FOR_SSH 5m now\n";
+        assert!(parse_isotope_spec(extra).is_err());
+    }
+
+    #[test]
+    fn test_write_labels() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             LABEL name=\"demo\"\n\
+             STAGE os_configure\n\
+             WRITE_LABELS\n\
+             WRITE_LABELS@admin --sudo /etc/demo-build\n",
+        )
+        .unwrap();
+
+        let instructions = &spec.stages[0].instructions;
+        let Instruction::WriteLabels { path, login, sudo } = &instructions[0] else {
+            panic!("expected WRITE_LABELS, got {:?}", instructions[0]);
+        };
+        assert_eq!(path, &PathBuf::from("/etc/isotope-build"));
+        assert!(login.is_none() && !*sudo);
+        let Instruction::WriteLabels { path, login, sudo } = &instructions[1] else {
+            panic!("expected WRITE_LABELS, got {:?}", instructions[1]);
+        };
+        assert_eq!(path, &PathBuf::from("/etc/demo-build"));
+        assert_eq!(login.as_deref(), Some("admin"));
+        assert!(*sudo);
+    }
+
+    #[test]
+    fn test_idle_watchdog() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE os_install\n\
+             IDLE_WATCHDOG 90s enter\n\
+             WAIT 10m FOR \"Installation complete\"\n",
+        )
+        .unwrap();
+
+        let stage = &spec.stages[0];
+        assert_eq!(
+            stage.idle_watchdog,
+            Some(IdleWatchdog {
+                after: "90s".to_string(),
+                key: Some("enter".to_string()),
+            })
+        );
+        // The watchdog is a stage setting, not a step
+        assert_eq!(stage.instructions.len(), 1);
+
+        assert!(parse_isotope_spec("FROM ./base.iso\nIDLE_WATCHDOG 90s\n").is_err());
+        assert!(parse_isotope_spec(
+            "FROM ./base.iso\nSTAGE os_install\nIDLE_WATCHDOG 90s enter extra\n"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_expect_checksum() {
+        let spec = parse_isotope_spec(
+            "FROM ./base.iso\n\
+             STAGE pack\n\
+             EXPORT ./out.iso\n\
+             EXPECT_CHECKSUM SHA256:ABCDEF\n",
+        )
+        .unwrap();
+
+        let Instruction::ExpectChecksum { algorithm, value } = &spec.stages[0].instructions[1]
+        else {
+            panic!("expected EXPECT_CHECKSUM, got {:?}", spec.stages[0].instructions[1]);
+        };
+        assert_eq!(algorithm, "sha256");
+        assert_eq!(value, "abcdef");
+
+        let missing = "FROM ./base.iso\nSTAGE pack\nEXPECT_CHECKSUM abcdef\n";
+        assert!(parse_isotope_spec(missing).is_err());
+    }
+
+    #[test]
+    fn test_wait_for_any_text() {
+        let instruction = parse_stage_instruction(
+            "WAIT",
+            "60s FOR \"Install Ubuntu\" | \"Try Ubuntu\" | \"GRUB\" # boot menu",
+            1,
+        )
+        .unwrap();
+        let Instruction::Wait { condition: Some(condition), .. } = &instruction else {
+            panic!("expected WAIT FOR, got {:?}", instruction);
+        };
+        assert_eq!(
+            screen_text_patterns(condition),
+            ["Install Ubuntu", "Try Ubuntu", "GRUB"]
+        );
+
+        // A bar inside one quoted pattern is literal text
+        let instruction = parse_stage_instruction("WAIT", "10s FOR \"a | b\"", 1).unwrap();
+        let Instruction::Wait { condition: Some(condition), .. } = &instruction else {
+            panic!("expected WAIT FOR, got {:?}", instruction);
+        };
+        assert_eq!(screen_text_patterns(condition), ["a | b"]);
+    }
+}
@@ -1,10 +1,21 @@
 use anyhow::{anyhow, Result};
 use log::{debug, info};
+use std::collections::HashSet;
 use std::path::Path;
 
+use crate::automation::library_keyboard_input::LibraryBasedKeyboardMapper;
+use crate::automation::vm::{
+    BootOrder, ClockConfig, DeviceConfig, DiskController, Firmware, NetworkAdapterType,
+    Resolution, VmProvider, MAX_ISO_SLOT, MAX_NETWORK_ADAPTERS,
+};
 use crate::config::Stage;
+use crate::core::steps;
+use crate::iso::answer_file::AnswerFileKind;
+use crate::iso::packager;
 
-use super::{Instruction, IsotopeSpec, StageType};
+use super::{
+    keywords, parse_duration, parse_file_mode, Instruction, IsotopeSpec, StageType, WaitCondition,
+};
 
 pub fn validate_spec(spec: &IsotopeSpec) -> Result<()> {
     // Validate FROM instruction
@@ -17,22 +28,155 @@ pub fn validate_spec(spec: &IsotopeSpec) -> Result<()> {
         return Err(anyhow!("At least one stage is required"));
     }
 
-    // Validate each stage
+    // Validate each stage; LOGINs carry over into later os_configure stages
+    let mut known_logins = HashSet::new();
     for stage in &spec.stages {
-        validate_stage(stage)?;
+        validate_stage(stage, &mut known_logins)?;
+    }
+
+    validate_press_keys(spec)?;
+    validate_idle_watchdogs(spec)?;
+
+    let writes_labels = spec.stages.iter().flat_map(|stage| &stage.instructions).any(
+        |instruction| matches!(instruction, Instruction::WriteLabels { .. }),
+    );
+    if writes_labels && spec.labels.is_empty() {
+        return Err(anyhow!("WRITE_LABELS needs at least one LABEL in the spec"));
     }
 
     // Validate stage-specific requirements
     validate_stage_requirements(spec)?;
 
+    validate_defaults(spec)?;
+
+    Ok(())
+}
+
+/// Check PRESS keys and modifiers, and TYPE --hold modifiers, against the keyboard mapper
+/// used at runtime, so a typo fails here instead of deep into a build
+fn validate_press_keys(spec: &IsotopeSpec) -> Result<()> {
+    let mut mapper = LibraryBasedKeyboardMapper::new();
+
+    for range in steps::step_ranges(spec) {
+        let stage = range.stage(spec);
+        for (offset, instruction) in stage.instructions.iter().enumerate() {
+            let step_error = |e: anyhow::Error| {
+                anyhow!(
+                    "Step {} ({} instruction {}): {}",
+                    range.first + offset,
+                    stage.display_name(),
+                    offset + 1,
+                    e
+                )
+            };
+            match instruction {
+                Instruction::Press { key, modifiers, .. } => {
+                    let modifiers = modifiers.as_deref().unwrap_or_default();
+                    for key in key.keys() {
+                        mapper.validate_press(modifiers, key).map_err(step_error)?;
+                    }
+                }
+                Instruction::Type {
+                    modifiers: Some(modifiers),
+                    ..
+                } => {
+                    for modifier in modifiers {
+                        mapper.modifier_to_scancodes(modifier, true).map_err(step_error)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_idle_watchdogs(spec: &IsotopeSpec) -> Result<()> {
+    let mut mapper = LibraryBasedKeyboardMapper::new();
+
+    for stage in &spec.stages {
+        let Some(watchdog) = &stage.idle_watchdog else {
+            continue;
+        };
+        let keyword = keywords::lookup("IDLE_WATCHDOG").expect("IDLE_WATCHDOG is a keyword");
+        if !keyword.valid_in(&stage.name) {
+            return Err(anyhow!(
+                "IDLE_WATCHDOG is only supported in {} stages, not {}",
+                keyword.stage_list(),
+                stage.display_name()
+            ));
+        }
+        if !is_valid_duration(&watchdog.after) {
+            return Err(anyhow!("Invalid IDLE_WATCHDOG duration: {}", watchdog.after));
+        }
+        if let Some(key) = &watchdog.key {
+            mapper.validate_press(&[], key).map_err(|e| {
+                anyhow!("IDLE_WATCHDOG key in {}: {}", stage.display_name(), e)
+            })?;
+        }
+    }
+
     Ok(())
 }
 
-fn validate_stage(stage: &Stage) -> Result<()> {
+fn validate_defaults(spec: &IsotopeSpec) -> Result<()> {
+    let defaults = &spec.defaults;
+    for (key, value) in [
+        ("wait-timeout", &defaults.wait_timeout),
+        ("press-delay", &defaults.press_delay),
+        ("wait-poll", &defaults.wait_poll),
+    ] {
+        if let Some(value) = value {
+            if !is_valid_duration(value) {
+                return Err(anyhow!("Invalid DEFAULT {} duration: {}", key, value));
+            }
+        }
+    }
+
+    // A WAIT FOR, EXPECT, CAPTURE or WAIT_FOR_SSH without its own timeout needs a default to
+    // fall back on. A plain WAIT without a duration was already rejected with its stage.
+    let needs_wait_timeout = spec.stages.iter().any(|stage| {
+        stage.instructions.iter().any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::Wait {
+                    duration: None,
+                    condition: Some(_),
+                    ..
+                } | Instruction::Expect { timeout: None, .. }
+                    | Instruction::Capture { timeout: None, .. }
+                    | Instruction::WaitForSsh { timeout: None, .. }
+            )
+        })
+    });
+    if needs_wait_timeout && defaults.wait_timeout.is_none() {
+        return Err(anyhow!(
+            "WAIT FOR, EXPECT, CAPTURE or WAIT_FOR_SSH without a timeout requires \
+             'DEFAULT wait-timeout=<duration>'"
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_stage<'a>(stage: &'a Stage, known_logins: &mut HashSet<&'a str>) -> Result<()> {
+    for instruction in &stage.instructions {
+        let keyword = instruction.keyword_spec();
+        if !keyword.valid_in(&stage.name) {
+            return Err(anyhow!(
+                "{} is not allowed in the {} stage, only in: {}",
+                keyword.keyword,
+                stage.display_name(),
+                keyword.stage_list()
+            ));
+        }
+    }
+
     match stage.name {
         StageType::Init => validate_init_stage(stage),
         StageType::OsInstall => validate_os_install_stage(stage),
-        StageType::OsConfigure => validate_os_configure_stage(stage),
+        StageType::OsConfigure => validate_os_configure_stage(stage, known_logins),
         StageType::Pack => validate_pack_stage(stage),
     }
 }
@@ -40,6 +184,10 @@ fn validate_stage(stage: &Stage) -> Result<()> {
 fn validate_init_stage(stage: &Stage) -> Result<()> {
     let mut has_vm_provider = false;
     let mut has_vm_memory = false;
+    let mut iso_slots = HashSet::new();
+    let mut network_indexes = HashSet::new();
+    let mut disk_controller = DiskController::default();
+    let mut firmware = None;
 
     for instruction in &stage.instructions {
         match instruction {
@@ -47,9 +195,7 @@ fn validate_init_stage(stage: &Stage) -> Result<()> {
                 match key.as_str() {
                     "provider" => {
                         has_vm_provider = true;
-                        if value.as_str().to_lowercase() != "virtualbox" {
-                            return Err(anyhow!("Invalid VM provider: {}. Only VirtualBox is supported.", value));
-                        }
+                        value.parse::<VmProvider>()?;
                     }
                     "memory" => {
                         has_vm_memory = true;
@@ -68,8 +214,15 @@ fn validate_init_stage(stage: &Stage) -> Result<()> {
                         }
                     }
                     "boot-wait" => {
-                        if !is_valid_duration(value) {
-                            return Err(anyhow!("Invalid boot-wait duration: {}", value));
+                        // Either a duration or a WAIT condition such as screen text
+                        let condition = value.trim().trim_matches('"');
+                        if condition.is_empty() {
+                            return Err(anyhow!("boot-wait needs a duration or a condition"));
+                        }
+                        if !is_valid_duration(condition) {
+                            if let Err(e) = validate_wait_condition(condition) {
+                                return Err(anyhow!("Invalid boot-wait '{}': {}", condition, e));
+                            }
                         }
                     }
                     "timeout" => {
@@ -77,9 +230,93 @@ fn validate_init_stage(stage: &Stage) -> Result<()> {
                             return Err(anyhow!("Invalid timeout duration: {}", value));
                         }
                     }
+                    "disk-controller" => {
+                        disk_controller = value.parse()?;
+                    }
+                    "firmware" => {
+                        firmware = Some(value.parse::<Firmware>()?);
+                    }
+                    "resolution" => {
+                        value.parse::<Resolution>()?;
+                    }
+                    "boot-order" => {
+                        value.parse::<BootOrder>()?;
+                    }
+                    key if ClockConfig::is_clock_key(key) => {
+                        ClockConfig::default().apply(key, value)?;
+                    }
+                    key if DeviceConfig::is_device_key(key) => {
+                        DeviceConfig::default().apply(key, value)?;
+                    }
+                    "os-type" => {
+                        let valid = !value.is_empty()
+                            && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                        if !valid {
+                            return Err(anyhow!("Invalid os-type: {}", value));
+                        }
+                    }
+                    "slow" if !is_valid_duration(value) => {
+                        return Err(anyhow!("Invalid slow mode delay: {}", value));
+                    }
+                    "serial-port" if !value.parse::<u16>().is_ok_and(|port| port != 0) => {
+                        return Err(anyhow!("Invalid serial-port: {}", value));
+                    }
+                    "ssh-port" if !value.parse::<u16>().is_ok_and(|port| port != 0) => {
+                        return Err(anyhow!("Invalid ssh-port: {} (expected 1-65535)", value));
+                    }
                     _ => {} // Allow other VM parameters
                 }
             }
+            Instruction::VmRaw { args } => {
+                if args.is_empty() {
+                    return Err(anyhow!("VM_RAW instruction requires arguments"));
+                }
+            }
+            Instruction::AnswerFile { kind, path } => {
+                kind.parse::<AnswerFileKind>()?;
+                if !path.exists() {
+                    return Err(anyhow!("Answer file does not exist: {}", path.display()));
+                }
+            }
+            Instruction::Network {
+                index,
+                mode,
+                adapter,
+            } => {
+                if !(2..=MAX_NETWORK_ADAPTERS).contains(index) {
+                    return Err(anyhow!(
+                        "NETWORK adapter {} is out of range (2-{}, adapter 1 is the NAT adapter)",
+                        index,
+                        MAX_NETWORK_ADAPTERS
+                    ));
+                }
+                if !network_indexes.insert(*index) {
+                    return Err(anyhow!("NETWORK adapter {} is configured more than once", index));
+                }
+                let adapter_type = mode.parse::<NetworkAdapterType>()?;
+                if adapter_type.requires_attachment() && adapter.is_none() {
+                    return Err(anyhow!(
+                        "NETWORK adapter {} in {} mode needs a host interface",
+                        index,
+                        mode
+                    ));
+                }
+            }
+            Instruction::AttachIso { path, slot } => {
+                if !(1..=MAX_ISO_SLOT).contains(slot) {
+                    return Err(anyhow!(
+                        "ATTACH_ISO slot {} is out of range (1-{}, slot 0 is the install ISO)",
+                        slot,
+                        MAX_ISO_SLOT
+                    ));
+                }
+                if !iso_slots.insert(*slot) {
+                    return Err(anyhow!("ATTACH_ISO slot {} is used more than once", slot));
+                }
+                if !path.exists() {
+                    return Err(anyhow!("ISO file does not exist: {}", path.display()));
+                }
+            }
             _ => {
                 return Err(anyhow!(
                     "Invalid instruction in init stage: {:?}",
@@ -97,36 +334,63 @@ fn validate_init_stage(stage: &Stage) -> Result<()> {
         return Err(anyhow!("VM memory is required in init stage"));
     }
 
+    if disk_controller.requires_efi() && firmware == Some(Firmware::Bios) {
+        return Err(anyhow!(
+            "disk-controller={:?} cannot boot with firmware=bios, use firmware=efi",
+            disk_controller
+        ));
+    }
+
     Ok(())
 }
 
 fn validate_os_install_stage(stage: &Stage) -> Result<()> {
     for instruction in &stage.instructions {
         match instruction {
-            Instruction::Wait { duration, .. } => {
-                if !is_valid_duration(duration) {
-                    return Err(anyhow!("Invalid wait duration: {}", duration));
+            Instruction::Wait {
+                duration,
+                condition,
+                poll,
+                matches,
+            } => {
+                match duration {
+                    Some(duration) if !is_valid_duration(duration) => {
+                        return Err(anyhow!("Invalid wait duration: {}", duration));
+                    }
+                    None if condition.is_none() => {
+                        return Err(anyhow!("WAIT requires a duration"));
+                    }
+                    _ => {}
                 }
-            }
-            Instruction::Press { key, modifiers, .. } => {
-                if key.is_empty() {
-                    return Err(anyhow!("Press instruction requires a key"));
+                if let Some(condition) = condition {
+                    validate_wait_condition(condition)?;
                 }
-
-                // Validate modifiers if present
-                if let Some(modifier_list) = modifiers {
-                    for modifier in modifier_list {
-                        if !matches!(modifier.as_str(), "ctrl" | "alt" | "shift" | "meta" | "cmd") {
-                            return Err(anyhow!("Invalid modifier: {}", modifier));
-                        }
+                if let Some(poll) = poll {
+                    if !is_valid_duration(poll) {
+                        return Err(anyhow!("Invalid WAIT --poll interval: {}", poll));
                     }
                 }
+                if let Some(matches) = matches {
+                    validate_wait_matches(condition.as_deref(), *matches)?;
+                }
+            }
+            Instruction::Press { key, .. } => {
+                if key.keys().iter().all(|key| key.is_empty()) {
+                    return Err(anyhow!("Press instruction requires a key"));
+                }
             }
-            Instruction::Type { text } => {
+            Instruction::Type { text, .. } => {
                 if text.is_empty() {
                     return Err(anyhow!("Type instruction requires text"));
                 }
             }
+            Instruction::Breakpoint { .. } => {}
+            Instruction::Expect { timeout, .. } => validate_expect_timeout(timeout.as_deref())?,
+            Instruction::Capture {
+                pattern,
+                var,
+                timeout,
+            } => validate_capture(pattern, var, timeout.as_deref())?,
             _ => {
                 return Err(anyhow!(
                     "Invalid instruction in os_install stage: {:?}",
@@ -139,15 +403,82 @@ fn validate_os_install_stage(stage: &Stage) -> Result<()> {
     Ok(())
 }
 
-fn validate_os_configure_stage(stage: &Stage) -> Result<()> {
+fn validate_expect_timeout(timeout: Option<&str>) -> Result<()> {
+    match timeout {
+        Some(timeout) if !is_valid_duration(timeout) => {
+            Err(anyhow!("Invalid EXPECT timeout: {}", timeout))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_capture(pattern: &str, var: &str, timeout: Option<&str>) -> Result<()> {
+    let regex = regex::Regex::new(pattern)
+        .map_err(|e| anyhow!("Invalid CAPTURE pattern '{}': {}", pattern, e))?;
+    if regex.captures_len() < 2 {
+        return Err(anyhow!(
+            "CAPTURE pattern '{}' needs a capture group, e.g. 'IP: ([0-9.]+)'",
+            pattern
+        ));
+    }
+    if !is_valid_env_key(var) {
+        return Err(anyhow!("Invalid CAPTURE variable name: {}", var));
+    }
+    match timeout {
+        Some(timeout) if !is_valid_duration(timeout) => {
+            Err(anyhow!("Invalid CAPTURE timeout: {}", timeout))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_os_configure_stage<'a>(
+    stage: &'a Stage,
+    known_logins: &mut HashSet<&'a str>,
+) -> Result<()> {
     for instruction in &stage.instructions {
         match instruction {
-            Instruction::Run { command } => {
+            Instruction::Run {
+                command,
+                login,
+                env,
+                ..
+            } => {
                 if command.is_empty() {
                     return Err(anyhow!("Run instruction requires a command"));
                 }
+                validate_login_reference(login.as_deref(), known_logins)?;
+                for key in env.keys() {
+                    if !is_valid_env_key(key) {
+                        return Err(anyhow!(
+                            "Invalid environment variable name for RUN: {}",
+                            key
+                        ));
+                    }
+                }
             }
-            Instruction::Copy { from, to } => {
+            Instruction::Copy {
+                from,
+                to,
+                login,
+                method,
+                mode,
+                owner,
+                group,
+            } => {
+                validate_login_reference(login.as_deref(), known_logins)?;
+                if !["scp", "guest"].contains(&method.as_str()) {
+                    return Err(anyhow!("Invalid COPY method: {}. Supported: scp, guest", method));
+                }
+                if let Some(mode) = mode {
+                    parse_file_mode(mode)?;
+                }
+                if method == "guest" && (mode.is_some() || owner.is_some() || group.is_some()) {
+                    return Err(anyhow!("COPY --guest does not support --chmod or --chown"));
+                }
+                if let Some(name) = owner.iter().chain(group).find(|n| !is_valid_owner_name(n)) {
+                    return Err(anyhow!("Invalid COPY --chown user or group: {}", name));
+                }
                 if !from.exists() {
                     return Err(anyhow!(
                         "Copy source file does not exist: {}",
@@ -158,35 +489,86 @@ fn validate_os_configure_stage(stage: &Stage) -> Result<()> {
                     return Err(anyhow!("Copy destination cannot be empty"));
                 }
             }
-            Instruction::Wait { duration, .. } => {
-                if !is_valid_duration(duration) {
-                    return Err(anyhow!("Invalid wait duration: {}", duration));
+            Instruction::CopyFrom {
+                remote,
+                local,
+                login,
+            } => {
+                validate_login_reference(login.as_deref(), known_logins)?;
+                if remote.is_empty() {
+                    return Err(anyhow!("COPY_FROM source cannot be empty"));
+                }
+                if local.to_string_lossy().is_empty() {
+                    return Err(anyhow!("COPY_FROM destination cannot be empty"));
                 }
             }
-            Instruction::Press { key, modifiers, .. } => {
-                if key.is_empty() {
-                    return Err(anyhow!("Press instruction requires a key"));
+            Instruction::WaitForSsh { timeout, login } => {
+                validate_login_reference(login.as_deref(), known_logins)?;
+                if let Some(timeout) = timeout {
+                    if !is_valid_duration(timeout) {
+                        return Err(anyhow!("Invalid WAIT_FOR_SSH timeout: {}", timeout));
+                    }
                 }
-
-                // Validate modifiers if present
-                if let Some(modifier_list) = modifiers {
-                    for modifier in modifier_list {
-                        if !matches!(modifier.as_str(), "ctrl" | "alt" | "shift" | "meta" | "cmd") {
-                            return Err(anyhow!("Invalid modifier: {}", modifier));
-                        }
+            }
+            Instruction::WriteLabels { path, login, .. } => {
+                validate_login_reference(login.as_deref(), known_logins)?;
+                if !path.to_string_lossy().starts_with('/') {
+                    return Err(anyhow!(
+                        "WRITE_LABELS path must be absolute in the guest: {}",
+                        path.display()
+                    ));
+                }
+            }
+            Instruction::Wait {
+                duration,
+                condition,
+                poll,
+                matches,
+            } => {
+                match duration {
+                    Some(duration) if !is_valid_duration(duration) => {
+                        return Err(anyhow!("Invalid wait duration: {}", duration));
+                    }
+                    None if condition.is_none() => {
+                        return Err(anyhow!("WAIT requires a duration"));
                     }
+                    _ => {}
+                }
+                if let Some(condition) = condition {
+                    validate_wait_condition(condition)?;
+                }
+                if let Some(poll) = poll {
+                    if !is_valid_duration(poll) {
+                        return Err(anyhow!("Invalid WAIT --poll interval: {}", poll));
+                    }
+                }
+                if let Some(matches) = matches {
+                    validate_wait_matches(condition.as_deref(), *matches)?;
                 }
             }
-            Instruction::Type { text } => {
+            Instruction::Press { key, .. } => {
+                if key.keys().iter().all(|key| key.is_empty()) {
+                    return Err(anyhow!("Press instruction requires a key"));
+                }
+            }
+            Instruction::Type { text, .. } => {
                 if text.is_empty() {
                     return Err(anyhow!("Type instruction requires text"));
                 }
             }
-            Instruction::Login { username, .. } => {
+            Instruction::Login { name, username, .. } => {
                 if username.is_empty() {
                     return Err(anyhow!("Login instruction requires a username"));
                 }
+                known_logins.insert(name.as_str());
             }
+            Instruction::Breakpoint { .. } => {}
+            Instruction::Expect { timeout, .. } => validate_expect_timeout(timeout.as_deref())?,
+            Instruction::Capture {
+                pattern,
+                var,
+                timeout,
+            } => validate_capture(pattern, var, timeout.as_deref())?,
             _ => {
                 return Err(anyhow!(
                     "Invalid instruction in os_configure stage: {:?}",
@@ -199,8 +581,25 @@ fn validate_os_configure_stage(stage: &Stage) -> Result<()> {
     Ok(())
 }
 
+fn validate_login_reference(login: Option<&str>, known_logins: &HashSet<&str>) -> Result<()> {
+    if let Some(name) = login {
+        if !known_logins.contains(name) {
+            return Err(anyhow!(
+                "Unknown login '{}': define it with a LOGIN instruction before use",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
 fn validate_pack_stage(stage: &Stage) -> Result<()> {
     let mut has_export = false;
+    let mut not_raw = None;
+    let mut resizes_image = false;
+    let mut expects_checksum = false;
+    let mut format_limit = None;
+    let mut resize_to = None;
 
     for instruction in &stage.instructions {
         match instruction {
@@ -211,14 +610,44 @@ fn validate_pack_stage(stage: &Stage) -> Result<()> {
                 }
             }
             Instruction::Format { format } => {
-                if !["iso9660", "udf"].contains(&format.as_str()) {
+                if !["iso9660", "udf", "vmdk", "vhd", "vhdx", "docker"].contains(&format.as_str()) {
                     return Err(anyhow!(
-                        "Invalid format: {}. Supported: iso9660, udf",
+                        "Invalid format: {}. Supported: iso9660, udf, vmdk, vhd, vhdx, docker",
                         format
                     ));
                 }
+                not_raw = ["vmdk", "docker"].contains(&format.as_str()).then_some(format);
+                format_limit = packager::max_image_size(format).map(|max| (format, max));
             }
             Instruction::Bootable { .. } => {} // Always valid
+            Instruction::ResizeDisk { size } => {
+                resize_to = Some((size, packager::parse_image_size(size)?));
+                resizes_image = true;
+            }
+            Instruction::Shrink { enabled } => resizes_image |= *enabled,
+            Instruction::ExpectChecksum { algorithm, value } => {
+                if expects_checksum {
+                    return Err(anyhow!("Pack stage allows only one EXPECT_CHECKSUM"));
+                }
+                expects_checksum = true;
+                let length = match algorithm.as_str() {
+                    "sha256" => 64,
+                    "sha512" => 128,
+                    _ => {
+                        return Err(anyhow!(
+                            "Unsupported EXPECT_CHECKSUM algorithm: {}. Supported: sha256, sha512",
+                            algorithm
+                        ))
+                    }
+                };
+                if value.len() != length || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(anyhow!(
+                        "EXPECT_CHECKSUM value must be {} hex digits for {}",
+                        length,
+                        algorithm
+                    ));
+                }
+            }
             Instruction::VolumeLabel { label } => {
                 if label.is_empty() {
                     return Err(anyhow!("Volume label cannot be empty"));
@@ -239,28 +668,55 @@ fn validate_pack_stage(stage: &Stage) -> Result<()> {
     if !has_export {
         return Err(anyhow!("Pack stage requires an EXPORT instruction"));
     }
+    if let (Some(format), true) = (not_raw, resizes_image) {
+        return Err(anyhow!(
+            "RESIZE_DISK and SHRINK apply to raw images and can't be combined with FORMAT {}",
+            format
+        ));
+    }
+    if let (Some((format, max)), Some((size, bytes))) = (format_limit, resize_to) {
+        if bytes > max {
+            return Err(anyhow!(
+                "RESIZE_DISK {} is larger than a {} disk can be ({} GiB)",
+                size,
+                format.to_uppercase(),
+                max >> 30
+            ));
+        }
+    }
 
     Ok(())
 }
 
 fn validate_stage_requirements(spec: &IsotopeSpec) -> Result<()> {
-    let mut has_init = false;
-    let mut has_pack = false;
-
-    for stage in &spec.stages {
-        match stage.name {
-            StageType::Init => has_init = true,
-            StageType::Pack => has_pack = true,
-            _ => {}
+    // os_install and os_configure may be split into several stages, run in file order
+    for stage_type in [StageType::Init, StageType::Pack] {
+        match spec.stages.iter().filter(|stage| stage.name == stage_type).count() {
+            0 => return Err(anyhow!("{} stage is required", stage_type.name())),
+            1 => {}
+            _ => return Err(anyhow!("Only one {} stage is allowed", stage_type.name())),
         }
     }
 
-    if !has_init {
-        return Err(anyhow!("init stage is required"));
-    }
-
-    if !has_pack {
-        return Err(anyhow!("pack stage is required"));
+    // The installer runs before the installed OS is configured, so the builder can't honour
+    // an os_install that the file puts after an os_configure
+    let first_configure = spec
+        .stages
+        .iter()
+        .position(|stage| stage.name == StageType::OsConfigure);
+    let last_install = spec
+        .stages
+        .iter()
+        .rposition(|stage| stage.name == StageType::OsInstall);
+    if let (Some(first_configure), Some(last_install)) = (first_configure, last_install) {
+        if last_install > first_configure {
+            return Err(anyhow!(
+                "{} comes after {}; every os_install stage must come before the first \
+                 os_configure stage",
+                spec.stages[last_install].display_name(),
+                spec.stages[first_configure].display_name()
+            ));
+        }
     }
 
     Ok(())
@@ -312,21 +768,55 @@ fn is_valid_disk_size(size: &str) -> bool {
     false
 }
 
-fn is_valid_duration(duration: &str) -> bool {
-    let duration_lower = duration.to_lowercase();
-
-    // Check longer suffixes first to avoid conflicts (ms before s)
-    if duration_lower.ends_with("ms") {
-        return duration_lower.trim_end_matches("ms").parse::<u64>().is_ok();
+fn validate_wait_condition(condition: &str) -> Result<()> {
+    match WaitCondition::parse(condition) {
+        WaitCondition::StableScreen(window) if !is_valid_duration(window) => {
+            Err(anyhow!("Invalid stable window: {}", window))
+        }
+        // Only the SSH port is forwarded to the host
+        WaitCondition::Port(port) if port != "22" && port != "ssh" => Err(anyhow!(
+            "Invalid wait port '{}': only port:22 (the forwarded SSH port) is supported",
+            port
+        )),
+        // A leading '-' would make ping read the host as an option
+        WaitCondition::Ping(host)
+            if host.is_empty() || host.starts_with('-') || host.contains(char::is_whitespace) =>
+        {
+            Err(anyhow!("Invalid ping host: '{}'", host))
+        }
+        _ => Ok(()),
     }
-    if duration_lower.ends_with("s") {
-        return duration_lower.trim_end_matches("s").parse::<u64>().is_ok();
+}
+
+/// `WAIT --matches` debounces OCR, so it needs screen text and at least one match
+fn validate_wait_matches(condition: Option<&str>, matches: u32) -> Result<()> {
+    if matches == 0 {
+        return Err(anyhow!("WAIT --matches must be at least 1"));
     }
-    if duration_lower.ends_with("m") {
-        return duration_lower.trim_end_matches("m").parse::<u64>().is_ok();
+    match condition.map(WaitCondition::parse) {
+        Some(WaitCondition::ScreenText(_)) => Ok(()),
+        _ => Err(anyhow!("WAIT --matches only applies to screen text conditions")),
     }
-    if duration_lower.ends_with("h") {
-        return duration_lower.trim_end_matches("h").parse::<u64>().is_ok();
+}
+
+pub(crate) fn is_valid_duration(duration: &str) -> bool {
+    parse_duration(duration).is_ok()
+}
+
+/// A COPY --chown user or group name, which ends up in a guest shell command
+pub(crate) fn is_valid_owner_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn is_valid_env_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
     }
-    false
 }
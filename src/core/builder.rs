@@ -1,23 +1,145 @@
 use anyhow::{anyhow, Context, Result};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tracing::{debug, error, info, warn};
 
 use crate::automation::{
+    ocr::OcrOptions,
     puppet::PuppetManager,
-    vm::{VmInstance, VmManager},
+    ssh_error::SshError,
+    vm::{BootOrder, Capability, Resolution, VmInstance, VmManager, VmState},
 };
-use crate::config::{IsotopeSpec, StageType};
+use crate::config::{HookWhen, Instruction, IsotopeSpec, StageType, WaitCondition};
+use crate::core::fingerprint;
+use crate::core::hooks::{self, HookContext};
+use crate::core::steps::{self, StepRange};
+use crate::core::profile::{BuildProfile, PhaseTiming, VmStateChange};
+use crate::core::report::BuildReport;
+use crate::iso::answer_file::{self, AnswerFileKind};
+use crate::iso::cache::IsoCache;
 use crate::iso::{extractor::IsoExtractor, packager::IsoPackager};
+use crate::utils::redact;
+use crate::utils::secrets::SecretStore;
 use crate::utils::{checksum::ChecksumVerifier, fs::FileSystemManager, VmMetadata};
 
+/// Working directory subdirectory the VM provider writes its temporary screenshots to
+const SCREENSHOT_DIR: &str = "screenshots";
+/// Output directory subdirectory holding `--record` frames; the video is named after it
+const RECORDING_DIR: &str = "recording";
+
+/// What `--verify-boot` waits for once the packed image is booting
+#[derive(Debug, Clone, PartialEq)]
+pub enum BootCheck {
+    /// The VM is still running after the init stage `boot-wait`
+    Running,
+    /// Text on the screen, read with OCR
+    ScreenText(String),
+    /// Text in the serial console log
+    Serial(String),
+}
+
+impl std::str::FromStr for BootCheck {
+    type Err = anyhow::Error;
+
+    /// Parses `serial:TEXT`, `screen:TEXT` or bare screen text; empty means `Running`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let check = if s.trim().is_empty() {
+            Self::Running
+        } else if let Some(text) = s.strip_prefix("serial:") {
+            Self::Serial(text.trim().to_string())
+        } else {
+            Self::ScreenText(s.strip_prefix("screen:").unwrap_or(s).trim().to_string())
+        };
+
+        match &check {
+            Self::Serial(text) | Self::ScreenText(text) if text.is_empty() => {
+                Err(anyhow!("Boot check '{}' has no text to wait for", s))
+            }
+            _ => Ok(check),
+        }
+    }
+}
+
+impl std::fmt::Display for BootCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Running => write!(f, "VM still running after boot wait"),
+            Self::ScreenText(text) => write!(f, "screen text '{}'", text),
+            Self::Serial(text) => write!(f, "serial output '{}'", text),
+        }
+    }
+}
+
+/// Whether a failed build is worth starting over: flaky networking or a host port taken by
+/// someone else, as opposed to an invalid spec or a step that failed on its own
+fn is_transient_failure(error: &anyhow::Error) -> bool {
+    // A command that ran and exited non-zero is deterministic, whatever its output says
+    if let Some(ssh_error) = error.chain().find_map(|cause| cause.downcast_ref::<SshError>()) {
+        return ssh_error.is_transient();
+    }
+
+    error.chain().any(|cause| {
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io_error.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::AddrInUse
+            );
+        }
+        if let Some(http_error) = cause.downcast_ref::<ureq::Error>() {
+            return !matches!(http_error, ureq::Error::StatusCode(code) if *code < 500);
+        }
+        // VBoxManage only reports a forwarded port lost to another process as text
+        let message = cause.to_string().to_lowercase();
+        message.contains("address already in use")
+            || message.contains("failed to set up redirection")
+    })
+}
+
 pub struct Builder {
     spec: IsotopeSpec,
     spec_file_path: Option<PathBuf>,
     working_dir: PathBuf,
     output_path: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
     continue_from_step: Option<usize>,
+    force_recreate: bool,
+    /// Fresh attempts allowed after a build fails on a transient error
+    retries: u32,
+    slow_mode: Option<String>,
+    keep_going: bool,
+    step_snapshots: bool,
+    secrets: SecretStore,
+    resolution: Option<Resolution>,
+    memory_mb: Option<u64>,
+    cpus: Option<u32>,
+    vm_name: Option<String>,
+    iso_cache: Option<IsoCache>,
+    interactive: bool,
+    ocr_monitor: bool,
+    /// `--record` screenshot interval, and the `--record-video` format (mp4 or gif)
+    record_interval: Option<String>,
+    record_video: Option<String>,
+    dump_scancodes: bool,
+    /// Boot the packed image in a throwaway VM and fail unless this check passes in time
+    verify_boot: Option<(BootCheck, Duration)>,
+    /// Print a per-phase timing breakdown and add it to report.json
+    profile: bool,
+    phase_timings: std::sync::Mutex<Vec<PhaseTiming>>,
+    /// Global number of the step that failed this build attempt, for `build --continue`, and
+    /// whether the pre-step snapshot was taken before that step
+    failed_step: std::sync::Mutex<Option<(usize, bool)>>,
+    /// Roll back to the pre-step snapshot before re-running the failed step
+    restore_failed_step: bool,
+    /// Leave the working directory and its per-stage subdirectories behind after the build
+    keep_working_dir: bool,
+    /// Skip the build when the artifact's stored fingerprint matches the current inputs
+    skip_if_unchanged: bool,
     vm_manager: Arc<Mutex<VmManager>>,
     puppet_manager: Arc<Mutex<PuppetManager>>,
     iso_extractor: IsoExtractor,
@@ -27,110 +149,257 @@ pub struct Builder {
 }
 
 impl Builder {
-    pub fn new(spec: IsotopeSpec) -> Self {
-        let working_dir = std::env::temp_dir().join(format!("isotope-{}", uuid::Uuid::new_v4()));
-
-        Self {
-            spec,
-            spec_file_path: None,
-            working_dir: working_dir.clone(),
-            output_path: None,
-            continue_from_step: None,
-            vm_manager: Arc::new(Mutex::new(VmManager::new())),
-            puppet_manager: Arc::new(Mutex::new(PuppetManager::new())),
-            iso_extractor: IsoExtractor::new(),
-            iso_packager: IsoPackager::new(),
-            fs_manager: FileSystemManager::new(working_dir),
-            checksum_verifier: ChecksumVerifier::new(),
-        }
+    pub fn new(spec: IsotopeSpec) -> Result<Self> {
+        Self::new_with_ocr_debug(spec, false, Some(OcrOptions::default()))
     }
 
-    pub fn new_with_ocr_debug(spec: IsotopeSpec, ocr_debug: bool) -> Self {
+    /// `ocr_options` of `None` (`--no-ocr`) never loads the OCR models
+    pub fn new_with_ocr_debug(
+        spec: IsotopeSpec,
+        ocr_debug: bool,
+        ocr_options: Option<OcrOptions>,
+    ) -> Result<Self> {
+        let puppet_manager = match ocr_options {
+            None => {
+                if let Some(step) = spec.ocr_steps().next() {
+                    return Err(anyhow!(
+                        "--no-ocr disables OCR, but {} reads text off the screen",
+                        step
+                    ));
+                }
+                info!("OCR disabled, skipping model loading");
+                PuppetManager::without_ocr(ocr_debug)
+            }
+            Some(ocr_options) => match PuppetManager::new_with_ocr_debug(ocr_debug, ocr_options) {
+                Ok(puppet_manager) => puppet_manager,
+                Err(e) if !spec.uses_ocr() => {
+                    warn!("OCR unavailable, continuing since no step reads screen text: {:#}", e);
+                    PuppetManager::without_ocr(ocr_debug)
+                }
+                Err(e) => {
+                    return Err(e.context(
+                        "Failed to initialize OCR, which this spec's screen text steps need \
+                         (the models are downloaded on first use; check network or proxy access)",
+                    ))
+                }
+            },
+        };
+
         let working_dir = std::env::temp_dir().join(format!("isotope-{}", uuid::Uuid::new_v4()));
+        let mut vm_manager = VmManager::new();
+        vm_manager.set_screenshot_dir(working_dir.join(SCREENSHOT_DIR));
 
-        Self {
+        Ok(Self {
             spec,
             spec_file_path: None,
             working_dir: working_dir.clone(),
             output_path: None,
+            output_dir: None,
             continue_from_step: None,
-            vm_manager: Arc::new(Mutex::new(VmManager::new())),
-            puppet_manager: Arc::new(Mutex::new(PuppetManager::new_with_ocr_debug(ocr_debug))),
+            force_recreate: false,
+            retries: 0,
+            slow_mode: None,
+            keep_going: false,
+            step_snapshots: false,
+            secrets: SecretStore::new(),
+            resolution: None,
+            memory_mb: None,
+            cpus: None,
+            vm_name: None,
+            iso_cache: None,
+            interactive: false,
+            ocr_monitor: false,
+            record_interval: None,
+            record_video: None,
+            dump_scancodes: false,
+            verify_boot: None,
+            profile: false,
+            phase_timings: std::sync::Mutex::new(Vec::new()),
+            failed_step: std::sync::Mutex::new(None),
+            restore_failed_step: false,
+            keep_working_dir: false,
+            skip_if_unchanged: false,
+            vm_manager: Arc::new(Mutex::new(vm_manager)),
+            puppet_manager: Arc::new(Mutex::new(puppet_manager)),
             iso_extractor: IsoExtractor::new(),
-            iso_packager: IsoPackager::new(),
+            iso_packager: IsoPackager::new(
+                working_dir.join(FileSystemManager::stage_dir_name(&StageType::Pack)),
+            ),
             fs_manager: FileSystemManager::new(working_dir),
             checksum_verifier: ChecksumVerifier::new(),
-        }
+        })
     }
 
     pub fn set_output_path(&mut self, path: PathBuf) {
         self.output_path = Some(path);
     }
 
-    pub fn set_continue_from_step(&mut self, step: usize) {
+    pub fn set_output_dir(&mut self, dir: PathBuf) {
+        self.output_dir = Some(dir);
+    }
+
+    /// Continue from a step number or `# @label:` name, returning the resolved step
+    pub fn set_continue_from(&mut self, target: &str) -> Result<usize> {
+        let step = steps::resolve_step(&self.spec, target)?;
         self.continue_from_step = Some(step);
+        Ok(step)
     }
 
-    pub fn set_spec_file_path(&mut self, path: PathBuf) {
-        self.spec_file_path = Some(path);
+    pub fn set_force_recreate(&mut self, force: bool) {
+        self.force_recreate = force;
     }
 
-    fn get_stage_step_mapping(&self, target_step: usize) -> Result<(StageType, usize)> {
-        let mut current_step = 1;
+    pub fn set_keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
 
-        // Check os_install stage
-        if let Some(os_install_stage) = self.spec.get_stage(&StageType::OsInstall) {
-            let stage_end = current_step + os_install_stage.instructions.len() - 1;
-            if target_step >= current_step && target_step <= stage_end {
-                let step_in_stage = target_step - current_step + 1;
-                return Ok((StageType::OsInstall, step_in_stage));
-            }
-            current_step += os_install_stage.instructions.len();
-        }
+    /// Pause at BREAKPOINT instructions and wait for the user
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive;
+    }
 
-        // Check os_configure stage
-        if let Some(os_configure_stage) = self.spec.get_stage(&StageType::OsConfigure) {
-            let stage_end = current_step + os_configure_stage.instructions.len() - 1;
-            if target_step >= current_step && target_step <= stage_end {
-                let step_in_stage = target_step - current_step + 1;
-                return Ok((StageType::OsConfigure, step_in_stage));
-            }
-            current_step += os_configure_stage.instructions.len();
-        }
+    /// Let the OCR background monitor watch the screen and drive WAIT FOR conditions
+    pub fn set_ocr_monitor(&mut self, enabled: bool) {
+        self.ocr_monitor = enabled;
+    }
 
-        Err(anyhow!(
-            "Step {} is out of range. Total steps available: {}",
-            target_step,
-            current_step - 1
-        ))
+    /// Save a screenshot every `interval` from VM start until the build ends, optionally
+    /// assembled into a `video` (mp4 or gif)
+    pub fn set_recording(&mut self, interval: String, video: Option<String>) {
+        self.record_interval = Some(interval);
+        self.record_video = video;
     }
 
-    fn print_step_summary(&self) {
-        let mut current_step = 1;
+    /// Log the characters and scancodes sent for every TYPE instruction
+    pub fn set_dump_scancodes(&mut self, enabled: bool) {
+        self.dump_scancodes = enabled;
+    }
 
-        info!("Step summary:");
+    /// Boot the packed image after the pack stage and wait up to `timeout` for `check`
+    pub fn set_verify_boot(&mut self, check: BootCheck, timeout: Duration) {
+        self.verify_boot = Some((check, timeout));
+    }
 
-        if let Some(os_install_stage) = self.spec.get_stage(&StageType::OsInstall) {
-            info!(
-                "  Steps {}-{}: os_install stage ({} instructions)",
-                current_step,
-                current_step + os_install_stage.instructions.len() - 1,
-                os_install_stage.instructions.len()
+    pub fn set_keep_working_dir(&mut self, keep: bool) {
+        self.keep_working_dir = keep;
+    }
+
+    pub fn set_skip_if_unchanged(&mut self, skip: bool) {
+        self.skip_if_unchanged = skip;
+    }
+
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    pub fn set_profile(&mut self, profile: bool) {
+        self.profile = profile;
+    }
+
+    /// Keep extracted source ISOs in `dir`, keyed by checksum, and reuse them across builds
+    pub fn set_iso_cache_dir(&mut self, dir: PathBuf) {
+        self.iso_cache = Some(IsoCache::new(dir));
+    }
+
+    /// Fixed VM name; an empty name derives one from the spec file path
+    pub fn set_vm_name(&mut self, name: String) -> Result<()> {
+        let name = if name.is_empty() {
+            let spec_file_path = self
+                .spec_file_path
+                .as_ref()
+                .ok_or_else(|| anyhow!("Deriving a VM name needs the spec file path"))?;
+            VmMetadata::derived_vm_name(spec_file_path)?
+        } else {
+            name
+        };
+
+        info!("Using fixed VM name {}", name);
+        self.vm_name = Some(name);
+        Ok(())
+    }
+
+    /// Guest memory in MB, taking precedence over the init stage `memory` key
+    pub fn set_memory_mb(&mut self, memory_mb: u64) {
+        self.memory_mb = Some(memory_mb);
+    }
+
+    /// Virtual CPU count, taking precedence over the init stage `cpus` key
+    pub fn set_cpus(&mut self, cpus: u32) {
+        self.cpus = Some(cpus);
+    }
+
+    /// Guest display mode, taking precedence over the init stage `resolution` key
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = Some(resolution);
+    }
+
+    /// Values for `{{secret:NAME}}` tokens; names missing here are looked up in the OS keyring
+    pub fn set_secrets(&mut self, secrets: SecretStore) {
+        self.secrets = secrets;
+    }
+
+    /// Snapshot the VM before each step so a failed build can be resumed from that state
+    pub fn set_step_snapshots(&mut self, enabled: bool) {
+        self.step_snapshots = enabled;
+    }
+
+    /// Resume from the step recorded as failed by the previous build of this spec
+    pub fn set_continue_from_failed_step(&mut self) -> Result<usize> {
+        let spec_file_path = self
+            .spec_file_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("--continue requires the spec file path"))?;
+
+        let metadata = VmMetadata::load_from_current_dir()?;
+        let entry = metadata.get_vm_for_isotope_file(spec_file_path);
+        let step = entry.and_then(|entry| entry.failed_step).ok_or_else(|| {
+            anyhow!(
+                "No failed step recorded for {}. Use --continue-from <STEP> instead.",
+                spec_file_path.display()
+            )
+        })?;
+
+        info!("Previous build failed at step {}", step);
+        self.continue_from_step = Some(step);
+        // The snapshot is only refreshed by --step-snapshots builds and may predate the failure
+        self.restore_failed_step = entry.and_then(|entry| entry.snapshot_step) == Some(step);
+        if !self.restore_failed_step {
+            warn!(
+                "No pre-step snapshot was taken before step {}; \
+                 resuming from the VM's current state",
+                step
             );
-            current_step += os_install_stage.instructions.len();
         }
+        Ok(step)
+    }
 
-        if let Some(os_configure_stage) = self.spec.get_stage(&StageType::OsConfigure) {
+    /// Delay inserted after every instruction, e.g. "1s" or "500ms"
+    pub fn set_slow_mode(&mut self, delay: String) {
+        self.slow_mode = Some(delay);
+    }
+
+    pub fn set_spec_file_path(&mut self, path: PathBuf) {
+        self.spec_file_path = Some(path);
+    }
+
+    fn get_stage_step_mapping(&self, target_step: usize) -> Result<(StepRange, usize)> {
+        steps::stage_for_step(&self.spec, target_step)
+    }
+
+    fn print_step_summary(&self) {
+        info!("Step summary:");
+
+        for range in steps::step_ranges(&self.spec) {
             info!(
-                "  Steps {}-{}: os_configure stage ({} instructions)",
-                current_step,
-                current_step + os_configure_stage.instructions.len() - 1,
-                os_configure_stage.instructions.len()
+                "  Steps {}-{}: {} stage ({} instructions)",
+                range.first,
+                range.last(),
+                range.stage(&self.spec).display_name(),
+                range.count
             );
-            current_step += os_configure_stage.instructions.len();
         }
 
-        info!("Total steps: {}", current_step - 1);
+        info!("Total steps: {}", steps::total_steps(&self.spec));
     }
 
     fn get_existing_vm_from_metadata(&self) -> Result<Option<VmInstance>> {
@@ -150,7 +419,7 @@ impl Builder {
             // The actual VM status check will happen when we try to use it
             let config = crate::automation::vm::VmConfig::default();
             
-            let vm_instance = VmInstance::new(
+            let mut vm_instance = VmInstance::new(
                 vm_entry.vm_id.clone(),
                 vm_entry.vm_name.clone(),
                 vm_entry
@@ -159,6 +428,7 @@ impl Builder {
                     .map_err(|_| anyhow!("Invalid provider: {}", vm_entry.provider))?,
                 config,
             );
+            vm_instance.screenshot_dir = Some(self.working_dir.join(SCREENSHOT_DIR));
 
             info!("Will attempt to reuse existing VM {}", vm_entry.vm_name);
             return Ok(Some(vm_instance));
@@ -181,29 +451,77 @@ impl Builder {
         Ok(())
     }
 
+    /// Returns true when the VM was rolled back to its pre-step snapshot
+    async fn restore_failed_step_snapshot(
+        &self,
+        vm_manager: &VmManager,
+        vm_instance: &VmInstance,
+    ) -> bool {
+        if !self.restore_failed_step {
+            return false;
+        }
+
+        match vm_manager.restore_pre_step_snapshot(vm_instance).await {
+            Ok(()) => {
+                info!("Restored VM {} to its state before the failed step", vm_instance.name);
+                true
+            }
+            Err(e) => {
+                warn!(
+                    "Could not restore pre-step snapshot ({:#}); \
+                     re-running from the current VM state",
+                    e
+                );
+                false
+            }
+        }
+    }
+
     async fn ensure_vm_running(
         &self,
         vm_manager: &mut VmManager,
         vm_instance: &VmInstance,
     ) -> Result<()> {
-        let is_running = vm_manager
+        let state = vm_manager
             .get_provider(&vm_instance.provider)?
-            .is_running(vm_instance)
+            .vm_state(vm_instance)
             .await
-            .unwrap_or(false);
+            .unwrap_or(VmState::Stopped);
 
-        if is_running {
+        if state == VmState::Running {
             info!("VM {} is already running", vm_instance.name);
+            self.start_recording(vm_instance).await;
+        } else if state == VmState::Suspended {
+            // A paused VM is locked against configuration changes and only needs resuming
+            info!("VM {} is paused, resuming it", vm_instance.name);
+            vm_manager
+                .start_vm(vm_instance)
+                .await
+                .context("Failed to resume VM")?;
+            self.start_recording(vm_instance).await;
         } else {
+            // Boot the installed OS rather than the installer unless `boot-order` says otherwise;
+            // providers without boot order control are left to boot as they are
+            let boot_order = match vm_manager.default_config().boot_order.clone() {
+                Some(boot_order) => Some(boot_order),
+                None => vm_manager
+                    .require_capability(Capability::BootOrder)
+                    .is_ok()
+                    .then(BootOrder::installed),
+            };
+            if let Some(boot_order) = boot_order {
+                vm_manager.set_boot_order(vm_instance, &boot_order).await?;
+            }
+
             info!("Starting VM {}", vm_instance.name);
             vm_manager
                 .start_vm(vm_instance)
                 .await
                 .context("Failed to start VM")?;
+            self.start_recording(vm_instance).await;
 
             // Wait for OS boot
-            vm_manager
-                .wait_for_boot(vm_instance)
+            self.wait_for_boot(vm_manager, vm_instance)
                 .await
                 .context("Failed to wait for OS boot")?;
         }
@@ -211,18 +529,338 @@ impl Builder {
         Ok(())
     }
 
+    /// Wait for a started VM: the `boot-wait` condition if one is set, else the fixed delay
+    async fn wait_for_boot(&self, vm_manager: &VmManager, vm_instance: &VmInstance) -> Result<()> {
+        match &vm_manager.default_config().boot_condition {
+            Some(condition) => {
+                let puppet_manager = self.puppet_manager.lock().await;
+                puppet_manager
+                    .wait_for_boot_condition(vm_instance, condition, vm_manager)
+                    .await
+            }
+            None => vm_manager.wait_for_boot(vm_instance).await,
+        }
+    }
+
+
+    /// Fail before any VM work when the spec or build options need a provider feature the
+    /// configured provider lacks
+    async fn check_provider_capabilities(&self) -> Result<()> {
+        let instructions = || self.spec.stages.iter().flat_map(|stage| &stage.instructions);
+        let mut required = Vec::new();
+
+        let watches_screen = self.spec.stages.iter().any(|stage| stage.idle_watchdog.is_some())
+            || instructions().any(|instruction| {
+                matches!(
+                    instruction,
+                    Instruction::Wait { condition: Some(condition), .. }
+                        if matches!(
+                            WaitCondition::parse(condition),
+                            WaitCondition::ScreenText(_) | WaitCondition::StableScreen(_)
+                        )
+                )
+            })
+            || instructions().any(|instruction| matches!(instruction, Instruction::Capture { .. }))
+            || matches!(self.verify_boot, Some((BootCheck::ScreenText(_), _)));
+        if watches_screen {
+            required.push((Capability::Screenshot, "screen WAITs and CAPTURE"));
+        }
+        if instructions().any(|instruction| matches!(instruction, Instruction::Expect { .. }))
+            || matches!(self.verify_boot, Some((BootCheck::Serial(_), _)))
+        {
+            required.push((Capability::SerialConsole, "EXPECT and serial boot checks"));
+        }
+        if instructions().any(|instruction| {
+            matches!(instruction, Instruction::Copy { method, .. } if method == "guest")
+        }) {
+            required.push((Capability::GuestCopy, "COPY --guest"));
+        }
+        if self.step_snapshots || self.restore_failed_step {
+            required.push((Capability::LiveSnapshot, "--step-snapshots and --continue"));
+        }
+
+        let vm_manager = self.vm_manager.lock().await;
+        if vm_manager.default_config().boot_order.is_some() {
+            required.push((Capability::BootOrder, "VM boot-order"));
+        }
+        for (capability, needed_for) in required {
+            vm_manager
+                .require_capability(capability)
+                .with_context(|| format!("{} can't be used", needed_for))?;
+        }
+        Ok(())
+    }
 
+    /// Build the spec, starting over on a fresh VM after transient failures if `--retries` allows
     pub async fn build(&self) -> Result<()> {
+        let fingerprint = if self.skip_if_unchanged {
+            let (artifact, fingerprint) = self.build_fingerprint()?;
+            if fingerprint::is_current(&artifact, &fingerprint) {
+                info!(
+                    "Spec, its files, source ISO, settings and tools are unchanged since {} \
+                     was built, skipping build",
+                    artifact.display()
+                );
+                return Ok(());
+            }
+            Some(fingerprint)
+        } else {
+            None
+        };
+
+        let mut attempt = 0;
+        loop {
+            match self.build_attempt().await {
+                Ok(artifact) => {
+                    if let Some(fingerprint) = &fingerprint {
+                        fingerprint::write(&artifact, fingerprint)?;
+                    }
+                    return Ok(());
+                }
+                Err(e) if attempt < self.retries && is_transient_failure(&e) => {
+                    attempt += 1;
+                    warn!(
+                        "Build failed on a transient error, retrying from scratch ({}/{}): {:#}",
+                        attempt, self.retries, e
+                    );
+                    self.cleanup()
+                        .await
+                        .context("Failed to clean up before retrying the build")?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Artifact this build will produce and the fingerprint of the inputs it depends on
+    fn build_fingerprint(&self) -> Result<(PathBuf, String)> {
+        let spec_file = self
+            .spec_file_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("--skip-if-unchanged needs the spec file path"))?;
+        let pack_stage = self
+            .spec
+            .get_stage(&StageType::Pack)
+            .ok_or_else(|| anyhow!("pack stage is required but not found"))?;
+        let output_path = self.get_final_output_path(pack_stage)?;
+        let artifact = IsoPackager::artifact_path(&output_path, pack_stage);
+
+        // A declared CHECKSUM was verified against the ISO, so it stands in for hashing it
+        let source_checksum = match &self.spec.checksum {
+            Some(checksum) => format!("{}:{}", checksum.algorithm.to_lowercase(), checksum.value),
+            None => {
+                let sha256 = self
+                    .checksum_verifier
+                    .calculate_checksum(Path::new(&self.spec.from), "sha256")
+                    .context("Failed to hash source ISO for the build fingerprint")?;
+                format!("sha256:{}", sha256)
+            }
+        };
+
+        let overrides = fingerprint::VmOverrides {
+            memory_mb: self.memory_mb,
+            cpus: self.cpus,
+            resolution: self.resolution,
+            vm_name: self.vm_name.clone(),
+        };
+        let fingerprint = fingerprint::compute(
+            &self.spec,
+            spec_file,
+            &source_checksum.to_lowercase(),
+            &overrides,
+            &self.secrets,
+        )?;
+        debug!("Build fingerprint for {}: {}", artifact.display(), fingerprint);
+        Ok((artifact, fingerprint))
+    }
+
+    async fn build_attempt(&self) -> Result<PathBuf> {
+        let started_at = chrono::Utc::now();
+        let started = Instant::now();
+        if let Ok(mut failed_step) = self.failed_step.lock() {
+            *failed_step = None;
+        }
+        let result = self.run_build().await;
+        self.record_failed_step(result.is_ok());
+        self.finish_recording().await;
+
+        let profile = if self.profile {
+            let profile = self.build_profile(started).await;
+            profile.print();
+            Some(profile)
+        } else {
+            None
+        };
+
+        // Record the outcome alongside the artifacts when an output directory is used
+        if let Some(output_dir) = &self.output_dir {
+            let mut report = BuildReport::new(self.spec_file_path.clone(), started_at);
+            report.labels = self.spec.labels.clone().into_iter().collect();
+            report.profile = profile;
+            match &result {
+                Ok((artifact, checksum)) => {
+                    report.success = true;
+                    report.artifact = Some(artifact.clone());
+                    report.checksum = checksum.clone();
+                }
+                Err(e) => report.error = Some(redact::redact(&format!("{:#}", e))),
+            }
+            report.finish(started_at);
+            if let Err(e) = report.write_to_dir(output_dir) {
+                warn!("Failed to write build report: {}", e);
+            }
+        }
+
+        result.map(|(artifact, _)| artifact)
+    }
+
+    /// Start the `--record` capture of `vm_instance`, a no-op while it is already running
+    async fn start_recording(&self, vm_instance: &VmInstance) {
+        self.puppet_manager.lock().await.start_recording(vm_instance);
+    }
+
+    /// Stop the `--record` capture and assemble its video, whether or not the build passed
+    async fn finish_recording(&self) {
+        let mut puppet_manager = self.puppet_manager.lock().await;
+        puppet_manager.stop_recording().await;
+
+        if let (Some(format), Some(output_dir)) = (&self.record_video, &self.output_dir) {
+            let video = output_dir.join(format!("{}.{}", RECORDING_DIR, format));
+            if let Err(e) = puppet_manager.assemble_recording(&video) {
+                warn!("Failed to assemble the recording video: {:#}", e);
+            }
+        }
+    }
+
+    /// Collect phase timings from the builder, step/OCR timings from the puppet manager and
+    /// the VM state changes of the attempt that started at `started`
+    async fn build_profile(&self, started: Instant) -> BuildProfile {
+        let puppet_manager = self.puppet_manager.lock().await;
+        let (ocr_time, ocr_calls) = puppet_manager.ocr_time();
+        let vm_states = self
+            .vm_manager
+            .lock()
+            .await
+            .state_history()
+            .into_iter()
+            .filter(|(_, _, at)| *at >= started)
+            .map(|(vm, state, at)| VmStateChange {
+                vm,
+                state,
+                at_secs: (at - started).as_secs_f64(),
+            })
+            .collect();
+
+        BuildProfile {
+            phases: self
+                .phase_timings
+                .lock()
+                .map(|timings| timings.clone())
+                .unwrap_or_default(),
+            steps: puppet_manager
+                .step_timings()
+                .iter()
+                .map(|(name, duration)| PhaseTiming::new(name.clone(), *duration))
+                .collect(),
+            ocr_secs: ocr_time.as_secs_f64(),
+            ocr_calls,
+            vm_states,
+        }
+    }
+
+    /// Time a build phase, recording it even when the phase fails
+    async fn timed<T>(
+        &self,
+        name: &str,
+        phase: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let result = phase.await;
+        self.record_phase(name, started);
+        result
+    }
+
+    fn record_phase(&self, name: &str, started: Instant) {
+        if let Ok(mut timings) = self.phase_timings.lock() {
+            timings.push(PhaseTiming::new(name, started.elapsed()));
+        }
+    }
+
+    /// Remember which step failed so `build --continue` can pick up from it
+    fn record_failed_step(&self, succeeded: bool) {
+        let Some(spec_file_path) = &self.spec_file_path else {
+            return;
+        };
+
+        let (failed_step, snapshot_step) = if succeeded {
+            (None, None)
+        } else {
+            let Some((step, snapshotted)) = self.failed_step.lock().ok().and_then(|step| *step)
+            else {
+                return; // Failed outside of a step, e.g. while preparing the ISO
+            };
+            (Some(step), snapshotted.then_some(step))
+        };
+
+        let mut metadata = VmMetadata::load_from_current_dir().unwrap_or_default();
+        match metadata.set_failed_step(spec_file_path, failed_step, snapshot_step) {
+            Ok(()) => {
+                if let Err(e) = metadata.save_to_current_dir() {
+                    warn!("Failed to record failed step: {}", e);
+                } else if let Some(step) = failed_step {
+                    info!(
+                        "Recorded failed step {}; rerun with `isotope build --continue` to resume",
+                        step
+                    );
+                }
+            }
+            Err(e) => debug!("Not recording failed step: {}", e),
+        }
+    }
+
+    /// Runs every stage and returns the packaged artifact with its sidecar checksum, if any
+    async fn run_build(&self) -> Result<(PathBuf, Option<String>)> {
         info!("Starting ISO build process");
 
+        if let Some(output_dir) = &self.output_dir {
+            std::fs::create_dir_all(output_dir).with_context(|| {
+                format!("Failed to create output directory: {}", output_dir.display())
+            })?;
+            self.puppet_manager
+                .lock()
+                .await
+                .set_debug_steps_dir(output_dir.join("debug-steps"));
+        }
+
         // Show step summary for user reference
         self.print_step_summary();
 
+        // Fail now rather than after a full build that can't be verified
+        let packs_rootfs = self
+            .spec
+            .get_stage(&StageType::Pack)
+            .and_then(IsoPackager::pack_format)
+            .is_some_and(|format| format == "docker");
+        if self.verify_boot.is_some() && packs_rootfs {
+            return Err(anyhow!(
+                "--verify-boot boots the packed disk, which FORMAT docker doesn't produce"
+            ));
+        }
+        if let Some((BootCheck::ScreenText(_), _)) = &self.verify_boot {
+            if !self.puppet_manager.lock().await.has_ocr() {
+                return Err(anyhow!(
+                    "--verify-boot with screen text needs OCR, which is disabled or failed to load"
+                ));
+            }
+        }
+
         if let Some(step) = self.continue_from_step {
-            let (stage, step_in_stage) = self.get_stage_step_mapping(step)?;
+            let (range, step_in_stage) = self.get_stage_step_mapping(step)?;
             info!(
-                "Continuing from step {} (stage: {:?}, step {} within stage)",
-                step, stage, step_in_stage
+                "Continuing from step {} (stage: {}, step {} within stage)",
+                step,
+                range.stage(&self.spec).display_name(),
+                step_in_stage
             );
         }
 
@@ -232,25 +870,78 @@ impl Builder {
             .context("Failed to create working directory")?;
 
         // Step 1: Validate and prepare source ISO
-        let source_iso_path = self.prepare_source_iso().await?;
+        let source_iso_path = self.timed("prepare_iso", self.prepare_source_iso()).await?;
 
         // Step 2: Execute init stage (VM setup)
-        self.execute_init_stage().await?;
+        self.timed(
+            StageType::Init.name(),
+            self.with_stage_hooks(StageType::Init, self.execute_init_stage()),
+        )
+        .await?;
+        self.check_provider_capabilities().await?;
 
         // Step 3: Execute os_install stage (automated installation in VM)
-        let vm_instance = self.execute_os_install_stage(&source_iso_path).await?;
+        let vm_instance = self
+            .timed(
+                StageType::OsInstall.name(),
+                self.with_stage_hooks(
+                    StageType::OsInstall,
+                    self.execute_os_install_stage(&source_iso_path),
+                ),
+            )
+            .await?;
 
         // Step 4: Execute os_configure stage (live OS configuration)
-        let final_vm_instance = self.execute_os_configure_stage(vm_instance).await?;
+        let final_vm_instance = self
+            .timed(
+                StageType::OsConfigure.name(),
+                self.with_stage_hooks(
+                    StageType::OsConfigure,
+                    self.execute_os_configure_stage(vm_instance),
+                ),
+            )
+            .await?;
 
         // Step 5: Execute pack stage (create final ISO)
-        self.execute_pack_stage(final_vm_instance).await?;
+        let artifact = self
+            .timed(
+                StageType::Pack.name(),
+                self.with_stage_hooks(StageType::Pack, self.execute_pack_stage(final_vm_instance)),
+            )
+            .await?;
+
+        if let Some((algorithm, expected)) = self.expected_checksum() {
+            self.timed("expect_checksum", async {
+                self.verify_expected_checksum(&artifact, algorithm, expected)
+            })
+            .await?;
+        }
+
+        // Step 6: Boot the packed image to make sure it actually works
+        if let Some((check, timeout)) = &self.verify_boot {
+            self.timed("verify_boot", self.verify_boot(&artifact, check, *timeout))
+                .await?;
+        }
+
+        // Write a checksum sidecar next to the artifact in the output directory
+        let checksum = if self.output_dir.is_some() {
+            let value = self
+                .timed("checksum", async {
+                    self.checksum_verifier
+                        .generate_checksum_file(&artifact, "sha256")
+                        .context("Failed to write artifact checksum")
+                })
+                .await?;
+            Some(format!("sha256:{}", value))
+        } else {
+            None
+        };
 
         // Cleanup
         self.cleanup().await?;
 
         info!("ISO build completed successfully");
-        Ok(())
+        Ok((artifact, checksum))
     }
 
     pub async fn test(&self) -> Result<()> {
@@ -277,6 +968,81 @@ impl Builder {
         Ok(())
     }
 
+    /// Run os_install and os_configure end to end without packing, always cleaning up afterwards
+    pub async fn test_full(&self) -> Result<()> {
+        info!("Starting full ISO test process (pack stage will be skipped)");
+
+        // Create working directory
+        self.fs_manager
+            .create_working_directory()
+            .context("Failed to create working directory")?;
+
+        let result = self.run_full_test_stages().await;
+
+        // Always clean up, even when a stage failed
+        if let Err(e) = self.cleanup().await {
+            warn!("Cleanup after full test failed: {}", e);
+        }
+
+        match &result {
+            Ok(()) => info!("Full ISO test passed"),
+            Err(e) => {
+                error!("Full ISO test failed: {:#}", e);
+                error!("Failure screenshots are saved in the debug-steps directory");
+            }
+        }
+
+        result
+    }
+
+    async fn run_full_test_stages(&self) -> Result<()> {
+        let source_iso_path = self.prepare_source_iso().await?;
+
+        self.with_stage_hooks(StageType::Init, self.execute_init_stage())
+            .await?;
+
+        let vm_instance = self
+            .with_stage_hooks(
+                StageType::OsInstall,
+                self.execute_os_install_stage(&source_iso_path),
+            )
+            .await
+            .context("os_install stage failed")?;
+
+        self.with_stage_hooks(
+            StageType::OsConfigure,
+            self.execute_os_configure_stage(vm_instance),
+        )
+        .await
+        .context("os_configure stage failed")?;
+
+        Ok(())
+    }
+
+    /// Run the spec's pre hooks, the stage itself, then its post hooks
+    async fn with_stage_hooks<T>(
+        &self,
+        stage: StageType,
+        stage_future: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let context = HookContext {
+            spec_file: self.spec_file_path.clone(),
+            working_dir: self.working_dir.clone(),
+            stage_dir: self.fs_manager.stage_dir(&stage)?,
+            output_dir: self.output_dir.clone(),
+        };
+
+        let pre_hooks = self.spec.get_hooks(HookWhen::StageStart, &stage);
+        hooks::run_pre_stage_hooks(&pre_hooks, &stage, &context)?;
+
+        let result = stage_future.await;
+
+        let post_hooks = self.spec.get_hooks(HookWhen::StageEnd, &stage);
+        hooks::run_post_stage_hooks(&post_hooks, &stage, &context, result.is_ok());
+
+        result
+    }
+
     async fn prepare_source_iso(&self) -> Result<PathBuf> {
         info!("Preparing source ISO: {}", self.spec.from);
 
@@ -296,13 +1062,93 @@ impl Builder {
                 .context("Checksum verification failed")?;
         }
 
+        // Remaster the ISO with an answer file for unattended installs
+        if let Some((kind, answer_file)) = self.get_answer_file()? {
+            return self.remaster_with_answer_file(source_path, kind, &answer_file);
+        }
+
         Ok(source_path.to_path_buf())
     }
 
+    fn get_answer_file(&self) -> Result<Option<(AnswerFileKind, PathBuf)>> {
+        let Some(init_stage) = self.spec.get_stage(&StageType::Init) else {
+            return Ok(None);
+        };
+
+        for instruction in &init_stage.instructions {
+            if let Instruction::AnswerFile { kind, path } = instruction {
+                return Ok(Some((kind.parse()?, path.clone())));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn remaster_with_answer_file(
+        &self,
+        source_path: &Path,
+        kind: AnswerFileKind,
+        answer_file: &Path,
+    ) -> Result<PathBuf> {
+        info!(
+            "Remastering {} with {:?} answer file {}",
+            source_path.display(),
+            kind,
+            answer_file.display()
+        );
+
+        // The answer file is declared in init, so its remaster lives in init's directory
+        let stage_dir = self.fs_manager.stage_dir(&StageType::Init)?;
+        let extract_dir = stage_dir.join("iso-extract");
+        if let Some(iso_cache) = &self.iso_cache {
+            // The cached tree stays pristine; the answer file goes into a working copy
+            let sha256 = self.source_iso_sha256(source_path)?;
+            let cached_tree = iso_cache
+                .extracted_tree(source_path, &sha256, &self.iso_extractor)
+                .context("Failed to prepare cached ISO extraction")?;
+            self.fs_manager
+                .copy_directory(&cached_tree, &extract_dir)
+                .context("Failed to copy cached ISO extraction")?;
+        } else {
+            self.iso_extractor
+                .extract_iso(source_path, &extract_dir)
+                .context("Failed to extract source ISO for answer file injection")?;
+        }
+
+        answer_file::inject_answer_file(&extract_dir, kind, answer_file)
+            .context("Failed to inject answer file")?;
+
+        // Installers may find their stage2 by the volume ID, so the remaster keeps it
+        let volume_id = self
+            .iso_extractor
+            .volume_id(source_path)
+            .context("Failed to read the source ISO's volume ID")?;
+        let remastered_path = stage_dir.join("remastered.iso");
+        self.iso_packager
+            .repack_iso(&extract_dir, &remastered_path, &volume_id, &self.spec.labels)
+            .context("Failed to repack ISO with answer file")?;
+
+        Ok(remastered_path)
+    }
+
+    /// Reuse the spec's verified SHA-256 when it has one instead of hashing the ISO again
+    fn source_iso_sha256(&self, source_path: &Path) -> Result<String> {
+        match &self.spec.checksum {
+            Some(checksum) if checksum.algorithm.eq_ignore_ascii_case("sha256") => {
+                Ok(checksum.value.to_lowercase())
+            }
+            _ => self
+                .checksum_verifier
+                .calculate_checksum(source_path, "sha256")
+                .context("Failed to hash source ISO for the ISO cache"),
+        }
+    }
+
     async fn execute_init_stage(&self) -> Result<()> {
         info!("Executing init stage");
 
-        if let Some(init_stage) = self.spec.get_stage(&StageType::Init) {
+        let init_stage = self.spec.get_stage(&StageType::Init);
+        if let Some(init_stage) = init_stage {
             let mut vm_manager = self.vm_manager.lock().await;
             vm_manager
                 .configure_from_stage(init_stage)
@@ -311,13 +1157,161 @@ impl Builder {
             warn!("No init stage found, using default VM configuration");
         }
 
+        {
+            let mut vm_manager = self.vm_manager.lock().await;
+            if let Some(resolution) = self.resolution {
+                vm_manager.set_resolution(resolution);
+            }
+            if let Some(memory_mb) = self.memory_mb {
+                info!("Overriding VM memory with {}MB", memory_mb);
+                vm_manager.set_memory_mb(memory_mb);
+            }
+            if let Some(cpus) = self.cpus {
+                info!("Overriding VM CPU count with {}", cpus);
+                vm_manager.set_cpus(cpus);
+            }
+            if let Some(vm_name) = &self.vm_name {
+                vm_manager.set_vm_name(vm_name.clone())?;
+            }
+        }
+
+        // The --slow flag takes precedence over `VM slow` in the init stage
+        let slow_mode = self.slow_mode.clone().or_else(|| {
+            init_stage.and_then(|stage| {
+                stage.instructions.iter().find_map(|instruction| match instruction {
+                    Instruction::Vm { key, value } if key == "slow" => Some(value.clone()),
+                    _ => None,
+                })
+            })
+        });
+        let mut puppet_manager = self.puppet_manager.lock().await;
+        if let Some(delay) = slow_mode {
+            puppet_manager
+                .set_slow_mode(&delay)
+                .context("Invalid slow mode delay")?;
+        }
+        puppet_manager.set_keep_going(self.keep_going);
+        puppet_manager.set_pre_step_snapshots(self.step_snapshots);
+        puppet_manager.set_secrets(self.secrets.clone());
+        puppet_manager.set_interactive(self.interactive);
+        puppet_manager.set_ocr_monitor(self.ocr_monitor);
+        if let Some(interval) = &self.record_interval {
+            let output_dir = self
+                .output_dir
+                .as_ref()
+                .ok_or_else(|| anyhow!("--record needs an output directory"))?;
+            puppet_manager
+                .set_recording(interval, output_dir.join(RECORDING_DIR))
+                .context("Invalid recording interval")?;
+        }
+        puppet_manager.set_dump_scancodes(self.dump_scancodes);
+        puppet_manager.set_defaults(self.spec.defaults.clone());
+        puppet_manager.set_labels(self.spec.labels.clone());
+        drop(puppet_manager);
+
+        let mut vm_manager = self.vm_manager.lock().await;
+        vm_manager.set_reuse_existing_disk(self.continue_from_step.is_some());
+        // EXPECT and `serial-port` talk to the guest over a TCP serial port, created with the VM
+        let has_expect = self.spec.stages.iter().any(|stage| {
+            stage
+                .instructions
+                .iter()
+                .any(|instruction| matches!(instruction, Instruction::Expect { .. }))
+        });
+        let shared = vm_manager.default_config().serial_share_port.is_some();
+        vm_manager.set_serial_console(has_expect || shared);
+
+        if self.force_recreate {
+            let existing_vm = self.get_existing_vm_from_metadata()?;
+            if let Some(existing_vm) = &existing_vm {
+                info!(
+                    "--force-recreate: removing VM {} from a previous build",
+                    existing_vm.name
+                );
+                vm_manager
+                    .force_remove_vm(existing_vm)
+                    .await
+                    .context("Failed to remove existing VM for --force-recreate")?;
+            } else {
+                info!("--force-recreate: no previous VM recorded for this spec");
+            }
+
+            // A fixed name may also be held by a VM this spec's metadata doesn't know about
+            if let Some(vm_name) = vm_manager.vm_name().map(str::to_string) {
+                if existing_vm.is_none_or(|vm| vm.name != vm_name) {
+                    vm_manager
+                        .force_remove_vm_named(&vm_name)
+                        .await
+                        .context("Failed to remove existing VM for --force-recreate")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Step ranges of every stage of `stage_type`, in file order
+    fn stage_ranges(&self, stage_type: StageType) -> Vec<StepRange> {
+        steps::step_ranges(&self.spec)
+            .into_iter()
+            .filter(|range| range.stage == stage_type)
+            .collect()
+    }
+
+    /// Run stages of one type in file order. When resuming, the stages before the one holding
+    /// the target step are skipped and that one starts at the target step.
+    async fn execute_numbered_stages(
+        &self,
+        puppet_manager: &mut PuppetManager,
+        vm_instance: &VmInstance,
+        ranges: &[StepRange],
+        vm_manager: &VmManager,
+        resume: Option<&(StepRange, usize)>,
+    ) -> Result<()> {
+        for range in ranges {
+            let stage = range.stage(&self.spec);
+            let continue_from = match resume {
+                Some((target, step_in_stage)) if target.stage == range.stage => {
+                    match range.stage_index.cmp(&target.stage_index) {
+                        std::cmp::Ordering::Less => {
+                            info!("Skipping {} stage, resuming later", stage.display_name());
+                            continue;
+                        }
+                        std::cmp::Ordering::Equal => {
+                            info!(
+                                "Continuing {} stage from step {}",
+                                stage.display_name(),
+                                step_in_stage
+                            );
+                            Some(*step_in_stage)
+                        }
+                        std::cmp::Ordering::Greater => None,
+                    }
+                }
+                _ => None,
+            };
+
+            let result = puppet_manager
+                .execute_stage_instructions_from_step(vm_instance, stage, vm_manager, continue_from)
+                .await;
+            if result.is_err() {
+                if let Some(index) = puppet_manager.take_failed_step() {
+                    let snapshotted = puppet_manager.last_snapshot_step() == Some(index);
+                    if let Ok(mut failed_step) = self.failed_step.lock() {
+                        *failed_step = Some((range.first + index - 1, snapshotted));
+                    }
+                }
+            }
+            result?;
+        }
         Ok(())
     }
 
     async fn execute_os_install_stage(&self, source_iso_path: &Path) -> Result<Option<VmInstance>> {
         info!("Executing os_install stage");
 
-        if let Some(os_install_stage) = self.spec.get_stage(&StageType::OsInstall) {
+        let os_install_ranges = self.stage_ranges(StageType::OsInstall);
+        if !os_install_ranges.is_empty() {
             let mut vm_manager = self.vm_manager.lock().await;
 
             // Check if we should reuse an existing VM (only when using --continue-from)
@@ -332,11 +1326,17 @@ impl Builder {
                 }
             } else {
                 info!("Creating new VM (not continuing from previous build)");
-                vm_manager
+                let started = Instant::now();
+                let instance = vm_manager
                     .create_vm()
-                    .context("Failed to create VM instance")?
+                    .await
+                    .context("Failed to create VM instance")?;
+                self.record_phase("os_install: vm create", started);
+                instance
             };
 
+            let restored = self.restore_failed_step_snapshot(&vm_manager, &vm_instance).await;
+
             // Check if VM is already running when continuing
             let is_already_running = if self.continue_from_step.is_some() {
                 vm_manager
@@ -353,9 +1353,27 @@ impl Builder {
                     "VM {} is already running, skipping start and ISO attachment",
                     vm_instance.name
                 );
+                self.start_recording(&vm_instance).await;
+            } else if restored {
+                // The snapshot already carries the attached media and saved machine state
+                info!("Resuming VM {} from its pre-step snapshot", vm_instance.name);
+
+                vm_manager
+                    .start_vm(&vm_instance)
+                    .await
+                    .context("Failed to start VM")?;
+                self.start_recording(&vm_instance).await;
             } else {
                 info!("Starting VM {} and attaching ISO", vm_instance.name);
 
+                // A reused VM keeps the order it was created with unless one is given explicitly
+                if self.continue_from_step.is_some() {
+                    if let Some(boot_order) = vm_manager.default_config().boot_order.clone() {
+                        vm_manager.set_boot_order(&vm_instance, &boot_order).await?;
+                    }
+                }
+
+                let started = Instant::now();
                 vm_manager
                     .attach_iso(&vm_instance, source_iso_path)
                     .await
@@ -365,6 +1383,18 @@ impl Builder {
                     .start_vm(&vm_instance)
                     .await
                     .context("Failed to start VM")?;
+                self.start_recording(&vm_instance).await;
+
+                // Only a boot-wait condition holds up the first PRESS; the install stage
+                // schedules its own waits otherwise
+                if let Some(condition) = vm_manager.default_config().boot_condition.clone() {
+                    let puppet_manager = self.puppet_manager.lock().await;
+                    puppet_manager
+                        .wait_for_boot_condition(&vm_instance, &condition, &vm_manager)
+                        .await
+                        .context("Failed to wait for the installer to boot")?;
+                }
+                self.record_phase("os_install: vm boot", started);
             }
 
             // Get the updated VM instance (SSH port may have been updated during attach_iso)
@@ -373,40 +1403,36 @@ impl Builder {
                 .ok_or_else(|| anyhow!("VM instance not found after setup"))?
                 .clone();
 
+            // Track the VM before running any steps so a failed install can be resumed
+            self.save_vm_metadata(&updated_vm_instance)?;
+
             // Execute puppet automation
             let mut puppet_manager = self.puppet_manager.lock().await;
 
-            // Check if we need to continue from a specific step in this stage
-            let continue_from = if let Some(target_step) = self.continue_from_step {
-                match self.get_stage_step_mapping(target_step)? {
-                    (StageType::OsInstall, step_in_stage) => {
-                        info!("Continuing os_install stage from step {}", step_in_stage);
-                        Some(step_in_stage)
-                    }
-                    (other_stage, _) => {
-                        info!(
-                            "Target step {} is in {:?} stage, skipping os_install",
-                            target_step, other_stage
-                        );
-                        return Ok(Some(updated_vm_instance)); // Skip this stage entirely
-                    }
-                }
-            } else {
-                None
-            };
-
-            puppet_manager
-                .execute_stage_instructions_from_step(
-                    &updated_vm_instance,
-                    os_install_stage,
-                    &vm_manager,
-                    continue_from,
-                )
-                .await
-                .context("Failed to execute OS installation instructions")?;
+            // Check if we need to continue from a specific step in these stages
+            let resume = self
+                .continue_from_step
+                .map(|target_step| self.get_stage_step_mapping(target_step))
+                .transpose()?;
+            if let Some((range, _)) = resume.as_ref().filter(|(range, _)| {
+                range.stage != StageType::OsInstall
+            }) {
+                info!(
+                    "Target step is in {} stage, skipping os_install",
+                    range.stage(&self.spec).display_name()
+                );
+                return Ok(Some(updated_vm_instance)); // Skip this stage entirely
+            }
 
-            // Save VM metadata for future --continue runs
-            self.save_vm_metadata(&updated_vm_instance)?;
+            self.execute_numbered_stages(
+                &mut puppet_manager,
+                &updated_vm_instance,
+                &os_install_ranges,
+                &vm_manager,
+                resume.as_ref(),
+            )
+            .await
+            .context("Failed to execute OS installation instructions")?;
 
             Ok(Some(updated_vm_instance))
         } else {
@@ -418,7 +1444,8 @@ impl Builder {
     async fn execute_os_configure_stage(&self, vm_instance: Option<VmInstance>) -> Result<Option<VmInstance>> {
         info!("Executing os_configure stage");
 
-        if let Some(os_configure_stage) = self.spec.get_stage(&StageType::OsConfigure) {
+        let os_configure_ranges = self.stage_ranges(StageType::OsConfigure);
+        if !os_configure_ranges.is_empty() {
             let mut vm_manager = self.vm_manager.lock().await;
 
             let vm_instance = if let Some(existing_instance) = vm_instance {
@@ -426,18 +1453,24 @@ impl Builder {
                     "Reusing VM instance from os_install stage: {}",
                     existing_instance.name
                 );
+                // Installers that power off at the end are booted from disk here
+                self.ensure_vm_running(&mut vm_manager, &existing_instance)
+                    .await?;
                 existing_instance
             } else {
                 // Check if we can reuse an existing VM when continuing directly to os_configure
                 if let Some(target_step) = self.continue_from_step {
-                    if let Ok((StageType::OsConfigure, _)) =
-                        self.get_stage_step_mapping(target_step)
-                    {
+                    let continues_here = self
+                        .get_stage_step_mapping(target_step)
+                        .is_ok_and(|(range, _)| range.stage == StageType::OsConfigure);
+                    if continues_here {
                         if let Some(existing_vm) = self.get_existing_vm_from_metadata()? {
                             info!(
                                 "Reusing existing VM {} for --continue in os_configure stage",
                                 existing_vm.name
                             );
+                            self.restore_failed_step_snapshot(&vm_manager, &existing_vm)
+                                .await;
                             // Ensure the existing VM is running
                             self.ensure_vm_running(&mut vm_manager, &existing_vm)
                                 .await?;
@@ -446,6 +1479,7 @@ impl Builder {
                             info!("No existing VM found for --continue, creating new one");
                             let instance = vm_manager
                                 .get_or_create_configured_vm()
+                                .await
                                 .context("Failed to get configured VM")?;
 
                             self.ensure_vm_running(&mut vm_manager, &instance).await?;
@@ -455,6 +1489,7 @@ impl Builder {
                         info!("No VM instance from os_install, creating new one");
                         let instance = vm_manager
                             .get_or_create_configured_vm()
+                            .await
                             .context("Failed to get configured VM")?;
 
                         self.ensure_vm_running(&mut *vm_manager, &instance).await?;
@@ -464,6 +1499,7 @@ impl Builder {
                     info!("No VM instance from os_install, creating new one");
                     let instance = vm_manager
                         .get_or_create_configured_vm()
+                        .await
                         .context("Failed to get configured VM")?;
 
                     self.ensure_vm_running(&mut *vm_manager, &instance).await?;
@@ -471,41 +1507,27 @@ impl Builder {
                 }
             };
 
+            self.save_vm_metadata(&vm_instance)?;
+
             // Execute configuration instructions
             let mut puppet_manager = self.puppet_manager.lock().await;
 
-            // Check if we need to continue from a specific step in this stage
-            let continue_from = if let Some(target_step) = self.continue_from_step {
-                match self.get_stage_step_mapping(target_step)? {
-                    (StageType::OsConfigure, step_in_stage) => {
-                        info!("Continuing os_configure stage from step {}", step_in_stage);
-                        Some(step_in_stage)
-                    }
-                    (StageType::OsInstall, _) => {
-                        // If target step is in os_install, we should execute os_configure normally
-                        None
-                    }
-                    (other_stage, _) => {
-                        info!(
-                            "Target step {} is in {:?} stage, skipping os_configure",
-                            target_step, other_stage
-                        );
-                        return Ok(None); // Skip this stage entirely
-                    }
-                }
-            } else {
-                None
-            };
-
-            puppet_manager
-                .execute_stage_instructions_from_step(
-                    &vm_instance,
-                    os_configure_stage,
-                    &vm_manager,
-                    continue_from,
-                )
-                .await
-                .context("Failed to execute OS configuration instructions")?;
+            // A target step in os_install means os_configure runs in full
+            let resume = self
+                .continue_from_step
+                .map(|target_step| self.get_stage_step_mapping(target_step))
+                .transpose()?;
+
+            self.execute_numbered_stages(
+                &mut puppet_manager,
+                &vm_instance,
+                &os_configure_ranges,
+                &vm_manager,
+                resume.as_ref(),
+            )
+            .await
+            .context("Failed to execute OS configuration instructions")?;
+            puppet_manager.stop_ocr_monitor().await;
 
             // Create live OS snapshot
             vm_manager
@@ -525,7 +1547,7 @@ impl Builder {
         }
     }
 
-    async fn execute_pack_stage(&self, vm_instance: Option<VmInstance>) -> Result<()> {
+    async fn execute_pack_stage(&self, vm_instance: Option<VmInstance>) -> Result<PathBuf> {
         info!("Executing pack stage");
 
         if let Some(pack_stage) = self.spec.get_stage(&StageType::Pack) {
@@ -555,22 +1577,37 @@ impl Builder {
             // Convert VDI disk to bootable IMG
             let output_path = self.get_final_output_path(pack_stage)?;
             self.iso_packager
-                .create_bootable_image(&disk_path, &output_path, pack_stage)
+                .check_free_space(&disk_path, &output_path, pack_stage)?;
+            let vm_config = vm_instance
+                .as_ref()
+                .map_or_else(|| vm_manager.default_config(), |instance| &instance.config);
+            let image_path = self
+                .iso_packager
+                .create_bootable_image(&disk_path, &output_path, pack_stage, vm_config)
                 .context("Failed to create bootable IMG")?;
+            self.iso_packager
+                .stamp_labels(&image_path, &self.spec.labels)
+                .context("Failed to record labels with the artifact")?;
+            if IsoPackager::pack_format(pack_stage).as_deref() == Some("docker") {
+                self.iso_packager
+                    .import_docker_image(&image_path, &self.spec.labels);
+            }
 
-            info!("Bootable IMG created successfully: {}", output_path.display());
+            info!("Bootable IMG created successfully: {}", image_path.display());
+            Ok(image_path)
         } else {
-            return Err(anyhow::anyhow!("pack stage is required but not found"));
+            Err(anyhow::anyhow!("pack stage is required but not found"))
         }
-
-        Ok(())
     }
 
     async fn test_vm_boot(&self, source_iso_path: &Path) -> Result<()> {
         info!("Testing VM boot with source ISO");
 
         let mut vm_manager = self.vm_manager.lock().await;
-        let vm_instance = vm_manager.create_vm().context("Failed to create test VM")?;
+        let vm_instance = vm_manager
+            .create_vm()
+            .await
+            .context("Failed to create test VM")?;
 
         vm_manager
             .attach_iso(&vm_instance, source_iso_path)
@@ -597,32 +1634,132 @@ impl Builder {
         Ok(())
     }
 
-    fn get_final_output_path(&self, pack_stage: &crate::config::Stage) -> Result<PathBuf> {
-        // Check if output path was provided via CLI
-        if let Some(path) = &self.output_path {
-            return Ok(path.clone());
+    /// `EXPECT_CHECKSUM` of the pack stage, as (algorithm, value)
+    fn expected_checksum(&self) -> Option<(&str, &str)> {
+        let pack_stage = self.spec.get_stage(&StageType::Pack)?;
+        pack_stage.instructions.iter().find_map(|instruction| match instruction {
+            Instruction::ExpectChecksum { algorithm, value } => {
+                Some((algorithm.as_str(), value.as_str()))
+            }
+            _ => None,
+        })
+    }
+
+    /// Hash the packed artifact and fail if it differs from the spec's EXPECT_CHECKSUM
+    fn verify_expected_checksum(
+        &self,
+        artifact: &Path,
+        algorithm: &str,
+        expected: &str,
+    ) -> Result<()> {
+        let actual = self
+            .checksum_verifier
+            .calculate_checksum(artifact, algorithm)
+            .context("Failed to hash the packed artifact")?;
+        if actual.eq_ignore_ascii_case(expected) {
+            info!("✓ Artifact matches EXPECT_CHECKSUM {}:{}", algorithm, expected);
+            return Ok(());
         }
 
-        // Look for EXPORT instruction in pack stage
-        for instruction in &pack_stage.instructions {
-            if let crate::config::Instruction::Export { path } = instruction {
-                return Ok(path.clone());
+        Err(anyhow!(
+            "Artifact {} does not match EXPECT_CHECKSUM\n\
+             Expected: {}:{}\n\
+             Actual:   {}:{}\n\
+             The build is not reproducible. Common sources of nondeterminism are file \
+             timestamps and logs written during the build, the ISO volume creation date, \
+             /etc/machine-id, SSH host keys, random disk and filesystem UUIDs, and packages \
+             or mirrors that changed since the checksum was recorded",
+            artifact.display(),
+            algorithm,
+            expected,
+            algorithm,
+            actual
+        ))
+    }
+
+    /// Boot a copy of the packed image in a throwaway VM and wait for `check`, removing the VM
+    /// whether or not it passes
+    async fn verify_boot(
+        &self,
+        image_path: &Path,
+        check: &BootCheck,
+        timeout: Duration,
+    ) -> Result<()> {
+        info!("Verifying that {} boots ({})", image_path.display(), check);
+
+        let mut vm_manager = self.vm_manager.lock().await;
+        let vm_instance = vm_manager
+            .create_vm_from_image(image_path)
+            .await
+            .context("Failed to create boot verification VM")?;
+
+        let result = async {
+            if let BootCheck::Serial(_) = check {
+                // Reading the console of a powered-off VM routes its serial port to a log file
+                vm_manager.get_console_output(&vm_instance).await?;
             }
+
+            vm_manager
+                .start_vm(&vm_instance)
+                .await
+                .context("Failed to start boot verification VM")?;
+
+            let booted = async {
+                match check {
+                    BootCheck::Running => vm_manager.wait_for_boot_test(&vm_instance).await,
+                    BootCheck::ScreenText(text) => {
+                        self.puppet_manager
+                            .lock()
+                            .await
+                            .wait_for_screen_text(&vm_instance, text, None, 1, &vm_manager)
+                            .await
+                            .map(|_| ())
+                    }
+                    BootCheck::Serial(text) => {
+                        vm_manager.wait_for_console_text(&vm_instance, text).await
+                    }
+                }
+            };
+
+            tokio::time::timeout(timeout, booted).await.map_err(|_| {
+                anyhow!("Timed out after {:?} waiting for {}", timeout, check)
+            })?
         }
+        .await;
 
-        // Fall back to default based on spec name
-        let default_name = self
-            .spec
-            .get_label("name")
-            .map(|s| format!("{}.iso", s))
-            .unwrap_or_else(|| "output.iso".to_string());
+        if let Err(e) = vm_manager.remove_vm(&vm_instance).await {
+            warn!("Failed to remove boot verification VM {}: {}", vm_instance.name, e);
+        }
+
+        result.with_context(|| format!("Packed image {} failed to boot", image_path.display()))?;
+        info!("Packed image {} booted successfully", image_path.display());
+        Ok(())
+    }
 
-        Ok(PathBuf::from(default_name))
+    fn get_final_output_path(&self, pack_stage: &crate::config::Stage) -> Result<PathBuf> {
+        let output_path = self.get_export_path(pack_stage)?;
+
+        // Place the artifact directly under the output directory when one is set
+        match (&self.output_dir, output_path.file_name()) {
+            (Some(output_dir), Some(file_name)) => Ok(output_dir.join(file_name)),
+            _ => Ok(output_path),
+        }
+    }
+
+    fn get_export_path(&self, pack_stage: &crate::config::Stage) -> Result<PathBuf> {
+        // Check if output path was provided via CLI
+        if let Some(path) = &self.output_path {
+            return Ok(path.clone());
+        }
+
+        Ok(self.spec.export_path(pack_stage))
     }
 
     async fn cleanup(&self) -> Result<()> {
         info!("Cleaning up working directory");
 
+        self.puppet_manager.lock().await.stop_ocr_monitor().await;
+
         // Stop and cleanup VMs
         let mut vm_manager = self.vm_manager.lock().await;
         vm_manager
@@ -630,6 +1767,14 @@ impl Builder {
             .await
             .context("Failed to cleanup VMs")?;
 
+        if self.keep_working_dir {
+            info!(
+                "Keeping working directory {} for inspection",
+                self.working_dir.display()
+            );
+            return Ok(());
+        }
+
         // Remove working directory
         self.fs_manager
             .cleanup()
@@ -638,3 +1783,44 @@ impl Builder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_is_transient_failure() {
+        let transient = [
+            anyhow!(
+                "VBoxManage: error: Failed to set up redirection for rule 'ssh' (Address already \
+                 in use)"
+            )
+            .context("Failed to start VM"),
+            anyhow::Error::new(std::io::Error::from(ErrorKind::ConnectionRefused))
+                .context("Failed to download OCR model"),
+            anyhow::Error::new(SshError::Timeout {
+                endpoint: "127.0.0.1:2222".to_string(),
+                seconds: 30,
+            })
+            .context("RUN failed"),
+        ];
+        for error in &transient {
+            assert!(is_transient_failure(error), "{:#}", error);
+        }
+
+        let permanent = [
+            anyhow!("Invalid memory size: 2X"),
+            anyhow::Error::new(std::io::Error::from(ErrorKind::NotFound))
+                .context("Failed to read spec file"),
+            // The guest's output mentioning a transient error doesn't make the exit transient
+            anyhow::Error::new(SshError::NonZeroExit {
+                code: 1,
+                output: "curl: (7) Failed to connect: Address already in use".to_string(),
+            }),
+        ];
+        for error in &permanent {
+            assert!(!is_transient_failure(error), "{:#}", error);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+use crate::automation::vm::providers::create_provider;
+use crate::automation::vm::{Resolution, VmProvider};
+use crate::config::{Instruction, IsotopeSpec};
+use crate::core::preflight;
+use crate::utils::secrets::SecretStore;
+use crate::utils::template;
+
+/// Command-line settings that override the spec's VM configuration
+#[derive(Debug, Clone, Default)]
+pub struct VmOverrides {
+    pub memory_mb: Option<u64>,
+    pub cpus: Option<u32>,
+    pub resolution: Option<Resolution>,
+    pub vm_name: Option<String>,
+}
+
+/// Hash of everything a build's output depends on: the spec file, the source ISO checksum,
+/// the VM overrides, the template variables and secrets the spec renders, the host files it
+/// reads and the versions of isotope and the tools it drives
+pub fn compute(
+    spec: &IsotopeSpec,
+    spec_file: &Path,
+    source_checksum: &str,
+    overrides: &VmOverrides,
+    secrets: &SecretStore,
+) -> Result<String> {
+    let contents = std::fs::read_to_string(spec_file)
+        .with_context(|| format!("Failed to read spec file: {}", spec_file.display()))?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(format!("isotope {}\n", env!("CARGO_PKG_VERSION")));
+    hasher.update(&contents);
+    hasher.update(format!("\nsource {}\n", source_checksum));
+    hasher.update(format!(
+        "memory {:?} cpus {:?} resolution {:?} vm-name {:?}\n",
+        overrides.memory_mb, overrides.cpus, overrides.resolution, overrides.vm_name
+    ));
+    hash_template_values(&mut hasher, &contents, secrets);
+    for path in referenced_files(spec) {
+        hash_path(&mut hasher, path)?;
+    }
+    for provider in VmProvider::ALL {
+        let version = create_provider(&provider)
+            .tool_version()
+            .unwrap_or_else(|_| format!("{} missing", provider.name()));
+        hasher.update(format!("{}\n", version));
+    }
+    let qemu_img = preflight::probe_binary(&["qemu-img"], &["--version"], "RESIZE_DISK");
+    hasher.update(format!("qemu-img {}\n", qemu_img.result.unwrap_or_else(|_| "missing".into())));
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Feed the values of the environment variables and secrets `contents` renders to `hasher`;
+/// one that can't be resolved is hashed as missing, so the build itself reports it
+fn hash_template_values(hasher: &mut Sha256, contents: &str, secrets: &SecretStore) {
+    let (variables, secret_names) = template::referenced_names(contents);
+    for name in variables {
+        let value = std::env::var(name).ok();
+        hasher.update(format!("var {} {:?}\n", name, value));
+    }
+    for name in secret_names {
+        let value = secrets.resolve(name).ok();
+        hasher.update(format!("secret {} {:?}\n", name, value));
+    }
+}
+
+/// Host files and directories the spec copies into the build: COPY sources, answer files,
+/// attached ISOs and LOGIN private keys
+fn referenced_files(spec: &IsotopeSpec) -> Vec<&Path> {
+    spec.stages
+        .iter()
+        .flat_map(|stage| &stage.instructions)
+        .filter_map(|instruction| match instruction {
+            // `--guest` COPY still reads its source from the host
+            Instruction::Copy { from, .. } => Some(from.as_path()),
+            Instruction::AnswerFile { path, .. } => Some(path.as_path()),
+            Instruction::AttachIso { path, .. } => Some(path.as_path()),
+            Instruction::Login {
+                private_key: Some(key),
+                ..
+            } => Some(key.as_path()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Feed `path`'s contents to `hasher`, every file under it in name order for a directory
+fn hash_path(hasher: &mut Sha256, path: &Path) -> Result<()> {
+    hasher.update(format!("file {}\n", path.display()));
+    if !path.exists() {
+        hasher.update("missing\n");
+        return Ok(());
+    }
+
+    for entry in WalkDir::new(path).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to read {}", path.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+        let mut file = std::fs::File::open(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        hasher.update(format!("{}\n", relative.display()));
+        std::io::copy(&mut file, hasher)
+            .with_context(|| format!("Failed to hash {}", entry.path().display()))?;
+    }
+    Ok(())
+}
+
+/// `<artifact>.fingerprint`, written next to the artifact after a successful build
+pub fn sidecar_path(artifact: &Path) -> PathBuf {
+    let mut file_name = artifact.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".fingerprint");
+    artifact.with_file_name(file_name)
+}
+
+/// Whether `artifact` exists and was built from inputs with this fingerprint
+pub fn is_current(artifact: &Path, fingerprint: &str) -> bool {
+    if !artifact.exists() {
+        debug!("No artifact at {}, build needed", artifact.display());
+        return false;
+    }
+    std::fs::read_to_string(sidecar_path(artifact))
+        .is_ok_and(|stored| stored.trim() == fingerprint)
+}
+
+pub fn write(artifact: &Path, fingerprint: &str) -> Result<()> {
+    let path = sidecar_path(artifact);
+    std::fs::write(&path, format!("{}\n", fingerprint))
+        .with_context(|| format!("Failed to write fingerprint: {}", path.display()))?;
+    info!("Build fingerprint written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parser::parse_isotope_spec;
+
+    #[test]
+    fn test_referenced_files_change_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = dir.path().join("files");
+        std::fs::create_dir(&files).unwrap();
+        std::fs::write(files.join("motd"), "hello").unwrap();
+        std::fs::write(dir.path().join("id_ed25519"), "key-1").unwrap();
+
+        let spec_file = dir.path().join("build.isotope");
+        let contents = format!(
+            "FROM ./base.iso\n\
+             STAGE os_configure\n\
+             LOGIN admin private_key={}\n\
+             COPY {} /etc/isotope\n",
+            dir.path().join("id_ed25519").display(),
+            files.display()
+        );
+        std::fs::write(&spec_file, &contents).unwrap();
+        let spec = parse_isotope_spec(&contents).unwrap();
+        assert_eq!(referenced_files(&spec).len(), 2);
+
+        let compute = |checksum| {
+            compute(&spec, &spec_file, checksum, &VmOverrides::default(), &SecretStore::new())
+        };
+        let first = compute("sha256:00").unwrap();
+        assert_eq!(compute("sha256:00").unwrap(), first);
+        assert_ne!(compute("sha256:01").unwrap(), first);
+
+        std::fs::write(files.join("motd"), "changed").unwrap();
+        let copied = compute("sha256:00").unwrap();
+        assert_ne!(copied, first);
+
+        std::fs::write(dir.path().join("id_ed25519"), "key-2").unwrap();
+        assert_ne!(compute("sha256:00").unwrap(), copied);
+    }
+
+    #[test]
+    fn test_overrides_and_template_values_change_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let secrets_file = dir.path().join("secrets.env");
+        std::fs::write(&secrets_file, "ROOT_PASSWORD=first\n").unwrap();
+        let spec_file = dir.path().join("build.isotope");
+        let contents = "FROM ./base.iso\n\
+                        STAGE os_configure\n\
+                        RUN echo ${ISOTOPE_FINGERPRINT_TEST_HOST}\n\
+                        TYPE {{secret:ROOT_PASSWORD}}\n";
+        std::fs::write(&spec_file, contents).unwrap();
+        let spec = parse_isotope_spec(contents).unwrap();
+
+        let secrets = SecretStore::from_file(&secrets_file).unwrap();
+        let compute = |overrides: &VmOverrides, secrets: &SecretStore| {
+            compute(&spec, &spec_file, "sha256:00", overrides, secrets).unwrap()
+        };
+        let defaults = VmOverrides::default();
+        let first = compute(&defaults, &secrets);
+
+        let more_memory = VmOverrides {
+            memory_mb: Some(8192),
+            ..Default::default()
+        };
+        assert_ne!(compute(&more_memory, &secrets), first);
+
+        std::fs::write(&secrets_file, "ROOT_PASSWORD=second\n").unwrap();
+        let changed = SecretStore::from_file(&secrets_file).unwrap();
+        assert_ne!(compute(&defaults, &changed), first);
+
+        std::env::set_var("ISOTOPE_FINGERPRINT_TEST_HOST", "build-box");
+        assert_ne!(compute(&defaults, &secrets), first);
+        std::env::remove_var("ISOTOPE_FINGERPRINT_TEST_HOST");
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, warn};
+use walkdir::WalkDir;
+
+/// Shared scratch directories of the VM manager and ISO extractor in the temp directory
+const SCRATCH_DIRS: [&str; 2] = ["isotope-vms", "isotope-extract"];
+
+/// A leftover of an earlier build found in the temp directory
+struct Leftover {
+    path: PathBuf,
+    bytes: u64,
+    age: Duration,
+}
+
+/// Parse an `--older-than` age such as 90m, 12h or 7d
+pub fn parse_age(value: &str) -> Result<Duration> {
+    let lower = value.trim().to_lowercase();
+    let units = [("s", 1), ("m", 60), ("h", 3600), ("d", 86_400)];
+    units
+        .into_iter()
+        .find_map(|(suffix, unit)| {
+            let number = lower.strip_suffix(suffix)?.parse::<u64>().ok()?;
+            number.checked_mul(unit).map(Duration::from_secs)
+        })
+        .ok_or_else(|| anyhow!("Invalid age: {}. Expected e.g. 90m, 12h or 7d", value))
+}
+
+/// Remove build working directories, scratch directories and screenshot files that failed or
+/// killed builds left in the temp directory, when nothing in them changed for `older_than`
+pub fn collect_garbage(older_than: Duration, dry_run: bool) -> Result<()> {
+    let temp_dir = std::env::temp_dir();
+    let mut leftovers = Vec::new();
+    for entry in std::fs::read_dir(&temp_dir)
+        .with_context(|| format!("Failed to read temp directory: {}", temp_dir.display()))?
+    {
+        let Ok(entry) = entry else { continue };
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !is_isotope_leftover(&name) {
+            continue;
+        }
+
+        let (bytes, age) = usage(&entry.path());
+        if age < older_than {
+            debug!("Skipping {}, changed {:?} ago", entry.path().display(), age);
+            continue;
+        }
+        leftovers.push(Leftover {
+            path: entry.path(),
+            bytes,
+            age,
+        });
+    }
+
+    if leftovers.is_empty() {
+        println!("Nothing to clean up in {}", temp_dir.display());
+        return Ok(());
+    }
+
+    let mut reclaimed = 0;
+    for leftover in &leftovers {
+        let result = if dry_run {
+            Ok(())
+        } else if leftover.path.is_dir() {
+            std::fs::remove_dir_all(&leftover.path)
+        } else {
+            std::fs::remove_file(&leftover.path)
+        };
+        match result {
+            Ok(()) => {
+                reclaimed += leftover.bytes;
+                println!(
+                    "  {} {:>10}  {} (unchanged for {})",
+                    if dry_run { "would remove" } else { "removed" },
+                    format_size(leftover.bytes),
+                    leftover.path.display(),
+                    format_age(leftover.age)
+                );
+            }
+            Err(e) => warn!("Failed to remove {}: {}", leftover.path.display(), e),
+        }
+    }
+
+    println!(
+        "{} {}",
+        if dry_run { "Would reclaim" } else { "Reclaimed" },
+        format_size(reclaimed)
+    );
+    Ok(())
+}
+
+/// Whether a temp directory entry follows one of isotope's naming conventions:
+/// `isotope-<uuid>` build working directories, the shared scratch directories and
+/// `<vm>-screenshot-<id8>.png` captures
+fn is_isotope_leftover(name: &str) -> bool {
+    if SCRATCH_DIRS.contains(&name) {
+        return true;
+    }
+    if name
+        .strip_prefix("isotope-")
+        .is_some_and(|id| uuid::Uuid::parse_str(id).is_ok())
+    {
+        return true;
+    }
+    name.strip_suffix(".png")
+        .and_then(|stem| stem.rsplit_once("-screenshot-"))
+        .is_some_and(|(_, id)| id.len() == 8 && id.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Total size of the files under `path` and the time since the newest of them changed,
+/// so a directory a running build still writes to is never old
+fn usage(path: &Path) -> (u64, Duration) {
+    let now = SystemTime::now();
+    let mut bytes = 0;
+    let mut age = Duration::MAX;
+    for metadata in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+    {
+        if metadata.is_file() {
+            bytes += metadata.len();
+        }
+        if let Some(since) = metadata.modified().ok().and_then(|m| now.duration_since(m).ok()) {
+            age = age.min(since);
+        }
+    }
+    (bytes, age)
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs if secs >= 86_400 => format!("{}d", secs / 86_400),
+        secs if secs >= 3600 => format!("{}h", secs / 3600),
+        secs => format!("{}m", secs / 60),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    const MIB: f64 = (1 << 20) as f64;
+    if bytes >= 1 << 30 {
+        format!("{:.1} GiB", bytes as f64 / (MIB * 1024.0))
+    } else {
+        format!("{:.1} MiB", bytes as f64 / MIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isotope_leftovers() {
+        assert!(is_isotope_leftover("isotope-0b5d7c1e-3f4a-4b8e-9c2d-1a2b3c4d5e6f"));
+        assert!(is_isotope_leftover("isotope-vms"));
+        assert!(is_isotope_leftover("isotope-vm-0b5d7c1e-screenshot-9f3a1c2b.png"));
+        // VM names and unrelated files are left alone
+        assert!(!is_isotope_leftover("isotope-0b5d7c1e"));
+        assert!(!is_isotope_leftover("isotope-notes"));
+        assert!(!is_isotope_leftover("my-screenshot-final.png"));
+
+        assert_eq!(parse_age("7d").unwrap(), Duration::from_secs(7 * 86_400));
+        assert_eq!(parse_age("90m").unwrap(), Duration::from_secs(90 * 60));
+        assert!(parse_age("soon").is_err());
+    }
+}
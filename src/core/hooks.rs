@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::PathBuf;
+use std::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::config::{Hook, HookWhen, StageType};
+
+/// Build details exposed to hook commands as `ISOTOPE_*` environment variables
+pub struct HookContext {
+    pub spec_file: Option<PathBuf>,
+    pub working_dir: PathBuf,
+    /// The stage's own subdirectory of `working_dir`
+    pub stage_dir: PathBuf,
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Run pre-stage hooks in order, stopping at the first one that fails
+pub fn run_pre_stage_hooks(
+    hooks: &[&Hook],
+    stage: &StageType,
+    context: &HookContext,
+) -> Result<()> {
+    for hook in hooks {
+        run_hook(hook, stage, context, None)
+            .with_context(|| format!("Pre-stage hook for {} failed", stage.name()))?;
+    }
+    Ok(())
+}
+
+/// Run post-stage hooks, only warning about failures since the stage already ran
+pub fn run_post_stage_hooks(
+    hooks: &[&Hook],
+    stage: &StageType,
+    context: &HookContext,
+    stage_succeeded: bool,
+) {
+    for hook in hooks {
+        if let Err(e) = run_hook(hook, stage, context, Some(stage_succeeded)) {
+            warn!("Post-stage hook for {} failed: {:#}", stage.name(), e);
+        }
+    }
+}
+
+fn run_hook(
+    hook: &Hook,
+    stage: &StageType,
+    context: &HookContext,
+    stage_succeeded: Option<bool>,
+) -> Result<()> {
+    let when = match hook.when {
+        HookWhen::StageStart => "pre",
+        HookWhen::StageEnd => "post",
+    };
+    info!("Running {} hook for stage {}: {}", when, stage.name(), hook.command);
+
+    let mut command = shell_command(&hook.command);
+    command
+        .env("ISOTOPE_HOOK", when)
+        .env("ISOTOPE_STAGE", stage.name())
+        .env("ISOTOPE_WORKING_DIR", &context.working_dir)
+        .env("ISOTOPE_STAGE_DIR", &context.stage_dir);
+    if let Some(spec_file) = &context.spec_file {
+        command.env("ISOTOPE_SPEC_FILE", spec_file);
+    }
+    if let Some(output_dir) = &context.output_dir {
+        command.env("ISOTOPE_OUTPUT_DIR", output_dir);
+    }
+    if let Some(succeeded) = stage_succeeded {
+        command.env(
+            "ISOTOPE_STAGE_RESULT",
+            if succeeded { "success" } else { "failure" },
+        );
+    }
+
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to execute hook: {}", hook.command))?;
+
+    log_output("stdout", &output.stdout);
+    log_output("stderr", &output.stderr);
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Hook '{}' exited with {}: {}",
+            hook.command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+fn shell_command(command_line: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut command = Command::new("cmd");
+        command.args(["/C", command_line]);
+        command
+    }
+    #[cfg(unix)]
+    {
+        let mut command = Command::new("sh");
+        command.args(["-c", command_line]);
+        command
+    }
+}
+
+fn log_output(stream: &str, bytes: &[u8]) {
+    for line in String::from_utf8_lossy(bytes).lines() {
+        debug!("hook {}: {}", stream, line);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::automation::vm::VmManager;
+use crate::config::keywords::KEYWORDS;
+use crate::config::{IsotopeSpec, StageType};
+use crate::core::steps;
+use crate::iso::packager::IsoPackager;
+use crate::utils::redact;
+use crate::utils::template::TemplateEngine;
+
+/// Print the configuration a build of `spec` would use, without creating a VM
+pub fn inspect_spec(spec: &IsotopeSpec, spec_file: &Path) -> Result<()> {
+    println!("Spec: {}", spec_file.display());
+    println!("Source ISO: {}", spec.from);
+    if let Some(checksum) = &spec.checksum {
+        println!("Checksum: {}:{}", checksum.algorithm, checksum.value);
+    }
+
+    // Resolve the VM config the same way the init stage does
+    let mut vm_manager = VmManager::new();
+    if let Some(init_stage) = spec.get_stage(&StageType::Init) {
+        vm_manager
+            .configure_from_stage(init_stage)
+            .context("Failed to resolve VM configuration from init stage")?;
+    }
+    let vm_config = vm_manager.default_config();
+    println!();
+    println!("VM:");
+    println!("  provider:        {:?}", vm_manager.configured_provider());
+    println!("  memory:          {} MB", vm_config.memory_mb);
+    println!("  cpus:            {}", vm_config.cpus);
+    println!("  disk:            {} GB", vm_config.disk_size_gb);
+    println!("  disk controller: {:?}", vm_config.disk_controller);
+    if let Some(resolution) = vm_config.resolution {
+        println!("  resolution:      {}", resolution);
+    }
+    println!("  clock:           {}", vm_config.clock);
+    println!("  devices:         {}", vm_config.devices);
+    match &vm_config.boot_condition {
+        Some(condition) => println!("  boot wait:       until '{}'", condition),
+        None => println!("  boot wait:       {:?}", vm_config.boot_wait),
+    }
+    println!("  timeout:         {:?}", vm_config.timeout);
+    for (slot, iso) in &vm_config.additional_isos {
+        println!("  iso slot {}:      {}", slot, iso.display());
+    }
+    if !vm_config.raw_args.is_empty() {
+        println!("  raw args:        {}", vm_config.raw_args.join(" "));
+    }
+
+    if !spec.labels.is_empty() {
+        let template_engine = TemplateEngine::new();
+        let variables = TemplateEngine::create_context_from_env();
+        let mut labels: Vec<_> = spec.labels.iter().collect();
+        labels.sort();
+
+        println!();
+        println!("Labels:");
+        for (key, value) in labels {
+            let rendered = template_engine
+                .render_string(value, &variables)
+                .with_context(|| format!("Failed to render label {}", key))?;
+            println!("  {} = {}", key, rendered);
+        }
+    }
+
+    println!();
+    println!("Steps:");
+    for range in steps::step_ranges(spec) {
+        let stage = range.stage(spec);
+        println!("  {}:", stage.display_name());
+        redact::register_stage(stage);
+        for (offset, instruction) in stage.instructions.iter().enumerate() {
+            if let Some((label, _)) = stage.step_labels.iter().find(|(_, i)| **i == offset) {
+                println!("    @{}", label);
+            }
+            let line = format!("    {:>3}. {:?}", range.first + offset, instruction);
+            println!("{}", redact::redact(&line));
+        }
+    }
+    println!("  Total steps: {}", steps::total_steps(spec));
+
+    if let Some(pack_stage) = spec.get_stage(&StageType::Pack) {
+        let export_path = spec.export_path(pack_stage);
+        println!();
+        println!(
+            "Output: {}",
+            IsoPackager::artifact_path(&export_path, pack_stage).display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the keyword table the parser and validator use, as text or JSON
+pub fn print_keywords(json: bool) -> Result<()> {
+    if json {
+        let table =
+            serde_json::to_string_pretty(KEYWORDS).context("Failed to serialize keywords")?;
+        println!("{}", table);
+        return Ok(());
+    }
+
+    for keyword in KEYWORDS {
+        let mut names = vec![keyword.keyword];
+        names.extend(keyword.aliases);
+        let login = if keyword.login { "[@login]" } else { "" };
+        println!("{}{} {}", names.join("|"), login, keyword.syntax);
+        println!("    {}", keyword.summary);
+
+        let scope = match (keyword.stages.is_empty(), keyword.step) {
+            (true, _) => "spec level".to_string(),
+            (false, true) => format!("step in {}", keyword.stage_list()),
+            (false, false) => format!("setting of {}", keyword.stage_list()),
+        };
+        println!("    scope:   {}", scope);
+        let fields: Vec<String> = keyword
+            .fields
+            .iter()
+            .map(|field| format!("{}: {}", field.name, field.kind))
+            .collect();
+        println!("    fields:  {}", fields.join(", "));
+        println!("    example: {}", keyword.example);
+        println!();
+    }
+    Ok(())
+}
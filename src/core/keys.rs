@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+
+use crate::automation::library_keyboard_input::LibraryBasedKeyboardMapper;
+use crate::config::{parser, Instruction};
+
+/// Print the scancodes `TYPE <text>` or `PRESS <combo>` would send, without a VM
+pub fn print_scancodes(text: Option<&str>, press: Option<&str>, unicode: bool) -> Result<()> {
+    let mut mapper = LibraryBasedKeyboardMapper::new();
+
+    match (text, press) {
+        (_, Some(combo)) => print_press_scancodes(&mut mapper, combo),
+        (Some(text), None) => print_text_scancodes(&mut mapper, text, unicode),
+        (None, None) => Err(anyhow!("Give either text to type or --press <key>")),
+    }
+}
+
+/// One line per character, then the whole sequence as it is sent
+fn print_text_scancodes(
+    mapper: &mut LibraryBasedKeyboardMapper,
+    text: &str,
+    unicode: bool,
+) -> Result<()> {
+    let mut to_scancodes = |text: &str| {
+        if unicode {
+            mapper.text_to_unicode_scancodes(text)
+        } else {
+            mapper.text_to_scancodes(text)
+        }
+    };
+
+    for ch in text.chars() {
+        let scancodes = to_scancodes(&ch.to_string())?;
+        println!("{:<8} {}", format!("{:?}", ch), scancodes.join(" "));
+    }
+
+    println!();
+    println!("{}", to_scancodes(text)?.join(" "));
+    Ok(())
+}
+
+fn print_press_scancodes(mapper: &mut LibraryBasedKeyboardMapper, combo: &str) -> Result<()> {
+    // Parse it as a spec line so combos and repeat counts are read exactly like PRESS
+    let (key, modifiers, repeat) = match parser::parse_stage_instruction("PRESS", combo, 1)? {
+        Instruction::Press {
+            key,
+            modifiers,
+            repeat,
+        } => (key, modifiers.unwrap_or_default(), repeat.unwrap_or(1)),
+        other => return Err(anyhow!("Expected a PRESS, parsed {:?}", other)),
+    };
+
+    if !modifiers.is_empty() {
+        println!("modifiers: {}", modifiers.join("+"));
+    }
+    println!("key:       {}", key);
+    println!();
+
+    let scancodes = key
+        .keys()
+        .iter()
+        .map(|key| mapper.press_to_scancodes(&modifiers, key))
+        .collect::<Result<Vec<_>>>()?;
+    for _ in 0..repeat {
+        for key_scancodes in &scancodes {
+            println!("{}", key_scancodes.join(" "));
+        }
+    }
+    Ok(())
+}
@@ -1,5 +1,17 @@
 pub mod builder;
+pub mod fingerprint;
+pub mod gc;
+pub mod hooks;
+pub mod inspect;
+pub mod keys;
 pub mod modifier;
+pub mod ocr_check;
+pub mod preflight;
+pub mod profile;
+pub mod providers;
+pub mod provision;
+pub mod report;
+pub mod steps;
 pub mod tester;
 
-pub use builder::Builder;
+pub use builder::{BootCheck, Builder};
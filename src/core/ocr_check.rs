@@ -0,0 +1,124 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::automation::ocr::{OcrEngine, OcrOptions};
+use crate::config::{screen_text_patterns, Instruction, IsotopeSpec, WaitCondition};
+use crate::core::steps;
+use crate::utils::template::TemplateEngine;
+
+/// A screen text WAIT of the spec, with its global step number
+struct ScreenWait {
+    step: usize,
+    pattern: String,
+}
+
+/// Run every screen text WAIT of `spec` against each PNG in `images_dir`, e.g. a previous
+/// build's debug-steps, and report which screenshots each pattern would have matched
+pub async fn check_images(
+    spec: &IsotopeSpec,
+    images_dir: &Path,
+    ocr_options: OcrOptions,
+) -> Result<()> {
+    let waits = screen_waits(spec)?;
+    if waits.is_empty() {
+        return Err(anyhow!("Spec has no WAIT FOR conditions on screen text"));
+    }
+
+    let images = screenshots(images_dir)?;
+    if images.is_empty() {
+        return Err(anyhow!("No PNG screenshots in {}", images_dir.display()));
+    }
+
+    let ocr_engine = OcrEngine::from_options(ocr_options).context("Failed to load OCR models")?;
+
+    // OCR each screenshot once, the patterns are then matched against the text
+    let mut texts = Vec::with_capacity(images.len());
+    for path in &images {
+        let image = image::open(path)
+            .with_context(|| format!("Failed to open screenshot: {}", path.display()))?;
+        let text = ocr_engine
+            .extract_text(&image)
+            .await
+            .with_context(|| format!("OCR failed on {}", path.display()))?;
+        texts.push(text.to_lowercase());
+    }
+
+    println!("Screen text waits against {}:", images_dir.display());
+    let mut unmatched = 0;
+    for wait in &waits {
+        let patterns: Vec<String> = screen_text_patterns(&wait.pattern)
+            .into_iter()
+            .map(str::to_lowercase)
+            .collect();
+        let matches: Vec<String> = images
+            .iter()
+            .zip(&texts)
+            .filter(|(_, text)| patterns.iter().any(|pattern| text.contains(pattern)))
+            .map(|(path, _)| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+            .collect();
+
+        if matches.is_empty() {
+            unmatched += 1;
+            println!("  ✗ step {:>3} WAIT FOR \"{}\": no match", wait.step, wait.pattern);
+        } else {
+            println!(
+                "  ✓ step {:>3} WAIT FOR \"{}\": {}",
+                wait.step,
+                wait.pattern,
+                matches.join(", ")
+            );
+        }
+    }
+
+    println!(
+        "{} of {} screen text waits matched at least one of {} screenshots",
+        waits.len() - unmatched,
+        waits.len(),
+        images.len()
+    );
+    Ok(())
+}
+
+/// Screen text conditions in step order, rendered the way the build renders them
+fn screen_waits(spec: &IsotopeSpec) -> Result<Vec<ScreenWait>> {
+    let template_engine = TemplateEngine::new();
+    let variables = TemplateEngine::create_context_from_env();
+    let mut waits = Vec::new();
+
+    for range in steps::step_ranges(spec) {
+        for (offset, instruction) in range.stage(spec).instructions.iter().enumerate() {
+            let Instruction::Wait {
+                condition: Some(condition),
+                ..
+            } = instruction
+            else {
+                continue;
+            };
+            if let WaitCondition::ScreenText(text) = WaitCondition::parse(condition) {
+                let pattern = template_engine
+                    .render_string(text, &variables)
+                    .with_context(|| format!("Failed to render WAIT condition '{}'", text))?;
+                waits.push(ScreenWait {
+                    step: range.first + offset,
+                    pattern,
+                });
+            }
+        }
+    }
+    Ok(waits)
+}
+
+/// PNGs in `dir` by name, leaving out the `--ocr-debug` annotated copies
+fn screenshots(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut images: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read screenshot directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+                && !path.to_string_lossy().ends_with("-annotated.png")
+        })
+        .collect();
+    images.sort();
+    Ok(images)
+}
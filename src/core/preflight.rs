@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Result};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::automation::models;
+use crate::automation::ocr::{DETECTION_MODEL, RECOGNITION_MODEL};
+use crate::config::{Instruction, IsotopeSpec, WaitCondition};
+use crate::iso::packager::ISO_TOOLS;
+
+/// How long to wait for the OCR model host when the models are not cached yet
+const MODEL_HOST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One external dependency of the spec and what probing it found
+pub(crate) struct ToolCheck {
+    name: String,
+    needed_for: &'static str,
+    /// Version or location on success
+    pub(crate) result: Result<String>,
+}
+
+/// Check that the external tools the spec's features rely on are present and runnable,
+/// printing a version (or the failure) for each, and fail if any is missing
+pub fn check_tools(spec: &IsotopeSpec) -> Result<()> {
+    let instructions: Vec<&Instruction> = spec
+        .stages
+        .iter()
+        .flat_map(|stage| &stage.instructions)
+        .collect();
+
+    let mut checks = vec![probe_binary(
+        &["VBoxManage"],
+        &["--version"],
+        "VirtualBox VMs and packing",
+    )];
+
+    if instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::AnswerFile { .. }))
+    {
+        checks.push(probe_binary(&ISO_TOOLS, &["-version"], "ANSWER_FILE remaster"));
+        #[cfg(unix)]
+        {
+            checks.push(probe_binary(&["mount"], &["--version"], "ANSWER_FILE ISO extraction"));
+            checks.push(probe_binary(&["umount"], &["--version"], "ANSWER_FILE ISO extraction"));
+        }
+        #[cfg(windows)]
+        checks.push(probe_binary(
+            &["7z", "powershell"],
+            &["-help"],
+            "ANSWER_FILE ISO extraction",
+        ));
+    }
+
+    if instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::ResizeDisk { .. }))
+    {
+        checks.push(probe_binary(&["qemu-img"], &["--version"], "RESIZE_DISK"));
+    }
+    if instructions.iter().any(|instruction| {
+        matches!(instruction, Instruction::Format { format } if format.starts_with("vhd"))
+    }) {
+        checks.push(probe_binary(&["qemu-img"], &["--version"], "FORMAT vhd/vhdx"));
+    }
+    if instructions.iter().any(|instruction| {
+        matches!(instruction, Instruction::Format { format } if format == "docker")
+    }) {
+        checks.push(probe_binary(&["guestfish"], &["--version"], "FORMAT docker"));
+    }
+    if instructions
+        .iter()
+        .any(|instruction| matches!(instruction, Instruction::Shrink { enabled: true }))
+    {
+        checks.push(probe_binary(&["virt-sparsify", "qemu-img"], &["--version"], "SHRINK"));
+    }
+
+    let conditions: Vec<WaitCondition> = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Wait {
+                condition: Some(condition),
+                ..
+            } => Some(WaitCondition::parse(condition)),
+            _ => None,
+        })
+        .collect();
+
+    if conditions
+        .iter()
+        .any(|condition| matches!(condition, WaitCondition::Ping(_)))
+    {
+        #[cfg(unix)]
+        checks.push(probe_binary(&["ping"], &["-V"], "WAIT FOR ping:"));
+        #[cfg(windows)]
+        checks.push(probe_binary(&["ping"], &["/?"], "WAIT FOR ping:"));
+    }
+
+    if spec.uses_ocr() {
+        for url in [DETECTION_MODEL, RECOGNITION_MODEL] {
+            checks.push(probe_ocr_model(url));
+        }
+    }
+
+    if !spec.hooks.is_empty() {
+        #[cfg(unix)]
+        checks.push(probe_binary(&["sh"], &["-c", "true"], "HOOK commands"));
+        #[cfg(windows)]
+        checks.push(probe_binary(&["cmd"], &["/C", "ver"], "HOOK commands"));
+    }
+
+    println!("Tools required by this spec:");
+    for check in &checks {
+        match &check.result {
+            Ok(version) => println!(
+                "  ✓ {:<28} {:<28} {}",
+                check.name, check.needed_for, version
+            ),
+            Err(e) => println!("  ✗ {:<28} {:<28} {}", check.name, check.needed_for, e),
+        }
+    }
+
+    let missing: Vec<&str> = checks
+        .iter()
+        .filter(|check| check.result.is_err())
+        .map(|check| check.name.as_str())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!("Missing required tools: {}", missing.join(", ")));
+    }
+    Ok(())
+}
+
+/// Run the first of `candidates` that starts and report the first line it prints
+pub(crate) fn probe_binary(
+    candidates: &[&str],
+    args: &[&str],
+    needed_for: &'static str,
+) -> ToolCheck {
+    let found = candidates.iter().find_map(|tool| {
+        let output = Command::new(tool).args(args).output().ok()?;
+        let text = if output.stdout.is_empty() {
+            output.stderr
+        } else {
+            output.stdout
+        };
+        let version = String::from_utf8_lossy(&text)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or("runnable")
+            .to_string();
+        Some((tool, version))
+    });
+
+    match found {
+        Some((tool, version)) => ToolCheck {
+            name: tool.to_string(),
+            needed_for,
+            result: Ok(version),
+        },
+        None => ToolCheck {
+            name: candidates.join("/"),
+            needed_for,
+            result: Err(anyhow!("not found")),
+        },
+    }
+}
+
+/// A cached OCR model is enough; otherwise its download host has to be reachable
+fn probe_ocr_model(url: &str) -> ToolCheck {
+    let needed_for = "WAIT FOR screen text";
+    let cached = match models::cached_path(url, None) {
+        Ok(path) => path,
+        Err(e) => {
+            return ToolCheck {
+                name: url.to_string(),
+                needed_for,
+                result: Err(e),
+            }
+        }
+    };
+    let name = cached
+        .file_name()
+        .map_or_else(|| url.to_string(), |name| name.to_string_lossy().to_string());
+
+    let result = if cached.exists() {
+        Ok(format!("cached at {}", cached.display()))
+    } else {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(MODEL_HOST_TIMEOUT))
+            .build()
+            .into();
+        agent
+            .head(url)
+            .call()
+            .map(|_| "not cached, download host reachable".to_string())
+            .map_err(|e| anyhow!("not cached and {} is unreachable: {}", url, e))
+    };
+
+    ToolCheck {
+        name,
+        needed_for,
+        result,
+    }
+}
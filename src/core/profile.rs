@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::automation::vm::VmState;
+
+/// Wall-clock time spent in one part of a build
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub name: String,
+    pub duration_secs: f64,
+}
+
+impl PhaseTiming {
+    pub fn new(name: impl Into<String>, duration: Duration) -> Self {
+        Self {
+            name: name.into(),
+            duration_secs: duration.as_secs_f64(),
+        }
+    }
+}
+
+/// A VM entering `state`, `at_secs` after the build attempt started
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmStateChange {
+    pub vm: String,
+    pub state: VmState,
+    pub at_secs: f64,
+}
+
+/// Timing breakdown collected with `--profile`, printed at the end and added to report.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BuildProfile {
+    pub phases: Vec<PhaseTiming>,
+    pub steps: Vec<PhaseTiming>,
+    /// OCR is reported on its own since it usually dominates WAIT FOR steps
+    pub ocr_secs: f64,
+    pub ocr_calls: usize,
+    /// Timeline of VM state changes, to tell a slow boot from a crash or a stuck start
+    #[serde(default)]
+    pub vm_states: Vec<VmStateChange>,
+}
+
+impl BuildProfile {
+    pub fn print(&self) {
+        println!();
+        println!("Build profile:");
+        for phase in &self.phases {
+            println!("  {:<40} {:>9.2}s", phase.name, phase.duration_secs);
+        }
+        println!(
+            "  {:<40} {:>9.2}s",
+            format!("ocr ({} calls)", self.ocr_calls),
+            self.ocr_secs
+        );
+
+        if !self.steps.is_empty() {
+            println!("Steps:");
+            for step in &self.steps {
+                println!("  {:<40} {:>9.2}s", step.name, step.duration_secs);
+            }
+        }
+
+        if !self.vm_states.is_empty() {
+            println!("VM states:");
+            for change in &self.vm_states {
+                println!(
+                    "  {:<40} {:>9.2}s",
+                    format!("{} {:?}", change.vm, change.state),
+                    change.at_secs
+                );
+            }
+        }
+    }
+}
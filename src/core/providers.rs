@@ -0,0 +1,22 @@
+use anyhow::Result;
+
+use crate::automation::vm::providers::create_provider;
+use crate::automation::vm::{Capability, VmProvider};
+
+/// Print every provider with the version of its installed tool and the features it supports
+pub fn list_providers() -> Result<()> {
+    for provider_type in VmProvider::ALL {
+        let provider = create_provider(&provider_type);
+        match provider.tool_version() {
+            Ok(version) => println!("✓ {:<12} {}", provider_type.name(), version),
+            Err(e) => println!("✗ {:<12} not available: {:#}", provider_type.name(), e),
+        }
+
+        let capabilities = provider.capabilities();
+        for capability in Capability::ALL {
+            let mark = if capabilities.supports(capability) { "✓" } else { "✗" };
+            println!("    {} {}", mark, capability);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::automation::puppet::{HostKeyPolicy, PuppetManager, SshCredentials};
+use crate::automation::vm::{VmConfig, VmInstance, VmProvider};
+use crate::config::{IsotopeSpec, StageType};
+use crate::utils::redact;
+
+/// Default SSH port of an external host given without `:PORT`
+const DEFAULT_SSH_PORT: u16 = 22;
+
+/// Environment variable holding the SSH password when there is no `--password-file`
+pub const PASSWORD_ENV: &str = "ISOTOPE_SSH_PASSWORD";
+
+/// `USER@HOST[:PORT]` of a machine isotope provisions but doesn't manage
+#[derive(Debug, Clone)]
+pub struct ExternalHost {
+    pub username: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl std::str::FromStr for ExternalHost {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid host '{}'. Expected USER@HOST[:PORT]", s);
+        let (username, address) = s.split_once('@').ok_or_else(invalid)?;
+
+        // A bracketed IPv6 address keeps its colons, e.g. root@[fe80::1]:2222
+        let (host, port) = match address.strip_prefix('[') {
+            Some(rest) => {
+                let (host, port) = rest.split_once(']').ok_or_else(invalid)?;
+                match port {
+                    "" => (host, None),
+                    port => (host, Some(port.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            }
+            None => match address.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().ok().filter(|port| *port != 0).ok_or_else(invalid)?,
+            None => DEFAULT_SSH_PORT,
+        };
+        if username.is_empty() || host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            username: username.to_string(),
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// SSH password from the first line of `password_file`, else `ISOTOPE_SSH_PASSWORD`, else a
+/// hidden prompt when `prompt` is set and stdin is a terminal
+pub fn read_password(password_file: Option<&Path>, prompt: bool) -> Result<Option<String>> {
+    if let Some(path) = password_file {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read password file {}", path.display()))?;
+        let password = contents.lines().next().unwrap_or_default();
+        if password.is_empty() {
+            return Err(anyhow!("Password file {} is empty", path.display()));
+        }
+        return Ok(Some(password.to_string()));
+    }
+
+    if let Some(password) = std::env::var(PASSWORD_ENV).ok().filter(|p| !p.is_empty()) {
+        return Ok(Some(password));
+    }
+
+    if prompt && std::io::stdin().is_terminal() {
+        let term = console::Term::stderr();
+        term.write_str("SSH password: ").context("Failed to prompt for the SSH password")?;
+        let password = term.read_secure_line().context("Failed to read the SSH password")?;
+        return Ok(Some(password).filter(|p| !p.is_empty()));
+    }
+
+    Ok(None)
+}
+
+/// Run the SSH instructions of every os_configure stage of `spec` against `target`,
+/// without creating, starting or stopping a VM. The host key must be in ~/.ssh/known_hosts
+/// unless `accept_new_host_key` lets an unknown host's key be added
+pub async fn provision(
+    spec: &IsotopeSpec,
+    target: &ExternalHost,
+    private_key: Option<PathBuf>,
+    password: Option<String>,
+    accept_new_host_key: bool,
+) -> Result<()> {
+    let stages: Vec<_> = spec
+        .stages
+        .iter()
+        .filter(|stage| stage.name == StageType::OsConfigure)
+        .collect();
+    if stages.is_empty() {
+        return Err(anyhow!("Spec has no os_configure stage to provision"));
+    }
+
+    if let Some(password) = &password {
+        redact::register(password);
+    }
+    let credentials = SshCredentials {
+        username: target.username.clone(),
+        password,
+        private_key,
+        host_key: if accept_new_host_key {
+            HostKeyPolicy::AcceptNew
+        } else {
+            HostKeyPolicy::KnownHosts
+        },
+    };
+
+    // The puppet manager addresses its guest through a VM instance, which only names it here
+    let name = format!("{}:{}", target.host, target.port);
+    let vm = VmInstance::new(name.clone(), name, VmProvider::VirtualBox, VmConfig::default());
+
+    let mut puppet_manager = PuppetManager::without_ocr(false);
+    puppet_manager.set_ssh_endpoint(target.host.clone(), target.port);
+    puppet_manager.set_defaults(spec.defaults.clone());
+    puppet_manager.set_labels(spec.labels.clone());
+
+    for stage in stages {
+        puppet_manager
+            .provision_stage(&vm, stage, &credentials)
+            .await?;
+    }
+
+    info!("Provisioned {}@{}", target.username, vm.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_host() {
+        let host: ExternalHost = "admin@10.0.0.5".parse().unwrap();
+        assert_eq!(host.username, "admin");
+        assert_eq!((host.host.as_str(), host.port), ("10.0.0.5", 22));
+
+        let host: ExternalHost = "admin@build-box:2222".parse().unwrap();
+        assert_eq!((host.host.as_str(), host.port), ("build-box", 2222));
+
+        let host: ExternalHost = "root@[fe80::1]:2222".parse().unwrap();
+        assert_eq!((host.host.as_str(), host.port), ("fe80::1", 2222));
+
+        let host: ExternalHost = "root@[::1]".parse().unwrap();
+        assert_eq!((host.host.as_str(), host.port), ("::1", 22));
+
+        let invalid = ["10.0.0.5", "@host", "admin@", "admin@host:0", "admin@host:ssh"];
+        for invalid in invalid.into_iter().chain(["root@[::1", "root@[::1]2222"]) {
+            assert!(invalid.parse::<ExternalHost>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_read_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "s3cret\n").unwrap();
+        assert_eq!(read_password(Some(&path), false).unwrap().as_deref(), Some("s3cret"));
+
+        std::fs::write(&path, "").unwrap();
+        assert!(read_password(Some(&path), false).is_err());
+        assert!(read_password(Some(&dir.path().join("missing")), false).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::core::profile::BuildProfile;
+
+/// Summary of a build written to `report.json` in the output directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildReport {
+    pub spec_file: Option<PathBuf>,
+    pub success: bool,
+    pub started_at: String,  // ISO 8601 timestamp
+    pub finished_at: String, // ISO 8601 timestamp
+    pub duration_secs: f64,
+    pub artifact: Option<PathBuf>,
+    pub checksum: Option<String>, // "algorithm:value" of the artifact
+    pub error: Option<String>,
+    /// The spec's LABELs, for provenance of the artifact
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// Timing breakdown, only present with `--profile`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<BuildProfile>,
+}
+
+impl BuildReport {
+    pub const FILE_NAME: &'static str = "report.json";
+
+    pub fn new(spec_file: Option<PathBuf>, started_at: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            spec_file,
+            success: false,
+            started_at: started_at.to_rfc3339(),
+            finished_at: String::new(),
+            duration_secs: 0.0,
+            artifact: None,
+            checksum: None,
+            error: None,
+            labels: BTreeMap::new(),
+            profile: None,
+        }
+    }
+
+    pub fn finish(&mut self, started_at: chrono::DateTime<chrono::Utc>) {
+        let now = chrono::Utc::now();
+        self.finished_at = now.to_rfc3339();
+        self.duration_secs = (now - started_at).num_milliseconds() as f64 / 1000.0;
+    }
+
+    pub fn write_to_dir(&self, dir: &Path) -> Result<PathBuf> {
+        let report_path = dir.join(Self::FILE_NAME);
+        let content =
+            serde_json::to_string_pretty(self).context("Failed to serialize build report")?;
+
+        std::fs::write(&report_path, content)
+            .with_context(|| format!("Failed to write {}", report_path.display()))?;
+
+        info!("Build report written to {}", report_path.display());
+        Ok(report_path)
+    }
+}
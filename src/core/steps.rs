@@ -0,0 +1,99 @@
+use anyhow::{anyhow, Result};
+
+use crate::config::{IsotopeSpec, Stage, StageType};
+
+/// Stage types whose instructions get global step numbers. A spec may have several stages of
+/// each; they run in file order, which the validator keeps to os_install before os_configure.
+const NUMBERED_STAGES: [StageType; 2] = [StageType::OsInstall, StageType::OsConfigure];
+
+/// A stage's share of the global (1-based) step numbering used by `--continue-from`
+#[derive(Debug, Clone)]
+pub struct StepRange {
+    pub stage: StageType,
+    /// Position of the stage in `spec.stages`
+    pub stage_index: usize,
+    pub first: usize,
+    pub count: usize,
+}
+
+impl StepRange {
+    pub fn stage<'a>(&self, spec: &'a IsotopeSpec) -> &'a Stage {
+        &spec.stages[self.stage_index]
+    }
+
+    pub fn last(&self) -> usize {
+        self.first + self.count.saturating_sub(1)
+    }
+
+    pub fn contains(&self, step: usize) -> bool {
+        self.count > 0 && step >= self.first && step <= self.last()
+    }
+}
+
+pub fn step_ranges(spec: &IsotopeSpec) -> Vec<StepRange> {
+    let mut next_step = 1;
+    let mut ranges = Vec::new();
+
+    for (stage_index, stage) in spec.stages.iter().enumerate() {
+        if !NUMBERED_STAGES.contains(&stage.name) {
+            continue;
+        }
+        ranges.push(StepRange {
+            stage: stage.name.clone(),
+            stage_index,
+            first: next_step,
+            count: stage.instructions.len(),
+        });
+        next_step += stage.instructions.len();
+    }
+
+    ranges
+}
+
+pub fn total_steps(spec: &IsotopeSpec) -> usize {
+    step_ranges(spec).iter().map(|range| range.count).sum()
+}
+
+/// Resolve a `--continue-from` target, either a step number or a `# @label:` name
+pub fn resolve_step(spec: &IsotopeSpec, target: &str) -> Result<usize> {
+    if let Ok(step) = target.parse::<usize>() {
+        return Ok(step);
+    }
+
+    for range in step_ranges(spec) {
+        if let Some(index) = range.stage(spec).step_labels.get(target) {
+            return Ok(range.first + index);
+        }
+    }
+
+    let labelled_elsewhere = spec
+        .stages
+        .iter()
+        .any(|stage| stage.step_labels.contains_key(target));
+    if labelled_elsewhere {
+        return Err(anyhow!(
+            "Step label '{}' is not in os_install or os_configure, which are the only stages \
+             that can be continued from",
+            target
+        ));
+    }
+    Err(anyhow!("Unknown step label '{}'", target))
+}
+
+/// Map a global step number to its stage's range and 1-based position within that stage
+pub fn stage_for_step(spec: &IsotopeSpec, target_step: usize) -> Result<(StepRange, usize)> {
+    step_ranges(spec)
+        .into_iter()
+        .find(|range| range.contains(target_step))
+        .map(|range| {
+            let step_in_stage = target_step - range.first + 1;
+            (range, step_in_stage)
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "Step {} is out of range. Total steps available: {}",
+                target_step,
+                total_steps(spec)
+            )
+        })
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+/// Unattended-install answer file formats that can be injected into a source ISO
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnswerFileKind {
+    Preseed,
+    Kickstart,
+    AutoYast,
+}
+
+impl std::str::FromStr for AnswerFileKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "preseed" => Ok(AnswerFileKind::Preseed),
+            "kickstart" => Ok(AnswerFileKind::Kickstart),
+            "autoyast" => Ok(AnswerFileKind::AutoYast),
+            _ => Err(anyhow!(
+                "Unsupported answer file kind: {}. Supported: preseed, kickstart, autoyast",
+                s
+            )),
+        }
+    }
+}
+
+impl AnswerFileKind {
+    /// File name the answer file is stored under at the root of the ISO
+    pub fn iso_file_name(&self) -> &'static str {
+        match self {
+            AnswerFileKind::Preseed => "preseed.cfg",
+            AnswerFileKind::Kickstart => "ks.cfg",
+            AnswerFileKind::AutoYast => "autoinst.xml",
+        }
+    }
+
+    /// Kernel command line arguments pointing the installer at the injected file
+    pub fn kernel_args(&self) -> &'static str {
+        match self {
+            AnswerFileKind::Preseed => "auto=true priority=critical file=/cdrom/preseed.cfg",
+            AnswerFileKind::Kickstart => "inst.ks=cdrom:/ks.cfg",
+            AnswerFileKind::AutoYast => "autoyast=cd:/autoinst.xml",
+        }
+    }
+}
+
+/// Bootloader configs patched to reference the answer file, relative to the ISO root
+const BOOT_CONFIGS: &[&str] = &[
+    "isolinux/isolinux.cfg",
+    "isolinux/txt.cfg",
+    "isolinux/adtxt.cfg",
+    "boot/grub/grub.cfg",
+    "EFI/BOOT/grub.cfg",
+];
+
+/// Copy the answer file into an extracted ISO tree and patch its bootloader configs
+pub fn inject_answer_file(
+    extract_dir: &Path,
+    kind: AnswerFileKind,
+    answer_file: &Path,
+) -> Result<()> {
+    info!(
+        "Injecting {:?} answer file {} into extracted ISO",
+        kind,
+        answer_file.display()
+    );
+
+    let target = extract_dir.join(kind.iso_file_name());
+    std::fs::copy(answer_file, &target).with_context(|| {
+        format!(
+            "Failed to copy answer file {} to {}",
+            answer_file.display(),
+            target.display()
+        )
+    })?;
+
+    let mut patched = 0;
+    for config in BOOT_CONFIGS {
+        let config_path = extract_dir.join(config);
+        if !config_path.exists() {
+            continue;
+        }
+
+        let content = std::fs::read_to_string(&config_path)
+            .with_context(|| format!("Failed to read boot config: {}", config_path.display()))?;
+        let updated = patch_boot_config(&content, kind);
+
+        if updated != content {
+            std::fs::write(&config_path, updated).with_context(|| {
+                format!("Failed to write boot config: {}", config_path.display())
+            })?;
+            debug!("Patched boot config {}", config_path.display());
+            patched += 1;
+        }
+    }
+
+    if patched == 0 {
+        warn!("No bootloader config was patched; the installer may not pick up the answer file");
+    } else {
+        info!("Patched {} bootloader config(s) to use the answer file", patched);
+    }
+
+    Ok(())
+}
+
+/// Append the answer file kernel arguments to every isolinux `append` and grub `linux` line
+fn patch_boot_config(content: &str, kind: AnswerFileKind) -> String {
+    let args = kind.kernel_args();
+
+    let mut patched = content
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim_start().to_lowercase();
+            let is_kernel_line = trimmed.starts_with("append ")
+                || trimmed.starts_with("linux ")
+                || trimmed.starts_with("linuxefi ");
+
+            if !is_kernel_line || line.contains(args) {
+                return line.to_string();
+            }
+
+            // Keep a trailing "---" separator (Debian/Ubuntu) at the end of the line
+            match line.find(" ---") {
+                Some(pos) => format!("{} {}{}", &line[..pos], args, &line[pos..]),
+                None => format!("{} {}", line.trim_end(), args),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if content.ends_with('\n') {
+        patched.push('\n');
+    }
+    patched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_boot_config() {
+        let isolinux = "label install\n  kernel /install/vmlinuz\n  \
+                        append vga=788 initrd=/install/initrd.gz --- quiet\n";
+        let patched = patch_boot_config(isolinux, AnswerFileKind::Preseed);
+        assert_eq!(
+            patched,
+            "label install\n  kernel /install/vmlinuz\n  append vga=788 \
+             initrd=/install/initrd.gz auto=true priority=critical file=/cdrom/preseed.cfg \
+             --- quiet\n"
+        );
+        // Patching twice doesn't append the arguments again
+        assert_eq!(patch_boot_config(&patched, AnswerFileKind::Preseed), patched);
+
+        let grub = "menuentry 'Install' {\n\tlinuxefi /images/pxeboot/vmlinuz quiet\n}";
+        assert_eq!(
+            patch_boot_config(grub, AnswerFileKind::Kickstart),
+            "menuentry 'Install' {\n\tlinuxefi /images/pxeboot/vmlinuz quiet \
+             inst.ks=cdrom:/ks.cfg\n}"
+        );
+        // Lines that don't load a kernel are left alone
+        let other = "timeout 50\ndefault install";
+        assert_eq!(patch_boot_config(other, AnswerFileKind::AutoYast), other);
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::iso::IsoExtractor;
+
+/// Written last into a cache entry, holding the source checksum it was extracted from
+const COMPLETE_MARKER: &str = ".complete";
+
+/// Share of the ISO's size the extracted files must add up to; an ISO 9660 image is
+/// almost all file data, so a tree well below it was cut short
+const MIN_EXTRACTED_SHARE: f64 = 0.5;
+
+/// Content-addressed store of extracted ISO trees, keyed by the source ISO's SHA-256
+pub struct IsoCache {
+    root: PathBuf,
+}
+
+impl IsoCache {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// Return the extracted tree for `iso_path`, extracting it on a cache miss
+    pub fn extracted_tree(
+        &self,
+        iso_path: &Path,
+        sha256: &str,
+        extractor: &IsoExtractor,
+    ) -> Result<PathBuf> {
+        self.extracted_tree_with(iso_path, sha256, |tree_dir| {
+            extractor.extract_iso(iso_path, tree_dir)
+        })
+    }
+
+    fn extracted_tree_with(
+        &self,
+        iso_path: &Path,
+        sha256: &str,
+        extract: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<PathBuf> {
+        let sha256 = sha256.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Invalid SHA-256 cache key: {}", sha256));
+        }
+
+        let entry_dir = self.root.join(&sha256);
+        let tree_dir = entry_dir.join("tree");
+        let marker = entry_dir.join(COMPLETE_MARKER);
+
+        match std::fs::read_to_string(&marker) {
+            Ok(recorded) if recorded.trim() == sha256 && tree_dir.is_dir() => {
+                info!("Reusing cached extraction of {} ({})", iso_path.display(), sha256);
+                return Ok(tree_dir);
+            }
+            Ok(_) => warn!(
+                "Cached extraction {} does not match its checksum, re-extracting",
+                entry_dir.display()
+            ),
+            Err(_) => info!("No cached extraction for {}, extracting", iso_path.display()),
+        }
+
+        // Drop partial or mismatched entries before extracting again
+        if entry_dir.exists() {
+            std::fs::remove_dir_all(&entry_dir).with_context(|| {
+                format!("Failed to remove stale cache entry: {}", entry_dir.display())
+            })?;
+        }
+
+        // Extract next to the entry's tree and only move it into place once it checks out, so
+        // the marker never vouches for a tree an interrupted or failed extraction left behind
+        let partial_dir = entry_dir.join("tree.partial");
+        extract(&partial_dir).context("Failed to extract ISO into cache")?;
+        check_extracted_tree(&partial_dir, iso_path)?;
+        std::fs::rename(&partial_dir, &tree_dir).with_context(|| {
+            format!("Failed to move extraction into cache: {}", tree_dir.display())
+        })?;
+
+        std::fs::write(&marker, &sha256)
+            .with_context(|| format!("Failed to write cache marker: {}", marker.display()))?;
+
+        Ok(tree_dir)
+    }
+}
+
+/// Fail unless `tree_dir` holds files adding up to most of the ISO's size
+fn check_extracted_tree(tree_dir: &Path, iso_path: &Path) -> Result<()> {
+    let iso_bytes = std::fs::metadata(iso_path)
+        .with_context(|| format!("Failed to read ISO metadata: {}", iso_path.display()))?
+        .len();
+    let extracted_bytes: u64 = WalkDir::new(tree_dir)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    if extracted_bytes == 0 || (extracted_bytes as f64) < iso_bytes as f64 * MIN_EXTRACTED_SHARE {
+        return Err(anyhow!(
+            "Extraction of {} is incomplete: {} of {} bytes, not caching it",
+            iso_path.display(),
+            extracted_bytes,
+            iso_bytes
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "6c2b3a9ab7ba5f2c1ccd4a4a43bbc56ed31d4db5c1e3c1b7a6d8a0e7b4f0c9d1";
+
+    #[test]
+    fn test_cache_hit_and_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let iso_path = dir.path().join("source.iso");
+        std::fs::write(&iso_path, [0u8; 64]).unwrap();
+        let cache = IsoCache::new(dir.path().join("cache"));
+
+        let extract = |tree_dir: &Path| {
+            std::fs::create_dir_all(tree_dir)?;
+            std::fs::write(tree_dir.join("vmlinuz"), [1u8; 64])?;
+            Ok(())
+        };
+        let tree = cache.extracted_tree_with(&iso_path, SHA256, extract).unwrap();
+        assert!(tree.join("vmlinuz").is_file());
+
+        // A hit never extracts again
+        let hit = cache
+            .extracted_tree_with(&iso_path, SHA256, |_| panic!("extracted on a cache hit"))
+            .unwrap();
+        assert_eq!(hit, tree);
+    }
+
+    #[test]
+    fn test_incomplete_extraction_is_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let iso_path = dir.path().join("source.iso");
+        std::fs::write(&iso_path, [0u8; 64]).unwrap();
+        let cache = IsoCache::new(dir.path().join("cache"));
+
+        let truncated = |tree_dir: &Path| {
+            std::fs::create_dir_all(tree_dir)?;
+            std::fs::write(tree_dir.join("vmlinuz"), [1u8; 8])?;
+            Ok(())
+        };
+        assert!(cache.extracted_tree_with(&iso_path, SHA256, truncated).is_err());
+        assert!(!dir.path().join("cache").join(SHA256).join(COMPLETE_MARKER).exists());
+    }
+}
@@ -1,8 +1,12 @@
 use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tracing::{debug, info};
 
+/// Byte offset of the ISO 9660 primary volume descriptor (sector 16)
+const PRIMARY_VOLUME_DESCRIPTOR: u64 = 16 * 2048;
+
 pub struct IsoExtractor {
     temp_dir: PathBuf,
 }
@@ -62,13 +66,12 @@ impl IsoExtractor {
             ));
         }
 
-        // Copy all files from mounted ISO to extraction directory
+        // Copy all files from mounted ISO to extraction directory; `<mount>/.` includes
+        // dotfiles and needs no shell glob
         let cp_output = Command::new("cp")
-            .args([
-                "-r", // Recursive
-                &format!("{}/*", mount_point.display()),
-                extract_path.to_str().unwrap(),
-            ])
+            .arg("-a")
+            .arg(mount_point.join("."))
+            .arg(extract_path)
             .output();
 
         // Always try to unmount, even if copy failed
@@ -92,6 +95,20 @@ impl IsoExtractor {
             debug!("Warning: failed to unmount ISO: {}", e);
         }
 
+        // ISO 9660 files come out read-only, which would stop answer file injection
+        let chmod_output = Command::new("chmod")
+            .arg("-R")
+            .arg("u+w")
+            .arg(extract_path)
+            .output()
+            .context("Failed to run chmod on extracted ISO")?;
+        if !chmod_output.status.success() {
+            return Err(anyhow!(
+                "Failed to make extracted ISO writable: {}",
+                String::from_utf8_lossy(&chmod_output.stderr)
+            ));
+        }
+
         info!("Successfully extracted ISO to: {}", extract_path.display());
         Ok(())
     }
@@ -169,6 +186,19 @@ impl IsoExtractor {
         Ok(())
     }
 
+    /// Volume ID of an ISO 9660 image, which installers such as Anaconda find their
+    /// stage2 by (`inst.stage2=hd:LABEL=<id>`)
+    pub fn volume_id(&self, iso_path: &Path) -> Result<String> {
+        let mut file = std::fs::File::open(iso_path)
+            .with_context(|| format!("Failed to open ISO: {}", iso_path.display()))?;
+        let mut descriptor = [0u8; 2048];
+        file.seek(SeekFrom::Start(PRIMARY_VOLUME_DESCRIPTOR))
+            .and_then(|_| file.read_exact(&mut descriptor))
+            .with_context(|| format!("Failed to read ISO header: {}", iso_path.display()))?;
+        parse_volume_id(&descriptor)
+            .ok_or_else(|| anyhow!("No ISO 9660 volume ID in {}", iso_path.display()))
+    }
+
     pub fn verify_iso_structure(&self, extract_path: &Path) -> Result<()> {
         info!("Verifying extracted ISO structure");
 
@@ -213,3 +243,33 @@ impl IsoExtractor {
         Ok(())
     }
 }
+
+/// Volume ID of an ISO 9660 primary volume descriptor: 32 space-padded bytes at offset 40
+fn parse_volume_id(descriptor: &[u8]) -> Option<String> {
+    if descriptor.first() != Some(&1) || descriptor.get(1..6) != Some(b"CD001".as_slice()) {
+        return None;
+    }
+    let id = String::from_utf8_lossy(descriptor.get(40..72)?);
+    let id = id.trim_end_matches([' ', '\0']);
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_volume_id() {
+        let mut descriptor = [0u8; 2048];
+        descriptor[0] = 1;
+        descriptor[1..6].copy_from_slice(b"CD001");
+        descriptor[40..72].copy_from_slice(format!("{:<32}", "Rocky-9-4-x86_64-dvd").as_bytes());
+        assert_eq!(parse_volume_id(&descriptor).as_deref(), Some("Rocky-9-4-x86_64-dvd"));
+
+        descriptor[40..72].fill(b' ');
+        assert_eq!(parse_volume_id(&descriptor), None);
+        // Not a primary volume descriptor
+        descriptor[0] = 2;
+        assert_eq!(parse_volume_id(&descriptor), None);
+    }
+}
@@ -1,3 +1,5 @@
+pub mod answer_file;
+pub mod cache;
 pub mod extractor;
 pub mod packager;
 
@@ -1,34 +1,311 @@
 use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use tracing::{debug, info, warn};
 
+use crate::automation::vm::providers::VBoxManageCommand;
+use crate::automation::vm::VmConfig;
 use crate::config::{Instruction, Stage};
 
+/// ISO authoring tools `repack_iso` can drive, in order of preference. All of them take
+/// mkisofs-style arguments; xorriso needs `-as mkisofs` to switch into that mode.
+pub(crate) const ISO_TOOLS: [&str; 3] = ["xorriso", "mkisofs", "genisoimage"];
+
+/// Free space a raw pack needs per byte of virtual disk: the image, plus the second copy
+/// a qemu-img sparse copy writes next to it
+const PACK_SPACE_FACTOR: u64 = 2;
+
+/// Azure only accepts VHDs whose virtual size is a whole number of MiB
+const VHD_ALIGNMENT: u64 = 1 << 20;
+
+/// Largest virtual disk each virtual hard disk format can hold
+const VHD_MAX_SIZE: u64 = 2040 << 30;
+const VHDX_MAX_SIZE: u64 = 64 << 40;
+
+/// Pseudo and runtime filesystem contents left out of a FORMAT docker rootfs tarball; their
+/// empty mount points stay so the container runtime can mount over them
+const ROOTFS_EXCLUDES: [&str; 5] = ["./proc/*", "./sys/*", "./dev/*", "./run/*", "./tmp/*"];
+
+/// Length of the application and publisher ID fields of an ISO 9660 volume descriptor
+const ISO_ID_FIELD_LEN: usize = 128;
+
+/// Largest image FORMAT `format` can hold, None when the format has no limit we enforce
+pub fn max_image_size(format: &str) -> Option<u64> {
+    match format {
+        "vhd" => Some(VHD_MAX_SIZE),
+        "vhdx" => Some(VHDX_MAX_SIZE),
+        _ => None,
+    }
+}
+
+/// First ISO authoring tool on this host that can be executed
+fn find_iso_tool() -> Result<&'static str> {
+    ISO_TOOLS
+        .into_iter()
+        .find(|tool| Command::new(tool).arg("-version").output().is_ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "No ISO authoring tool found, install one of: {}",
+                ISO_TOOLS.join(", ")
+            )
+        })
+}
+
+/// Parse a RESIZE_DISK size such as `512M`, `8G` or `1T` into bytes
+pub fn parse_image_size(size: &str) -> Result<u64> {
+    let lower = size.trim().to_lowercase();
+    let lower = lower.strip_suffix('b').unwrap_or(&lower);
+    let (number, unit) = match lower.char_indices().last() {
+        Some((index, 'm')) => (&lower[..index], 1u64 << 20),
+        Some((index, 'g')) => (&lower[..index], 1 << 30),
+        Some((index, 't')) => (&lower[..index], 1 << 40),
+        _ => return Err(anyhow!("Invalid image size: {}. Expected e.g. 512M, 8G or 1T", size)),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| anyhow!("Invalid image size: {}. Expected e.g. 512M, 8G or 1T", size))
+}
+
+/// Partition table of a raw image, read from its first sector
+#[derive(Debug, PartialEq)]
+enum PartitionTable {
+    /// No MBR boot signature, e.g. a filesystem written straight to the disk
+    None,
+    /// Protective MBR of a GPT disk, whose backup table lives in the last sectors
+    Gpt,
+    /// MBR partitions, ending `end` bytes into the disk
+    Mbr { end: u64 },
+}
+
+/// Read the partition table out of an image's first 512-byte sector. Extended partitions
+/// span their logical ones, so the primary entries are enough to find the end.
+fn partition_table(sector: &[u8]) -> PartitionTable {
+    if sector.len() < 512 || sector[510..512] != [0x55, 0xAA] {
+        return PartitionTable::None;
+    }
+    let entries = sector[446..510].chunks_exact(16);
+    if entries.clone().any(|entry| entry[4] == 0xEE) {
+        return PartitionTable::Gpt;
+    }
+    let end = entries
+        .filter(|entry| entry[4] != 0)
+        .map(|entry| {
+            let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+            let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+            (start + sectors) * 512
+        })
+        .max()
+        .unwrap_or(0);
+    PartitionTable::Mbr { end }
+}
+
+/// Bytes a file actually occupies, which is less than its length when it is sparse
+fn allocated_bytes(path: &Path) -> Result<u64> {
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Failed to read image metadata: {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(metadata.blocks() * 512)
+    }
+
+    #[cfg(not(unix))]
+    {
+        Ok(metadata.len())
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`
+#[cfg(unix)]
+fn available_bytes(dir: &Path) -> Result<Option<u64>> {
+    let stat = nix::sys::statvfs::statvfs(dir)
+        .with_context(|| format!("Failed to query free space of {}", dir.display()))?;
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.blocks_available() as u64 * stat.fragment_size() as u64))
+}
+
+#[cfg(not(unix))]
+fn available_bytes(_dir: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Parse `Capacity: 20480 MBytes` from `VBoxManage showmediuminfo` output into bytes
+fn parse_medium_capacity(info: &str) -> Option<u64> {
+    let capacity = info
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Capacity:"))?
+        .trim();
+    let (number, unit) = capacity.split_once(' ')?;
+    let unit = match unit.trim() {
+        "Bytes" => 1,
+        "KBytes" => 1 << 10,
+        "MBytes" => 1 << 20,
+        "GBytes" => 1 << 30,
+        "TBytes" => 1u64 << 40,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn format_gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
+
 pub struct IsoPackager {
     temp_dir: PathBuf,
 }
 
 impl IsoPackager {
-    pub fn new() -> Self {
-        Self {
-            temp_dir: std::env::temp_dir().join("isotope-iso-work"),
+    /// `temp_dir` should belong to one build so concurrent builds don't clobber each other
+    pub fn new(temp_dir: PathBuf) -> Self {
+        Self { temp_dir }
+    }
+
+    /// Path of the file the pack stage produces for `output_path` (extension follows FORMAT)
+    pub fn artifact_path(output_path: &Path, pack_stage: &Stage) -> PathBuf {
+        match Self::pack_format(pack_stage).as_deref() {
+            Some(format @ ("vmdk" | "vhd" | "vhdx")) => output_path.with_extension(format),
+            Some("docker") => output_path.with_extension("tar"),
+            _ => output_path.with_extension("img"),
         }
     }
 
-    pub fn create_bootable_image(
+    /// Lowercase FORMAT of the pack stage, None for the default raw image
+    pub fn pack_format(pack_stage: &Stage) -> Option<String> {
+        pack_stage.instructions.iter().find_map(|instruction| match instruction {
+            Instruction::Format { format } => Some(format.to_lowercase()),
+            _ => None,
+        })
+    }
+
+    fn pack_resize(pack_stage: &Stage) -> Option<&str> {
+        pack_stage.instructions.iter().find_map(|instruction| match instruction {
+            Instruction::ResizeDisk { size } => Some(size.as_str()),
+            _ => None,
+        })
+    }
+
+    fn pack_shrink(pack_stage: &Stage) -> bool {
+        pack_stage.instructions.iter().any(|instruction| {
+            matches!(instruction, Instruction::Shrink { enabled: true })
+        })
+    }
+
+    /// Fail before converting when the output filesystem can't hold the packed image of
+    /// `vdi_path`, instead of letting the converter die halfway through
+    pub fn check_free_space(
         &self,
         vdi_path: &Path,
         output_path: &Path,
-        _pack_stage: &Stage,
+        pack_stage: &Stage,
     ) -> Result<()> {
+        let capacity = match self.disk_capacity(vdi_path) {
+            Ok(capacity) => capacity,
+            Err(e) => {
+                warn!("Skipping free space check before pack: {:#}", e);
+                return Ok(());
+            }
+        };
+        // RESIZE_DISK can grow the image beyond the VM disk
+        let image_size = match Self::pack_resize(pack_stage) {
+            Some(size) => capacity.max(parse_image_size(size)?),
+            None => capacity,
+        };
+        // A VMDK is a single dynamic copy, never larger than the disk
+        let factor = if Self::pack_format(pack_stage).as_deref() == Some("vmdk") {
+            1
+        } else {
+            PACK_SPACE_FACTOR
+        };
+        let required = image_size.saturating_mul(factor);
+
+        // The output directory may not exist yet, its nearest existing ancestor is on the
+        // same filesystem
+        let artifact_path = Self::artifact_path(output_path, pack_stage);
+        let Some(dir) = artifact_path
+            .ancestors()
+            .skip(1)
+            .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir })
+            .find(|dir| dir.is_dir())
+        else {
+            return Ok(());
+        };
+        let Some(available) = available_bytes(dir)? else {
+            debug!("Free space check is not supported on this platform");
+            return Ok(());
+        };
+
+        debug!(
+            "Pack needs {} bytes in {}, {} available",
+            required,
+            dir.display(),
+            available
+        );
+        if available < required {
+            return Err(anyhow!(
+                "Not enough free space to pack {}: {} needs about {} ({} disk x {}) but only {} \
+                 is free. Free up space or set a different output path.",
+                vdi_path.display(),
+                dir.display(),
+                format_gib(required),
+                format_gib(image_size),
+                factor,
+                format_gib(available)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Virtual size of a VirtualBox disk, which is what the raw image grows to
+    fn disk_capacity(&self, vdi_path: &Path) -> Result<u64> {
+        let output = VBoxManageCommand::new()
+            .args(["showmediuminfo", "disk"])
+            .arg(vdi_path)
+            .output()
+            .context("Failed to execute VBoxManage showmediuminfo")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "VBoxManage showmediuminfo failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        parse_medium_capacity(&String::from_utf8_lossy(&output.stdout))
+            .ok_or_else(|| anyhow!("No capacity in medium info of {}", vdi_path.display()))
+    }
+
+    /// `vm_config` is the built VM's, which a VMware appliance's VMX mirrors
+    pub fn create_bootable_image(
+        &self,
+        vdi_path: &Path,
+        output_path: &Path,
+        pack_stage: &Stage,
+        vm_config: &VmConfig,
+    ) -> Result<PathBuf> {
+        match Self::pack_format(pack_stage).as_deref() {
+            Some("vmdk") => {
+                return self.create_vmware_appliance(vdi_path, output_path, vm_config)
+            }
+            Some(format @ ("vhd" | "vhdx")) => {
+                return self.create_virtual_hard_disk(vdi_path, output_path, pack_stage, format)
+            }
+            Some("docker") => return self.create_rootfs_tarball(vdi_path, output_path),
+            _ => {}
+        }
+
         info!(
             "Creating bootable IMG from VDI disk: {}",
             vdi_path.display()
         );
 
         // Always create raw IMG format - this is what we support
-        let img_path = output_path.with_extension("img");
+        let img_path = Self::artifact_path(output_path, pack_stage);
         
         // Create output directory if it doesn't exist
         if let Some(parent) = img_path.parent() {
@@ -39,7 +316,463 @@ impl IsoPackager {
         // Convert VDI to raw IMG using VBoxManage
         self.convert_to_raw_img(vdi_path, &img_path)?;
 
+        // Sparsify first so the resize check sees the space the image really uses
+        if Self::pack_shrink(pack_stage) {
+            self.shrink_raw_img(&img_path)?;
+        }
+        if let Some(size) = Self::pack_resize(pack_stage) {
+            self.resize_raw_img(&img_path, size)?;
+        }
+
         info!("Bootable IMG created successfully: {}", img_path.display());
+        Ok(img_path)
+    }
+
+    /// Build the raw image as usual next to the output, then convert it to a VHD (fixed, as
+    /// Azure requires) or dynamic VHDX with a virtual size rounded up to a whole MiB
+    fn create_virtual_hard_disk(
+        &self,
+        vdi_path: &Path,
+        output_path: &Path,
+        pack_stage: &Stage,
+        format: &str,
+    ) -> Result<PathBuf> {
+        let raw_stage = Stage {
+            instructions: pack_stage
+                .instructions
+                .iter()
+                .filter(|instruction| !matches!(instruction, Instruction::Format { .. }))
+                .cloned()
+                .collect(),
+            ..pack_stage.clone()
+        };
+        // Without FORMAT the VM config goes unused, only an appliance's VMX needs it
+        let img_path =
+            self.create_bootable_image(vdi_path, output_path, &raw_stage, &VmConfig::default())?;
+        let result = self.convert_raw_to_vhd(&img_path, output_path, format);
+        if let Err(e) = std::fs::remove_file(&img_path) {
+            warn!("Failed to remove intermediate image {}: {}", img_path.display(), e);
+        }
+        result
+    }
+
+    fn convert_raw_to_vhd(
+        &self,
+        img_path: &Path,
+        output_path: &Path,
+        format: &str,
+    ) -> Result<PathBuf> {
+        let size = std::fs::metadata(img_path)
+            .with_context(|| format!("Failed to read image metadata: {}", img_path.display()))?
+            .len();
+        let aligned = size.div_ceil(VHD_ALIGNMENT) * VHD_ALIGNMENT;
+        if let Some(max) = max_image_size(format).filter(|max| aligned > *max) {
+            return Err(anyhow!(
+                "The {} byte image exceeds the {} byte limit of {} disks",
+                aligned,
+                max,
+                format.to_uppercase()
+            ));
+        }
+        if aligned != size {
+            debug!("Padding {} from {} to {} bytes", img_path.display(), size, aligned);
+            self.resize_raw_img(img_path, &format!("{}M", aligned / VHD_ALIGNMENT))?;
+        }
+
+        let vhd_path = output_path.with_extension(format);
+        // force_size keeps the exact virtual size instead of rounding it to a CHS geometry
+        let (qemu_format, options) = match format {
+            "vhd" => ("vpc", "subformat=fixed,force_size=on"),
+            _ => ("vhdx", "subformat=dynamic"),
+        };
+        info!("Converting {} to {}", img_path.display(), vhd_path.display());
+        let output = Command::new("qemu-img")
+            .args(["convert", "-f", "raw", "-O", qemu_format, "-o", options])
+            .arg(img_path)
+            .arg(&vhd_path)
+            .output()
+            .context("Failed to execute qemu-img convert (is qemu-img installed?)")?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&vhd_path);
+            return Err(anyhow!(
+                "qemu-img convert to {} failed: {}",
+                format.to_uppercase(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        info!("{} created successfully: {}", format.to_uppercase(), vhd_path.display());
+        Ok(vhd_path)
+    }
+
+    /// Convert the VM disk to a VMDK and write a minimal VMX next to it, returning the VMDK path
+    pub fn create_vmware_appliance(
+        &self,
+        vdi_path: &Path,
+        output_path: &Path,
+        vm_config: &VmConfig,
+    ) -> Result<PathBuf> {
+        info!(
+            "Creating VMware appliance from VDI disk: {}",
+            vdi_path.display()
+        );
+
+        let vmdk_path = output_path.with_extension("vmdk");
+        let vmx_path = output_path.with_extension("vmx");
+
+        if let Some(parent) = vmdk_path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create output directory")?;
+        }
+
+        // clonemedium refuses to overwrite an existing target
+        if vmdk_path.exists() {
+            std::fs::remove_file(&vmdk_path).with_context(|| {
+                format!("Failed to remove existing VMDK: {}", vmdk_path.display())
+            })?;
+        }
+
+        let output = VBoxManageCommand::new()
+            .args(["clonemedium", "disk"])
+            .arg(vdi_path)
+            .arg(&vmdk_path)
+            .args(["--format", "VMDK"])
+            .output()
+            .context("Failed to execute VBoxManage clonemedium")?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "VBoxManage clonemedium to VMDK failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        close_medium(&vmdk_path);
+
+        let display_name = output_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "isotope".to_string());
+        let vmdk_file_name = vmdk_path.file_name().unwrap().to_string_lossy();
+
+        std::fs::write(&vmx_path, vmx_contents(&display_name, &vmdk_file_name, vm_config))
+            .with_context(|| format!("Failed to write VMX: {}", vmx_path.display()))?;
+
+        info!(
+            "VMware appliance created: {} ({})",
+            vmx_path.display(),
+            vmdk_path.display()
+        );
+        Ok(vmdk_path)
+    }
+
+    /// Rebuild a bootable ISO from an extracted (and possibly modified) ISO tree, naming the
+    /// spec's `name`/`version` labels as application and `maintainer` as publisher
+    pub fn repack_iso(
+        &self,
+        source_dir: &Path,
+        output_path: &Path,
+        volume_label: &str,
+        labels: &HashMap<String, String>,
+    ) -> Result<()> {
+        info!(
+            "Repacking {} into ISO {}",
+            source_dir.display(),
+            output_path.display()
+        );
+
+        let tool = find_iso_tool()?;
+        debug!("Using {} to build the ISO", tool);
+
+        let mut args: Vec<String> = Vec::new();
+        if tool == "xorriso" {
+            args.extend(["-as", "mkisofs"].map(String::from));
+        }
+        args.extend([
+            "-o".into(),
+            output_path.to_string_lossy().to_string(),
+            "-V".into(),
+            volume_label.to_string(),
+            "-J".into(),
+            "-R".into(),
+        ]);
+        if let Some(application) = application_id(labels) {
+            args.extend(["-A".into(), application]);
+        }
+        if let Some(maintainer) = labels.get("maintainer") {
+            args.extend(["-publisher".into(), truncate_iso_field(maintainer)]);
+        }
+
+        // Legacy BIOS boot via isolinux when the source ISO has it
+        if source_dir.join("isolinux/isolinux.bin").exists() {
+            debug!("Using isolinux El Torito boot image");
+            args.extend(
+                [
+                    "-b",
+                    "isolinux/isolinux.bin",
+                    "-c",
+                    "isolinux/boot.cat",
+                    "-no-emul-boot",
+                    "-boot-load-size",
+                    "4",
+                    "-boot-info-table",
+                ]
+                .map(String::from),
+            );
+        }
+
+        // UEFI boot image, if one of the common locations exists
+        let efi_image = ["boot/grub/efi.img", "images/efiboot.img", "EFI/BOOT/efiboot.img"]
+            .into_iter()
+            .find(|path| source_dir.join(path).exists());
+        if let Some(efi_image) = efi_image {
+            debug!("Using EFI boot image {}", efi_image);
+            args.extend(
+                ["-eltorito-alt-boot", "-e", efi_image, "-no-emul-boot"].map(String::from),
+            );
+        } else {
+            warn!("No EFI boot image found, repacked ISO will only support BIOS boot");
+        }
+
+        args.push(source_dir.to_string_lossy().to_string());
+
+        let output = Command::new(tool)
+            .args(&args)
+            .output()
+            .with_context(|| format!("Failed to execute {}", tool))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed to repack ISO: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        info!("Repacked ISO created: {}", output_path.display());
+        Ok(())
+    }
+
+    /// Grow or shrink a raw image with qemu-img, refusing sizes below the space in use
+    fn resize_raw_img(&self, img_path: &Path, size: &str) -> Result<()> {
+        let requested = parse_image_size(size)?;
+        let current = std::fs::metadata(img_path)
+            .with_context(|| format!("Failed to read image metadata: {}", img_path.display()))?
+            .len();
+        let used = allocated_bytes(img_path)?;
+
+        if requested < used {
+            return Err(anyhow!(
+                "RESIZE_DISK {} ({} bytes) is smaller than the {} bytes the image already uses",
+                size,
+                requested,
+                used
+            ));
+        }
+
+        let mut args = vec!["resize".to_string(), "-f".into(), "raw".into()];
+        if requested < current {
+            self.check_shrink(img_path, size, requested)?;
+            warn!(
+                "Shrinking {} from {} to {} bytes",
+                img_path.display(),
+                current,
+                requested
+            );
+            args.push("--shrink".into());
+        }
+        args.push(img_path.to_string_lossy().to_string());
+        args.push(requested.to_string());
+
+        info!("Resizing {} to {}", img_path.display(), size);
+        let output = Command::new("qemu-img")
+            .args(&args)
+            .output()
+            .context("Failed to execute qemu-img resize (is qemu-img installed?)")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "qemu-img resize failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuse a shrink that would cut into a partition. GPT disks keep a backup table in
+    /// their last sectors, which any shrink would cut off, and an image without a partition
+    /// table gives no way to tell where its data ends.
+    fn check_shrink(&self, img_path: &Path, size: &str, requested: u64) -> Result<()> {
+        let mut sector = [0u8; 512];
+        std::fs::File::open(img_path)
+            .and_then(|mut file| file.read_exact(&mut sector))
+            .with_context(|| format!("Failed to read partition table: {}", img_path.display()))?;
+
+        match partition_table(&sector) {
+            PartitionTable::Mbr { end } if requested >= end => Ok(()),
+            PartitionTable::Mbr { end } => Err(anyhow!(
+                "RESIZE_DISK {} ({} bytes) would cut into partitions ending at byte {}",
+                size,
+                requested,
+                end
+            )),
+            PartitionTable::Gpt => Err(anyhow!(
+                "RESIZE_DISK {} can't shrink a GPT disk, which keeps its backup partition table \
+                 in the last sectors",
+                size
+            )),
+            PartitionTable::None => Err(anyhow!(
+                "RESIZE_DISK {} can't shrink an image without an MBR partition table",
+                size
+            )),
+        }
+    }
+
+    /// Zero free space and punch holes with virt-sparsify, falling back to a qemu-img copy
+    /// that only skips blocks that are already zero
+    fn shrink_raw_img(&self, img_path: &Path) -> Result<()> {
+        let before = allocated_bytes(img_path)?;
+        info!("Shrinking {} ({} bytes used)", img_path.display(), before);
+
+        let sparsified = Command::new("virt-sparsify")
+            .args(["--in-place", &img_path.to_string_lossy()])
+            .output();
+        match sparsified {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => {
+                warn!(
+                    "virt-sparsify failed, falling back to qemu-img: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                self.sparse_copy_raw_img(img_path)?;
+            }
+            Err(_) => {
+                debug!("virt-sparsify not available, using qemu-img convert");
+                self.sparse_copy_raw_img(img_path)?;
+            }
+        }
+
+        info!(
+            "Shrunk {} from {} to {} bytes used",
+            img_path.display(),
+            before,
+            allocated_bytes(img_path)?
+        );
+        Ok(())
+    }
+
+    fn sparse_copy_raw_img(&self, img_path: &Path) -> Result<()> {
+        let sparse_path = img_path.with_extension("sparse.img");
+        let output = Command::new("qemu-img")
+            .args(["convert", "-f", "raw", "-O", "raw", "-S", "4k"])
+            .arg(img_path)
+            .arg(&sparse_path)
+            .output()
+            .context("Failed to execute qemu-img convert (install qemu-img or virt-sparsify)")?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&sparse_path);
+            return Err(anyhow!(
+                "qemu-img convert failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        std::fs::rename(&sparse_path, img_path).with_context(|| {
+            format!("Failed to replace {} with its sparse copy", img_path.display())
+        })
+    }
+
+    /// Tar the guest's root filesystem out of a raw copy of the disk with guestfish, for
+    /// `docker import`; owners stay numeric since the host's users mean nothing in the image
+    fn create_rootfs_tarball(&self, vdi_path: &Path, output_path: &Path) -> Result<PathBuf> {
+        let tar_path = output_path.with_extension("tar");
+        info!("Creating rootfs tarball from VDI disk: {}", vdi_path.display());
+
+        if let Some(parent) = tar_path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create output directory")?;
+        }
+        std::fs::create_dir_all(&self.temp_dir).with_context(|| {
+            format!("Failed to create pack directory: {}", self.temp_dir.display())
+        })?;
+        let img_path = self.temp_dir.join("rootfs.img");
+        self.convert_to_raw_img(vdi_path, &img_path)?;
+
+        let output = Command::new("guestfish")
+            .args(["--ro", "-a"])
+            .arg(&img_path)
+            .args(["-i", "tar-out", "/"])
+            .arg(&tar_path)
+            .arg("numericowner:true")
+            .arg(format!("excludes:{}", ROOTFS_EXCLUDES.join(" ")))
+            .output();
+        let _ = std::fs::remove_file(&img_path);
+
+        let output = output.context("Failed to execute guestfish (install libguestfs-tools)")?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(&tar_path);
+            return Err(anyhow!(
+                "guestfish tar-out failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("Rootfs tarball created successfully: {}", tar_path.display());
+        Ok(tar_path)
+    }
+
+    /// `docker import` a rootfs tarball as `<name>:<version>` from the spec labels when the
+    /// Docker CLI is available; the tarball is the artifact, so failures only warn
+    pub fn import_docker_image(&self, tar_path: &Path, labels: &HashMap<String, String>) {
+        let Some(tag) = docker_tag(labels) else {
+            info!("No name label, skipping docker import of {}", tar_path.display());
+            return;
+        };
+
+        let mut command = Command::new("docker");
+        command.arg("import");
+        for (key, value) in sorted_labels(labels) {
+            command.arg("--change").arg(format!(
+                "LABEL {}={}",
+                serde_json::Value::from(key),
+                serde_json::Value::from(value)
+            ));
+        }
+        match command.arg(tar_path).arg(&tag).output() {
+            Ok(output) if output.status.success() => info!("Imported Docker image {}", tag),
+            Ok(output) => warn!(
+                "docker import of {} failed: {}",
+                tar_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(_) => info!("Docker CLI not found, skipping import of {}", tar_path.display()),
+        }
+    }
+
+    /// Record the spec's labels with a packed artifact: sorted `key=value` lines in
+    /// `<artifact>.labels`, the same format WRITE_LABELS puts in the guest, and for a VMware
+    /// appliance also the VMX annotation shown in the VM's summary
+    pub fn stamp_labels(&self, artifact: &Path, labels: &HashMap<String, String>) -> Result<()> {
+        if labels.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = sorted_labels(labels)
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+
+        let mut labels_path = artifact.as_os_str().to_owned();
+        labels_path.push(".labels");
+        let labels_path = PathBuf::from(labels_path);
+        std::fs::write(&labels_path, format!("{}\n", lines.join("\n")))
+            .with_context(|| format!("Failed to write labels: {}", labels_path.display()))?;
+
+        let vmx_path = artifact.with_extension("vmx");
+        if artifact.extension().is_some_and(|extension| extension == "vmdk") && vmx_path.exists() {
+            let mut vmx = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&vmx_path)
+                .with_context(|| format!("Failed to open VMX: {}", vmx_path.display()))?;
+            writeln!(vmx, "annotation = \"{}\"", vmx_annotation(&lines))
+                .with_context(|| format!("Failed to write VMX: {}", vmx_path.display()))?;
+        }
+
+        info!("Stamped {} labels onto {}", lines.len(), artifact.display());
         Ok(())
     }
 
@@ -59,15 +792,11 @@ impl IsoPackager {
         // Use VirtualBox VBoxManage to convert VDI to raw
         info!("Converting VDI to raw using VBoxManage");
 
-        let output = Command::new("VBoxManage")
-            .args([
-                "clonemedium",
-                "disk",
-                source_path.to_str().unwrap(),
-                output_path.to_str().unwrap(),
-                "--format",
-                "RAW",
-            ])
+        let output = VBoxManageCommand::new()
+            .args(["clonemedium", "disk"])
+            .arg(source_path)
+            .arg(output_path)
+            .args(["--format", "RAW"])
             .output()
             .context("Failed to execute VBoxManage clonemedium")?;
 
@@ -77,8 +806,192 @@ impl IsoPackager {
                 String::from_utf8_lossy(&output.stderr)
             ));
         }
+        close_medium(output_path);
 
         info!("Successfully converted VDI to raw IMG using VBoxManage");
         Ok(())
     }
 }
+
+/// Drop a medium clonemedium registered from VirtualBox's media registry, keeping the file;
+/// a stale entry would make the next clone to the same path fail
+fn close_medium(path: &Path) {
+    match VBoxManageCommand::new()
+        .args(["closemedium", "disk"])
+        .arg(path)
+        .output()
+    {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!(
+            "Failed to unregister {} from VirtualBox: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => warn!("Failed to run VBoxManage closemedium: {}", e),
+    }
+}
+
+/// Volume descriptor application ID from the `name` and `version` labels
+fn application_id(labels: &HashMap<String, String>) -> Option<String> {
+    let name = labels.get("name")?;
+    let id = match labels.get("version") {
+        Some(version) => format!("{} {}", name, version),
+        None => name.clone(),
+    };
+    Some(truncate_iso_field(&id))
+}
+
+/// Docker image reference for the `name` and `version` labels, lowercased with characters
+/// Docker doesn't allow replaced by `-`
+fn docker_tag(labels: &HashMap<String, String>) -> Option<String> {
+    let sanitize = |value: &str, allowed: &[char]| -> String {
+        value
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || allowed.contains(&c) { c } else { '-' })
+            .collect()
+    };
+    let name = sanitize(labels.get("name")?.trim(), &['.', '_', '/']);
+    let version = labels.get("version").map_or_else(
+        || "latest".to_string(),
+        |version| sanitize(version.trim(), &['.', '_']),
+    );
+    Some(format!("{}:{}", name, version))
+}
+
+fn sorted_labels(labels: &HashMap<String, String>) -> impl Iterator<Item = (&str, &str)> {
+    let mut labels: Vec<_> = labels.iter().collect();
+    labels.sort();
+    labels
+        .into_iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+}
+
+/// VMX annotation text: one label per line, with VMware's `|XX` hex escapes for the line
+/// breaks and for characters that would end or garble the quoted value
+fn vmx_annotation(lines: &[String]) -> String {
+    lines
+        .join("\n")
+        .chars()
+        .map(|c| match c {
+            '\n' | '"' | '|' | '#' => format!("|{:02X}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Application and publisher IDs are 128-byte fields in the primary volume descriptor
+fn truncate_iso_field(value: &str) -> String {
+    let mut end = value.len().min(ISO_ID_FIELD_LEN);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+/// Minimal VMX for VMware Workstation/Player/ESXi, the disk is expected next to it
+fn vmx_contents(display_name: &str, vmdk_file_name: &str, vm_config: &VmConfig) -> String {
+    let memsize = vm_config.memory_mb;
+    let numvcpus = vm_config.cpus;
+    let firmware = vm_config.firmware().name();
+    format!(
+        r#".encoding = "UTF-8"
+config.version = "8"
+virtualHW.version = "14"
+displayName = "{display_name}"
+guestOS = "other-64"
+memsize = "{memsize}"
+numvcpus = "{numvcpus}"
+firmware = "{firmware}"
+sata0.present = "TRUE"
+sata0:0.present = "TRUE"
+sata0:0.fileName = "{vmdk_file_name}"
+ethernet0.present = "TRUE"
+ethernet0.connectionType = "nat"
+ethernet0.virtualDev = "e1000"
+ethernet0.addressType = "generated"
+"#
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_medium_capacity() {
+        let info = "UUID:           0b1c\nState:          created\nCapacity:       20480 MBytes\n";
+        assert_eq!(parse_medium_capacity(info), Some(20 << 30));
+        assert_eq!(parse_medium_capacity("Capacity: 512 Bytes"), Some(512));
+        assert_eq!(parse_medium_capacity("Capacity: 2 TBytes"), Some(2 << 40));
+        assert_eq!(parse_medium_capacity("Capacity: 20 PBytes"), None);
+        assert_eq!(parse_medium_capacity("Capacity: lots MBytes"), None);
+        assert_eq!(parse_medium_capacity("Size on disk: 4 MBytes"), None);
+        assert_eq!(parse_medium_capacity("Capacity: 99999999999 TBytes"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_available_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(available_bytes(dir.path()).unwrap().is_some_and(|bytes| bytes > 0));
+        assert!(available_bytes(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_parse_image_size() {
+        assert_eq!(parse_image_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_image_size("8g").unwrap(), 8 << 30);
+        assert_eq!(parse_image_size(" 1TB ").unwrap(), 1 << 40);
+        assert!(parse_image_size("0G").is_err());
+        assert!(parse_image_size("8").is_err());
+        assert!(parse_image_size("big").is_err());
+        assert!(parse_image_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_partition_table() {
+        let mut sector = [0u8; 512];
+        assert_eq!(partition_table(&sector), PartitionTable::None);
+
+        sector[510..512].copy_from_slice(&[0x55, 0xAA]);
+        assert_eq!(partition_table(&sector), PartitionTable::Mbr { end: 0 });
+
+        // Linux partition at sector 2048 with 4 GiB, then swap at 8390656 with 512 MiB
+        let entry = |sector: &mut [u8], index: usize, kind: u8, start: u32, sectors: u32| {
+            let entry = &mut sector[446 + index * 16..446 + (index + 1) * 16];
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        };
+        entry(&mut sector, 0, 0x83, 2048, 8_388_608);
+        entry(&mut sector, 1, 0x82, 8_390_656, 1_048_576);
+        assert_eq!(
+            partition_table(&sector),
+            PartitionTable::Mbr {
+                end: (8_390_656 + 1_048_576) * 512
+            }
+        );
+
+        entry(&mut sector, 0, 0xEE, 1, u32::MAX);
+        assert_eq!(partition_table(&sector), PartitionTable::Gpt);
+    }
+
+    #[test]
+    fn test_label_stamps() {
+        let mut labels = HashMap::new();
+        assert_eq!(application_id(&labels), None);
+        labels.insert("name".to_string(), "ubuntu-server".to_string());
+        assert_eq!(application_id(&labels).as_deref(), Some("ubuntu-server"));
+        labels.insert("version".to_string(), "1.0.0".to_string());
+        assert_eq!(application_id(&labels).as_deref(), Some("ubuntu-server 1.0.0"));
+        // Cut to the 128-byte field without splitting a character
+        labels.insert("name".to_string(), "é".repeat(100));
+        let id = application_id(&labels).unwrap();
+        assert_eq!(id.len(), 128);
+        assert!(id.chars().all(|c| c == 'é'));
+
+        let lines = ["name=\"web\" | db".to_string(), "version=1.0".to_string()];
+        assert_eq!(vmx_annotation(&lines), "name=|22web|22 |7C db|0Aversion=1.0");
+    }
+}
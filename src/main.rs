@@ -1,7 +1,11 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 use tracing::{error, info};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry};
 
 mod automation;
 mod cli;
@@ -10,9 +14,12 @@ mod core;
 mod iso;
 mod utils;
 
+use automation::ocr::OcrOptions;
 use cli::Commands;
 use config::IsotopeSpec;
 use core::Builder;
+use utils::redact::{self, RedactingMakeWriter};
+use utils::secrets::SecretStore;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -27,58 +34,251 @@ struct Cli {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
-    /// Enable OCR debug messages during screen text detection
-    #[arg(long)]
+    /// Enable OCR debug messages and save annotated screenshots showing detected text boxes
+    #[arg(long, alias = "verbose-ocr")]
     ocr_debug: bool,
+
+    /// Use beam search OCR decoding: recognizes small installer fonts more reliably,
+    /// but each OCR pass is several times slower than the default greedy decoding
+    #[arg(long)]
+    ocr_beam_search: bool,
+
+    /// Minimum milliseconds between OCR screen refreshes; lower reacts faster to
+    /// screen changes at the cost of more CPU
+    #[arg(long, value_name = "MS", default_value_t = 100)]
+    ocr_threshold: u64,
+
+    /// Never load the OCR models, for specs that only use serial, SSH and non-screen WAITs;
+    /// a WAIT FOR on screen text is then an error
+    #[arg(long)]
+    no_ocr: bool,
+
+    /// Also write debug-level logs to this file (truncated each run), whatever the console
+    /// level; builds with --output-dir do this to isotope.log by default
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
+    /// Log one JSON object per line, with stage, step and vm_name as span fields, on the
+    /// console and in the log file
+    #[arg(long)]
+    json_logs: bool,
+
+    /// Throttle OCR model downloads to this rate in bytes per second, e.g. 500K or 2M
+    #[arg(long, value_name = "RATE")]
+    download_limit: Option<String>,
+
+    /// Text that is never masked in logs even when typed at a password-like prompt (repeatable)
+    #[arg(long, value_name = "TEXT")]
+    redact_allow: Vec<String>,
+}
+
+/// Formatting layer for one log destination, human-readable or JSON lines
+fn log_layer<W>(
+    json: bool,
+    ansi: bool,
+    writer: W,
+    filter: EnvFilter,
+) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_ansi(ansi).with_writer(writer);
+    if json {
+        layer.json().with_span_list(false).with_filter(filter).boxed()
+    } else {
+        layer.with_filter(filter).boxed()
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    // Initialize logging
-    let log_level = if cli.verbose { "debug" } else { "info" };
-    tracing_subscriber::fmt()
-        .with_env_filter(format!("isotope={},warn", log_level))
-        .init();
+    for text in &cli.redact_allow {
+        redact::allow(text);
+    }
+
+    // Initialize logging; the log file always gets debug detail, the console only with --verbose
+    // Commands printing JSON to stdout keep the console quiet so the output stays parseable
+    let json_stdout = matches!(
+        cli.command,
+        Commands::Instructions { json: true } | Commands::Schema { output: None }
+    );
+    let log_level = match (cli.verbose, json_stdout) {
+        (true, _) => "debug",
+        (false, true) => "warn",
+        (false, false) => "info",
+    };
+    let log_path = match (&cli.log_file, &cli.command) {
+        (Some(log_path), _) => Some(log_path.clone()),
+        (
+            None,
+            Commands::Build {
+                output_dir: Some(output_dir),
+                ..
+            },
+        ) => Some(output_dir.join("isotope.log")),
+        _ => None,
+    };
+    let log_file = match &log_path {
+        Some(log_path) => {
+            if let Some(parent) = log_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent).with_context(|| {
+                    format!("Failed to create log directory: {}", parent.display())
+                })?;
+            }
+            Some(
+                std::fs::File::create(log_path)
+                    .with_context(|| format!("Failed to create log file: {}", log_path.display()))?,
+            )
+        }
+        None => None,
+    };
+    let mut layers = vec![log_layer(
+        cli.json_logs,
+        true,
+        RedactingMakeWriter::new(std::io::stdout),
+        EnvFilter::new(format!("isotope={},warn", log_level)),
+    )];
+    if let Some(file) = log_file {
+        layers.push(log_layer(
+            cli.json_logs,
+            false,
+            RedactingMakeWriter::new(Mutex::new(file)),
+            EnvFilter::new("isotope=debug,warn"),
+        ));
+    }
+    tracing_subscriber::registry().with(layers).init();
 
     info!("Isotope v{} starting", env!("CARGO_PKG_VERSION"));
 
+    if let Some(rate) = &cli.download_limit {
+        automation::models::set_download_limit(automation::models::parse_rate(rate)?);
+    }
+
+    let ocr_options = (!cli.no_ocr).then_some(OcrOptions {
+        beam_search: cli.ocr_beam_search,
+        update_threshold: Duration::from_millis(cli.ocr_threshold),
+    });
+
     let result = match cli.command {
         Commands::Build {
             spec_file,
             output,
+            output_dir,
             continue_from,
+            continue_failed,
+            step_snapshots,
+            force_recreate,
+            retries,
+            skip_if_unchanged,
+            vm_name,
+            slow,
+            keep_going,
+            secrets_file,
+            resolution,
+            memory,
+            cpus,
+            iso_cache_dir,
+            interactive,
+            no_cleanup,
+            profile,
+            parallel_ocr_monitor,
+            record,
+            record_video,
+            dump_scancodes_on_type,
+            verify_boot,
+            verify_boot_timeout,
         } => {
             info!("Building ISO from specification: {}", spec_file.display());
 
-            if let Some(step) = continue_from {
-                info!("Continuing from step {}", step);
-            }
-
             let spec = IsotopeSpec::from_file(&spec_file)
                 .with_context(|| format!("Failed to load spec file: {}", spec_file.display()))?;
 
-            let mut builder = Builder::new_with_ocr_debug(spec, cli.ocr_debug);
+            let mut builder = Builder::new_with_ocr_debug(spec, cli.ocr_debug, ocr_options)?;
             builder.set_spec_file_path(spec_file.clone());
 
             if let Some(output_path) = output {
                 builder.set_output_path(output_path);
             }
 
-            if let Some(step) = continue_from {
-                builder.set_continue_from_step(step);
+            if let Some(output_dir) = output_dir {
+                builder.set_output_dir(output_dir);
+            }
+
+            if let Some(target) = continue_from {
+                let step = builder.set_continue_from(&target)?;
+                info!("Continuing from step {}", step);
+            }
+
+            if continue_failed {
+                let step = builder.set_continue_from_failed_step()?;
+                info!("Continuing from failed step {}", step);
+            }
+
+            builder.set_force_recreate(force_recreate);
+            builder.set_retries(retries);
+            builder.set_skip_if_unchanged(skip_if_unchanged);
+
+            if let Some(vm_name) = vm_name {
+                builder.set_vm_name(vm_name)?;
+            }
+
+            builder.set_keep_going(keep_going);
+            builder.set_step_snapshots(step_snapshots);
+            builder.set_interactive(interactive);
+            builder.set_keep_working_dir(no_cleanup);
+            builder.set_profile(profile);
+            builder.set_ocr_monitor(parallel_ocr_monitor);
+            if let Some(interval) = record {
+                builder.set_recording(interval, record_video);
+            }
+            builder.set_dump_scancodes(dump_scancodes_on_type);
+
+            if let Some(check) = verify_boot {
+                builder.set_verify_boot(check, Duration::from_secs(verify_boot_timeout));
+            }
+
+            if let Some(iso_cache_dir) = iso_cache_dir {
+                builder.set_iso_cache_dir(iso_cache_dir);
+            }
+
+            if let Some(resolution) = resolution {
+                builder.set_resolution(resolution);
+            }
+
+            if let Some(memory) = memory {
+                builder.set_memory_mb(memory);
+            }
+
+            if let Some(cpus) = cpus {
+                builder.set_cpus(cpus);
+            }
+
+            if let Some(secrets_file) = secrets_file {
+                builder.set_secrets(SecretStore::from_file(&secrets_file)?);
+            }
+
+            if let Some(delay) = slow {
+                builder.set_slow_mode(delay);
             }
 
             builder.build().await
         }
-        Commands::Validate { spec_file } => {
+        Commands::Validate {
+            spec_file,
+            check_tools,
+        } => {
             info!("Validating specification: {}", spec_file.display());
 
             match IsotopeSpec::from_file(&spec_file) {
                 Ok(spec) => {
                     info!("✓ Specification is valid");
-                    spec.validate()
+                    spec.validate()?;
+                    if check_tools {
+                        core::preflight::check_tools(&spec)?;
+                    }
+                    Ok(())
                 }
                 Err(e) => {
                     error!("✗ Specification is invalid: {}", e);
@@ -86,14 +286,76 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        Commands::Test { spec_file } => {
+        Commands::Test { spec_file, full } => {
             info!("Testing specification: {}", spec_file.display());
 
             let spec = IsotopeSpec::from_file(&spec_file)
                 .with_context(|| format!("Failed to load spec file: {}", spec_file.display()))?;
 
-            let builder = Builder::new_with_ocr_debug(spec, cli.ocr_debug);
-            builder.test().await
+            let mut builder = Builder::new_with_ocr_debug(spec, cli.ocr_debug, ocr_options)?;
+            builder.set_spec_file_path(spec_file.clone());
+
+            if full {
+                builder.test_full().await
+            } else {
+                builder.test().await
+            }
+        }
+        Commands::Provision {
+            spec_file,
+            host,
+            key,
+            password_file,
+            accept_new_host_key,
+        } => {
+            let spec = IsotopeSpec::from_file(&spec_file)
+                .with_context(|| format!("Failed to load spec file: {}", spec_file.display()))?;
+            let password = core::provision::read_password(password_file.as_deref(), key.is_none())?;
+            if key.is_none() && password.is_none() {
+                return Err(anyhow::anyhow!(
+                    "provision needs --key, --password-file or {}",
+                    core::provision::PASSWORD_ENV
+                ));
+            }
+
+            core::provision::provision(&spec, &host, key, password, accept_new_host_key).await
+        }
+        Commands::Gc {
+            older_than,
+            dry_run,
+        } => core::gc::collect_garbage(older_than, dry_run),
+        Commands::Providers => core::providers::list_providers(),
+        Commands::Inspect { spec_file } => {
+            let spec = IsotopeSpec::from_file(&spec_file)
+                .with_context(|| format!("Failed to load spec file: {}", spec_file.display()))?;
+
+            core::inspect::inspect_spec(&spec, &spec_file)
+        }
+        Commands::OcrCheck { images, spec_file } => {
+            let spec = IsotopeSpec::from_file(&spec_file)
+                .with_context(|| format!("Failed to load spec file: {}", spec_file.display()))?;
+
+            let ocr_options = ocr_options
+                .ok_or_else(|| anyhow!("ocr-check runs OCR and can't be combined with --no-ocr"))?;
+            core::ocr_check::check_images(&spec, &images, ocr_options).await
+        }
+        Commands::Keys {
+            text,
+            press,
+            unicode,
+        } => core::keys::print_scancodes(text.as_deref(), press.as_deref(), unicode),
+        Commands::Instructions { json } => core::inspect::print_keywords(json),
+        Commands::Schema { output } => {
+            let schema = serde_json::to_string_pretty(&schemars::schema_for!(IsotopeSpec))
+                .context("Failed to serialize the spec schema")?;
+            match output {
+                Some(path) => std::fs::write(&path, schema)
+                    .with_context(|| format!("Failed to write schema: {}", path.display())),
+                None => {
+                    println!("{}", schema);
+                    Ok(())
+                }
+            }
         }
         Commands::Convert { input, output } => {
             info!("Converting {} to Isotope format", input.display());
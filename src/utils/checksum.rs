@@ -93,7 +93,8 @@ impl ChecksumVerifier {
         }
     }
 
-    pub fn generate_checksum_file(&self, file_path: &Path, algorithm: &str) -> Result<()> {
+    /// Write a `<file>.<algorithm>` sidecar next to the file and return the checksum
+    pub fn generate_checksum_file(&self, file_path: &Path, algorithm: &str) -> Result<String> {
         let checksum = self.calculate_checksum(file_path, algorithm)?;
         let checksum_filename = format!(
             "{}.{}",
@@ -112,7 +113,7 @@ impl ChecksumVerifier {
         })?;
 
         info!("Generated checksum file: {}", checksum_path.display());
-        Ok(())
+        Ok(checksum)
     }
 
     pub fn verify_checksum_file(&self, checksum_file: &Path) -> Result<()> {
@@ -4,6 +4,8 @@ use anyhow::{Context, Result};
 use std::path::{Path, PathBuf};
 use tracing::{debug, info, warn};
 
+use crate::config::StageType;
+
 pub struct FileSystemManager {
     working_dir: PathBuf,
 }
@@ -71,6 +73,22 @@ impl FileSystemManager {
         &self.working_dir
     }
 
+    /// Name of a stage's subdirectory, numbered in build order: `01-init` ... `04-pack`
+    pub fn stage_dir_name(stage: &StageType) -> String {
+        let position = match stage {
+            StageType::Init => 1,
+            StageType::OsInstall => 2,
+            StageType::OsConfigure => 3,
+            StageType::Pack => 4,
+        };
+        format!("{:02}-{}", position, stage.name())
+    }
+
+    /// Create (if needed) the subdirectory holding a stage's intermediate files
+    pub fn stage_dir(&self, stage: &StageType) -> Result<PathBuf> {
+        self.create_subdirectory(&Self::stage_dir_name(stage))
+    }
+
     pub fn create_subdirectory(&self, name: &str) -> Result<PathBuf> {
         let subdir = self.working_dir.join(name);
         std::fs::create_dir_all(&subdir)
@@ -126,7 +144,11 @@ impl FileSystemManager {
             let source_path = entry.path();
             let dest_path = to.join(entry.file_name());
 
-            if entry_type.is_dir() {
+            // file_type() doesn't follow links, so a link such as Debian's `debian -> .` is
+            // recreated instead of recursed into
+            if entry_type.is_symlink() {
+                self.copy_symlink(&source_path, &dest_path)?;
+            } else if entry_type.is_dir() {
                 self.copy_dir_recursive(&source_path, &dest_path)?;
             } else {
                 self.copy_file(&source_path, &dest_path)?;
@@ -136,6 +158,26 @@ impl FileSystemManager {
         Ok(())
     }
 
+    #[cfg(unix)]
+    fn copy_symlink(&self, from: &Path, to: &Path) -> Result<()> {
+        let target = std::fs::read_link(from)
+            .with_context(|| format!("Failed to read symlink: {}", from.display()))?;
+        std::os::unix::fs::symlink(&target, to).with_context(|| {
+            format!("Failed to create symlink {} -> {}", to.display(), target.display())
+        })
+    }
+
+    /// Windows needs privileges for symlinks, so only links to files are copied, as files
+    #[cfg(windows)]
+    fn copy_symlink(&self, from: &Path, to: &Path) -> Result<()> {
+        if from.is_file() {
+            self.copy_file(from, to)
+        } else {
+            warn!("Skipping directory symlink {}", from.display());
+            Ok(())
+        }
+    }
+
     pub fn write_file(&self, path: &Path, content: &[u8]) -> Result<()> {
         debug!("Writing file: {}", path.display());
 
@@ -229,3 +271,29 @@ impl FileSystemManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_directory_keeps_symlinks() {
+        let source = tempfile::tempdir().unwrap();
+        std::fs::create_dir(source.path().join("dists")).unwrap();
+        std::fs::write(source.path().join("dists/Release"), "Suite: stable").unwrap();
+        // Debian ISOs link `debian` to the root, which a link-following copy never finishes
+        std::os::unix::fs::symlink(".", source.path().join("debian")).unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        let copy = dest.path().join("copy");
+        let manager = FileSystemManager::new(dest.path().to_path_buf());
+        manager.copy_directory(source.path(), &copy).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(copy.join("dists/Release")).unwrap(),
+            "Suite: stable"
+        );
+        assert_eq!(std::fs::read_link(copy.join("debian")).unwrap(), Path::new("."));
+    }
+}
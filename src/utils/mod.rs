@@ -1,6 +1,8 @@
 pub mod checksum;
 pub mod fs;
 pub mod net;
+pub mod redact;
+pub mod secrets;
 pub mod template;
 pub mod vm_metadata;
 
@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::sync::{OnceLock, RwLock};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::config::{Instruction, Stage};
+
+/// Replacement shown in place of any registered sensitive value
+pub const MASK: &str = "****";
+
+fn registry() -> &'static RwLock<BTreeSet<String>> {
+    static SENSITIVE: OnceLock<RwLock<BTreeSet<String>>> = OnceLock::new();
+    SENSITIVE.get_or_init(|| RwLock::new(BTreeSet::new()))
+}
+
+fn allowlist() -> &'static RwLock<BTreeSet<String>> {
+    static ALLOWED: OnceLock<RwLock<BTreeSet<String>>> = OnceLock::new();
+    ALLOWED.get_or_init(|| RwLock::new(BTreeSet::new()))
+}
+
+/// Mask `value` wherever it appears in log output from now on
+pub fn register(value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    if allowlist().read().is_ok_and(|allowed| allowed.contains(value)) {
+        return;
+    }
+    if let Ok(mut sensitive) = registry().write() {
+        sensitive.insert(value.to_string());
+    }
+}
+
+/// Never mask `value`, e.g. a "yes" typed at a prompt that only looks like a password prompt
+pub fn allow(value: &str) {
+    if let Ok(mut allowed) = allowlist().write() {
+        allowed.insert(value.to_string());
+    }
+    if let Ok(mut sensitive) = registry().write() {
+        sensitive.remove(value);
+    }
+}
+
+/// Whether a WAIT FOR condition looks like the screen is asking for a password
+pub fn is_password_prompt(condition: &str) -> bool {
+    let condition = condition.to_lowercase();
+    ["password", "passphrase", "passwd", "pin:", "secret"]
+        .iter()
+        .any(|keyword| condition.contains(keyword))
+}
+
+/// Register a stage's literal credentials before its instructions are logged
+pub fn register_stage(stage: &Stage) {
+    let mut after_password_prompt = false;
+
+    for instruction in &stage.instructions {
+        match instruction {
+            Instruction::Login {
+                password: Some(password),
+                ..
+            } if !is_templated(password) => register(password),
+            Instruction::Expect { prompt, send, .. }
+                if is_password_prompt(prompt) && !is_templated(send) =>
+            {
+                register(send)
+            }
+            Instruction::Wait { condition, .. } => {
+                after_password_prompt = condition.as_deref().is_some_and(is_password_prompt);
+            }
+            Instruction::Type { text, .. } => {
+                if after_password_prompt && !is_templated(text) {
+                    register(text);
+                }
+                after_password_prompt = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Templated values are registered once rendered, at the point they are used
+fn is_templated(text: &str) -> bool {
+    text.contains("{{") || text.contains("${")
+}
+
+/// Replace every registered sensitive value in `text` with the mask
+pub fn redact(text: &str) -> String {
+    let Ok(sensitive) = registry().read() else {
+        return text.to_string();
+    };
+
+    // Longest first so a secret containing another secret is masked whole
+    let mut values: Vec<&String> = sensitive.iter().collect();
+    values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+
+    let mut result = text.to_string();
+    for value in values {
+        if result.contains(value.as_str()) {
+            result = result.replace(value.as_str(), MASK);
+        }
+        // JSON log lines carry the escaped form, e.g. `pa\"ss` for `pa"ss`
+        let escaped = json_escaped(value);
+        if escaped != *value && result.contains(&escaped) {
+            result = result.replace(&escaped, MASK);
+        }
+    }
+    result
+}
+
+/// `value` as it appears inside a JSON string literal
+fn json_escaped(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// Wraps a tracing writer so registered secrets never reach the terminal or log file
+pub struct RedactingMakeWriter<M> {
+    inner: M,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.inner.make_writer(),
+        }
+    }
+}
+
+pub struct RedactingWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The fmt layer writes each formatted event in one call, so secrets are never split
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.inner.write_all(redact(text).as_bytes())?,
+            Err(_) => self.inner.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacting_writer_masks_secrets() {
+        register("hunter2-redact");
+        let mut writer = RedactingWriter { inner: Vec::new() };
+        writer.write_all(b"INFO Typing 'hunter2-redact' at the prompt\n").unwrap();
+        assert_eq!(
+            String::from_utf8(writer.inner).unwrap(),
+            "INFO Typing '****' at the prompt\n"
+        );
+
+        // The JSON log format escapes quotes, backslashes and control characters
+        register("pa\"ss\\word-redact");
+        let line = serde_json::json!({ "message": "password pa\"ss\\word-redact" }).to_string();
+        assert_eq!(redact(&line), r#"{"message":"password ****"}"#);
+
+        allow("yes-redact");
+        register("yes-redact");
+        assert_eq!(redact("yes-redact"), "yes-redact");
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, info};
+
+use crate::utils::redact;
+
+/// Service name secrets are stored under in the OS credential store
+const KEYRING_SERVICE: &str = "isotope";
+
+/// Resolves `{{secret:NAME}}` tokens from a secrets file, falling back to the OS keyring
+#[derive(Debug, Clone, Default)]
+pub struct SecretStore {
+    values: HashMap<String, String>,
+}
+
+impl SecretStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a dotenv-style file of `NAME=value` lines
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secrets file: {}", path.display()))?;
+
+        let mut values = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=').ok_or_else(|| {
+                anyhow!(
+                    "Invalid line {} in secrets file {}: expected NAME=value",
+                    index + 1,
+                    path.display()
+                )
+            })?;
+
+            values.insert(name.trim().to_string(), unquote(value.trim()).to_string());
+        }
+
+        info!("Loaded {} secret(s) from {}", values.len(), path.display());
+        Ok(Self { values })
+    }
+
+    /// Look up a secret and register its value for log redaction
+    pub fn resolve(&self, name: &str) -> Result<String> {
+        let value = match self.values.get(name) {
+            Some(value) => value.clone(),
+            None => {
+                debug!("Secret {} not in secrets file, trying OS keyring", name);
+                keyring::Entry::new(KEYRING_SERVICE, name)
+                    .and_then(|entry| entry.get_password())
+                    .map_err(|e| {
+                        anyhow!(
+                            "Secret '{}' not found in the secrets file or OS keyring: {}",
+                            name,
+                            e
+                        )
+                    })?
+            }
+        };
+
+        redact::register(&value);
+        Ok(value)
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
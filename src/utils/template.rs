@@ -2,11 +2,39 @@
 
 use anyhow::{Context, Result};
 use handlebars::Handlebars;
+use regex::Regex;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 use tracing::debug;
 
+use crate::utils::secrets::SecretStore;
+
+/// `{{secret:NAME}}`, with optional spaces inside the braces
+static SECRET_TOKEN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*secret:([A-Za-z0-9_.-]+)\s*\}\}").unwrap());
+
+/// `${VAR}`, `${env.VAR}` or `{{VAR}}`: a variable rendered from the environment
+static VARIABLE_TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$\{(?:env\.)?([^}]+)\}|\{\{\s*([A-Za-z_][A-Za-z0-9_.]*)\s*\}\}").unwrap()
+});
+
+/// Names of the variables and of the secrets `template` refers to, in order of appearance
+pub fn referenced_names(template: &str) -> (Vec<&str>, Vec<&str>) {
+    let variables = VARIABLE_TOKEN
+        .captures_iter(template)
+        .filter_map(|captures| captures.get(1).or(captures.get(2)))
+        .map(|name| name.as_str())
+        .collect();
+    let secrets = SECRET_TOKEN
+        .captures_iter(template)
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .collect();
+    (variables, secrets)
+}
+
 pub struct TemplateEngine {
     handlebars: Handlebars<'static>,
+    secrets: SecretStore,
 }
 
 impl TemplateEngine {
@@ -16,7 +44,14 @@ impl TemplateEngine {
         // Configure handlebars settings
         handlebars.set_strict_mode(false); // Allow undefined variables
 
-        Self { handlebars }
+        Self {
+            handlebars,
+            secrets: SecretStore::new(),
+        }
+    }
+
+    pub fn set_secrets(&mut self, secrets: SecretStore) {
+        self.secrets = secrets;
     }
 
     pub fn render_string(
@@ -29,9 +64,47 @@ impl TemplateEngine {
         // Convert environment variable format ${VAR} to handlebars format {{VAR}}
         let handlebars_template = self.convert_env_vars_to_handlebars(template);
 
-        self.handlebars
+        // Secrets are substituted after rendering so handlebars never escapes or logs them
+        let (handlebars_template, secrets) = self.extract_secrets(&handlebars_template)?;
+
+        let mut rendered = self
+            .handlebars
             .render_template(&handlebars_template, variables)
-            .with_context(|| format!("Failed to render template: {}", template))
+            .with_context(|| format!("Failed to render template: {}", template))?;
+
+        for (placeholder, value) in secrets {
+            rendered = rendered.replace(&placeholder, &value);
+        }
+        Ok(rendered)
+    }
+
+    /// Resolve only `{{secret:NAME}}` tokens, leaving any other text untouched
+    pub fn resolve_secrets(&self, text: &str) -> Result<String> {
+        let (mut resolved, secrets) = self.extract_secrets(text)?;
+        for (placeholder, value) in secrets {
+            resolved = resolved.replace(&placeholder, &value);
+        }
+        Ok(resolved)
+    }
+
+    /// Swap `{{secret:NAME}}` tokens for placeholders, returning them with their values
+    fn extract_secrets(&self, template: &str) -> Result<(String, Vec<(String, String)>)> {
+        let mut secrets = Vec::new();
+        let mut result = String::with_capacity(template.len());
+        let mut last_end = 0;
+        for captures in SECRET_TOKEN.captures_iter(template) {
+            let token = captures.get(0).unwrap();
+            let value = self.secrets.resolve(&captures[1])?;
+            let placeholder = format!("\u{1}secret{}\u{1}", secrets.len());
+
+            result.push_str(&template[last_end..token.start()]);
+            result.push_str(&placeholder);
+            last_end = token.end();
+            secrets.push((placeholder, value));
+        }
+        result.push_str(&template[last_end..]);
+
+        Ok((result, secrets))
     }
 
     pub fn render_file(
@@ -136,3 +209,31 @@ impl TemplateEngine {
             .with_context(|| format!("Template validation failed: {}", template))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::redact;
+
+    #[test]
+    fn test_secrets_are_rendered_and_masked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.env");
+        std::fs::write(&path, "ROOT_PASSWORD=\"t0ps3cret-template\"\n").unwrap();
+
+        let mut engine = TemplateEngine::new();
+        engine.set_secrets(SecretStore::from_file(&path).unwrap());
+        let variables = HashMap::from([("USER".to_string(), "admin".to_string())]);
+
+        // Handlebars would HTML-escape the value if it ever saw it
+        let rendered = engine
+            .render_string("{{USER}}:{{ secret:ROOT_PASSWORD }}", &variables)
+            .unwrap();
+        assert_eq!(rendered, "admin:t0ps3cret-template");
+        assert_eq!(redact::redact(&rendered), format!("admin:{}", redact::MASK));
+
+        let resolved = engine.resolve_secrets("pw={{secret:ROOT_PASSWORD}} {{USER}}").unwrap();
+        assert_eq!(resolved, "pw=t0ps3cret-template {{USER}}");
+        assert!(!redact::redact(&resolved).contains("t0ps3cret"));
+    }
+}
@@ -15,6 +15,12 @@ pub struct VmMetadataEntry {
     pub created_at: String, // ISO 8601 timestamp
     pub last_used: String,  // ISO 8601 timestamp
     pub provider: String,
+    /// Global step number that failed the last build, consumed by `build --continue`
+    #[serde(default)]
+    pub failed_step: Option<usize>,
+    /// `failed_step` again when the `isotope-pre-step` snapshot was taken before that step
+    #[serde(default)]
+    pub snapshot_step: Option<usize>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -54,6 +60,21 @@ impl VmMetadata {
         Ok(())
     }
 
+    /// Stable VM name for an isotope file: `isotope-` plus a hash of its absolute path
+    pub fn derived_vm_name(isotope_path: &Path) -> Result<String> {
+        let abs_path = isotope_path.canonicalize().with_context(|| {
+            format!(
+                "Failed to resolve absolute path for {}",
+                isotope_path.display()
+            )
+        })?;
+        let hash = ring::digest::digest(
+            &ring::digest::SHA256,
+            abs_path.to_string_lossy().as_bytes(),
+        );
+        Ok(format!("isotope-{}", hex::encode(&hash.as_ref()[..4])))
+    }
+
     pub fn get_vm_for_isotope_file(&self, isotope_path: &Path) -> Option<&VmMetadataEntry> {
         let abs_path = match isotope_path.canonicalize() {
             Ok(path) => path,
@@ -76,6 +97,12 @@ impl VmMetadata {
         })?;
         let now = chrono::Utc::now().to_rfc3339();
         let key = abs_path.to_string_lossy().to_string();
+        // Keep the recorded failure while the same VM is being tracked
+        let (failed_step, snapshot_step) = self
+            .vms
+            .get(&key)
+            .filter(|entry| entry.vm_id == vm_instance.id)
+            .map_or((None, None), |entry| (entry.failed_step, entry.snapshot_step));
         let entry = VmMetadataEntry {
             vm_name: vm_instance.name.clone(),
             vm_id: vm_instance.id.clone(),
@@ -87,16 +114,46 @@ impl VmMetadata {
             },
             last_used: now,
             provider: format!("{:?}", vm_instance.provider),
+            failed_step,
+            snapshot_step,
         };
         info!(
             "Tracking VM {} for isotope file {}",
             vm_instance.name,
             abs_path.display()
         );
+        // With a fixed --vm-name another spec may have tracked the same VM; it belongs here now
+        self.vms
+            .retain(|other_key, other| *other_key == key || other.vm_name != vm_instance.name);
         self.vms.insert(key, entry);
         Ok(())
     }
 
+    /// Record (or clear with `None`) the step that failed the last build of this isotope file
+    /// and the step the pre-step snapshot belongs to
+    pub fn set_failed_step(
+        &mut self,
+        isotope_path: &Path,
+        step: Option<usize>,
+        snapshot_step: Option<usize>,
+    ) -> Result<()> {
+        let abs_path = isotope_path.canonicalize().with_context(|| {
+            format!(
+                "Failed to resolve absolute path for {}",
+                isotope_path.display()
+            )
+        })?;
+
+        let key = abs_path.to_string_lossy().to_string();
+        let entry = self
+            .vms
+            .get_mut(&key)
+            .ok_or_else(|| anyhow!("No VM tracked for {}", abs_path.display()))?;
+        entry.failed_step = step;
+        entry.snapshot_step = snapshot_step;
+        Ok(())
+    }
+
     pub fn remove_vm(&mut self, isotope_path: &Path) -> Result<()> {
         let abs_path = isotope_path.canonicalize().with_context(|| {
             format!(